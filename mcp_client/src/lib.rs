@@ -1,5 +1,7 @@
-use reqwest::blocking::Client;
+use reqwest::blocking::{Client, Response};
 use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
 use std::sync::atomic::{AtomicU64, Ordering};
 
 #[derive(Debug, thiserror::Error)]
@@ -8,35 +10,123 @@ pub enum Error {
     Transport(#[from] reqwest::Error),
     #[error("rpc error: {0}")]
     Rpc(Value),
+    #[error("stream: {0}")]
+    Stream(String),
+    #[error("response larger than {0} bytes")]
+    TooLarge(usize),
+}
+
+/// Wire-level settings for servers that deviate from plain JSON-RPC 2.0 method names.
+#[derive(Debug, Clone)]
+pub struct RpcConfig {
+    /// Value of the `jsonrpc` field; `"2.0"` by default.
+    pub version: String,
+    /// Prepended to every method name without an explicit mapping, e.g. `"mcp."`.
+    pub prefix: String,
+    /// Full renames that take precedence over `prefix`, keyed by the standard name.
+    pub methods: HashMap<String, String>,
+    /// Sent as the `User-Agent` of every request; `mcp_client/<version>` by default.
+    pub user_agent: String,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            version: "2.0".into(),
+            prefix: String::new(),
+            methods: HashMap::new(),
+            user_agent: concat!("mcp_client/", env!("CARGO_PKG_VERSION")).into(),
+        }
+    }
+}
+
+impl RpcConfig {
+    fn method(&self, name: &str) -> String {
+        match self.methods.get(name) {
+            Some(mapped) => mapped.clone(),
+            None => format!("{}{name}", self.prefix),
+        }
+    }
 }
 
 pub struct McpClient {
     base_url: String,
     http: Client,
     id: AtomicU64,
+    config: RpcConfig,
+    max_response_bytes: Option<usize>,
 }
 
 impl McpClient {
     pub fn new(base_url: impl Into<String>) -> Result<Self, Error> {
-        let client = Client::new();
+        Self::with_config(base_url, RpcConfig::default())
+    }
+
+    /// Like [`McpClient::new`], but speaking the version and method names in `config`.
+    pub fn with_config(base_url: impl Into<String>, config: RpcConfig) -> Result<Self, Error> {
+        let client = Client::builder().user_agent(&config.user_agent).build()?;
         let this = Self {
             base_url: base_url.into(),
             http: client,
             id: AtomicU64::new(1),
+            config,
+            max_response_bytes: None,
         };
         // Perform handshake to ensure server is reachable
         let _ = this.handshake()?;
         Ok(this)
     }
 
+    /// Fails any response whose body exceeds `max_response_bytes` with
+    /// [`Error::TooLarge`], without reading past the limit.
+    pub fn with_max_response_bytes(mut self, max_response_bytes: usize) -> Self {
+        self.max_response_bytes = Some(max_response_bytes);
+        self
+    }
+
+    fn read_json(&self, resp: Response) -> Result<Value, Error> {
+        let Some(limit) = self.max_response_bytes else {
+            return Ok(resp.json()?);
+        };
+        if resp.content_length().is_some_and(|len| len > limit as u64) {
+            return Err(Error::TooLarge(limit));
+        }
+        let mut body = Vec::new();
+        resp.take(limit as u64 + 1)
+            .read_to_end(&mut body)
+            .map_err(|e| Error::Stream(e.to_string()))?;
+        if body.len() > limit {
+            return Err(Error::TooLarge(limit));
+        }
+        serde_json::from_slice(&body).map_err(|e| Error::Stream(e.to_string()))
+    }
+
     fn rpc(&self, method: &str, params: Value) -> Result<Value, Error> {
+        self.rpc_with_headers(method, params, &[])
+    }
+
+    fn rpc_with_headers(
+        &self,
+        method: &str,
+        params: Value,
+        headers: &[(&str, &str)],
+    ) -> Result<Value, Error> {
         let id = self.id.fetch_add(1, Ordering::SeqCst);
-        let req = json!({"jsonrpc":"2.0","id":id,"method":method,"params":params});
-        let resp: Value = self.http.post(&self.base_url).json(&req).send()?.json()?;
-        if let Some(err) = resp.get("error") {
-            return Err(Error::Rpc(err.clone()));
+        let req = self.request(id, method, params);
+        let mut builder = self.http.post(&self.base_url);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
         }
-        Ok(resp["result"].clone())
+        response(self.read_json(builder.json(&req).send()?)?)
+    }
+
+    fn request(&self, id: u64, method: &str, params: Value) -> Value {
+        json!({
+            "jsonrpc": self.config.version,
+            "id": id,
+            "method": self.config.method(method),
+            "params": params,
+        })
     }
 
     pub fn handshake(&self) -> Result<Value, Error> {
@@ -50,6 +140,101 @@ impl McpClient {
     pub fn invoke(&self, tool: &str, input: Value) -> Result<Value, Error> {
         self.rpc("invoke", json!({"tool": tool, "input": input}))
     }
+
+    /// Invokes a tool with extra HTTP headers such as `x-request-id`.
+    pub fn invoke_with_headers(
+        &self,
+        tool: &str,
+        input: Value,
+        headers: &[(&str, &str)],
+    ) -> Result<Value, Error> {
+        self.rpc_with_headers("invoke", json!({"tool": tool, "input": input}), headers)
+    }
+
+    /// Invokes a tool, passing each `notifications/progress` the server sends before
+    /// the result to `on_progress`.
+    ///
+    /// The request carries a progress token and accepts `text/event-stream`; servers
+    /// that answer with plain JSON simply produce no progress.
+    pub fn invoke_with_progress(
+        &self,
+        tool: &str,
+        input: Value,
+        headers: &[(&str, &str)],
+        on_progress: &mut dyn FnMut(Value),
+    ) -> Result<Value, Error> {
+        let id = self.id.fetch_add(1, Ordering::SeqCst);
+        let params = json!({"tool": tool, "input": input, "_meta": {"progressToken": id}});
+        let req = self.request(id, "invoke", params);
+        let mut builder = self
+            .http
+            .post(&self.base_url)
+            .header("accept", "application/json, text/event-stream");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let resp = builder.json(&req).send()?;
+        let streaming = resp
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/event-stream"));
+        if !streaming {
+            return response(self.read_json(resp)?);
+        }
+        let mut read = 0;
+        for line in BufReader::new(resp).lines() {
+            let line = line.map_err(|e| Error::Stream(e.to_string()))?;
+            read += line.len() + 1;
+            if let Some(limit) = self.max_response_bytes.filter(|limit| read > *limit) {
+                return Err(Error::TooLarge(limit));
+            }
+            let Some(data) = line.strip_prefix("data:") else {
+                continue;
+            };
+            let msg: Value =
+                serde_json::from_str(data.trim()).map_err(|e| Error::Stream(e.to_string()))?;
+            if msg.get("method").and_then(Value::as_str) == Some("notifications/progress") {
+                on_progress(msg["params"].clone());
+            } else if msg.get("id") == Some(&json!(id)) {
+                return response(msg);
+            }
+        }
+        Err(Error::Stream("stream ended without a result".into()))
+    }
+
+    /// Lists the resources the server exposes, from `result.resources`.
+    pub fn list_resources(&self) -> Result<Vec<Value>, Error> {
+        let result = self.rpc("resources/list", json!({}))?;
+        Ok(array(&result, "resources"))
+    }
+
+    /// Reads a resource and returns the entries of its `contents` array, each with a
+    /// `uri` and either `text` or base64 `blob`.
+    pub fn read_resource(&self, uri: &str) -> Result<Vec<Value>, Error> {
+        let result = self.rpc("resources/read", json!({"uri": uri}))?;
+        Ok(array(&result, "contents"))
+    }
+
+    /// Renders a prompt template with `arguments`; the result carries its `messages`.
+    pub fn get_prompt(&self, name: &str, arguments: Value) -> Result<Value, Error> {
+        self.rpc("prompts/get", json!({"name": name, "arguments": arguments}))
+    }
+}
+
+fn response(resp: Value) -> Result<Value, Error> {
+    if let Some(err) = resp.get("error") {
+        return Err(Error::Rpc(err.clone()));
+    }
+    Ok(resp["result"].clone())
+}
+
+fn array(result: &Value, key: &str) -> Vec<Value> {
+    result
+        .get(key)
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default()
 }
 
 #[cfg(test)]
@@ -62,12 +247,13 @@ mod tests {
         let server = MockServer::start();
         let _handshake = server.mock(|when, then| {
             when.method(POST)
-                .json_partial(json!({"method":"handshake"}));
+                .json_body_partial(json!({"method":"handshake"}).to_string());
             then.status(200)
                 .json_body(json!({"jsonrpc":"2.0","id":1,"result":{"ok":true}}));
         });
         let _invoke = server.mock(|when, then| {
-            when.method(POST).json_partial(json!({"method":"invoke"}));
+            when.method(POST)
+                .json_body_partial(json!({"method":"invoke"}).to_string());
             then.status(200)
                 .json_body(json!({"jsonrpc":"2.0","id":2,"result":{"pong":true}}));
         });
@@ -76,4 +262,161 @@ mod tests {
         let out = client.invoke("ping", json!({})).unwrap();
         assert_eq!(out, json!({"pong":true}));
     }
+
+    fn server_with_handshake() -> MockServer {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(POST)
+                .json_body_partial(json!({"method":"handshake"}).to_string());
+            then.status(200)
+                .json_body(json!({"jsonrpc":"2.0","id":1,"result":{"ok":true}}));
+        });
+        server
+    }
+
+    #[test]
+    fn client_lists_resources() {
+        let server = server_with_handshake();
+        let list = server.mock(|when, then| {
+            when.method(POST)
+                .json_body_partial(json!({"method":"resources/list","params":{}}).to_string());
+            then.status(200)
+                .json_body(json!({"jsonrpc":"2.0","id":2,"result":{
+                    "resources":[{"uri":"file:///notes.md","name":"notes"}]
+                }}));
+        });
+
+        let client = McpClient::new(server.url("/")).unwrap();
+        let resources = client.list_resources().unwrap();
+        list.assert();
+        assert_eq!(
+            resources,
+            vec![json!({"uri":"file:///notes.md","name":"notes"})]
+        );
+    }
+
+    #[test]
+    fn client_reads_resource_contents() {
+        let server = server_with_handshake();
+        let read = server.mock(|when, then| {
+            when.method(POST).json_body_partial(
+                json!({
+                    "method":"resources/read",
+                    "params":{"uri":"file:///notes.md"}
+                })
+                .to_string(),
+            );
+            then.status(200)
+                .json_body(json!({"jsonrpc":"2.0","id":2,"result":{
+                    "contents":[
+                        {"uri":"file:///notes.md","mimeType":"text/markdown","text":"# hi"},
+                        {"uri":"file:///notes.md#img","blob":"aGk="}
+                    ]
+                }}));
+        });
+
+        let client = McpClient::new(server.url("/")).unwrap();
+        let contents = client.read_resource("file:///notes.md").unwrap();
+        read.assert();
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents[0]["text"], "# hi");
+        assert_eq!(contents[1]["blob"], "aGk=");
+    }
+
+    #[test]
+    fn client_gets_prompt() {
+        let server = server_with_handshake();
+        let get = server.mock(|when, then| {
+            when.method(POST).json_body_partial(
+                json!({
+                    "method":"prompts/get",
+                    "params":{"name":"summarize","arguments":{"style":"brief"}}
+                })
+                .to_string(),
+            );
+            then.status(200)
+                .json_body(json!({"jsonrpc":"2.0","id":2,"result":{
+                    "description":"Summarize text",
+                    "messages":[{"role":"user","content":{"type":"text","text":"Be brief."}}]
+                }}));
+        });
+
+        let client = McpClient::new(server.url("/")).unwrap();
+        let prompt = client
+            .get_prompt("summarize", json!({"style":"brief"}))
+            .unwrap();
+        get.assert();
+        assert_eq!(prompt["messages"][0]["content"]["text"], "Be brief.");
+    }
+
+    #[test]
+    fn client_streams_progress_before_result() {
+        let server = server_with_handshake();
+        let body = [
+            json!({"jsonrpc":"2.0","method":"notifications/progress",
+                   "params":{"progressToken":2,"progress":1,"total":2}}),
+            json!({"jsonrpc":"2.0","method":"notifications/progress",
+                   "params":{"progressToken":2,"progress":2,"total":2}}),
+            json!({"jsonrpc":"2.0","id":2,"result":{"done":true}}),
+        ]
+        .iter()
+        .map(|msg| format!("event: message\ndata: {msg}\n\n"))
+        .collect::<String>();
+        let invoke = server.mock(|when, then| {
+            when.method(POST)
+                .header("accept", "application/json, text/event-stream")
+                .json_body_partial(
+                    json!({"method":"invoke","params":{"_meta":{"progressToken":2}}}).to_string(),
+                );
+            then.status(200)
+                .header("content-type", "text/event-stream")
+                .body(body);
+        });
+
+        let client = McpClient::new(server.url("/")).unwrap();
+        let mut seen = Vec::new();
+        let out = client
+            .invoke_with_progress("slow", json!({}), &[], &mut |p| seen.push(p))
+            .unwrap();
+        invoke.assert();
+        assert_eq!(out, json!({"done":true}));
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[1]["progress"], 2);
+    }
+
+    #[test]
+    fn client_applies_method_prefix_and_version() {
+        let server = MockServer::start();
+        let handshake = server.mock(|when, then| {
+            when.method(POST)
+                .json_body_partial(json!({"jsonrpc":"1.0","method":"mcp.handshake"}).to_string());
+            then.status(200)
+                .json_body(json!({"jsonrpc":"1.0","id":1,"result":{"ok":true}}));
+        });
+        let invoke = server.mock(|when, then| {
+            when.method(POST)
+                .json_body_partial(json!({"jsonrpc":"1.0","method":"mcp.invoke"}).to_string());
+            then.status(200)
+                .json_body(json!({"jsonrpc":"1.0","id":2,"result":{"pong":true}}));
+        });
+        let schema = server.mock(|when, then| {
+            when.method(POST)
+                .json_body_partial(json!({"method":"tools.describe"}).to_string());
+            then.status(200)
+                .json_body(json!({"jsonrpc":"1.0","id":3,"result":{"type":"object"}}));
+        });
+
+        let config = RpcConfig {
+            version: "1.0".into(),
+            prefix: "mcp.".into(),
+            methods: HashMap::from([("schema".to_string(), "tools.describe".to_string())]),
+            ..RpcConfig::default()
+        };
+        let client = McpClient::with_config(server.url("/"), config).unwrap();
+        assert_eq!(client.invoke("ping", json!({})).unwrap(), json!({"pong":true}));
+        assert_eq!(client.schema("ping").unwrap(), json!({"type":"object"}));
+        handshake.assert();
+        invoke.assert();
+        schema.assert();
+    }
 }
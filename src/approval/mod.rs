@@ -0,0 +1,29 @@
+use futures::future::{self, BoxFuture};
+use serde_json::Value;
+
+/// ApprovalDecision is an approver's verdict on a pending tool call.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApprovalDecision {
+    Approve,
+    /// The run stops with `{"error": "tool_denied", "tool", "reason"}`.
+    Deny(String),
+    /// The tool runs with this input instead of the requested one.
+    Modify(Value),
+}
+
+/// ApprovalHook is consulted before each call of a tool it guards.
+///
+/// Closures `Fn(&str, &Value) -> ApprovalDecision` implement it directly; implement
+/// the trait by hand when approval has to wait, e.g. on a human.
+pub trait ApprovalHook: Send + Sync {
+    fn approve<'a>(&'a self, op: &'a str, input: &'a Value) -> BoxFuture<'a, ApprovalDecision>;
+}
+
+impl<F> ApprovalHook for F
+where
+    F: Fn(&str, &Value) -> ApprovalDecision + Send + Sync,
+{
+    fn approve<'a>(&'a self, op: &'a str, input: &'a Value) -> BoxFuture<'a, ApprovalDecision> {
+        Box::pin(future::ready(self(op, input)))
+    }
+}
@@ -0,0 +1,309 @@
+use serde_json::{json, Value};
+
+/// Where an image comes from: a fetchable URL or inline base64 data.
+#[derive(Clone, Debug)]
+pub enum ImageSource {
+    Url(String),
+    Base64 { media_type: String, data: String },
+}
+
+impl ImageSource {
+    pub(crate) fn data_url(&self) -> String {
+        match self {
+            ImageSource::Url(url) => url.clone(),
+            ImageSource::Base64 { media_type, data } => {
+                format!("data:{media_type};base64,{data}")
+            }
+        }
+    }
+}
+
+/// Builds a user message carrying `text` and one image in the content-part
+/// shape expected by `dialect` (`openai`, `dashscope` or `anthropic`).
+pub fn image_message(dialect: &str, text: &str, image: &ImageSource) -> Value {
+    let parts = match dialect {
+        "dashscope" => json!([
+            { "image": image.data_url() },
+            { "text": text }
+        ]),
+        "anthropic" => {
+            let source = match image {
+                ImageSource::Url(url) => json!({ "type": "url", "url": url }),
+                ImageSource::Base64 { media_type, data } => json!({
+                    "type": "base64",
+                    "media_type": media_type,
+                    "data": data
+                }),
+            };
+            json!([
+                { "type": "image", "source": source },
+                { "type": "text", "text": text }
+            ])
+        }
+        _ => json!([
+            { "type": "text", "text": text },
+            { "type": "image_url", "image_url": { "url": image.data_url() } }
+        ]),
+    };
+    json!({ "role": "user", "content": parts })
+}
+
+fn part_supported(dialect: &str, part: &Value) -> bool {
+    let kind = part.get("type").and_then(|v| v.as_str());
+    match dialect {
+        "dashscope" => {
+            kind.is_none()
+                && ["text", "image", "file"]
+                    .iter()
+                    .any(|k| part.get(*k).is_some())
+        }
+        "anthropic" => matches!(kind, Some("text" | "image" | "document" | "tool_result")),
+        _ => matches!(kind, Some("text" | "image_url" | "file")),
+    }
+}
+
+/// Checks that every content-part array in `messages` uses a shape the dialect
+/// understands. Plain string contents always pass.
+pub fn validate_messages(dialect: &str, messages: &Value) -> Result<(), String> {
+    let Some(messages) = messages.as_array() else {
+        return Ok(());
+    };
+    for message in messages {
+        let Some(parts) = message.get("content").and_then(|c| c.as_array()) else {
+            continue;
+        };
+        if let Some(part) = parts.iter().find(|p| !part_supported(dialect, p)) {
+            return Err(format!(
+                "content part {part} is not supported by dialect {dialect}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the role sequence of `messages` against the dialect's ordering rules:
+/// known roles only, `system`/`developer` messages before any other (dashscope allows
+/// a single leading `system`), and every `tool` (dashscope: `function`) message
+/// answering a call made by the assistant message just before its block.
+pub fn validate_roles(dialect: &str, messages: &Value) -> Result<(), String> {
+    let Some(messages) = messages.as_array() else {
+        return Ok(());
+    };
+    let dashscope = dialect == "dashscope";
+    let mut pending: Option<Vec<Value>> = None;
+    let mut seen_chat = false;
+    for (index, message) in messages.iter().enumerate() {
+        let role = message.get("role").and_then(|r| r.as_str()).unwrap_or("");
+        match role {
+            "system" | "developer" => {
+                if dashscope && (role == "developer" || index > 0) {
+                    return Err(format!(
+                        "message {index}: dialect dashscope allows one {role:?} message, first"
+                    ));
+                }
+                if seen_chat {
+                    return Err(format!(
+                        "message {index}: {role} message after the conversation started"
+                    ));
+                }
+                continue;
+            }
+            "tool" if !dashscope => {
+                let Some(calls) = &pending else {
+                    return Err(format!(
+                        "message {index}: tool message without a preceding tool call"
+                    ));
+                };
+                let id = &message["tool_call_id"];
+                if !calls.contains(id) {
+                    return Err(format!(
+                        "message {index}: tool_call_id {id} matches no preceding tool call"
+                    ));
+                }
+            }
+            "function" if dashscope => {
+                if pending.is_none() {
+                    return Err(format!(
+                        "message {index}: function message without a preceding function call"
+                    ));
+                }
+            }
+            "user" => pending = None,
+            "assistant" => {
+                pending = if dashscope {
+                    message.get("function_call").map(|_| Vec::new())
+                } else {
+                    message["tool_calls"]
+                        .as_array()
+                        .map(|calls| calls.iter().map(|c| c["id"].clone()).collect())
+                };
+            }
+            _ => {
+                return Err(format!(
+                    "message {index}: role {role:?} is not supported by dialect {dialect}"
+                ))
+            }
+        }
+        seen_chat = true;
+    }
+    Ok(())
+}
+
+/// An image or file returned by a tool, as recognized by [`tool_media`].
+#[derive(Clone, Debug)]
+pub enum ToolMedia {
+    Image(ImageSource),
+    File(ImageSource),
+}
+
+/// Reads a URL or `data:` URL into a source.
+pub(crate) fn media_source(url: &str) -> ImageSource {
+    let inline = url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"));
+    match inline {
+        Some((media_type, data)) => ImageSource::Base64 {
+            media_type: media_type.to_string(),
+            data: data.to_string(),
+        },
+        None => ImageSource::Url(url.to_string()),
+    }
+}
+
+/// Recognizes a tool output that is an image or file: an object with an
+/// `image_url`/`image` or `file_url`/`file` string, holding a URL or a `data:` URL.
+pub fn tool_media(output: &Value) -> Option<ToolMedia> {
+    let field = |keys: [&str; 2]| {
+        keys.iter()
+            .find_map(|key| output.get(*key).and_then(Value::as_str))
+            .map(media_source)
+    };
+    field(["image_url", "image"])
+        .map(ToolMedia::Image)
+        .or_else(|| field(["file_url", "file"]).map(ToolMedia::File))
+}
+
+/// One tool result fed back to the model.
+#[derive(Clone, Copy, Debug)]
+pub struct ToolResult<'a> {
+    pub name: &'a str,
+    /// The id of the call this answers, when the model gave one.
+    pub call_id: Option<&'a str>,
+    pub output: &'a Value,
+}
+
+fn result_text(output: &Value) -> String {
+    output
+        .as_str()
+        .map_or_else(|| output.to_string(), str::to_string)
+}
+
+fn media_note(name: &str, media: &ToolMedia) -> String {
+    match media {
+        ToolMedia::Image(_) => format!("{name} returned an image"),
+        ToolMedia::File(_) => format!("{name} returned a file"),
+    }
+}
+
+/// The content part carrying `media` in `dialect`'s shape.
+fn media_part(dialect: &str, media: &ToolMedia) -> Value {
+    match (dialect, media) {
+        ("dashscope", ToolMedia::Image(source)) => json!({ "image": source.data_url() }),
+        ("dashscope", ToolMedia::File(source)) => json!({ "file": source.data_url() }),
+        ("anthropic", ToolMedia::Image(source) | ToolMedia::File(source)) => {
+            let kind = match media {
+                ToolMedia::Image(_) => "image",
+                ToolMedia::File(_) => "document",
+            };
+            let source = match source {
+                ImageSource::Url(url) => json!({ "type": "url", "url": url }),
+                ImageSource::Base64 { media_type, data } => json!({
+                    "type": "base64",
+                    "media_type": media_type,
+                    "data": data
+                }),
+            };
+            json!({ "type": kind, "source": source })
+        }
+        (_, ToolMedia::Image(source)) => {
+            json!({ "type": "image_url", "image_url": { "url": source.data_url() } })
+        }
+        (_, ToolMedia::File(source @ ImageSource::Base64 { .. })) => {
+            json!({ "type": "file", "file": { "file_data": source.data_url() } })
+        }
+        // openai only takes inline file data, so a linked file is passed as its URL
+        (_, ToolMedia::File(source)) => json!({ "type": "text", "text": source.data_url() }),
+    }
+}
+
+/// Builds the messages feeding tool results back to the model in `dialect`'s shape,
+/// with images and files (see [`tool_media`]) as content parts:
+///
+/// - `openai`: a `tool` message per call id, then one `user` message carrying the
+///   media, since tool messages only hold text;
+/// - `anthropic`: one `user` message of `tool_result` blocks;
+/// - `dashscope`: a `function` message per result, with content items for media.
+pub fn tool_result_messages(dialect: &str, results: &[ToolResult]) -> Vec<Value> {
+    match dialect {
+        "dashscope" => results
+            .iter()
+            .map(|result| {
+                let content = match tool_media(result.output) {
+                    Some(media) => json!([media_part(dialect, &media)]),
+                    None => json!(result_text(result.output)),
+                };
+                json!({ "role": "function", "name": result.name, "content": content })
+            })
+            .collect(),
+        "anthropic" => {
+            let blocks: Vec<Value> = results
+                .iter()
+                .map(|result| {
+                    let content = match tool_media(result.output) {
+                        Some(media) => media_part(dialect, &media),
+                        None => json!({ "type": "text", "text": result_text(result.output) }),
+                    };
+                    match result.call_id {
+                        Some(id) => json!({
+                            "type": "tool_result",
+                            "tool_use_id": id,
+                            "content": [content]
+                        }),
+                        None => content,
+                    }
+                })
+                .collect();
+            vec![json!({ "role": "user", "content": blocks })]
+        }
+        _ => {
+            let mut messages = Vec::new();
+            let mut parts = Vec::new();
+            for result in results {
+                let media = tool_media(result.output);
+                let text = match &media {
+                    Some(media) => media_note(result.name, media),
+                    None => result_text(result.output),
+                };
+                match result.call_id {
+                    Some(id) => messages.push(json!({
+                        "role": "tool",
+                        "tool_call_id": id,
+                        "content": text
+                    })),
+                    None if media.is_none() => {
+                        messages.push(json!({ "role": "user", "content": text }))
+                    }
+                    None => {}
+                }
+                if let Some(media) = media {
+                    parts.push(json!({ "type": "text", "text": media_note(result.name, &media) }));
+                    parts.push(media_part(dialect, &media));
+                }
+            }
+            if !parts.is_empty() {
+                messages.push(json!({ "role": "user", "content": parts }));
+            }
+            messages
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Map, Value};
+
+use super::content::{media_source, ImageSource};
+use crate::Ask;
+
+/// Reads one tool definition in `dialect`'s shape into the neutral
+/// `{"name", "description", "parameters"}` form asks carry in `context.tools`.
+fn tool_from(dialect: &str, tool: &Value) -> Value {
+    match dialect {
+        "dashscope" => tool.clone(),
+        "anthropic" => rename(tool, "input_schema", "parameters"),
+        _ => match tool.get("function") {
+            Some(function) if tool["type"] == "function" => function.clone(),
+            _ => tool.clone(),
+        },
+    }
+}
+
+/// Writes one neutral tool definition in `dialect`'s shape.
+fn tool_to(dialect: &str, tool: &Value) -> Value {
+    match dialect {
+        "dashscope" => tool.clone(),
+        "anthropic" => rename(tool, "parameters", "input_schema"),
+        _ => json!({ "type": "function", "function": tool }),
+    }
+}
+
+/// A copy of `object` with the `from` key renamed to `to`, keeping every other key.
+fn rename(object: &Value, from: &str, to: &str) -> Value {
+    let Some(fields) = object.as_object() else {
+        return object.clone();
+    };
+    let renamed: Map<String, Value> = fields
+        .iter()
+        .map(|(key, value)| {
+            let key = if key == from { to } else { key };
+            (key.to_string(), value.clone())
+        })
+        .collect();
+    Value::Object(renamed)
+}
+
+/// Writes neutral tool definitions (`{"name", "description", "parameters"}`) in the
+/// shape `dialect` expects: wrapped as `{"type": "function", "function"}` for
+/// `openai`, with `input_schema` for `anthropic`, as is for `dashscope`.
+pub fn tools_for(dialect: &str, tools: &Value) -> Value {
+    map_array(tools, |tool| tool_to(dialect, tool))
+}
+
+/// Converts tool definitions from `from`'s shape to `to`'s. Keys a dialect does not
+/// rename, such as `strict`, are carried over.
+pub fn convert_tools(from: &str, to: &str, tools: &Value) -> Value {
+    map_array(tools, |tool| tool_to(to, &tool_from(from, tool)))
+}
+
+fn map_array(values: &Value, f: impl Fn(&Value) -> Value) -> Value {
+    match values.as_array() {
+        Some(values) => values.iter().map(f).collect(),
+        None => values.clone(),
+    }
+}
+
+/// Normalizes `tool_choice` for the dialect. Besides each dialect's native values,
+/// callers may pass `"required"` or `{"force": "tool_name"}`.
+pub fn tool_choice(dialect: &str, choice: &Value) -> Result<Value, String> {
+    if let Some(name) = choice.get("force").and_then(|v| v.as_str()) {
+        return Ok(json!({ "type": "function", "function": { "name": name } }));
+    }
+    match (dialect, choice.as_str()) {
+        ("dashscope", Some("required")) => {
+            Err("tool_choice \"required\" is not supported by dialect dashscope".into())
+        }
+        _ => Ok(choice.clone()),
+    }
+}
+
+/// Converts a messages array from `from`'s shape to `to`'s: content parts, assistant
+/// tool calls (`tool_calls`, `function_call` or `tool_use` blocks) and tool results
+/// (`tool` messages, `function` messages or `tool_result` blocks).
+///
+/// `system` messages are kept as they are; dashscope calls, which carry no id, are
+/// given `call_{index}` ids from their message's position.
+pub fn convert_messages(from: &str, to: &str, messages: &Value) -> Value {
+    let Some(messages) = messages.as_array() else {
+        return messages.clone();
+    };
+    if from == to {
+        return Value::Array(messages.clone());
+    }
+    Value::Array(from_openai(to, to_openai(from, messages)))
+}
+
+/// Reads `messages` into the openai shape, the hub every conversion goes through.
+fn to_openai(dialect: &str, messages: &[Value]) -> Vec<Value> {
+    let mut out = Vec::new();
+    // a dashscope function result answers the oldest unanswered call of its name
+    let mut pending: HashMap<String, Vec<String>> = HashMap::new();
+    for (index, message) in messages.iter().enumerate() {
+        let role = message["role"].as_str().unwrap_or("");
+        match (dialect, role) {
+            ("dashscope", "assistant") if message.get("function_call").is_some() => {
+                let call = &message["function_call"];
+                let name = call["name"].as_str().unwrap_or("").to_string();
+                let id = format!("call_{index}");
+                pending.entry(name.clone()).or_default().push(id.clone());
+                out.push(json!({
+                    "role": "assistant",
+                    "content": message.get("content").cloned().unwrap_or(Value::Null),
+                    "tool_calls": [{
+                        "id": id,
+                        "type": "function",
+                        "function": { "name": name, "arguments": arguments_text(&call["arguments"]) }
+                    }]
+                }));
+            }
+            ("dashscope", "function") => {
+                let name = message["name"].as_str().unwrap_or("");
+                let id = pending
+                    .get_mut(name)
+                    .filter(|ids| !ids.is_empty())
+                    .map(|ids| ids.remove(0));
+                out.push(json!({
+                    "role": "tool",
+                    "tool_call_id": id,
+                    "content": content_to_openai(dialect, &message["content"]),
+                }));
+            }
+            ("anthropic", "assistant") if message["content"].is_array() => {
+                let mut text = Vec::new();
+                let mut calls = Vec::new();
+                for block in message["content"].as_array().into_iter().flatten() {
+                    match block["type"].as_str() {
+                        Some("tool_use") => calls.push(json!({
+                            "id": block["id"],
+                            "type": "function",
+                            "function": {
+                                "name": block["name"],
+                                "arguments": arguments_text(&block["input"]),
+                            }
+                        })),
+                        _ => text.extend(block["text"].as_str()),
+                    }
+                }
+                let mut converted = json!({ "role": "assistant", "content": text.join("") });
+                if !calls.is_empty() {
+                    converted["tool_calls"] = Value::Array(calls);
+                }
+                out.push(converted);
+            }
+            ("anthropic", "user") if message["content"].is_array() => {
+                let mut parts = Vec::new();
+                for block in message["content"].as_array().into_iter().flatten() {
+                    if block["type"] == "tool_result" {
+                        out.push(json!({
+                            "role": "tool",
+                            "tool_call_id": block["tool_use_id"],
+                            "content": result_to_openai(&block["content"]),
+                        }));
+                    } else {
+                        parts.push(part_to_openai(dialect, block));
+                    }
+                }
+                if !parts.is_empty() {
+                    out.push(json!({ "role": "user", "content": parts }));
+                }
+            }
+            _ => {
+                let mut converted = message.clone();
+                if message["content"].is_array() {
+                    converted["content"] = content_to_openai(dialect, &message["content"]);
+                }
+                out.push(converted);
+            }
+        }
+    }
+    out
+}
+
+/// Writes openai-shaped `messages` in `dialect`'s shape.
+fn from_openai(dialect: &str, messages: Vec<Value>) -> Vec<Value> {
+    if dialect != "dashscope" && dialect != "anthropic" {
+        return messages;
+    }
+    let mut out: Vec<Value> = Vec::new();
+    let mut names: HashMap<String, String> = HashMap::new();
+    for message in messages {
+        let role = message["role"].as_str().unwrap_or("");
+        let calls = message["tool_calls"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        match (dialect, role) {
+            ("dashscope", "assistant") if !calls.is_empty() => {
+                // one function call per assistant message, as Qwen-Agent does
+                for (index, call) in calls.iter().enumerate() {
+                    let name = call["function"]["name"].clone();
+                    if let (Some(id), Some(name)) = (call["id"].as_str(), name.as_str()) {
+                        names.insert(id.to_string(), name.to_string());
+                    }
+                    let content = match index {
+                        0 => text_content(&message["content"]),
+                        _ => json!(""),
+                    };
+                    out.push(json!({
+                        "role": "assistant",
+                        "content": content,
+                        "function_call": {
+                            "name": name,
+                            "arguments": call["function"]["arguments"],
+                        }
+                    }));
+                }
+            }
+            ("dashscope", "tool") => {
+                let name = message["tool_call_id"]
+                    .as_str()
+                    .and_then(|id| names.get(id))
+                    .map_or(Value::Null, |name| json!(name));
+                out.push(json!({
+                    "role": "function",
+                    "name": name,
+                    "content": content_from_openai(dialect, &message["content"]),
+                }));
+            }
+            ("anthropic", "assistant") if !calls.is_empty() => {
+                let mut blocks = Vec::new();
+                if let Some(text) = message["content"].as_str().filter(|t| !t.is_empty()) {
+                    blocks.push(json!({ "type": "text", "text": text }));
+                }
+                for call in &calls {
+                    let arguments = &call["function"]["arguments"];
+                    let input = arguments
+                        .as_str()
+                        .and_then(|text| serde_json::from_str(text).ok())
+                        .unwrap_or_else(|| arguments.clone());
+                    blocks.push(json!({
+                        "type": "tool_use",
+                        "id": call["id"],
+                        "name": call["function"]["name"],
+                        "input": input,
+                    }));
+                }
+                out.push(json!({ "role": "assistant", "content": blocks }));
+            }
+            ("anthropic", "tool") => {
+                let content = match &message["content"] {
+                    Value::String(text) => json!([{ "type": "text", "text": text }]),
+                    parts => content_from_openai(dialect, parts),
+                };
+                let block = json!({
+                    "type": "tool_result",
+                    "tool_use_id": message["tool_call_id"],
+                    "content": content,
+                });
+                // consecutive results share one user message
+                match out.last_mut() {
+                    Some(last) if is_tool_results(last) => {
+                        if let Some(blocks) = last["content"].as_array_mut() {
+                            blocks.push(block);
+                        }
+                    }
+                    _ => out.push(json!({ "role": "user", "content": [block] })),
+                }
+            }
+            _ => {
+                let mut converted = message.clone();
+                if message["content"].is_array() {
+                    converted["content"] = content_from_openai(dialect, &message["content"]);
+                }
+                out.push(converted);
+            }
+        }
+    }
+    out
+}
+
+fn is_tool_results(message: &Value) -> bool {
+    message["role"] == "user"
+        && message["content"]
+            .as_array()
+            .is_some_and(|blocks| blocks.iter().all(|b| b["type"] == "tool_result"))
+}
+
+/// Tool call arguments as the JSON text openai and dashscope carry.
+fn arguments_text(arguments: &Value) -> Value {
+    match arguments {
+        Value::String(_) => arguments.clone(),
+        Value::Null => json!("{}"),
+        other => json!(other.to_string()),
+    }
+}
+
+/// The text of a content array, for fields that only take a string.
+fn text_content(content: &Value) -> Value {
+    match content.as_array() {
+        Some(parts) => json!(parts
+            .iter()
+            .filter_map(|p| p["text"].as_str())
+            .collect::<String>()),
+        None => content.clone(),
+    }
+}
+
+/// A `tool_result` block's content as an openai tool message's: text when every
+/// block is text, content parts otherwise.
+fn result_to_openai(content: &Value) -> Value {
+    match content.as_array() {
+        Some(blocks) if blocks.iter().all(|b| b["type"] == "text") => text_content(content),
+        _ => content_to_openai("anthropic", content),
+    }
+}
+
+fn content_to_openai(dialect: &str, content: &Value) -> Value {
+    map_array(content, |part| part_to_openai(dialect, part))
+}
+
+fn content_from_openai(dialect: &str, content: &Value) -> Value {
+    map_array(content, |part| part_from_openai(dialect, part))
+}
+
+/// Reads one content part into the openai shape; parts with no openai equivalent
+/// pass through unchanged.
+fn part_to_openai(dialect: &str, part: &Value) -> Value {
+    match dialect {
+        "dashscope" => {
+            if let Some(text) = part.get("text") {
+                json!({ "type": "text", "text": text })
+            } else if let Some(url) = part.get("image") {
+                json!({ "type": "image_url", "image_url": { "url": url } })
+            } else {
+                part.clone()
+            }
+        }
+        "anthropic" if part["type"] == "image" => {
+            let source = &part["source"];
+            let url = match source["type"].as_str() {
+                Some("base64") => ImageSource::Base64 {
+                    media_type: source["media_type"].as_str().unwrap_or("").into(),
+                    data: source["data"].as_str().unwrap_or("").into(),
+                }
+                .data_url(),
+                _ => source["url"].as_str().unwrap_or("").to_string(),
+            };
+            json!({ "type": "image_url", "image_url": { "url": url } })
+        }
+        _ => part.clone(),
+    }
+}
+
+/// Writes one openai content part in `dialect`'s shape.
+fn part_from_openai(dialect: &str, part: &Value) -> Value {
+    let image = part["image_url"]["url"].as_str();
+    match (dialect, part["type"].as_str()) {
+        ("dashscope", Some("text")) => json!({ "text": part["text"] }),
+        ("dashscope", Some("image_url")) => json!({ "image": image }),
+        ("anthropic", Some("image_url")) => {
+            let source = match media_source(image.unwrap_or("")) {
+                ImageSource::Url(url) => json!({ "type": "url", "url": url }),
+                ImageSource::Base64 { media_type, data } => json!({
+                    "type": "base64",
+                    "media_type": media_type,
+                    "data": data
+                }),
+            };
+            json!({ "type": "image", "source": source })
+        }
+        _ => part.clone(),
+    }
+}
+
+/// DialectAdapter converts requests authored for one dialect to another, so a
+/// caller can write messages and tools once and target any backend.
+///
+/// Dialects are `openai` (also the fallback for unknown names, as in
+/// [`HttpProvider`](super::http::HttpProvider)), `dashscope` and `anthropic`.
+#[derive(Clone, Debug)]
+pub struct DialectAdapter {
+    from: String,
+    to: String,
+}
+
+impl DialectAdapter {
+    pub fn new(from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    /// See [`convert_tools`].
+    pub fn tools(&self, tools: &Value) -> Value {
+        convert_tools(&self.from, &self.to, tools)
+    }
+
+    /// See [`convert_messages`].
+    pub fn messages(&self, messages: &Value) -> Value {
+        convert_messages(&self.from, &self.to, messages)
+    }
+
+    /// Converts an ask's message array input and points its `dialect` at the
+    /// target. `context.tools` is left alone: asks carry tools in the neutral shape
+    /// and the provider writes them for its dialect. A context that is not an
+    /// object is replaced by one holding just the dialect.
+    pub fn ask(&self, mut ask: Ask) -> Ask {
+        if ask.input.is_array() {
+            ask.input = self.messages(&ask.input);
+        }
+        if !ask.context.is_object() {
+            ask.context = json!({});
+        }
+        ask.context["dialect"] = json!(self.to);
+        ask
+    }
+}
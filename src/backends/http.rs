@@ -1,8 +1,23 @@
+use std::future::Future;
+use std::io::Write;
+use std::sync::OnceLock;
 use std::time::{Duration, Instant};
 
-use crate::{Ask, Provider, ProviderKind, Reply};
-use reqwest::blocking::Client;
+use super::content::{
+    tool_media, tool_result_messages, validate_messages, validate_roles, ToolResult,
+};
+use super::dialect::{tool_choice, tools_for};
+use super::stream::reasoning_text;
+use crate::{
+    off_runtime, usage, Ask, Health, HealthStatus, Provider, ProviderKind, Reply, USER_AGENT,
+};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use reqwest::{Client, RequestBuilder, Url};
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use tokio::runtime::Runtime;
+use tokio_util::sync::CancellationToken;
 
 #[derive(Clone)]
 pub struct HttpConfig {
@@ -12,18 +27,422 @@ pub struct HttpConfig {
     pub timeout: Duration,
 }
 
+/// Why an [`HttpConfig`] was rejected by [`HttpConfig::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// `base_url` does not parse as a URL.
+    InvalidUrl(String),
+    /// `base_url` uses a scheme other than `http` or `https`.
+    UnsupportedScheme(String),
+    /// `api_key` is empty or whitespace.
+    MissingApiKey,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUrl(url) => write!(f, "base_url {url:?} is not a valid URL"),
+            Self::UnsupportedScheme(scheme) => {
+                write!(f, "base_url scheme {scheme:?} is not http or https")
+            }
+            Self::MissingApiKey => write!(f, "api_key is empty"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl HttpConfig {
+    /// Checks that `base_url` is an http(s) URL and `api_key` is not blank.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.validate_url()?;
+        if self.api_key.trim().is_empty() {
+            return Err(ConfigError::MissingApiKey);
+        }
+        Ok(())
+    }
+
+    fn validate_url(&self) -> Result<(), ConfigError> {
+        let url = Url::parse(&self.base_url)
+            .map_err(|_| ConfigError::InvalidUrl(self.base_url.clone()))?;
+        match url.scheme() {
+            "http" | "https" => Ok(()),
+            scheme => Err(ConfigError::UnsupportedScheme(scheme.to_string())),
+        }
+    }
+}
+
+type DebugHook = Box<dyn Fn(&Value) + Send + Sync>;
+
 pub struct HttpProvider {
     config: HttpConfig,
     client: Client,
+    debug: Option<DebugHook>,
+    max_reply_bytes: Option<usize>,
+    max_input_messages: Option<usize>,
+    app: Option<(String, String)>,
+    auth: bool,
+    validate_roles: bool,
+    gzip_above: Option<usize>,
 }
 
 impl HttpProvider {
+    /// Builds a provider for `config` as given; bad settings surface on the first
+    /// request. Use [`HttpProvider::try_new`] to check them up front.
     pub fn new(config: HttpConfig) -> Self {
-        let client = Client::builder()
-            .timeout(config.timeout)
+        Self::build(config, true)
+    }
+
+    /// Like [`HttpProvider::new`], but rejects a config that fails
+    /// [`HttpConfig::validate`].
+    pub fn try_new(config: HttpConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+        Ok(Self::build(config, true))
+    }
+
+    /// Builds a provider for a server that needs no API key: `api_key` may be
+    /// empty and no `Authorization` header is sent.
+    pub fn without_auth(config: HttpConfig) -> Result<Self, ConfigError> {
+        config.validate_url()?;
+        Ok(Self::build(config, false))
+    }
+
+    fn build(config: HttpConfig, auth: bool) -> Self {
+        let client = build_client(&config, USER_AGENT);
+        Self {
+            config,
+            client,
+            debug: None,
+            max_reply_bytes: None,
+            max_input_messages: None,
+            app: None,
+            auth,
+            validate_roles: false,
+            gzip_above: None,
+        }
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        if self.auth {
+            request.header("Authorization", format!("Bearer {}", self.config.api_key))
+        } else {
+            request
+        }
+    }
+
+    /// Calls `hook` with `{"url", "headers", "body"}` before each chat request is sent.
+    /// The bearer token and any credential-looking header (see `sensitive_header`) are
+    /// masked in the reported headers.
+    pub fn with_debug_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Value) + Send + Sync + 'static,
+    {
+        self.debug = Some(Box::new(hook));
+        self
+    }
+
+    /// Identifies the calling app to OpenRouter: `referer` and `title` are sent as
+    /// `HTTP-Referer` and `X-Title` on asks with dialect `openrouter`.
+    pub fn with_openrouter_app(
+        mut self,
+        referer: impl Into<String>,
+        title: impl Into<String>,
+    ) -> Self {
+        self.app = Some((referer.into(), title.into()));
+        self
+    }
+
+    /// Stops reading a response body past `max_reply_bytes` and fails the ask with
+    /// `{"error": "response_too_large"}` instead of buffering it.
+    pub fn with_max_reply_bytes(mut self, max_reply_bytes: usize) -> Self {
+        self.max_reply_bytes = Some(max_reply_bytes);
+        self
+    }
+
+    /// Rejects an ask carrying more than `max_input_messages` messages locally, before
+    /// anything is sent.
+    pub fn with_max_input_messages(mut self, max_input_messages: usize) -> Self {
+        self.max_input_messages = Some(max_input_messages);
+        self
+    }
+
+    /// Checks the role order of each ask's messages with
+    /// [`validate_roles`](super::content::validate_roles) and rejects a bad sequence
+    /// locally instead of sending it.
+    pub fn with_role_validation(mut self, enabled: bool) -> Self {
+        self.validate_roles = enabled;
+        self
+    }
+
+    /// Gzips chat request bodies of at least `min_bytes` and sends them with
+    /// `Content-Encoding: gzip`. Off by default; only enable it for servers that
+    /// accept compressed requests.
+    pub fn with_gzip_requests(mut self, min_bytes: usize) -> Self {
+        self.gzip_above = Some(min_bytes);
+        self
+    }
+
+    /// Sends `user_agent` as the `User-Agent` of every request instead of
+    /// [`USER_AGENT`](crate::USER_AGENT).
+    pub fn with_user_agent(mut self, user_agent: &str) -> Self {
+        self.client = build_client(&self.config, user_agent);
+        self
+    }
+}
+
+/// Whether a header is masked in debug output: well-known credential headers and
+/// anything whose name mentions a token, secret or key.
+fn sensitive_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    matches!(
+        name.as_str(),
+        "authorization" | "proxy-authorization" | "cookie" | "set-cookie" | "x-api-key" | "api-key"
+    ) || ["token", "secret", "key"]
+        .iter()
+        .any(|word| name.contains(word))
+}
+
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes).expect("gzip into memory");
+    encoder.finish().expect("gzip into memory")
+}
+
+fn build_client(config: &HttpConfig, user_agent: &str) -> Client {
+    Client::builder()
+        .timeout(config.timeout)
+        .user_agent(user_agent)
+        .build()
+        .expect("http client")
+}
+
+/// The runtime driving every provider's HTTP I/O, so a cancelled ask can abort its
+/// request rather than wait on a blocked thread for a response nobody reads.
+fn io_runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("soma-http")
+            .enable_all()
             .build()
-            .expect("http client");
-        Self { config, client }
+            .expect("http runtime")
+    })
+}
+
+/// Runs `request` on the I/O runtime until it finishes or `token` is cancelled.
+/// Cancelling aborts the request, closing its connection, and yields `None`.
+fn send<T, F>(request: F, token: &CancellationToken) -> Option<T>
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static,
+{
+    let task = io_runtime().spawn(request);
+    let abort = task.abort_handle();
+    let token = token.clone();
+    off_runtime(move || {
+        io_runtime().block_on(async move {
+            tokio::select! {
+                result = task => result.ok(),
+                _ = token.cancelled() => {
+                    abort.abort();
+                    None
+                }
+            }
+        })
+    })
+}
+
+/// How much of a body that is not JSON is kept in the error reply.
+const BODY_SNIPPET_CHARS: usize = 512;
+
+/// Reads a response body, giving up with `None` once it passes `limit` bytes.
+async fn read_body(
+    mut response: reqwest::Response,
+    limit: Option<usize>,
+) -> reqwest::Result<Option<Vec<u8>>> {
+    let Some(limit) = limit else {
+        return Ok(Some(response.bytes().await?.to_vec()));
+    };
+    if response
+        .content_length()
+        .is_some_and(|len| len > limit as u64)
+    {
+        return Ok(None);
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(Some(body))
+}
+
+/// Wraps a bare string or `{"prompt": ...}` input into a single user message;
+/// message arrays pass through unchanged.
+fn messages(input: Value) -> Value {
+    match input {
+        Value::String(text) => json!([{ "role": "user", "content": text }]),
+        Value::Object(mut map) if map.len() == 1 && map.contains_key("prompt") => {
+            let prompt = map.remove("prompt").unwrap_or_default();
+            json!([{ "role": "user", "content": prompt }])
+        }
+        other => other,
+    }
+}
+
+/// The messages for a tool follow-up (an ask whose context names its `tool` or
+/// `tools`) when a result is an image or file, shaped for the dialect; `None` for any
+/// other input, which is sent as is.
+fn tool_messages(dialect: &str, context: &Value, input: &Value) -> Option<Value> {
+    let results: Vec<ToolResult> = match context.get("tool").and_then(Value::as_str) {
+        Some(name) => vec![ToolResult {
+            name,
+            call_id: context.get("tool_call_id").and_then(Value::as_str),
+            output: input,
+        }],
+        None => {
+            let names = context.get("tools")?.as_array()?;
+            let outputs = input.as_array().filter(|o| o.len() == names.len())?;
+            names
+                .iter()
+                .zip(outputs)
+                .enumerate()
+                .map(|(index, (name, output))| ToolResult {
+                    name: name.as_str().unwrap_or(""),
+                    call_id: context["tool_call_ids"][index].as_str(),
+                    output,
+                })
+                .collect()
+        }
+    };
+    if !results
+        .iter()
+        .any(|result| tool_media(result.output).is_some())
+    {
+        return None;
+    }
+    Some(Value::Array(tool_result_messages(dialect, &results)))
+}
+
+/// Checks the requested number of completions against the dialect's limits.
+fn completions(dialect: &str, n: &Value) -> Result<u64, String> {
+    let n = n
+        .as_u64()
+        .filter(|n| *n >= 1)
+        .ok_or_else(|| format!("n must be a positive integer, got {n}"))?;
+    match dialect {
+        "dashscope" if n > 4 => Err(format!("n {n} exceeds dialect dashscope's limit of 4")),
+        _ => Ok(n),
+    }
+}
+
+/// Checks `top_logprobs` against the dialect's limits.
+fn top_logprobs(dialect: &str, top: &Value) -> Result<u64, String> {
+    let top = top
+        .as_u64()
+        .ok_or_else(|| format!("top_logprobs must be a non-negative integer, got {top}"))?;
+    let limit = match dialect {
+        "dashscope" => 5,
+        _ => 20,
+    };
+    if top > limit {
+        return Err(format!(
+            "top_logprobs {top} exceeds dialect {dialect}'s limit of {limit}"
+        ));
+    }
+    Ok(top)
+}
+
+/// One sampled token with its log probability, as lifted into `output.logprobs`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    /// The most likely alternatives at this position, when `top_logprobs` was asked.
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+/// An alternative token considered at one position.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+}
+
+/// Reads the `output.logprobs` of an [`HttpProvider`] reply; `None` when the ask
+/// did not request them or the server sent none.
+pub fn token_logprobs(output: &Value) -> Option<Vec<TokenLogprob>> {
+    Vec::deserialize(output.get("logprobs")?).ok()
+}
+
+/// Lifts the first choice's per-token log probabilities to top-level `logprobs`.
+fn lift_logprobs(output: &mut Value) {
+    let content = &output["choices"][0]["logprobs"]["content"];
+    if content.is_array() {
+        output["logprobs"] = content.clone();
+    }
+}
+
+/// Collects every choice's message into `candidates` when the reply has more than
+/// one, so callers asking for `n` completions see all of them.
+fn collect_candidates(output: &mut Value) {
+    let Some(choices) = output["choices"].as_array().filter(|c| c.len() > 1) else {
+        return;
+    };
+    let candidates: Vec<Value> = choices.iter().map(|c| c["message"].clone()).collect();
+    output["candidates"] = Value::Array(candidates);
+}
+
+/// Lifts the first choice's reasoning and answer to top-level `reasoning_content` and
+/// `content`, so callers can drop the thinking without digging through `choices`.
+fn split_reasoning(output: &mut Value) {
+    let message = &output["choices"][0]["message"];
+    let Some(reasoning) = reasoning_text(message).map(str::to_string) else {
+        return;
+    };
+    let content = message["content"].clone();
+    output["reasoning_content"] = json!(reasoning);
+    output["content"] = content;
+}
+
+/// Headers taken from the ask context: `x-request-id`, `Idempotency-Key`,
+/// `traceparent`, then every string in the `headers` map. `Authorization` is never
+/// taken from the context, so per-ask headers cannot replace the API key.
+fn request_headers(context: &Value) -> Vec<(String, String)> {
+    let mut headers = Vec::new();
+    for (key, name) in [
+        ("request_id", "x-request-id"),
+        ("idempotency_key", "Idempotency-Key"),
+        ("traceparent", "traceparent"),
+    ] {
+        if let Some(value) = context.get(key).and_then(|v| v.as_str()) {
+            headers.push((name.to_string(), value.to_string()));
+        }
+    }
+    if let Some(extra) = context.get("headers").and_then(|v| v.as_object()) {
+        for (name, value) in extra {
+            if name.eq_ignore_ascii_case("authorization") {
+                continue;
+            }
+            if let Some(value) = value.as_str() {
+                headers.push((name.clone(), value.to_string()));
+            }
+        }
+    }
+    headers
+}
+
+/// Reply for an ask refused before anything was sent.
+fn rejected(error: String) -> Reply {
+    Reply {
+        ok: false,
+        output: json!({ "error": error }),
+        latency_ms: 0,
+        cost: json!({}),
     }
 }
 
@@ -32,42 +451,126 @@ impl Provider for HttpProvider {
         ProviderKind::RemoteGrpc
     }
 
+    /// Lists `/v1/models`: a success is `Ok`, an error status `Degraded` and a
+    /// transport failure `Down`.
+    fn health_check(&self) -> HealthStatus {
+        let url = format!("{}/v1/models", self.config.base_url.trim_end_matches('/'));
+        let request = self.authorize(self.client.get(url));
+        let start = Instant::now();
+        let result = send(
+            async move { request.send().await.map(|r| r.status()) },
+            &CancellationToken::new(),
+        );
+        let latency_ms = start.elapsed().as_millis() as u64;
+        let (health, detail) = match result {
+            Some(Ok(status)) if status.is_success() => (Health::Ok, None),
+            Some(Ok(status)) => (Health::Degraded, Some(format!("status {status}"))),
+            Some(Err(e)) => (Health::Down, Some(e.to_string())),
+            None => (Health::Down, Some("cancelled".into())),
+        };
+        HealthStatus {
+            health,
+            latency_ms,
+            detail,
+        }
+    }
+
     fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    /// Cancelling `token` aborts the in-flight request instead of waiting it out.
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
         let Ask {
             op: _,
             input,
             context,
         } = ask;
-        let mut body = json!({
-            "model": self.config.model,
-            "messages": input,
-        });
-
         let dialect = context
             .get("dialect")
             .and_then(|v| v.as_str())
             .unwrap_or("openai");
 
+        let messages = tool_messages(dialect, &context, &input).unwrap_or_else(|| messages(input));
+        let mut body = json!({
+            "model": self.config.model,
+            "messages": messages,
+        });
+
+        if let Some(max) = self.max_input_messages {
+            let count = body["messages"].as_array().map_or(0, Vec::len);
+            if count > max {
+                return rejected(format!(
+                    "{count} messages exceed max_input_messages of {max}"
+                ));
+            }
+        }
+        if let Err(e) = validate_messages(dialect, &body["messages"]) {
+            return rejected(e);
+        }
+        if self.validate_roles {
+            if let Err(e) = validate_roles(dialect, &body["messages"]) {
+                return rejected(e);
+            }
+        }
+
         if let Some(tools) = context.get("tools") {
+            // anthropic models are served through the openai-compatible endpoint
             match dialect {
-                "dashscope" => {
-                    body["functions"] = tools.clone();
-                }
-                _ => {
-                    let array = tools.as_array().cloned().unwrap_or_default();
-                    let wrapped: Vec<Value> = array
-                        .into_iter()
-                        .map(|t| json!({ "type": "function", "function": t }))
-                        .collect();
-                    body["tools"] = Value::from(wrapped);
-                }
+                "dashscope" => body["functions"] = tools_for(dialect, tools),
+                _ => body["tools"] = tools_for("openai", tools),
             }
         }
 
         if let Some(choice) = context.get("tool_choice") {
+            let choice = match tool_choice(dialect, choice) {
+                Ok(choice) => choice,
+                Err(e) => return rejected(e),
+            };
             match dialect {
-                "dashscope" => body["function_call"] = choice.clone(),
-                _ => body["tool_choice"] = choice.clone(),
+                "dashscope" => body["function_call"] = choice,
+                _ => body["tool_choice"] = choice,
+            }
+        }
+
+        if dialect == "openrouter" {
+            // fallback models and provider routing preferences pass straight through
+            for key in ["models", "provider"] {
+                if let Some(value) = context.get(key) {
+                    body[key] = value.clone();
+                }
+            }
+            // asks OpenRouter to report the call's cost in `usage.cost`
+            body["usage"] = json!({ "include": true });
+        }
+
+        if let Some(n) = context.get("n") {
+            match completions(dialect, n) {
+                Ok(n) => body["n"] = json!(n),
+                Err(e) => return rejected(e),
+            }
+        }
+
+        if let Some(seed) = context.get("seed") {
+            match seed.as_u64() {
+                Some(seed) => body["seed"] = json!(seed),
+                None => {
+                    return rejected(format!("seed must be a non-negative integer, got {seed}"))
+                }
+            }
+        }
+
+        if context.get("logprobs").and_then(Value::as_bool) == Some(true) {
+            body["logprobs"] = json!(true);
+        }
+        if let Some(top) = context.get("top_logprobs") {
+            match top_logprobs(dialect, top) {
+                // alternatives are only returned alongside the sampled tokens
+                Ok(top) => {
+                    body["logprobs"] = json!(true);
+                    body["top_logprobs"] = json!(top);
+                }
+                Err(e) => return rejected(e),
             }
         }
 
@@ -86,22 +589,95 @@ impl Provider for HttpProvider {
             "{}/v1/chat/completions",
             self.config.base_url.trim_end_matches('/')
         );
+        let mut headers = request_headers(&context);
+        if let Some((referer, title)) = self.app.as_ref().filter(|_| dialect == "openrouter") {
+            for (name, value) in [("HTTP-Referer", referer), ("X-Title", title)] {
+                if !headers.iter().any(|(h, _)| h.eq_ignore_ascii_case(name)) {
+                    headers.push((name.to_string(), value.clone()));
+                }
+            }
+        }
+        let mut payload = serde_json::to_vec(&body).expect("serializable body");
+        if self.gzip_above.is_some_and(|min| payload.len() >= min) {
+            payload = gzip(&payload);
+            headers.push(("Content-Encoding".into(), "gzip".into()));
+        }
+        if let Some(debug) = &self.debug {
+            let mut shown = json!({});
+            if self.auth {
+                shown["Authorization"] = json!("Bearer [REDACTED]");
+            }
+            for (name, value) in &headers {
+                shown[name] = if sensitive_header(name) {
+                    json!("[REDACTED]")
+                } else {
+                    json!(value)
+                };
+            }
+            debug(&json!({ "url": url, "headers": shown, "body": body }));
+        }
+        let mut request = self
+            .authorize(self.client.post(url))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(payload);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
         let start = Instant::now();
-        let resp = self
-            .client
-            .post(url)
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .json(&body)
-            .send();
+        let max_reply_bytes = self.max_reply_bytes;
+        let resp = send(
+            async move {
+                let r = request.send().await?;
+                let status = r.status();
+                let content_type = r
+                    .headers()
+                    .get(reqwest::header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_string);
+                let Some(bytes) = read_body(r, max_reply_bytes).await? else {
+                    return Ok((false, json!({ "error": "response_too_large" })));
+                };
+                let json = match serde_json::from_slice(&bytes) {
+                    Ok(json) => json,
+                    Err(e) => {
+                        let body: String = String::from_utf8_lossy(&bytes)
+                            .chars()
+                            .take(BODY_SNIPPET_CHARS)
+                            .collect();
+                        return Ok((
+                            false,
+                            json!({
+                                "error": "invalid_json",
+                                "detail": e.to_string(),
+                                "status": status.as_u16(),
+                                "content_type": content_type,
+                                "body": body,
+                            }),
+                        ));
+                    }
+                };
+                Ok::<_, reqwest::Error>((status.is_success(), json))
+            },
+            token,
+        );
         let latency = start.elapsed().as_millis() as u64;
 
         match resp {
-            Ok(r) => {
-                let status_ok = r.status().is_success();
-                let json: Value = r
-                    .json()
-                    .unwrap_or_else(|e| json!({ "error": e.to_string() }));
-                let cost = json.get("usage").cloned().unwrap_or_else(|| json!({}));
+            None => Reply {
+                ok: false,
+                output: json!({ "error": "cancelled" }),
+                latency_ms: latency,
+                cost: json!({}),
+            },
+            Some(Ok((status_ok, mut json))) => {
+                split_reasoning(&mut json);
+                lift_logprobs(&mut json);
+                collect_candidates(&mut json);
+                let mut cost = json.get("usage").cloned().unwrap_or_else(|| json!({}));
+                let reasoning = usage::reasoning_tokens(&cost);
+                if reasoning > 0 && cost.get("reasoning_tokens").is_none() {
+                    cost["reasoning_tokens"] = json!(reasoning);
+                }
                 Reply {
                     ok: status_ok,
                     output: json,
@@ -109,7 +685,7 @@ impl Provider for HttpProvider {
                     cost,
                 }
             }
-            Err(e) => Reply {
+            Some(Err(e)) => Reply {
                 ok: false,
                 output: json!({ "error": e.to_string() }),
                 latency_ms: latency,
@@ -1 +1,4 @@
+pub mod content;
+pub mod dialect;
 pub mod http;
+pub mod stream;
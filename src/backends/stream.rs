@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use serde_json::{json, Value};
+
+/// Parses the `data:` events of a server-sent-events body into JSON chunks.
+///
+/// Parsing stops at the OpenAI `[DONE]` sentinel; non-JSON events are skipped.
+pub fn parse_sse(body: &str) -> Vec<Value> {
+    let mut chunks = Vec::new();
+    for line in body.lines() {
+        let Some(data) = line.strip_prefix("data:") else {
+            continue;
+        };
+        let data = data.trim();
+        if data == "[DONE]" {
+            break;
+        }
+        if let Ok(chunk) = serde_json::from_str(data) {
+            chunks.push(chunk);
+        }
+    }
+    chunks
+}
+
+#[derive(Default)]
+struct PartialCall {
+    id: Option<String>,
+    name: String,
+    arguments: String,
+}
+
+/// ToolCallAccumulator reassembles streamed `delta.tool_calls` fragments per call index.
+///
+/// Arguments are only parsed in [`finish`](Self::finish), so incomplete JSON seen
+/// mid-stream is never surfaced as a tool call.
+#[derive(Default)]
+pub struct ToolCallAccumulator {
+    calls: BTreeMap<u64, PartialCall>,
+}
+
+impl ToolCallAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Folds one streamed chat completion chunk into the pending calls.
+    pub fn push_chunk(&mut self, chunk: &Value) {
+        let Some(choices) = chunk.get("choices").and_then(|v| v.as_array()) else {
+            return;
+        };
+        for choice in choices {
+            let Some(deltas) = choice["delta"]["tool_calls"].as_array() else {
+                continue;
+            };
+            for delta in deltas {
+                let index = delta.get("index").and_then(|v| v.as_u64()).unwrap_or(0);
+                let call = self.calls.entry(index).or_default();
+                if let Some(id) = delta.get("id").and_then(|v| v.as_str()) {
+                    call.id = Some(id.to_string());
+                }
+                if let Some(name) = delta["function"]["name"].as_str() {
+                    call.name.push_str(name);
+                }
+                if let Some(args) = delta["function"]["arguments"].as_str() {
+                    call.arguments.push_str(args);
+                }
+            }
+        }
+    }
+
+    /// Returns the completed calls as `{"op", "input"}` objects in index order.
+    ///
+    /// Calls without a name or whose arguments are not valid JSON are dropped.
+    pub fn finish(self) -> Vec<Value> {
+        self.calls
+            .into_values()
+            .filter(|call| !call.name.is_empty())
+            .filter_map(|call| {
+                let input = if call.arguments.trim().is_empty() {
+                    json!({})
+                } else {
+                    serde_json::from_str(&call.arguments).ok()?
+                };
+                let mut out = json!({"op": call.name, "input": input});
+                if let Some(id) = call.id {
+                    out["id"] = json!(id);
+                }
+                Some(out)
+            })
+            .collect()
+    }
+}
+
+/// DeltaRouter splits streamed text into reasoning and answer channels, so callers can
+/// show the answer as it arrives while logging or hiding the model's thinking.
+///
+/// Reasoning is read from `delta.reasoning_content` (DashScope, DeepSeek) or a string
+/// `delta.reasoning` (OpenRouter); answer text from `delta.content`.
+pub struct DeltaRouter {
+    reasoning: Sender<String>,
+    content: Sender<String>,
+}
+
+impl DeltaRouter {
+    /// Returns the router with the receivers for reasoning and content deltas.
+    pub fn new() -> (Self, Receiver<String>, Receiver<String>) {
+        let (reasoning, reasoning_rx) = mpsc::channel();
+        let (content, content_rx) = mpsc::channel();
+        (Self { reasoning, content }, reasoning_rx, content_rx)
+    }
+
+    /// Sends the non-empty deltas of one chunk; dropped receivers are ignored.
+    pub fn push_chunk(&self, chunk: &Value) {
+        let Some(choices) = chunk.get("choices").and_then(|v| v.as_array()) else {
+            return;
+        };
+        for choice in choices {
+            let delta = &choice["delta"];
+            if let Some(text) = reasoning_text(delta).filter(|t| !t.is_empty()) {
+                let _ = self.reasoning.send(text.to_string());
+            }
+            if let Some(text) = delta["content"].as_str().filter(|t| !t.is_empty()) {
+                let _ = self.content.send(text.to_string());
+            }
+        }
+    }
+}
+
+/// The reasoning text of a message or delta, under either provider's field name.
+pub(crate) fn reasoning_text(message: &Value) -> Option<&str> {
+    message["reasoning_content"]
+        .as_str()
+        .or_else(|| message["reasoning"].as_str())
+}
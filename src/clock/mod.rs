@@ -0,0 +1,26 @@
+use futures::future::BoxFuture;
+use tokio::time::Instant;
+
+/// Clock is the time source for retry delays and run deadlines.
+///
+/// [`SystemClock`] is the default; `testing::ManualClock` only moves when a test
+/// advances it, so backoff schedules can be checked without sleeping.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    /// Resolves once [`now`](Self::now) has reached `deadline`.
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()>;
+}
+
+/// SystemClock is tokio's clock, so it also follows `tokio::time::pause`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep_until(deadline))
+    }
+}
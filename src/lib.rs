@@ -1,15 +1,46 @@
+use futures::future::{join_all, BoxFuture};
+use futures::FutureExt;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::fs;
+use std::panic::AssertUnwindSafe;
 use std::path::PathBuf;
-use tokio::time::{sleep, Duration};
-use tokio_util::sync::CancellationToken;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::time::{Duration, Instant};
+use tokio_util::sync::{CancellationToken, DropGuard};
+use uuid::Uuid;
 
+use crate::approval::{ApprovalDecision, ApprovalHook};
+use crate::clock::{Clock, SystemClock};
+use crate::metrics::MetricsSink;
+use crate::select::{CandidateSelector, First};
+use crate::shutdown::{ActiveRun, Shutdown};
+
+pub mod approval;
 pub mod backends;
+pub mod clock;
 pub mod mcp;
-#[cfg(feature = "sandboxed_exec")]
+pub mod metrics;
+pub mod middleware;
+pub mod providers;
+pub mod redact;
+pub mod replay;
+pub mod schema;
+pub mod select;
+pub mod shutdown;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
 pub mod tools;
+mod trace;
+pub mod usage;
+
+/// The `User-Agent` sent by [`backends::http::HttpProvider`] and [`mcp::McpProvider`]
+/// unless overridden.
+pub const USER_AGENT: &str = concat!("soma_agent/", env!("CARGO_PKG_VERSION"));
 
 /// Ask represents a unit of work sent to a provider.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,7 +51,7 @@ pub struct Ask {
 }
 
 /// Reply captures the outcome of a provider invocation.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Reply {
     pub ok: bool,
     pub output: Value,
@@ -28,8 +59,63 @@ pub struct Reply {
     pub cost: Value,
 }
 
+/// ToolCall is one entry of a reply's `tool_calls`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub op: String,
+    #[serde(default)]
+    pub input: Value,
+    /// The model's id for the call, passed back as `tool_call_id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+}
+
+impl Ask {
+    /// Deserializes `input` into the tool's argument type.
+    pub fn parse_input<T: serde::de::DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        T::deserialize(&self.input)
+    }
+}
+
+impl Reply {
+    /// A failed reply for input the tool could not use, e.g. a [`Ask::parse_input`] error.
+    pub fn bad_input(error: impl std::fmt::Display) -> Self {
+        Reply {
+            ok: false,
+            output: json!({"error": "bad input", "detail": error.to_string()}),
+            latency_ms: 0,
+            cost: json!({}),
+        }
+    }
+
+    /// Whether the output asks for tool calls, i.e. carries a `tool_calls` array.
+    pub fn is_tool_call(&self) -> bool {
+        self.output.get("tool_calls").is_some_and(Value::is_array)
+    }
+
+    /// The requested tool calls, or `None` without a `tool_calls` array or when an
+    /// entry lacks an `op`.
+    pub fn tool_calls(&self) -> Option<Vec<ToolCall>> {
+        let calls = self.output.get("tool_calls").filter(|v| v.is_array())?;
+        Vec::<ToolCall>::deserialize(calls).ok()
+    }
+
+    /// The `error` string of a failed reply.
+    pub fn error_message(&self) -> Option<&str> {
+        if self.ok {
+            return None;
+        }
+        self.output.get("error").and_then(Value::as_str)
+    }
+
+    /// Whether the reply reports a cancelled call or run.
+    pub fn is_cancelled(&self) -> bool {
+        !self.ok && (self.error_message() == Some("cancelled") || self.output["cancelled"] == true)
+    }
+}
+
 /// ProviderKind enumerates the deployment form of a provider.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum ProviderKind {
     Embedded,
     SidecarUds,
@@ -53,10 +139,13 @@ impl ReasoningMode {
 }
 
 /// ReasoningPolicy scores an input and picks a reasoning mode.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReasoningPolicy {
     pub threshold: usize,
     pub tool_weight: usize,
+    /// Modes forced per op, consulted before the score.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub overrides: HashMap<String, ReasoningMode>,
 }
 
 impl Default for ReasoningPolicy {
@@ -64,18 +153,37 @@ impl Default for ReasoningPolicy {
         Self {
             threshold: 200,
             tool_weight: 50,
+            overrides: HashMap::new(),
         }
     }
 }
 
 impl ReasoningPolicy {
-    pub fn decide(&self, input: &Value, tool_count: usize) -> ReasoningMode {
+    /// Complexity score of an input: its length plus a weight per available tool.
+    pub fn score(&self, input: &Value, tool_count: usize) -> usize {
         let text = input
             .as_str()
             .map(|s| s.to_string())
             .unwrap_or_else(|| input.to_string());
-        let score = text.chars().count() + tool_count * self.tool_weight;
-        if score > self.threshold {
+        text.chars().count() + tool_count * self.tool_weight
+    }
+
+    /// Always uses `mode` for asks with `op`, whatever their score.
+    pub fn with_override(mut self, op: impl Into<String>, mode: ReasoningMode) -> Self {
+        self.overrides.insert(op.into(), mode);
+        self
+    }
+
+    /// The override for `op` if there is one, otherwise [`ReasoningPolicy::decide`].
+    pub fn decide_for(&self, op: &str, input: &Value, tool_count: usize) -> ReasoningMode {
+        match self.overrides.get(op) {
+            Some(mode) => *mode,
+            None => self.decide(input, tool_count),
+        }
+    }
+
+    pub fn decide(&self, input: &Value, tool_count: usize) -> ReasoningMode {
+        if self.score(input, tool_count) > self.threshold {
             ReasoningMode::Reasoned
         } else {
             ReasoningMode::Direct
@@ -83,19 +191,237 @@ impl ReasoningPolicy {
     }
 }
 
+/// ToolErrorPolicy decides what happens when some calls in a parallel tool step fail.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ToolErrorPolicy {
+    /// The first failing tool ends the run with a `tool invocation failed` reply.
+    #[default]
+    FailFast,
+    /// Every call runs to completion and the provider receives a per-tool
+    /// `{"tool", "ok", "output"}` array in call order.
+    AggregateErrors,
+}
+
+/// EmptyToolCallsPolicy decides what happens when a reply carries `tool_calls: []`.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum EmptyToolCallsPolicy {
+    /// The reply is the answer: it becomes `ok` without the `tool_calls` key. Only
+    /// when it has answer text (a string, `content` or a first choice's content);
+    /// otherwise it is handled like `Retry`.
+    #[default]
+    UseContent,
+    /// The output is fed back to the provider like any other failed reply.
+    Retry,
+}
+
+/// OutputCapPolicy decides what happens to a tool output over the tool's
+/// `max_output_tokens`, see [`Agent::with_max_output_tokens`].
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OutputCapPolicy {
+    /// The output is cut to a string of at most `max_output_tokens` ending in
+    /// [`TRUNCATION_MARKER`]; non-string outputs are cut from their JSON text.
+    #[default]
+    Truncate,
+    /// The call fails with `{"error": "tool_output_too_large", "tool", "tokens",
+    /// "max_output_tokens"}`.
+    Fail,
+}
+
+/// Appended to tool outputs cut by [`OutputCapPolicy::Truncate`].
+pub const TRUNCATION_MARKER: &str = "…[truncated]";
+
+/// UnknownToolPolicy decides what happens when the provider calls a tool that is not
+/// registered.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum UnknownToolPolicy {
+    /// The run ends with an `unknown tool` reply.
+    #[default]
+    Fail,
+    /// The call's result is `{"error": "no such tool", "tool", "available"}`, fed
+    /// back to the provider like any tool output so it can pick another tool.
+    FeedbackToModel,
+}
+
+/// Health is the coarse state reported by a health check.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Health {
+    Ok,
+    /// Reachable but answering with errors.
+    Degraded,
+    Down,
+}
+
+/// HealthStatus is the result of [`Provider::health_check`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HealthStatus {
+    pub health: Health,
+    pub latency_ms: u64,
+    pub detail: Option<String>,
+}
+
+impl HealthStatus {
+    /// Combines the statuses of several backends: `Down` when all are down (or there
+    /// are none), `Ok` when all are ok and `Degraded` otherwise. The latency is the
+    /// slowest probe's; the detail lists every backend that is not ok by position.
+    pub fn aggregate(statuses: impl IntoIterator<Item = HealthStatus>) -> HealthStatus {
+        let statuses: Vec<HealthStatus> = statuses.into_iter().collect();
+        if statuses.is_empty() {
+            return HealthStatus {
+                health: Health::Down,
+                latency_ms: 0,
+                detail: Some("no providers configured".into()),
+            };
+        }
+        let health = if statuses.iter().all(|s| s.health == Health::Down) {
+            Health::Down
+        } else if statuses.iter().all(|s| s.health == Health::Ok) {
+            Health::Ok
+        } else {
+            Health::Degraded
+        };
+        let problems: Vec<String> = statuses
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.health != Health::Ok)
+            .map(|(i, s)| match &s.detail {
+                Some(detail) => format!("{i}: {:?} ({detail})", s.health),
+                None => format!("{i}: {:?}", s.health),
+            })
+            .collect();
+        HealthStatus {
+            health,
+            latency_ms: statuses.iter().map(|s| s.latency_ms).max().unwrap_or(0),
+            detail: (!problems.is_empty()).then(|| problems.join("; ")),
+        }
+    }
+}
+
 /// Provider is the universal interface for all execution modules.
 pub trait Provider {
     fn kind(&self) -> ProviderKind;
     fn ask(&self, ask: Ask) -> Reply;
+
+    /// Like `ask`, but lets long-running providers stop early once `token` is cancelled.
+    ///
+    /// The agent always calls this method; the default ignores the token.
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        let _ = token;
+        self.ask(ask)
+    }
+
+    /// Like `ask_cancellable`, but hands partial output to `on_chunk` as it is produced.
+    ///
+    /// The returned reply is still the complete result. The default produces no chunks;
+    /// tools with incremental output override it. Decorators pass chunks through, except
+    /// where noted on the decorator.
+    fn ask_stream(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: &mut dyn FnMut(Value),
+    ) -> Reply {
+        let _ = on_chunk;
+        self.ask_cancellable(ask, token)
+    }
+
+    /// For natively async tools: the future behind `ask_cancellable`, which the agent
+    /// awaits on its own runtime instead of blocking a thread on the call.
+    ///
+    /// The default returns `None`, and the agent calls `ask_stream` as usual. Only the
+    /// `Arc` impl forwards it; decorators do blocking work around the call, so a
+    /// wrapped tool takes the blocking path.
+    fn ask_future<'a>(
+        &'a self,
+        ask: &Ask,
+        token: &'a CancellationToken,
+    ) -> Option<BoxFuture<'a, Reply>> {
+        let _ = (ask, token);
+        None
+    }
+
+    /// Probes whether the provider can take traffic without sending a real ask.
+    ///
+    /// The default reports `Ok` immediately; networked backends override it.
+    /// Decorators forward it, and ones over several backends report
+    /// [`HealthStatus::aggregate`] of them.
+    fn health_check(&self) -> HealthStatus {
+        HealthStatus {
+            health: Health::Ok,
+            latency_ms: 0,
+            detail: None,
+        }
+    }
+
+    /// Releases what the provider holds, such as child processes or connections.
+    ///
+    /// An [`Agent`] calls this on its tools when it is closed or dropped. The default
+    /// does nothing; decorators forward it to every provider they wrap.
+    fn close(&self) {}
+}
+
+/// Every method forwards to the shared provider, `close` included, so closing one
+/// handle closes it for all of them.
+impl<T: Provider + ?Sized> Provider for Arc<T> {
+    fn kind(&self) -> ProviderKind {
+        (**self).kind()
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        (**self).ask(ask)
+    }
+
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        (**self).ask_cancellable(ask, token)
+    }
+
+    fn ask_stream(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: &mut dyn FnMut(Value),
+    ) -> Reply {
+        (**self).ask_stream(ask, token, on_chunk)
+    }
+
+    fn ask_future<'a>(
+        &'a self,
+        ask: &Ask,
+        token: &'a CancellationToken,
+    ) -> Option<BoxFuture<'a, Reply>> {
+        (**self).ask_future(ask, token)
+    }
+
+    fn health_check(&self) -> HealthStatus {
+        (**self).health_check()
+    }
+
+    fn close(&self) {
+        (**self).close()
+    }
 }
 
 pub enum ToolSpec {
-    Provider(Box<dyn Provider>),
+    Provider(Box<dyn Provider + Send + Sync>),
     McpEndpoint(String),
     McpConfigFile(PathBuf),
+    /// Built on the tool's first invocation rather than at registration; see
+    /// [`tools::LazyTool`].
+    Factory(tools::ToolFactory),
 }
 
-impl<T: Provider + 'static> From<T> for ToolSpec {
+impl ToolSpec {
+    /// Wraps an async constructor, e.g. one spawning a subprocess, as a
+    /// [`ToolSpec::Factory`].
+    pub fn factory<F, Fut>(factory: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = tools::lazy::FactoryResult> + Send + 'static,
+    {
+        ToolSpec::Factory(Box::new(move || Box::pin(factory())))
+    }
+}
+
+impl<T: Provider + Send + Sync + 'static> From<T> for ToolSpec {
     fn from(p: T) -> Self {
         ToolSpec::Provider(Box::new(p))
     }
@@ -113,11 +439,198 @@ impl From<PathBuf> for ToolSpec {
     }
 }
 
-async fn call_with_retry<F>(mut op: F, max_retries: usize, token: CancellationToken) -> Reply
+/// RetryPredicate classifies a failed reply as transient (`true`, retry) or fatal.
+pub type RetryPredicate = Arc<dyn Fn(&Reply) -> bool + Send + Sync>;
+
+/// Attempts and run deadline for calls to one [`ProviderKind`]; see
+/// [`Agent::with_kind_defaults`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KindProfile {
+    /// Attempts per call, the first included; `1` means no retries.
+    pub max_attempts: usize,
+    /// Run deadline when the agent's provider is of this kind and no
+    /// [`Agent::with_timeout`] is set.
+    pub timeout: Option<Duration>,
+}
+
+impl KindProfile {
+    /// Embedded calls fail the same way every time, so they are not retried;
+    /// a sidecar gets one retry and a remote service two, with a longer deadline.
+    pub fn for_kind(kind: ProviderKind) -> Self {
+        match kind {
+            ProviderKind::Embedded => Self {
+                max_attempts: 1,
+                timeout: Some(Duration::from_secs(30)),
+            },
+            ProviderKind::SidecarUds => Self {
+                max_attempts: 2,
+                timeout: Some(Duration::from_secs(60)),
+            },
+            ProviderKind::RemoteGrpc => Self {
+                max_attempts: 3,
+                timeout: Some(Duration::from_secs(300)),
+            },
+        }
+    }
+}
+
+/// Rewrites a provider reply before the agent looks at it; see [`Agent::on_reply`].
+pub type ReplyHook = Arc<dyn Fn(Reply) -> Reply + Send + Sync>;
+
+/// Backoff shapes the delay between retries of a failed provider or tool call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// Delay before the first retry; doubled after every further failure.
+    pub initial: Duration,
+    /// Upper bound for a single delay.
+    pub max_delay: Duration,
+    /// Wall-clock budget for one call including all retries. Retrying stops, and the
+    /// last failure is returned, once the next delay would overrun it.
+    pub max_elapsed: Option<Duration>,
+    /// Randomises each delay between half and all of its nominal value.
+    pub jitter: bool,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(50),
+            max_delay: Duration::from_secs(30),
+            max_elapsed: None,
+            jitter: false,
+        }
+    }
+}
+
+impl Backoff {
+    fn delay(&self, nominal: Duration) -> Duration {
+        let nominal = nominal.min(self.max_delay);
+        if !self.jitter {
+            return nominal;
+        }
+        let unit = (next_random() % 1_000) as u32;
+        nominal / 2 + nominal / 2 * unit / 1_000
+    }
+}
+
+/// Next value of a per-thread xorshift generator, seeded once from the hasher keys
+/// std randomises per process. Good enough to spread retries, nothing more.
+fn next_random() -> u64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new({
+            use std::hash::{BuildHasher, Hasher};
+            std::collections::hash_map::RandomState::new()
+                .build_hasher()
+                .finish()
+                | 1
+        });
+    }
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        x
+    })
+}
+
+/// How failed calls are retried: the agent's backoff, clock and retry predicate, and
+/// the retries the run has left.
+struct RetryPolicy<'a> {
+    backoff: &'a Backoff,
+    clock: &'a dyn Clock,
+    retriable: &'a (dyn Fn(&Reply) -> bool + Send + Sync),
+    /// Retries taken so far in the run, counted across provider and tool calls.
+    used: &'a Cell<usize>,
+    max_run_retries: Option<usize>,
+}
+
+impl RetryPolicy<'_> {
+    /// Counts one more retry, or returns `false` once the run's budget is spent.
+    fn take_retry(&self) -> bool {
+        if self
+            .max_run_retries
+            .is_some_and(|max| self.used.get() >= max)
+        {
+            return false;
+        }
+        self.used.set(self.used.get() + 1);
+        true
+    }
+}
+
+/// A backoff schedule shared by the calls of one retry group within a step.
+///
+/// Each retry reserves the next slot: the delay doubles across the whole group and
+/// no two retries wake less than a delay apart, so calls failing against the same
+/// backend take turns instead of retrying in a burst.
+struct SharedBackoff {
+    nominal: Cell<Duration>,
+    last: Cell<Option<Instant>>,
+}
+
+impl SharedBackoff {
+    fn new(backoff: &Backoff) -> Self {
+        Self {
+            nominal: Cell::new(backoff.initial),
+            last: Cell::new(None),
+        }
+    }
+
+    fn reserve(&self, backoff: &Backoff, now: Instant) -> Instant {
+        let delay = backoff.delay(self.nominal.get());
+        self.nominal.set(self.nominal.get().saturating_mul(2));
+        let earliest = now + delay;
+        let wake = self
+            .last
+            .get()
+            .map_or(earliest, |last| earliest.max(last + delay));
+        self.last.set(Some(wake));
+        wake
+    }
+}
+
+async fn call_with_retry<F>(
+    mut op: F,
+    max_retries: usize,
+    policy: &RetryPolicy<'_>,
+    token: CancellationToken,
+    shared: Option<&SharedBackoff>,
+) -> Reply
 where
     F: FnMut() -> Reply,
 {
-    let mut delay = Duration::from_millis(50);
+    call_with_retry_async(
+        || std::future::ready(op()),
+        max_retries,
+        policy,
+        token,
+        shared,
+    )
+    .await
+}
+
+/// Like [`call_with_retry`], for attempts that are themselves futures.
+async fn call_with_retry_async<F, Fut>(
+    mut op: F,
+    max_retries: usize,
+    policy: &RetryPolicy<'_>,
+    token: CancellationToken,
+    shared: Option<&SharedBackoff>,
+) -> Reply
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Reply>,
+{
+    let RetryPolicy {
+        backoff,
+        clock,
+        retriable,
+        ..
+    } = *policy;
+    let start = clock.now();
+    let mut nominal = backoff.initial;
     for attempt in 0..max_retries {
         if token.is_cancelled() {
             return Reply {
@@ -127,12 +640,29 @@ where
                 cost: json!({}),
             };
         }
-        let reply = op();
-        if reply.ok || attempt + 1 == max_retries {
+        let reply = op().await;
+        // a reply requesting tool calls is a hand-off to the agent, not a failure to retry
+        let wants_tools = reply.is_tool_call();
+        if reply.ok || wants_tools || attempt + 1 == max_retries || !retriable(&reply) {
+            return reply;
+        }
+        let wake = match shared {
+            Some(shared) => shared.reserve(backoff, clock.now()),
+            None => {
+                let wake = clock.now() + backoff.delay(nominal);
+                nominal = nominal.saturating_mul(2);
+                wake
+            }
+        };
+        if backoff
+            .max_elapsed
+            .is_some_and(|budget| wake - start > budget)
+            || !policy.take_retry()
+        {
             return reply;
         }
         tokio::select! {
-            _ = sleep(delay) => { delay *= 2; }
+            _ = clock.sleep_until(wake) => {}
             _ = token.cancelled() => {
                 return Reply {
                     ok: false,
@@ -147,13 +677,51 @@ where
 }
 
 /// Agent orchestrates calls to a provider with a simple step limit.
+///
+/// Runs take `&self`, so one agent can serve several runs at once, and an
+/// `Agent<P>` is `Sync` whenever `P` is. Each run keeps its own state (steps, token
+/// budget, retries) and its own child of the agent's cancel token: cancelling the
+/// agent's token stops every run, while [`Agent::run_cancellable`] stops just one.
+/// Tools are `Send + Sync` and shared by all runs, so a tool holding state must
+/// synchronize it.
 pub struct Agent<P: Provider> {
     provider: P,
-    tools: HashMap<String, Box<dyn Provider>>,
+    tools: HashMap<String, Arc<dyn Provider + Send + Sync>>,
+    schemas: HashMap<String, Value>,
     max_steps: usize,
     policy: ReasoningPolicy,
     max_tokens: usize,
+    reserve_tokens: usize,
     max_retries: usize,
+    max_run_retries: Option<usize>,
+    kind_profiles: HashMap<ProviderKind, KindProfile>,
+    max_tool_calls: Option<usize>,
+    max_reply_bytes: Option<usize>,
+    max_continuations: usize,
+    max_parallel_tools: Option<usize>,
+    tool_error_policy: ToolErrorPolicy,
+    stream_tool_results: bool,
+    unknown_tool_policy: UnknownToolPolicy,
+    empty_tool_calls_policy: EmptyToolCallsPolicy,
+    retry_if: RetryPredicate,
+    on_reply: Option<ReplyHook>,
+    on_reasoning: Option<Arc<dyn Fn(ReasoningMode) -> ReasoningMode + Send + Sync>>,
+    backoff: Backoff,
+    retry_groups: HashMap<String, String>,
+    timeout: Option<Duration>,
+    history_limit: Option<f64>,
+    summarizer: Option<(usize, Box<dyn Provider + Send + Sync>)>,
+    input_schemas: HashMap<String, Value>,
+    coerce_inputs: bool,
+    output_schemas: HashMap<String, Value>,
+    output_caps: HashMap<String, (usize, OutputCapPolicy)>,
+    approvals: HashMap<String, Arc<dyn ApprovalHook>>,
+    selector: Arc<dyn CandidateSelector>,
+    metrics: Option<Arc<dyn MetricsSink>>,
+    tracer: trace::Tracer,
+    shutdown: Option<Shutdown>,
+    clock: Arc<dyn Clock>,
+    closed: AtomicBool,
     cancel_token: CancellationToken,
 }
 
@@ -165,15 +733,14 @@ impl<P: Provider> Agent<P> {
         max_retries: usize,
         cancel_token: CancellationToken,
     ) -> Self {
-        Self {
+        Self::with_policy(
             provider,
-            tools: HashMap::new(),
             max_steps,
-            policy: ReasoningPolicy::default(),
             max_tokens,
+            ReasoningPolicy::default(),
             max_retries,
             cancel_token,
-        }
+        )
     }
 
     pub fn with_policy(
@@ -187,430 +754,3744 @@ impl<P: Provider> Agent<P> {
         Self {
             provider,
             tools: HashMap::new(),
+            schemas: HashMap::new(),
             max_steps,
             policy,
             max_tokens,
+            reserve_tokens: 0,
             max_retries,
+            max_run_retries: None,
+            kind_profiles: HashMap::new(),
+            max_tool_calls: None,
+            max_reply_bytes: None,
+            max_continuations: 0,
+            max_parallel_tools: None,
+            tool_error_policy: ToolErrorPolicy::default(),
+            stream_tool_results: false,
+            unknown_tool_policy: UnknownToolPolicy::default(),
+            empty_tool_calls_policy: EmptyToolCallsPolicy::default(),
+            retry_if: Arc::new(|_| true),
+            on_reply: None,
+            on_reasoning: None,
+            backoff: Backoff::default(),
+            retry_groups: HashMap::new(),
+            timeout: None,
+            history_limit: None,
+            summarizer: None,
+            input_schemas: HashMap::new(),
+            coerce_inputs: false,
+            output_schemas: HashMap::new(),
+            output_caps: HashMap::new(),
+            approvals: HashMap::new(),
+            selector: Arc::new(First),
+            metrics: None,
+            tracer: trace::Tracer::default(),
+            shutdown: None,
+            clock: Arc::new(SystemClock),
+            closed: AtomicBool::new(false),
             cancel_token,
         }
     }
 
-    pub fn register_tool<S, T>(
-        &mut self,
-        name: S,
-        spec: T,
-    ) -> Result<(), Box<dyn std::error::Error>>
-    where
-        S: Into<String>,
-        T: Into<ToolSpec>,
-    {
-        let name = name.into();
-        match spec.into() {
-            ToolSpec::Provider(p) => {
-                self.tools.insert(name, p);
-            }
-            ToolSpec::McpEndpoint(url) => {
-                let provider = crate::mcp::McpProvider::new(url)?;
-                self.tools.insert(name, Box::new(provider));
-            }
-            ToolSpec::McpConfigFile(path) => {
-                let text = fs::read_to_string(path)?;
-                let map: HashMap<String, String> = serde_json::from_str(&text)?;
-                for (tool_name, url) in map {
-                    let provider = crate::mcp::McpProvider::new(url)?;
-                    self.tools.insert(tool_name, Box::new(provider));
-                }
-            }
+    /// Counts every run as active in `shutdown` and refuses new ones with
+    /// `{"error": "shutting_down"}` once it drains. The agent's cancel token is
+    /// replaced by the shutdown's, so a drain that times out cancels the runs.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.cancel_token = shutdown.token();
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Times retry delays and run deadlines with `clock` instead of tokio's.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Caps the total number of tool invocations across a run, counting each parallel call.
+    pub fn with_max_tool_calls(mut self, max_tool_calls: usize) -> Self {
+        self.max_tool_calls = Some(max_tool_calls);
+        self
+    }
+
+    /// Holds `reserve_tokens` of the token budget back for the final answer. Tool
+    /// inputs, tool replies, intermediate provider replies and follow-up asks may not
+    /// spend into it; when one would, the provider instead gets a last ask carrying
+    /// the partial result and `"final": true` in its context. A reply to that ask
+    /// that is not `ok` ends the run with `{"error": "token budget exceeded"}`.
+    pub fn with_reserve_tokens(mut self, reserve_tokens: usize) -> Self {
+        self.reserve_tokens = reserve_tokens;
+        self
+    }
+
+    /// Rejects provider and tool replies whose output serializes to more than
+    /// `max_reply_bytes`, before their tokens are estimated. An oversized provider reply
+    /// ends the run and an oversized tool reply fails the call, both with
+    /// `{"error": "response_too_large"}`.
+    pub fn with_max_reply_bytes(mut self, max_reply_bytes: usize) -> Self {
+        self.max_reply_bytes = Some(max_reply_bytes);
+        self
+    }
+
+    /// Re-asks up to `max_continuations` times when an `ok` reply was cut off with
+    /// `finish_reason: "length"`, appending each continuation's text to the answer
+    /// until one stops on its own.
+    pub fn with_auto_continue(mut self, max_continuations: usize) -> Self {
+        self.max_continuations = max_continuations;
+        self
+    }
+
+    /// Bounds how many of a step's parallel tool calls are in flight at once, counting
+    /// a call from its first attempt through any retry backoff. The rest wait in order
+    /// for a slot. Parallel calls run on tokio's blocking pool, so without a bound
+    /// every call of a step hits its tool at the same time.
+    pub fn with_max_parallel_tools(mut self, max_parallel_tools: usize) -> Self {
+        self.max_parallel_tools = Some(max_parallel_tools.max(1));
+        self
+    }
+
+    pub fn with_tool_error_policy(mut self, policy: ToolErrorPolicy) -> Self {
+        self.tool_error_policy = policy;
+        self
+    }
+
+    /// Forwards each chunk a streaming tool produces (see [`Provider::ask_stream`]) to
+    /// the model as it arrives, in an ask whose context carries `"partial": true` and
+    /// the chunk's index. A failed reply means "keep going"; an `ok` reply is taken as
+    /// the final answer, the tool is cancelled and the run ends. Applies to steps with
+    /// a single tool call.
+    pub fn with_tool_streaming(mut self, enabled: bool) -> Self {
+        self.stream_tool_results = enabled;
+        self
+    }
+
+    pub fn with_unknown_tool_policy(mut self, policy: UnknownToolPolicy) -> Self {
+        self.unknown_tool_policy = policy;
+        self
+    }
+
+    pub fn with_empty_tool_calls_policy(mut self, policy: EmptyToolCallsPolicy) -> Self {
+        self.empty_tool_calls_policy = policy;
+        self
+    }
+
+    /// Chooses which candidate a multi-candidate reply continues with; the first
+    /// candidate is kept by default.
+    pub fn with_candidate_selector(mut self, selector: impl CandidateSelector + 'static) -> Self {
+        self.selector = Arc::new(selector);
+        self
+    }
+
+    /// Reports every provider attempt, retry and tool attempt to `sink`.
+    pub fn with_metrics(mut self, sink: Arc<dyn MetricsSink>) -> Self {
+        self.metrics = Some(sink);
+        self
+    }
+
+    /// Records each run, step and tool call as spans on `tracer` and forwards the
+    /// trace context to providers as a `traceparent` context key.
+    #[cfg(feature = "otel")]
+    pub fn with_tracer(mut self, tracer: opentelemetry::global::BoxedTracer) -> Self {
+        self.tracer = trace::Tracer::new(tracer);
+        self
+    }
+
+    /// Retries a failed provider or tool reply only when `retriable` returns true.
+    ///
+    /// By default every failure is retried up to `max_retries` times.
+    pub fn with_retry_if(
+        mut self,
+        retriable: impl Fn(&Reply) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.retry_if = Arc::new(retriable);
+        self
+    }
+
+    /// Passes every provider reply through `hook` before the agent checks `ok`,
+    /// `tool_calls` or whether to retry, e.g. to pull a JSON block out of markdown.
+    /// Metrics still record the reply as the provider sent it.
+    pub fn on_reply(mut self, hook: impl Fn(Reply) -> Reply + Send + Sync + 'static) -> Self {
+        self.on_reply = Some(Arc::new(hook));
+        self
+    }
+
+    /// Shows `hook` the reasoning mode decided for each run, after the budget clamp,
+    /// and uses the mode it returns instead; that is what providers see in
+    /// `context.reasoning`. [`Agent::plan`] applies it too.
+    pub fn on_reasoning_decided(
+        mut self,
+        hook: impl Fn(ReasoningMode) -> ReasoningMode + Send + Sync + 'static,
+    ) -> Self {
+        self.on_reasoning = Some(Arc::new(hook));
+        self
+    }
+
+    fn post_process(&self, reply: Reply) -> Reply {
+        match &self.on_reply {
+            Some(hook) => hook(reply),
+            None => reply,
         }
-        Ok(())
     }
 
-    pub fn has_tool(&self, name: &str) -> bool {
-        self.tools.contains_key(name)
+    /// Replaces the single `max_retries` with [`KindProfile::for_kind`] defaults:
+    /// provider and tool calls get the attempts of their own kind, and without
+    /// [`Agent::with_timeout`] the run deadline follows the provider's kind.
+    pub fn with_kind_defaults(mut self) -> Self {
+        for kind in [
+            ProviderKind::Embedded,
+            ProviderKind::SidecarUds,
+            ProviderKind::RemoteGrpc,
+        ] {
+            self.kind_profiles
+                .entry(kind)
+                .or_insert_with(|| KindProfile::for_kind(kind));
+        }
+        self
     }
 
-    pub fn call_tool(&self, name: &str, ask: Ask) -> Option<Reply> {
-        self.tools.get(name).map(|p| p.ask(ask))
+    /// Overrides the profile of one kind, turning on kind-based defaults for the rest.
+    pub fn with_kind_profile(mut self, kind: ProviderKind, profile: KindProfile) -> Self {
+        self.kind_profiles.insert(kind, profile);
+        self.with_kind_defaults()
     }
 
-    /// Runs the agent until the provider returns `ok` or the step or token limit is hit.
-    pub async fn run(&self, ask: Ask) -> Reply {
-        let mut remaining = self.max_tokens;
-        let ask_tokens = estimate_tokens(&ask.input) + estimate_tokens(&ask.context);
-        if ask_tokens > remaining {
-            return Reply {
+    /// Attempts per call for a provider or tool of `kind`.
+    fn attempts(&self, kind: ProviderKind) -> usize {
+        self.kind_profiles
+            .get(&kind)
+            .map_or(self.max_retries, |profile| profile.max_attempts)
+    }
+
+    /// Caps the retries of a whole run, summed over every provider and tool call, at
+    /// `max_run_retries`. Once they are spent a failing call returns its failure
+    /// without retrying, whatever attempts it had left.
+    pub fn with_run_retry_budget(mut self, max_run_retries: usize) -> Self {
+        self.max_run_retries = Some(max_run_retries);
+        self
+    }
+
+    fn retry_policy<'a>(&'a self, used: &'a Cell<usize>) -> RetryPolicy<'a> {
+        RetryPolicy {
+            backoff: &self.backoff,
+            clock: &*self.clock,
+            retriable: &*self.retry_if,
+            used,
+            max_run_retries: self.max_run_retries,
+        }
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Puts `tools` in a retry group, e.g. tools backed by the same service. When
+    /// several calls of one group fail in the same parallel step their retries share
+    /// one backoff schedule and are spaced out rather than fired together. Calls made
+    /// on their own keep their independent backoff.
+    pub fn with_retry_group(mut self, group: impl Into<String>, tools: &[&str]) -> Self {
+        let group = group.into();
+        for tool in tools {
+            self.retry_groups.insert(tool.to_string(), group.clone());
+        }
+        self
+    }
+
+    /// Bounds the wall-clock time of each `run`. Past the deadline the run stops with
+    /// `{"error": "deadline_exceeded", "partial": ...}` and in-flight work sees its
+    /// cancellation token fire; the agent's own token is left untouched.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Trims a messages-array input once the ask is estimated above `fraction` of
+    /// `max_tokens`, dropping the oldest messages first. System messages and the
+    /// latest user message are always kept.
+    pub fn with_history_limit(mut self, fraction: f64) -> Self {
+        self.history_limit = Some(fraction);
+        self
+    }
+
+    /// Compresses the carried-over input between steps once it exceeds `threshold`
+    /// estimated tokens.
+    ///
+    /// `summarizer` receives `{"op": "summarize", "input": <input>, "context":
+    /// {"request_id", "target_tokens"}}` with the run's cancellation token; an `ok`
+    /// reply smaller than the input replaces it. Reading the input is charged to the
+    /// run budget, and summarizing is skipped when the budget cannot cover it.
+    pub fn with_summarizer(
+        mut self,
+        threshold: usize,
+        summarizer: impl Provider + Send + Sync + 'static,
+    ) -> Self {
+        self.summarizer = Some((threshold, Box::new(summarizer)));
+        self
+    }
+
+    /// Tokens intermediate work may still spend, leaving the final-answer reserve alone.
+    fn spendable(&self, state: &RunState) -> usize {
+        state.remaining_tokens.saturating_sub(self.reserve_tokens)
+    }
+
+    /// Charges the next ask against the budget. An ask that only fits by using the
+    /// reserve is marked final; one that does not fit at all ends the run.
+    fn charge_next(&self, state: &mut RunState) -> Option<Reply> {
+        let mut next_tokens =
+            estimate_tokens(&state.current.input) + estimate_tokens(&state.current.context);
+        if next_tokens > self.spendable(state) && !state.finalizing {
+            state.current.context["final"] = json!(true);
+            state.finalizing = true;
+            next_tokens =
+                estimate_tokens(&state.current.input) + estimate_tokens(&state.current.context);
+        }
+        if next_tokens > state.remaining_tokens {
+            return Some(Reply {
                 ok: false,
                 output: json!({"error": "token budget exceeded"}),
                 latency_ms: 0,
                 cost: json!({}),
-            };
+            });
         }
-        remaining -= ask_tokens;
-        let mode = if ask_tokens * 100 / self.max_tokens > 85 {
-            ReasoningMode::Direct
-        } else {
-            self.policy.decide(&ask.input, 0)
+        state.remaining_tokens -= next_tokens;
+        None
+    }
+
+    /// Ends intermediate work once it would spend the reserve: the model gets one last
+    /// ask, marked final, carrying the partial result so far.
+    fn finish_early(
+        &self,
+        state: &mut RunState,
+        mode: ReasoningMode,
+        request_id: &str,
+    ) -> Option<Reply> {
+        if state.finalizing {
+            return Some(Reply {
+                ok: false,
+                output: json!({"error": "token budget exceeded"}),
+                latency_ms: 0,
+                cost: json!({}),
+            });
+        }
+        state.current = Ask {
+            op: state.current.op.clone(),
+            input: state.partial.clone(),
+            context: json!({
+                "reasoning": mode.as_str(),
+                "request_id": request_id,
+                "final": true,
+            }),
         };
-        let mut current = Ask {
-            context: json!({"reasoning": mode.as_str()}),
-            ..ask
+        state.finalizing = true;
+        self.charge_next(state)
+    }
+
+    /// Applies the summarizer to `state.current.input` when it is over the threshold.
+    fn summarize(&self, state: &mut RunState, run_token: &CancellationToken) {
+        let Some((threshold, summarizer)) = &self.summarizer else {
+            return;
         };
-        for step in 0..self.max_steps {
-            let reply = call_with_retry(
-                || self.provider.ask(current.clone()),
-                self.max_retries,
-                self.cancel_token.clone(),
-            )
-            .await;
-            if self.cancel_token.is_cancelled() {
-                return reply;
-            }
-            let reply_tokens = estimate_tokens(&reply.output);
-            if reply_tokens > remaining {
-                return Reply {
-                    ok: false,
-                    output: json!({"error": "token budget exceeded"}),
-                    latency_ms: reply.latency_ms,
-                    cost: reply.cost,
-                };
-            }
-            remaining -= reply_tokens;
-            if reply.ok {
-                return reply;
-            }
-            if let Some(tool_calls) = reply.output.get("tool_calls").and_then(|v| v.as_array()) {
-                if tool_calls.len() == 1 {
-                    let tc = &tool_calls[0];
-                    let name = tc["op"].as_str().unwrap_or("");
-                    let input = tc["input"].clone();
-                    if let Some(tool) = self.tools.get(name) {
-                        let tool_tokens = estimate_tokens(&input);
-                        if tool_tokens > remaining {
-                            return Reply {
-                                ok: false,
-                                output: json!({"error": "token budget exceeded"}),
-                                latency_ms: 0,
-                                cost: json!({}),
-                            };
-                        }
-                        remaining -= tool_tokens;
-                        let name_owned = name.to_string();
-                        let input_clone = input.clone();
-                        let tool_ref = tool.as_ref();
-                        let tool_reply = call_with_retry(
-                            move || {
-                                tool_ref.ask(Ask {
-                                    op: name_owned.clone(),
-                                    input: input_clone.clone(),
-                                    context: json!({}),
-                                })
-                            },
-                            self.max_retries,
-                            self.cancel_token.clone(),
-                        )
-                        .await;
-                        if self.cancel_token.is_cancelled() {
-                            return tool_reply;
+        let input_tokens = estimate_tokens(&state.current.input);
+        if input_tokens <= *threshold || input_tokens > self.spendable(state) {
+            return;
+        }
+        state.remaining_tokens -= input_tokens;
+        let reply = ask_tool(
+            summarizer.as_ref(),
+            Ask {
+                op: "summarize".into(),
+                input: state.current.input.clone(),
+                context: json!({
+                    "request_id": state.request_id,
+                    "target_tokens": threshold,
+                }),
+            },
+            run_token,
+            &mut |_| {},
+        );
+        state.totals.add(&reply);
+        if reply.ok && estimate_tokens(&reply.output) < input_tokens {
+            state.current.input = reply.output;
+            state.current.context["summarized"] = json!(true);
+        }
+    }
+
+    /// Declares the schema of `tool`'s arguments, used by input coercion in place of
+    /// an `inputSchema` discovered at registration.
+    pub fn with_input_schema(mut self, tool: impl Into<String>, schema: Value) -> Self {
+        self.input_schemas.insert(tool.into(), schema);
+        self
+    }
+
+    /// Converts stringified numbers and booleans in tool arguments to the types the
+    /// tool's input schema declares (see [`schema::coerce`]) before approval and the
+    /// call. Tools without an input schema get their arguments unchanged.
+    pub fn with_input_coercion(mut self, enabled: bool) -> Self {
+        self.coerce_inputs = enabled;
+        self
+    }
+
+    /// Declares the schema successful outputs of `tool` must satisfy.
+    ///
+    /// Without one, an `outputSchema` in the tool's discovered schema is used. Outputs
+    /// that fail validation are turned into failed tool replies before reaching the
+    /// provider.
+    pub fn with_output_schema(mut self, tool: impl Into<String>, schema: Value) -> Self {
+        self.output_schemas.insert(tool.into(), schema);
+        self
+    }
+
+    /// Caps what one reply of `tool` may add to the next ask at `max_output_tokens`
+    /// (as estimated for the budget), applied to failed replies too. Keeps a verbose
+    /// tool from starving later steps.
+    pub fn with_max_output_tokens(
+        mut self,
+        tool: impl Into<String>,
+        max_output_tokens: usize,
+        policy: OutputCapPolicy,
+    ) -> Self {
+        self.output_caps
+            .insert(tool.into(), (max_output_tokens, policy));
+        self
+    }
+
+    /// What the provider sees in place of a call to an unregistered tool.
+    fn no_such_tool(&self, name: &str) -> Value {
+        json!({"error": "no such tool", "tool": name, "available": self.tool_names()})
+    }
+
+    /// Collapses a reply's `candidates` onto the one the selector picks.
+    fn select_candidate(&self, mut reply: Reply) -> Reply {
+        let Some(candidates) = reply.output["candidates"]
+            .as_array()
+            .filter(|c| c.len() > 1)
+        else {
+            return reply;
+        };
+        let index = self.selector.select(candidates);
+        if index > 0 && index < candidates.len() {
+            select::promote(&mut reply.output, index);
+        }
+        reply
+    }
+
+    /// Follows a length-truncated `ok` reply with continuation asks, see
+    /// [`Agent::with_auto_continue`]. The returned reply is the last continuation
+    /// with the concatenated text in place of its own.
+    fn continue_truncated(
+        &self,
+        state: &mut RunState,
+        mut reply: Reply,
+        run_token: &CancellationToken,
+    ) -> Reply {
+        let mut text = String::new();
+        for continuation in 1..=self.max_continuations {
+            if !reply.ok || !truncated(&reply.output) {
+                break;
+            }
+            let Some(part) = reply_text(&reply.output) else {
+                break;
+            };
+            text.push_str(part);
+            let ask = Ask {
+                op: state.current.op.clone(),
+                input: continuation_input(&state.current.input, &text),
+                context: json!({
+                    "reasoning": state.mode.as_str(),
+                    "request_id": state.request_id,
+                    "continuation": continuation,
+                }),
+            };
+            let ask_tokens = estimate_tokens(&ask.input) + estimate_tokens(&ask.context);
+            if ask_tokens > self.spendable(state) || run_token.is_cancelled() {
+                break;
+            }
+            state.remaining_tokens -= ask_tokens;
+            let next = self.provider.ask_cancellable(ask, run_token);
+            metrics::attempt(
+                self.metrics.as_deref(),
+                self.provider.kind(),
+                &state.current.op,
+                false,
+                &mut 0,
+                &next,
+            );
+            let next = self.select_candidate(self.post_process(next));
+            state.totals.add(&next);
+            if !next.ok {
+                break;
+            }
+            let mut cost = reply.cost;
+            merge_cost(&mut cost, &next.cost);
+            reply = Reply {
+                latency_ms: reply.latency_ms + next.latency_ms,
+                cost,
+                ..next
+            };
+        }
+        if !text.is_empty() {
+            if let Some(last) = reply_text(&reply.output) {
+                text.push_str(last);
+                set_reply_text(&mut reply.output, text);
+            }
+        }
+        reply
+    }
+
+    /// Replaces a reply over `max_reply_bytes` with a `response_too_large` failure.
+    fn limit_reply(&self, reply: Reply) -> Reply {
+        match self.max_reply_bytes {
+            Some(limit) if exceeds_bytes(&reply.output, limit) => Reply {
+                ok: false,
+                output: json!({"error": "response_too_large"}),
+                ..reply
+            },
+            _ => reply,
+        }
+    }
+
+    /// Truncates or fails a tool reply over the tool's `max_output_tokens`.
+    fn cap_output(&self, tool: &str, reply: Reply) -> Reply {
+        let Some(&(limit, policy)) = self.output_caps.get(tool) else {
+            return reply;
+        };
+        let tokens = estimate_tokens(&reply.output);
+        if tokens <= limit {
+            return reply;
+        }
+        let output = match policy {
+            OutputCapPolicy::Truncate => truncate_output(&reply.output, limit),
+            OutputCapPolicy::Fail => {
+                return Reply {
+                    ok: false,
+                    output: json!({
+                        "error": "tool_output_too_large",
+                        "tool": tool,
+                        "tokens": tokens,
+                        "max_output_tokens": limit,
+                    }),
+                    ..reply
+                }
+            }
+        };
+        Reply { output, ..reply }
+    }
+
+    /// Fails an `ok` tool reply whose output violates the tool's output schema.
+    fn check_output(&self, tool: &str, reply: Reply) -> Reply {
+        let schema = self
+            .output_schemas
+            .get(tool)
+            .or_else(|| self.schemas.get(tool).and_then(|s| s.get("outputSchema")));
+        let Some(schema) = schema.filter(|_| reply.ok) else {
+            return reply;
+        };
+        match schema::validate(schema, &reply.output) {
+            Ok(()) => reply,
+            Err(detail) => Reply {
+                ok: false,
+                output: json!({"error": "invalid tool output", "detail": detail}),
+                ..reply
+            },
+        }
+    }
+
+    /// Requires `hook` to approve every call of `tool` before it runs.
+    pub fn require_approval(
+        mut self,
+        tool: impl Into<String>,
+        hook: impl ApprovalHook + 'static,
+    ) -> Self {
+        self.approvals.insert(tool.into(), Arc::new(hook));
+        self
+    }
+
+    /// Registers a tool under `name` and returns the tool names actually added.
+    ///
+    /// A `McpConfigFile` maps remote tool names to endpoints; each entry is registered
+    /// as `{name}.{tool}` (e.g. `cfg.ping`) while still invoking `tool` on the server.
+    /// MCP tools have their schema fetched at registration so [`Agent::tool_schema`]
+    /// can report it.
+    pub fn register_tool<S, T>(
+        &mut self,
+        name: S,
+        spec: T,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>>
+    where
+        S: Into<String>,
+        T: Into<ToolSpec>,
+    {
+        let name = name.into();
+        match spec.into() {
+            ToolSpec::Provider(p) => {
+                self.schemas.remove(&name);
+                self.tools.insert(name.clone(), Arc::from(p));
+                Ok(vec![name])
+            }
+            ToolSpec::Factory(factory) => {
+                self.schemas.remove(&name);
+                self.tools
+                    .insert(name.clone(), Arc::new(tools::LazyTool::new(factory)));
+                Ok(vec![name])
+            }
+            ToolSpec::McpEndpoint(url) => {
+                let provider = crate::mcp::McpProvider::new(url)?;
+                self.insert_mcp_tool(name.clone(), &name, provider);
+                Ok(vec![name])
+            }
+            ToolSpec::McpConfigFile(path) => {
+                let text = fs::read_to_string(path)?;
+                let map: HashMap<String, String> = serde_json::from_str(&text)?;
+                let mut registered = Vec::with_capacity(map.len());
+                for (tool_name, url) in map {
+                    let provider = crate::mcp::McpProvider::new(url)?.for_tool(&tool_name);
+                    let key = format!("{name}.{tool_name}");
+                    self.insert_mcp_tool(key.clone(), &tool_name, provider);
+                    registered.push(key);
+                }
+                registered.sort();
+                Ok(registered)
+            }
+        }
+    }
+
+    fn insert_mcp_tool(&mut self, key: String, tool: &str, provider: crate::mcp::McpProvider) {
+        match provider.schema(tool) {
+            Some(schema) => {
+                self.schemas.insert(key.clone(), schema);
+            }
+            None => {
+                self.schemas.remove(&key);
+            }
+        }
+        self.tools.insert(key, Arc::new(provider));
+    }
+
+    /// Closes every registered tool and the summarizer with [`Provider::close`], e.g.
+    /// to stop tool subprocesses after a shutdown drain. Only the first call has any
+    /// effect; dropping the agent closes them too. The agent's own provider is left
+    /// open, since it is often shared.
+    ///
+    /// Wrapped tools are closed through their decorators. A tool registered as an
+    /// `Arc` is closed as well, even while other agents still hold it: when one tool
+    /// is shared by several agents, drop or close them only once the tool is no
+    /// longer needed by any of them.
+    pub fn close(&self) {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        for tool in self.tools.values() {
+            tool.close();
+        }
+        if let Some((_, summarizer)) = &self.summarizer {
+            summarizer.close();
+        }
+    }
+
+    /// Applies input coercion to arguments for `tool`, when enabled.
+    fn coerce_input(&self, tool: &str, mut input: Value) -> Value {
+        if !self.coerce_inputs {
+            return input;
+        }
+        let schema = self
+            .input_schemas
+            .get(tool)
+            .or_else(|| self.schemas.get(tool).and_then(|s| s.get("inputSchema")));
+        if let Some(schema) = schema {
+            schema::coerce(schema, &mut input);
+        }
+        input
+    }
+
+    /// Runs the approval hook guarding `tool`, if any, returning the input to use.
+    async fn approve(&self, tool: &str, input: Value) -> Result<Value, Reply> {
+        let Some(hook) = self.approvals.get(tool) else {
+            return Ok(input);
+        };
+        match hook.approve(tool, &input).await {
+            ApprovalDecision::Approve => Ok(input),
+            ApprovalDecision::Modify(input) => Ok(input),
+            ApprovalDecision::Deny(reason) => Err(Reply {
+                ok: false,
+                output: json!({"error": "tool_denied", "tool": tool, "reason": reason}),
+                latency_ms: 0,
+                cost: json!({}),
+            }),
+        }
+    }
+
+    /// Names of all registered tools, sorted.
+    pub fn tool_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.tools.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// The schema discovered for `name` at registration, if any.
+    pub fn tool_schema(&self, name: &str) -> Option<&Value> {
+        self.schemas.get(name)
+    }
+
+    /// Deregisters `name`, returning whether it was registered.
+    pub fn remove_tool(&mut self, name: &str) -> bool {
+        self.schemas.remove(name);
+        self.tools.remove(name).is_some()
+    }
+
+    pub fn has_tool(&self, name: &str) -> bool {
+        self.tools.contains_key(name)
+    }
+
+    pub fn call_tool(&self, name: &str, ask: Ask) -> Option<Reply> {
+        self.tools.get(name).map(|p| p.ask(ask))
+    }
+
+    /// Describes what `run` would do with `ask` without calling the provider.
+    ///
+    /// The returned plan carries the reasoning mode, the token budget math and the
+    /// first ask `run` would send. A missing `request_id` is generated here too.
+    pub fn plan(&self, ask: &Ask) -> RunPlan {
+        self.plan_within(ask, self.max_tokens)
+    }
+
+    fn plan_within(&self, ask: &Ask, max_tokens: usize) -> RunPlan {
+        let mut input = ask.input.clone();
+        let mut truncated_messages = 0;
+        if let (Some(fraction), Value::Array(messages)) = (self.history_limit, &mut input) {
+            let limit = (max_tokens as f64 * fraction) as usize;
+            let context_tokens = estimate_tokens(&ask.context);
+            truncated_messages = truncate_history(messages, limit.saturating_sub(context_tokens));
+        }
+        let estimated_tokens = estimate_tokens(&input) + estimate_tokens(&ask.context);
+        let within_budget = estimated_tokens <= max_tokens;
+        let mut mode = if !within_budget || estimated_tokens * 100 / max_tokens > 85 {
+            ReasoningMode::Direct
+        } else {
+            self.policy.decide_for(&ask.op, &input, 0)
+        };
+        if let Some(hook) = &self.on_reasoning {
+            mode = hook(mode);
+        }
+        let request_id = ask
+            .context
+            .get("request_id")
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        RunPlan {
+            mode,
+            estimated_tokens,
+            remaining_tokens: max_tokens.saturating_sub(estimated_tokens),
+            within_budget,
+            truncated_messages,
+            first_ask: Ask {
+                op: ask.op.clone(),
+                input,
+                context: json!({"reasoning": mode.as_str(), "request_id": request_id}),
+            },
+        }
+    }
+
+    /// Runs the agent until the provider returns `ok` or the step or token limit is hit.
+    ///
+    /// Every provider and tool ask carries a `request_id` in its context, taken from the
+    /// incoming ask context when present and generated otherwise.
+    pub async fn run(&self, ask: Ask) -> Reply {
+        self.run_within(ask, self.max_tokens).await
+    }
+
+    /// Like [`Agent::run`], but cancelling `token` stops this run alone, with
+    /// `{"error": "cancelled"}`; other runs of the agent carry on.
+    pub async fn run_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        self.drive(self.start(ask), Some(token)).await
+    }
+
+    async fn run_within(&self, ask: Ask, max_tokens: usize) -> Reply {
+        self.run_from(self.start_within(&ask, max_tokens)).await
+    }
+
+    /// Builds the initial [`RunState`] for `ask` without calling the provider.
+    ///
+    /// An ask over the token budget yields a state that is already finished.
+    pub fn start(&self, ask: Ask) -> RunState {
+        self.start_within(&ask, self.max_tokens)
+    }
+
+    fn start_within(&self, ask: &Ask, max_tokens: usize) -> RunState {
+        let RunPlan {
+            mode,
+            remaining_tokens,
+            first_ask,
+            within_budget,
+            ..
+        } = self.plan_within(ask, max_tokens);
+        let request_id = first_ask.context["request_id"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        RunState {
+            step: 0,
+            mode,
+            request_id,
+            current: first_ask,
+            remaining_tokens,
+            tool_calls_made: 0,
+            totals: RunTotals::default(),
+            partial: Value::Null,
+            finalizing: false,
+            retries: Cell::new(0),
+            outcome: (!within_budget).then(|| Reply {
+                ok: false,
+                output: json!({"error": "token budget exceeded"}),
+                latency_ms: 0,
+                cost: json!({}),
+            }),
+        }
+    }
+
+    /// Per-run token and deadline: the deadline and `caller`, if any, cancel the
+    /// returned token, and the guard stops the timer task once the caller is done.
+    fn run_guard(
+        &self,
+        caller: Option<&CancellationToken>,
+    ) -> (CancellationToken, DropGuard, Option<Instant>) {
+        // a child token lets the deadline stop in-flight work without touching the
+        // caller's token
+        let run_token = self.cancel_token.child_token();
+        let guard = run_token.clone().drop_guard();
+        if let Some(caller) = caller {
+            let linked = run_token.clone();
+            let caller = caller.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = caller.cancelled() => linked.cancel(),
+                    _ = linked.cancelled() => {}
+                }
+            });
+        }
+        let timeout = self.timeout.or_else(|| {
+            self.kind_profiles
+                .get(&self.provider.kind())
+                .and_then(|profile| profile.timeout)
+        });
+        let deadline = timeout.map(|t| self.clock.now() + t);
+        if let Some(deadline) = deadline {
+            let timer = run_token.clone();
+            let elapsed = self.clock.sleep_until(deadline);
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = elapsed => timer.cancel(),
+                    _ = timer.cancelled() => {}
+                }
+            });
+        }
+        (run_token, guard, deadline)
+    }
+
+    /// Registers a run with the shutdown coordinator, if any; the reply is the
+    /// refusal once it is draining.
+    fn enter_run(&self) -> Result<Option<ActiveRun>, Reply> {
+        let Some(shutdown) = &self.shutdown else {
+            return Ok(None);
+        };
+        shutdown.enter().map(Some).ok_or_else(|| Reply {
+            ok: false,
+            output: json!({"error": "shutting_down"}),
+            latency_ms: 0,
+            cost: json!({}),
+        })
+    }
+
+    /// Runs `state` to completion, e.g. after restoring a checkpoint.
+    pub async fn run_from(&self, state: RunState) -> Reply {
+        self.drive(state, None).await
+    }
+
+    async fn drive(&self, mut state: RunState, caller: Option<&CancellationToken>) -> Reply {
+        if let Some(reply) = state.outcome {
+            return reply;
+        }
+        let _active = match self.enter_run() {
+            Ok(active) => active,
+            Err(reply) => return reply,
+        };
+        let (run_token, _stop_timer, deadline) = self.run_guard(caller);
+        let expired = || deadline.is_some_and(|d| self.clock.now() >= d);
+        let span = self.tracer.start("agent.run", None);
+        span.set_str("soma.op", &state.current.op);
+        span.set_str("soma.reasoning", state.mode.as_str());
+        let budget = state.remaining_tokens;
+        loop {
+            if let Some(reply) = self
+                .step(&mut state, &run_token, &expired, Some(&span))
+                .await
+            {
+                span.set_int("soma.tokens", budget.saturating_sub(state.remaining_tokens));
+                span.set_bool("soma.ok", reply.ok);
+                return reply;
+            }
+        }
+    }
+
+    /// Advances `state` by one step and returns it, so a run can be checkpointed
+    /// between steps. Once [`RunState::is_finished`] is true the state is returned
+    /// unchanged. The agent's timeout bounds each call separately.
+    pub async fn step_once(&self, mut state: RunState) -> RunState {
+        if state.outcome.is_some() {
+            return state;
+        }
+        let _active = match self.enter_run() {
+            Ok(active) => active,
+            Err(reply) => {
+                state.outcome = Some(reply);
+                return state;
+            }
+        };
+        let (run_token, _stop_timer, deadline) = self.run_guard(None);
+        let expired = || deadline.is_some_and(|d| self.clock.now() >= d);
+        state.outcome = self.step(&mut state, &run_token, &expired, None).await;
+        state
+    }
+
+    /// Runs one step of `state`: a provider call plus any tool calls it requests.
+    ///
+    /// Returns the final reply once the run is over, `None` when another step is needed.
+    async fn step(
+        &self,
+        state: &mut RunState,
+        run_token: &CancellationToken,
+        expired: &dyn Fn() -> bool,
+        run_span: Option<&trace::Span>,
+    ) -> Option<Reply> {
+        if run_token.is_cancelled() || expired() {
+            return Some(state.totals.stopped(state.partial.clone(), expired()));
+        }
+        if state.step >= self.max_steps {
+            return Some(Reply {
+                ok: false,
+                output: json!({"error": "step limit exceeded"}),
+                latency_ms: 0,
+                cost: json!({}),
+            });
+        }
+        let step = state.step;
+        state.step += 1;
+        let mode = state.mode;
+        let request_id = state.request_id.clone();
+        let span = self.tracer.start("agent.step", run_span);
+        span.set_str("soma.op", &state.current.op);
+        span.set_str("soma.reasoning", mode.as_str());
+        span.set_int("soma.step", step);
+        let mut attempts = 0;
+        let reply = call_with_retry(
+            || {
+                let mut ask = state.current.clone();
+                span.inject(&mut ask.context);
+                let reply = self.provider.ask_cancellable(ask, run_token);
+                metrics::attempt(
+                    self.metrics.as_deref(),
+                    self.provider.kind(),
+                    &state.current.op,
+                    false,
+                    &mut attempts,
+                    &reply,
+                );
+                self.post_process(reply)
+            },
+            self.attempts(self.provider.kind()),
+            &self.retry_policy(&state.retries),
+            run_token.clone(),
+            None,
+        )
+        .await;
+        state.totals.add(&reply);
+        if run_token.is_cancelled() || expired() {
+            return Some(state.totals.stopped(state.partial.clone(), expired()));
+        }
+        if self
+            .max_reply_bytes
+            .is_some_and(|limit| exceeds_bytes(&reply.output, limit))
+        {
+            return Some(self.limit_reply(reply));
+        }
+        let reply = self.select_candidate(reply);
+        let reply = self.continue_truncated(state, reply, run_token);
+        // thinking tokens never show up in the output but are spent all the same
+        let reply_tokens =
+            estimate_tokens(&reply.output) + usage::reasoning_tokens(&reply.cost) as usize;
+        if reply_tokens > state.remaining_tokens || (state.finalizing && !reply.ok) {
+            return Some(Reply {
+                ok: false,
+                output: json!({"error": "token budget exceeded"}),
+                latency_ms: reply.latency_ms,
+                cost: reply.cost,
+            });
+        }
+        // only the final answer may spend the reserve
+        let over_reserve = state.remaining_tokens - reply_tokens < self.reserve_tokens;
+        state.remaining_tokens -= reply_tokens;
+        span.set_int("soma.tokens", reply_tokens);
+        if reply.ok {
+            return Some(reply);
+        }
+        let no_calls = reply.output["tool_calls"]
+            .as_array()
+            .is_some_and(Vec::is_empty);
+        if no_calls
+            && self.empty_tool_calls_policy == EmptyToolCallsPolicy::UseContent
+            && reply_text(&reply.output).is_some()
+        {
+            let mut output = reply.output;
+            if let Some(fields) = output.as_object_mut() {
+                fields.remove("tool_calls");
+            }
+            return Some(Reply {
+                ok: true,
+                output,
+                ..reply
+            });
+        }
+        if over_reserve {
+            return self.finish_early(state, mode, &request_id);
+        }
+        if let Some(tool_calls) = reply.output["tool_calls"]
+            .as_array()
+            .filter(|calls| !calls.is_empty())
+        {
+            let requested: Vec<String> = tool_calls
+                .iter()
+                .map(|tc| tc["op"].as_str().unwrap_or("").to_string())
+                .collect();
+            span.set_strs("soma.tools", &requested);
+            state.tool_calls_made += tool_calls.len();
+            if self
+                .max_tool_calls
+                .is_some_and(|limit| state.tool_calls_made > limit)
+            {
+                return Some(Reply {
+                    ok: false,
+                    output: json!({"error": "tool_call_limit_exceeded"}),
+                    latency_ms: state.totals.latency_ms,
+                    cost: state.totals.cost.clone(),
+                });
+            }
+            if tool_calls.len() == 1 {
+                let tc = &tool_calls[0];
+                let name = tc["op"].as_str().unwrap_or("");
+                let input = tc["input"].clone();
+                let call_id = tc.get("id");
+                if let Some(tool) = self.tools.get(name) {
+                    let input = self.coerce_input(name, input);
+                    let input = match self.approve(name, input).await {
+                        Ok(input) => input,
+                        Err(denied) => return Some(denied),
+                    };
+                    let tool_tokens = estimate_tokens(&input);
+                    if tool_tokens > self.spendable(state) {
+                        return self.finish_early(state, mode, &request_id);
+                    }
+                    state.remaining_tokens -= tool_tokens;
+                    let name_owned = name.to_string();
+                    let input_clone = input.clone();
+                    let mut tool_ctx = tool_context(
+                        mode,
+                        step,
+                        name,
+                        &request_id,
+                        self.spendable(state),
+                        idempotency_key(&request_id, step, 0, name, &input),
+                    );
+                    let tool_span = self.tracer.start("agent.tool", Some(&span));
+                    tool_span.set_str("soma.tool", name);
+                    tool_span.inject(&mut tool_ctx);
+                    let tool_ref = tool.as_ref();
+                    // cancelled when the model settles on an answer from partial output
+                    let tool_token = run_token.child_token();
+                    let token = &tool_token;
+                    let partials: RefCell<Vec<(Value, Reply)>> = RefCell::new(Vec::new());
+                    let model_op = state.current.op.clone();
+                    let forward = |chunk: Value| {
+                        if !self.stream_tool_results || tool_token.is_cancelled() {
+                            return;
                         }
-                        if !tool_reply.ok {
-                            return Reply {
-                                ok: false,
-                                output: json!({
-                                    "error": "tool invocation failed",
+                        let index = partials.borrow().len();
+                        let reply = self.provider.ask_cancellable(
+                            Ask {
+                                op: model_op.clone(),
+                                input: chunk.clone(),
+                                context: json!({
+                                    "reasoning": mode.as_str(),
+                                    "request_id": request_id,
                                     "tool": name,
-                                    "detail": tool_reply.output,
+                                    "partial": true,
+                                    "chunk": index,
                                 }),
-                                latency_ms: tool_reply.latency_ms,
-                                cost: tool_reply.cost,
-                            };
+                            },
+                            run_token,
+                        );
+                        metrics::attempt(
+                            self.metrics.as_deref(),
+                            self.provider.kind(),
+                            &model_op,
+                            false,
+                            &mut 0,
+                            &reply,
+                        );
+                        let reply = self.post_process(reply);
+                        if reply.ok {
+                            tool_token.cancel();
                         }
-                        let tool_reply_tokens = estimate_tokens(&tool_reply.output);
-                        if tool_reply_tokens > remaining {
-                            return Reply {
-                                ok: false,
-                                output: json!({"error": "token budget exceeded"}),
-                                latency_ms: 0,
-                                cost: json!({}),
+                        partials.borrow_mut().push((chunk, reply));
+                    };
+                    let sink = self.metrics.as_deref();
+                    let attempts = Cell::new(0);
+                    let forward = RefCell::new(forward);
+                    let (name, attempts, forward) = (&name_owned, &attempts, &forward);
+                    let tool_reply = call_with_retry_async(
+                        move || {
+                            let ask = Ask {
+                                op: name.clone(),
+                                input: input_clone.clone(),
+                                context: tool_ctx.clone(),
                             };
-                        }
-                        remaining -= tool_reply_tokens;
-                        current = Ask {
-                            op: current.op.clone(),
-                            input: tool_reply.output,
-                            context: json!({
-                                "reasoning": mode.as_str(),
+                            async move {
+                                let reply = match tool_ref.ask_future(&ask, token) {
+                                    Some(future) => await_tool(future).await,
+                                    None => {
+                                        ask_tool(tool_ref, ask, token, &mut *forward.borrow_mut())
+                                    }
+                                };
+                                let mut count = attempts.get();
+                                metrics::attempt(
+                                    sink,
+                                    tool_ref.kind(),
+                                    name,
+                                    true,
+                                    &mut count,
+                                    &reply,
+                                );
+                                attempts.set(count);
+                                reply
+                            }
+                        },
+                        self.attempts(tool_ref.kind()),
+                        &self.retry_policy(&state.retries),
+                        tool_token.clone(),
+                        None,
+                    )
+                    .await;
+                    tool_span.set_bool("soma.ok", tool_reply.ok);
+                    tool_span.end();
+                    state.totals.add(&tool_reply);
+                    if run_token.is_cancelled() || expired() {
+                        return Some(state.totals.stopped(state.partial.clone(), expired()));
+                    }
+                    let partials = partials.into_inner();
+                    let mut partial_tokens = 0;
+                    for (chunk, reply) in &partials {
+                        state.totals.add(reply);
+                        partial_tokens += estimate_tokens(chunk) + estimate_tokens(&reply.output);
+                    }
+                    if partial_tokens > self.spendable(state) {
+                        return self.finish_early(state, mode, &request_id);
+                    }
+                    state.remaining_tokens -= partial_tokens;
+                    if let Some((_, answer)) = partials.into_iter().find(|(_, reply)| reply.ok) {
+                        return Some(answer);
+                    }
+                    let tool_reply = self
+                        .cap_output(name, self.check_output(name, self.limit_reply(tool_reply)));
+                    if !tool_reply.ok {
+                        return Some(Reply {
+                            ok: false,
+                            output: json!({
+                                "error": "tool invocation failed",
                                 "tool": name,
+                                "detail": tool_reply.output,
                             }),
-                        };
-                        let next_tokens =
-                            estimate_tokens(&current.input) + estimate_tokens(&current.context);
-                        if next_tokens > remaining {
-                            return Reply {
+                            latency_ms: tool_reply.latency_ms,
+                            cost: tool_reply.cost,
+                        });
+                    }
+                    let tool_reply_tokens = estimate_tokens(&tool_reply.output);
+                    if tool_reply_tokens > self.spendable(state) {
+                        return self.finish_early(state, mode, &request_id);
+                    }
+                    state.remaining_tokens -= tool_reply_tokens;
+                    state.partial = tool_reply.output.clone();
+                    let mut context = json!({
+                        "reasoning": mode.as_str(),
+                        "request_id": request_id,
+                        "tool": name,
+                    });
+                    if let Some(id) = call_id {
+                        context["tool_call_id"] = id.clone();
+                    }
+                    state.current = Ask {
+                        op: state.current.op.clone(),
+                        input: tool_reply.output,
+                        context,
+                    };
+                    self.summarize(state, run_token);
+                    if let Some(reply) = self.charge_next(state) {
+                        return Some(reply);
+                    }
+                    return None;
+                } else if self.unknown_tool_policy == UnknownToolPolicy::FeedbackToModel {
+                    let feedback = self.no_such_tool(name);
+                    state.partial = feedback.clone();
+                    let mut context = json!({
+                        "reasoning": mode.as_str(),
+                        "request_id": request_id,
+                        "tool": name,
+                    });
+                    if let Some(id) = call_id {
+                        context["tool_call_id"] = id.clone();
+                    }
+                    state.current = Ask {
+                        op: state.current.op.clone(),
+                        input: feedback,
+                        context,
+                    };
+                    if let Some(reply) = self.charge_next(state) {
+                        return Some(reply);
+                    }
+                    return None;
+                } else {
+                    return Some(Reply {
+                        ok: false,
+                        output: json!({"error": "unknown tool", "tool": name}),
+                        latency_ms: 0,
+                        cost: json!({}),
+                    });
+                }
+            } else if !tool_calls.is_empty() {
+                let slots = RefCell::new(vec![None; tool_calls.len()]);
+                let permits = Semaphore::new(
+                    self.max_parallel_tools
+                        .unwrap_or(tool_calls.len())
+                        .min(Semaphore::MAX_PERMITS),
+                );
+                let shared: HashMap<&str, SharedBackoff> = self
+                    .retry_groups
+                    .values()
+                    .map(|group| (group.as_str(), SharedBackoff::new(&self.backoff)))
+                    .collect();
+                let mut names = Vec::new();
+                let mut unknown = Vec::new();
+                // a copy, since `state` is still updated while the calls are queued
+                let retries = Cell::new(state.retries.get());
+                let policy = self.retry_policy(&retries);
+                let mut futures = Vec::new();
+                let call_ids: Vec<Value> = tool_calls
+                    .iter()
+                    .map(|tc| tc.get("id").cloned().unwrap_or(Value::Null))
+                    .collect();
+                for tc in tool_calls {
+                    let name = tc["op"].as_str().unwrap_or("");
+                    let input = tc["input"].clone();
+                    let tool = match self.tools.get(name) {
+                        Some(t) => t,
+                        None if self.unknown_tool_policy == UnknownToolPolicy::FeedbackToModel => {
+                            slots.borrow_mut()[names.len()] = Some(Reply {
                                 ok: false,
-                                output: json!({"error": "token budget exceeded"}),
+                                output: self.no_such_tool(name),
                                 latency_ms: 0,
                                 cost: json!({}),
-                            };
+                            });
+                            names.push(name.to_string());
+                            unknown.push(true);
+                            continue;
                         }
-                        remaining -= next_tokens;
-                        continue;
-                    } else {
-                        return Reply {
-                            ok: false,
-                            output: json!({"error": "unknown tool", "tool": name}),
-                            latency_ms: 0,
-                            cost: json!({}),
-                        };
-                    }
-                } else if !tool_calls.is_empty() {
-                    let mut names = Vec::new();
-                    let mut futures = Vec::new();
-                    for tc in tool_calls {
-                        let name = tc["op"].as_str().unwrap_or("");
-                        let input = tc["input"].clone();
-                        let tool = match self.tools.get(name) {
-                            Some(t) => t,
-                            None => {
-                                return Reply {
-                                    ok: false,
-                                    output: json!({"error": "unknown tool", "tool": name}),
-                                    latency_ms: 0,
-                                    cost: json!({}),
-                                };
-                            }
-                        };
-                        let tool_tokens = estimate_tokens(&input);
-                        if tool_tokens > remaining {
-                            return Reply {
+                        None => {
+                            return Some(Reply {
                                 ok: false,
-                                output: json!({"error": "token budget exceeded"}),
+                                output: json!({"error": "unknown tool", "tool": name}),
                                 latency_ms: 0,
                                 cost: json!({}),
-                            };
-                        }
-                        remaining -= tool_tokens;
-                        names.push(name.to_string());
-                        let name_owned = name.to_string();
-                        let input_clone = input.clone();
-                        let tool_ref = tool.as_ref();
-                        let token = self.cancel_token.clone();
-                        let max_r = self.max_retries;
-                        futures.push(async move {
-                            Ok::<Reply, ()>(
-                                call_with_retry(
-                                    move || {
-                                        tool_ref.ask(Ask {
-                                            op: name_owned.clone(),
-                                            input: input_clone.clone(),
-                                            context: json!({}),
-                                        })
-                                    },
-                                    max_r,
-                                    token,
-                                )
-                                .await,
-                            )
-                        });
-                    }
-                    let results = match futures.len() {
-                        2 => {
-                            let (r1, r2) =
-                                tokio::try_join!(futures.remove(0), futures.remove(0)).unwrap();
-                            vec![r1, r2]
-                        }
-                        3 => {
-                            let (r1, r2, r3) = tokio::try_join!(
-                                futures.remove(0),
-                                futures.remove(0),
-                                futures.remove(0)
-                            )
-                            .unwrap();
-                            vec![r1, r2, r3]
-                        }
-                        _ => {
-                            let mut outs = Vec::new();
-                            for f in futures {
-                                outs.push(f.await.unwrap());
-                            }
-                            outs
+                            });
                         }
                     };
-                    if self.cancel_token.is_cancelled() {
-                        return Reply {
-                            ok: false,
-                            output: json!({"error": "cancelled"}),
-                            latency_ms: 0,
-                            cost: json!({}),
-                        };
+                    let input = self.coerce_input(name, input);
+                    let input = match self.approve(name, input).await {
+                        Ok(input) => input,
+                        Err(denied) => return Some(denied),
+                    };
+                    let tool_tokens = estimate_tokens(&input);
+                    if tool_tokens > self.spendable(state) {
+                        return self.finish_early(state, mode, &request_id);
                     }
-                    let mut outputs = Vec::new();
-                    for (name, reply) in names.iter().zip(results.into_iter()) {
-                        if !reply.ok {
-                            return Reply {
-                                ok: false,
-                                output: json!({
-                                    "error": "tool invocation failed",
-                                    "tool": name,
-                                    "detail": reply.output,
-                                }),
-                                latency_ms: reply.latency_ms,
-                                cost: reply.cost,
-                            };
-                        }
-                        let tool_reply_tokens = estimate_tokens(&reply.output);
-                        if tool_reply_tokens > remaining {
-                            return Reply {
-                                ok: false,
-                                output: json!({"error": "token budget exceeded"}),
-                                latency_ms: 0,
-                                cost: json!({}),
-                            };
+                    state.remaining_tokens -= tool_tokens;
+                    let index = names.len();
+                    names.push(name.to_string());
+                    unknown.push(false);
+                    let name_owned = name.to_string();
+                    let input_clone = input.clone();
+                    let mut tool_ctx = tool_context(
+                        mode,
+                        step,
+                        name,
+                        &request_id,
+                        self.spendable(state),
+                        idempotency_key(&request_id, step, index, name, &input),
+                    );
+                    let tool_span = self.tracer.start("agent.tool", Some(&span));
+                    tool_span.set_str("soma.tool", name);
+                    tool_span.inject(&mut tool_ctx);
+                    let tool = tool.clone();
+                    let kind = tool.kind();
+                    let token = run_token.clone();
+                    let max_r = self.attempts(kind);
+                    let policy = &policy;
+                    let slots = &slots;
+                    let permits = &permits;
+                    let shared = self
+                        .retry_groups
+                        .get(name)
+                        .map(|group| &shared[group.as_str()]);
+                    let sink = self.metrics.as_deref();
+                    futures.push(async move {
+                        let Ok(_permit) = permits.acquire().await else {
+                            return;
+                        };
+                        let tool_token = token.clone();
+                        let attempts = Cell::new(0);
+                        let (name, attempts) = (&name_owned, &attempts);
+                        let reply = call_with_retry_async(
+                            move || {
+                                let tool = tool.clone();
+                                let token = tool_token.clone();
+                                let ask = Ask {
+                                    op: name.clone(),
+                                    input: input_clone.clone(),
+                                    context: tool_ctx.clone(),
+                                };
+                                async move {
+                                    let direct = (tool.clone(), token.clone());
+                                    let reply = match direct.0.ask_future(&ask, &direct.1) {
+                                        Some(future) => await_tool(future).await,
+                                        // blocking tools get a blocking-pool thread each,
+                                        // so the step's calls really overlap
+                                        None => tokio::task::spawn_blocking(move || {
+                                            ask_tool(tool.as_ref(), ask, &token, &mut |_| {})
+                                        })
+                                        .await
+                                        .unwrap_or_else(|e| Reply {
+                                            ok: false,
+                                            output: json!({"error": "tool panicked", "detail": e.to_string()}),
+                                            latency_ms: 0,
+                                            cost: json!({}),
+                                        }),
+                                    };
+                                    let mut count = attempts.get();
+                                    metrics::attempt(sink, kind, name, true, &mut count, &reply);
+                                    attempts.set(count);
+                                    reply
+                                }
+                            },
+                            max_r,
+                            policy,
+                            token,
+                            shared,
+                        )
+                        .await;
+                        tool_span.set_bool("soma.ok", reply.ok);
+                        slots.borrow_mut()[index] = Some(reply);
+                    });
+                }
+                // dropping the joined futures on cancellation aborts any tool still waiting
+                tokio::select! {
+                    _ = join_all(futures) => {}
+                    _ = run_token.cancelled() => {}
+                }
+                state.retries.set(retries.get());
+                let results: Vec<Option<Reply>> = slots.take();
+                if run_token.is_cancelled() || expired() {
+                    let mut completed = Vec::new();
+                    for (name, reply) in names.iter().zip(results) {
+                        if let Some(reply) = reply {
+                            state.totals.add(&reply);
+                            if reply.ok {
+                                completed.push(json!({"tool": name, "output": reply.output}));
+                            }
                         }
-                        remaining -= tool_reply_tokens;
-                        outputs.push(reply.output);
                     }
-                    current = Ask {
-                        op: current.op.clone(),
-                        input: Value::Array(outputs),
-                        context: json!({
-                            "reasoning": mode.as_str(),
-                            "tools": names,
-                        }),
-                    };
-                    let next_tokens =
-                        estimate_tokens(&current.input) + estimate_tokens(&current.context);
-                    if next_tokens > remaining {
-                        return Reply {
+                    return Some(state.totals.stopped(Value::Array(completed), expired()));
+                }
+                let results: Vec<Reply> = names
+                    .iter()
+                    .zip(results)
+                    .map(|(name, slot)| {
+                        let reply = slot.unwrap_or_else(|| Reply {
                             ok: false,
-                            output: json!({"error": "token budget exceeded"}),
+                            output: json!({"error": "tool did not complete"}),
                             latency_ms: 0,
                             cost: json!({}),
-                        };
+                        });
+                        self.cap_output(name, self.check_output(name, self.limit_reply(reply)))
+                    })
+                    .collect();
+                for reply in &results {
+                    state.totals.add(reply);
+                }
+                let mut outputs = Vec::new();
+                let aggregate = self.tool_error_policy == ToolErrorPolicy::AggregateErrors;
+                for ((name, reply), unknown) in names.iter().zip(results).zip(unknown) {
+                    if !reply.ok && !aggregate && !unknown {
+                        return Some(Reply {
+                            ok: false,
+                            output: json!({
+                                "error": "tool invocation failed",
+                                "tool": name,
+                                "detail": reply.output,
+                            }),
+                            latency_ms: reply.latency_ms,
+                            cost: reply.cost,
+                        });
+                    }
+                    let tool_reply_tokens = estimate_tokens(&reply.output);
+                    if tool_reply_tokens > self.spendable(state) {
+                        return self.finish_early(state, mode, &request_id);
+                    }
+                    state.remaining_tokens -= tool_reply_tokens;
+                    if aggregate {
+                        outputs.push(json!({
+                            "tool": name,
+                            "ok": reply.ok,
+                            "output": reply.output,
+                        }));
+                    } else {
+                        outputs.push(reply.output);
                     }
-                    remaining -= next_tokens;
-                    continue;
                 }
-            }
-            // propagate failure output into the next ask context
-            current = Ask {
-                op: current.op.clone(),
-                input: reply.output,
-                context: json!({
+                state.partial = Value::Array(outputs.clone());
+                let mut context = json!({
                     "reasoning": mode.as_str(),
-                    "retry": step + 1
-                }),
-            };
-            let next_tokens = estimate_tokens(&current.input) + estimate_tokens(&current.context);
-            if next_tokens > remaining {
+                    "request_id": request_id,
+                    "tools": names,
+                });
+                if call_ids.iter().any(|id| !id.is_null()) {
+                    context["tool_call_ids"] = Value::Array(call_ids);
+                }
+                state.current = Ask {
+                    op: state.current.op.clone(),
+                    input: Value::Array(outputs),
+                    context,
+                };
+                self.summarize(state, run_token);
+                if let Some(reply) = self.charge_next(state) {
+                    return Some(reply);
+                }
+                return None;
+            }
+        }
+        // propagate failure output into the next ask context
+        state.partial = reply.output.clone();
+        state.current = Ask {
+            op: state.current.op.clone(),
+            input: reply.output,
+            context: json!({
+                "reasoning": mode.as_str(),
+                "request_id": request_id,
+                "retry": step + 1
+            }),
+        };
+        self.summarize(state, run_token);
+        self.charge_next(state)
+    }
+}
+
+/// Instruction sent after a length-truncated answer to ask for the rest.
+const CONTINUE_PROMPT: &str = "Continue exactly where you left off, without repeating anything.";
+
+/// Whether the model stopped because it hit its output limit.
+fn truncated(output: &Value) -> bool {
+    output.get("finish_reason").and_then(Value::as_str) == Some("length")
+        || output["choices"][0]["finish_reason"].as_str() == Some("length")
+}
+
+/// The answer text of a reply: a bare string, `content`, or the first choice's
+/// message content.
+fn reply_text(output: &Value) -> Option<&str> {
+    output
+        .as_str()
+        .or_else(|| output.get("content").and_then(Value::as_str))
+        .or_else(|| output["choices"][0]["message"]["content"].as_str())
+}
+
+/// Writes `text` wherever [`reply_text`] found the answer.
+fn set_reply_text(output: &mut Value, text: String) {
+    if output.is_string() {
+        *output = Value::String(text);
+    } else if output.get("content").is_some_and(Value::is_string) {
+        output["content"] = Value::String(text);
+    } else {
+        output["choices"][0]["message"]["content"] = Value::String(text);
+    }
+}
+
+/// The original conversation followed by the partial answer and a request to go on.
+fn continuation_input(input: &Value, so_far: &str) -> Value {
+    let mut messages = match input {
+        Value::Array(messages) => messages.clone(),
+        Value::String(text) => vec![json!({"role": "user", "content": text})],
+        other => vec![json!({"role": "user", "content": other.to_string()})],
+    };
+    messages.push(json!({"role": "assistant", "content": so_far}));
+    messages.push(json!({"role": "user", "content": CONTINUE_PROMPT}));
+    Value::Array(messages)
+}
+
+/// Runs blocking I/O on a scoped thread when called from inside a tokio runtime.
+///
+/// The blocking `reqwest` client panics if used from an async context, which is
+/// exactly where `Agent::run` invokes providers.
+pub(crate) fn off_runtime<T, F>(f: F) -> T
+where
+    T: Send,
+    F: FnOnce() -> T + Send,
+{
+    if tokio::runtime::Handle::try_current().is_err() {
+        return f();
+    }
+    std::thread::scope(|scope| {
+        scope
+            .spawn(f)
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+impl<P: Provider> Drop for Agent<P> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// An agent can be registered as another agent's tool.
+///
+/// The sub-agent runs its own loop on the ask. Its token budget is capped by the
+/// parent's `remaining_tokens` from the tool context, and it stops when either its
+/// own token or the caller's token is cancelled. Inside a tokio runtime this needs
+/// the multi-thread flavor, since the sub-agent's loop is driven with
+/// `block_in_place`; on a current-thread runtime the ask fails instead of deadlocking.
+impl<P: Provider> Provider for Agent<P> {
+    fn kind(&self) -> ProviderKind {
+        self.provider.kind()
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    fn health_check(&self) -> HealthStatus {
+        self.provider.health_check()
+    }
+
+    /// Closes the sub-agent's tools; see [`Agent::close`].
+    fn close(&self) {
+        Agent::close(self)
+    }
+
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        let budget = ask
+            .context
+            .get("remaining_tokens")
+            .and_then(|v| v.as_u64())
+            .map_or(self.max_tokens, |parent| {
+                self.max_tokens.min(parent as usize)
+            });
+        let run = async {
+            tokio::select! {
+                reply = self.run_within(ask, budget) => reply,
+                _ = token.cancelled() => RunTotals::default().cancelled(Value::Null),
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => match handle.runtime_flavor() {
+                tokio::runtime::RuntimeFlavor::CurrentThread => Reply {
+                    ok: false,
+                    output: json!({"error": "sub-agent requires a multi-thread runtime"}),
+                    latency_ms: 0,
+                    cost: json!({}),
+                },
+                _ => tokio::task::block_in_place(|| handle.block_on(run)),
+            },
+            Err(_) => match tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+            {
+                Ok(runtime) => runtime.block_on(run),
+                Err(e) => Reply {
+                    ok: false,
+                    output: json!({"error": e.to_string()}),
+                    latency_ms: 0,
+                    cost: json!({}),
+                },
+            },
+        }
+    }
+}
+
+/// RunPlan is the dry-run view of a run returned by [`Agent::plan`].
+#[derive(Debug, Clone)]
+pub struct RunPlan {
+    pub mode: ReasoningMode,
+    /// Estimated tokens of the incoming ask (input plus context).
+    pub estimated_tokens: usize,
+    /// Budget left for replies once the ask is accounted for.
+    pub remaining_tokens: usize,
+    /// False when the ask alone exceeds `max_tokens`; `run` would fail immediately.
+    pub within_budget: bool,
+    /// Messages dropped from the input by the history limit.
+    pub truncated_messages: usize,
+    /// The first ask `run` would send to the provider.
+    pub first_ask: Ask,
+}
+
+/// RunState is a serializable checkpoint of a run between steps.
+///
+/// Create one with [`Agent::start`], advance it with [`Agent::step_once`] and finish
+/// it with [`Agent::run_from`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunState {
+    step: usize,
+    mode: ReasoningMode,
+    request_id: String,
+    current: Ask,
+    remaining_tokens: usize,
+    tool_calls_made: usize,
+    totals: RunTotals,
+    partial: Value,
+    /// Set once the next ask had to use the final-answer reserve.
+    #[serde(default)]
+    finalizing: bool,
+    #[serde(default)]
+    retries: Cell<usize>,
+    outcome: Option<Reply>,
+}
+
+impl RunState {
+    /// Number of steps taken so far.
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    /// The ask the next step will send to the provider.
+    pub fn current(&self) -> &Ask {
+        &self.current
+    }
+
+    pub fn remaining_tokens(&self) -> usize {
+        self.remaining_tokens
+    }
+
+    /// Retries taken so far, across provider and tool calls.
+    pub fn retries(&self) -> usize {
+        self.retries.get()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.outcome.is_some()
+    }
+
+    /// The final reply once the run is finished.
+    pub fn outcome(&self) -> Option<&Reply> {
+        self.outcome.as_ref()
+    }
+}
+
+/// RunTotals accumulates latency and cost across every call made during a run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RunTotals {
+    latency_ms: u64,
+    cost: Value,
+}
+
+impl Default for RunTotals {
+    fn default() -> Self {
+        Self {
+            latency_ms: 0,
+            cost: json!({}),
+        }
+    }
+}
+
+impl RunTotals {
+    fn add(&mut self, reply: &Reply) {
+        self.latency_ms += reply.latency_ms;
+        merge_cost(&mut self.cost, &reply.cost);
+    }
+
+    /// Reply for a run stopped early, by the deadline when `deadline` is set.
+    fn stopped(&self, partial: Value, deadline: bool) -> Reply {
+        if !deadline {
+            return self.cancelled(partial);
+        }
+        Reply {
+            ok: false,
+            output: json!({"error": "deadline_exceeded", "partial": partial}),
+            latency_ms: self.latency_ms,
+            cost: self.cost.clone(),
+        }
+    }
+
+    fn cancelled(&self, partial: Value) -> Reply {
+        Reply {
+            ok: false,
+            output: json!({"error": "cancelled", "cancelled": true, "partial": partial}),
+            latency_ms: self.latency_ms,
+            cost: self.cost.clone(),
+        }
+    }
+}
+
+/// Calls `provider` the way a decorator was called: with `ask_stream` when there is a
+/// chunk callback, with `ask_cancellable` otherwise.
+pub(crate) fn forward<P: Provider + ?Sized>(
+    provider: &P,
+    ask: Ask,
+    token: &CancellationToken,
+    on_chunk: Option<&mut (dyn FnMut(Value) + '_)>,
+) -> Reply {
+    match on_chunk {
+        Some(on_chunk) => provider.ask_stream(ask, token, on_chunk),
+        None => provider.ask_cancellable(ask, token),
+    }
+}
+
+/// Sums numeric fields of `cost` into `total`, recursing into nested objects.
+pub(crate) fn merge_cost(total: &mut Value, cost: &Value) {
+    let (Some(total), Some(cost)) = (total.as_object_mut(), cost.as_object()) else {
+        return;
+    };
+    for (key, value) in cost {
+        match (total.get_mut(key), value) {
+            (Some(Value::Number(a)), Value::Number(b)) => {
+                let sum = match (a.as_u64(), b.as_u64()) {
+                    (Some(a), Some(b)) => json!(a + b),
+                    _ => json!(a.as_f64().unwrap_or(0.0) + b.as_f64().unwrap_or(0.0)),
+                };
+                total.insert(key.clone(), sum);
+            }
+            (Some(existing @ Value::Object(_)), Value::Object(_)) => merge_cost(existing, value),
+            _ => {
+                total.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Invokes a tool, converting a panic inside the tool into a failed reply.
+fn ask_tool(
+    tool: &dyn Provider,
+    ask: Ask,
+    token: &CancellationToken,
+    on_chunk: &mut dyn FnMut(Value),
+) -> Reply {
+    std::panic::catch_unwind(AssertUnwindSafe(|| tool.ask_stream(ask, token, on_chunk)))
+        .unwrap_or_else(panicked)
+}
+
+/// Awaits a tool's [`Provider::ask_future`], converting a panic into a failed reply.
+async fn await_tool(future: BoxFuture<'_, Reply>) -> Reply {
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .unwrap_or_else(panicked)
+}
+
+/// The failed reply for a tool that panicked with `panic`.
+fn panicked(panic: Box<dyn std::any::Any + Send>) -> Reply {
+    let detail = panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    Reply {
+        ok: false,
+        output: json!({"error": "tool panicked", "detail": detail}),
+        latency_ms: 0,
+        cost: json!({}),
+    }
+}
+
+/// Context handed to a tool so it can see how and when the agent invoked it.
+///
+/// `remaining_tokens` lets nested agents keep their budget within the parent's.
+fn tool_context(
+    mode: ReasoningMode,
+    step: usize,
+    tool: &str,
+    request_id: &str,
+    remaining_tokens: usize,
+    idempotency_key: String,
+) -> Value {
+    json!({
+        "idempotency_key": idempotency_key,
+        "reasoning": mode.as_str(),
+        "remaining_tokens": remaining_tokens,
+        "request_id": request_id,
+        "step": step,
+        "tool": tool,
+    })
+}
+
+/// Identifies one logical tool call: the run, the step, the call's position in the
+/// step and a hash of the tool and its input. It is computed before the first attempt,
+/// so retries reuse it and servers can dedupe side effects.
+fn idempotency_key(
+    request_id: &str,
+    step: usize,
+    call: usize,
+    tool: &str,
+    input: &Value,
+) -> String {
+    let hash = fnv1a(format!("{tool}\0{input}").as_bytes());
+    format!("{request_id}:{step}:{call}:{hash:016x}")
+}
+
+/// FNV-1a, a hash that is the same across processes and builds, unlike `DefaultHasher`.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Drops the oldest messages until `messages` fits in `limit` estimated tokens,
+/// keeping system messages and the latest user message. Returns how many were dropped.
+fn truncate_history(messages: &mut Vec<Value>, limit: usize) -> usize {
+    let role = |m: &Value| m.get("role").and_then(|r| r.as_str()).map(str::to_string);
+    let mut dropped = 0;
+    while estimate_tokens(&Value::Array(messages.clone())) > limit {
+        let latest_user = messages
+            .iter()
+            .rposition(|m| role(m).as_deref() == Some("user"));
+        let oldest = messages
+            .iter()
+            .enumerate()
+            .position(|(i, m)| role(m).as_deref() != Some("system") && Some(i) != latest_user);
+        match oldest {
+            Some(i) => {
+                messages.remove(i);
+                dropped += 1;
+            }
+            None => break,
+        }
+    }
+    dropped
+}
+
+/// Whether `value` serializes to more than `limit` bytes, without building the
+/// string: serialization stops as soon as the limit is passed.
+fn exceeds_bytes(value: &Value, limit: usize) -> bool {
+    struct Budget(usize);
+
+    impl std::io::Write for Budget {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 = self
+                .0
+                .checked_sub(buf.len())
+                .ok_or(std::io::ErrorKind::FileTooLarge)?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    serde_json::to_writer(Budget(limit), value).is_err()
+}
+
+fn estimate_tokens(value: &Value) -> usize {
+    value.to_string().chars().count()
+}
+
+/// Cuts `output` to a string of at most `limit` estimated tokens ending in
+/// [`TRUNCATION_MARKER`].
+fn truncate_output(output: &Value, limit: usize) -> Value {
+    let text = match output {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    };
+    let marker = TRUNCATION_MARKER.chars().count();
+    // every kept char costs at least one token, so dropping the overshoot converges
+    let mut keep = limit.saturating_sub(marker + 2);
+    loop {
+        let mut cut: String = text.chars().take(keep).collect();
+        cut.push_str(TRUNCATION_MARKER);
+        let cut = Value::String(cut);
+        let tokens = estimate_tokens(&cut);
+        if tokens <= limit || keep == 0 {
+            return cut;
+        }
+        keep = keep.saturating_sub(tokens - limit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Mutex;
+    use tokio_util::sync::CancellationToken;
+
+    struct EchoProvider;
+
+    impl Provider for EchoProvider {
+        fn kind(&self) -> ProviderKind {
+            ProviderKind::Embedded
+        }
+
+        fn ask(&self, ask: Ask) -> Reply {
+            Reply {
+                ok: true,
+                output: ask.input,
+                latency_ms: 0,
+                cost: json!({}),
+            }
+        }
+    }
+
+    #[test]
+    fn echo_roundtrip() {
+        let ask = Ask {
+            op: "echo".into(),
+            input: json!({"msg": "hi"}),
+            context: json!({}),
+        };
+        let provider = EchoProvider;
+        let reply = provider.ask(ask);
+        assert!(reply.ok);
+        assert_eq!(reply.output, json!({"msg": "hi"}));
+    }
+
+    fn reply(ok: bool, output: Value) -> Reply {
+        Reply {
+            ok,
+            output,
+            latency_ms: 0,
+            cost: json!({}),
+        }
+    }
+
+    #[test]
+    fn reply_is_tool_call() {
+        let calls = json!({"tool_calls": [{"op": "echo", "input": "hi"}]});
+        assert!(reply(false, calls).is_tool_call());
+        assert!(!reply(true, json!("done")).is_tool_call());
+        assert!(!reply(false, json!({"tool_calls": null})).is_tool_call());
+    }
+
+    #[test]
+    fn reply_tool_calls_are_typed() {
+        let calls = reply(
+            false,
+            json!({"tool_calls": [
+                {"op": "echo", "input": {"msg": "hi"}, "id": "call_1"},
+                {"op": "now"},
+            ]}),
+        );
+        assert_eq!(
+            calls.tool_calls(),
+            Some(vec![
+                ToolCall {
+                    op: "echo".into(),
+                    input: json!({"msg": "hi"}),
+                    id: Some("call_1".into()),
+                },
+                ToolCall {
+                    op: "now".into(),
+                    input: Value::Null,
+                    id: None,
+                },
+            ])
+        );
+        assert_eq!(reply(true, json!("done")).tool_calls(), None);
+        assert_eq!(
+            reply(false, json!({"tool_calls": [{"input": 1}]})).tool_calls(),
+            None
+        );
+    }
+
+    #[test]
+    fn reply_error_message() {
+        let failed = reply(false, json!({"error": "token budget exceeded"}));
+        assert_eq!(failed.error_message(), Some("token budget exceeded"));
+        assert_eq!(reply(false, json!("boom")).error_message(), None);
+        assert_eq!(reply(true, json!({"error": "none"})).error_message(), None);
+    }
+
+    #[test]
+    fn reply_is_cancelled() {
+        assert!(reply(false, json!({"error": "cancelled"})).is_cancelled());
+        let run = json!({"error": "cancelled", "cancelled": true, "partial": null});
+        assert!(reply(false, run).is_cancelled());
+        assert!(!reply(false, json!({"error": "deadline_exceeded"})).is_cancelled());
+        assert!(!reply(true, json!("cancelled")).is_cancelled());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn agent_runs_until_ok() {
+        let ask = Ask {
+            op: "echo".into(),
+            input: json!({"msg": "hi"}),
+            context: json!({}),
+        };
+        let agent = Agent::new(EchoProvider, 3, 1000, 3, CancellationToken::new());
+        let reply = agent.run(ask).await;
+        assert!(reply.ok);
+        assert_eq!(reply.output, json!({"msg": "hi"}));
+    }
+
+    struct FailProvider;
+
+    impl Provider for FailProvider {
+        fn kind(&self) -> ProviderKind {
+            ProviderKind::Embedded
+        }
+
+        fn ask(&self, _ask: Ask) -> Reply {
+            Reply {
+                ok: false,
+                output: json!({"error": "fail"}),
+                latency_ms: 0,
+                cost: json!({}),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn agent_respects_step_limit() {
+        let ask = Ask {
+            op: "fail".into(),
+            input: json!({}),
+            context: json!({}),
+        };
+        let agent = Agent::new(FailProvider, 2, 1000, 3, CancellationToken::new());
+        let reply = agent.run(ask).await;
+        assert!(!reply.ok);
+        assert_eq!(reply.output, json!({"error": "step limit exceeded"}));
+    }
+
+    #[test]
+    fn reasoning_policy_scores_inputs() {
+        let policy = ReasoningPolicy::default();
+        assert_eq!(policy.decide(&json!("short"), 0), ReasoningMode::Direct);
+        let long = "x".repeat(300);
+        assert_eq!(policy.decide(&json!(long), 0), ReasoningMode::Reasoned);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn retry_predicate_stops_on_fatal_errors() {
+        use crate::testing::MockProvider;
+        let provider = Arc::new(MockProvider::new());
+        provider
+            .push_failure("bad request")
+            .push_failure("bad request");
+        let agent = Agent::new(provider.clone(), 1, 10_000, 3, CancellationToken::new())
+            .with_retry_if(|reply| reply.output["error"] != "bad request");
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("hi"),
+                context: json!({}),
+            })
+            .await;
+        assert!(!reply.ok);
+        assert_eq!(provider.call_count(), 1);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn retries_stop_when_elapsed_budget_is_spent() {
+        use crate::testing::{ManualClock, MockProvider};
+        let provider = Arc::new(MockProvider::new());
+        for _ in 0..10 {
+            provider.push_failure("busy");
+        }
+        let clock = ManualClock::new();
+        let agent = Agent::new(provider.clone(), 1, 10_000, 10, CancellationToken::new())
+            .with_backoff(Backoff {
+                initial: Duration::from_millis(40),
+                max_elapsed: Some(Duration::from_millis(100)),
+                ..Backoff::default()
+            })
+            .with_clock(clock.clone());
+        let run = agent.run(Ask {
+            op: "chat".into(),
+            input: json!("hi"),
+            context: json!({}),
+        });
+        let (reply, ()) = tokio::join!(run, async {
+            while provider.call_count() < 1 || clock.sleepers() == 0 {
+                tokio::task::yield_now().await;
+            }
+            clock.advance(Duration::from_millis(40));
+        });
+        assert!(!reply.ok);
+        // 40ms after the first failure, then the 80ms delay would overrun the budget
+        assert_eq!(provider.call_count(), 2);
+        assert_eq!(clock.elapsed(), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn backoff_caps_and_jitters_delays() {
+        let backoff = Backoff {
+            max_delay: Duration::from_millis(100),
+            jitter: true,
+            ..Backoff::default()
+        };
+        let delays: Vec<Duration> = (0..20)
+            .map(|_| backoff.delay(Duration::from_secs(1)))
+            .collect();
+        for delay in &delays {
+            assert!(*delay >= Duration::from_millis(50) && *delay <= Duration::from_millis(100));
+        }
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+    }
+
+    #[test]
+    fn lists_and_removes_tools() {
+        let mut agent = Agent::new(EchoProvider, 1, 1000, 1, CancellationToken::new());
+        agent.register_tool("b", EchoProvider).unwrap();
+        agent.register_tool("a", EchoProvider).unwrap();
+        assert_eq!(agent.tool_names(), vec!["a", "b"]);
+        assert!(agent.tool_schema("a").is_none());
+
+        assert!(agent.remove_tool("a"));
+        assert!(!agent.remove_tool("a"));
+        assert_eq!(agent.tool_names(), vec!["b"]);
+        assert!(!agent.has_tool("a"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sub_agent_runs_as_a_tool() {
+        use crate::testing::MockProvider;
+        let child_provider = Arc::new(MockProvider::new());
+        child_provider.push_ok(json!("from child"));
+        let child = Agent::new(
+            child_provider.clone(),
+            2,
+            5_000,
+            1,
+            CancellationToken::new(),
+        );
+
+        let parent_provider = Arc::new(MockProvider::new());
+        parent_provider.push_reply(Reply {
+            ok: false,
+            output: json!({"tool_calls": [{"op": "child", "input": "delegate"}]}),
+            latency_ms: 0,
+            cost: json!({}),
+        });
+        parent_provider.push_ok(json!("done"));
+        let mut parent = Agent::new(
+            parent_provider.clone(),
+            3,
+            1_000,
+            1,
+            CancellationToken::new(),
+        );
+        parent.register_tool("child", child).unwrap();
+
+        let reply = parent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("hi"),
+                context: json!({"request_id": "r-1"}),
+            })
+            .await;
+        assert!(reply.ok);
+        assert_eq!(reply.output, json!("done"));
+
+        let child_asks = child_provider.asks();
+        assert_eq!(child_asks.len(), 1);
+        assert_eq!(child_asks[0].input, json!("delegate"));
+        assert_eq!(child_asks[0].context["request_id"], "r-1");
+        assert_eq!(parent_provider.asks()[1].input, json!("from child"));
+    }
+
+    #[test]
+    fn sub_agent_budget_is_capped_by_parent() {
+        use crate::testing::MockProvider;
+        let provider = Arc::new(MockProvider::new());
+        provider.push_ok(json!("unused"));
+        let child = Agent::new(provider.clone(), 1, 5_000, 1, CancellationToken::new());
+        let reply = child.ask(Ask {
+            op: "child".into(),
+            input: json!("x".repeat(100)),
+            context: json!({"remaining_tokens": 50}),
+        });
+        assert_eq!(reply.output["error"], "token budget exceeded");
+        assert_eq!(provider.call_count(), 0);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn slow_provider_hits_run_deadline() {
+        use crate::providers::FnProvider;
+        use crate::testing::{ManualClock, MockProvider};
+        let provider = Arc::new(MockProvider::new());
+        for _ in 0..5 {
+            provider.push_failure("busy");
+        }
+        let clock = ManualClock::new();
+        // every call takes 80ms of clock time
+        let slow = FnProvider::new({
+            let (provider, clock) = (provider.clone(), clock.clone());
+            move |ask| {
+                clock.advance(Duration::from_millis(80));
+                provider.ask(ask)
+            }
+        });
+        let token = CancellationToken::new();
+        let agent = Agent::new(slow, 5, 10_000, 1, token.clone())
+            .with_timeout(Duration::from_millis(100))
+            .with_clock(clock.clone());
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("hi"),
+                context: json!({}),
+            })
+            .await;
+        assert_eq!(reply.output["error"], "deadline_exceeded");
+        assert_eq!(reply.output["partial"]["error"], "busy");
+        assert_eq!(provider.call_count(), 2);
+        assert_eq!(clock.elapsed(), Duration::from_millis(160));
+        assert!(!token.is_cancelled());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn resumed_run_matches_uninterrupted_run() {
+        use crate::testing::MockProvider;
+        fn scripted() -> Arc<MockProvider> {
+            let provider = Arc::new(MockProvider::new());
+            provider.push_reply(Reply {
+                ok: false,
+                output: json!({"tool_calls": [{"op": "echo", "input": "ping"}]}),
+                latency_ms: 3,
+                cost: json!({"total_tokens": 5}),
+            });
+            provider.push_ok(json!("done"));
+            provider
+        }
+        let ask = Ask {
+            op: "chat".into(),
+            input: json!("hi"),
+            context: json!({"request_id": "r-1"}),
+        };
+
+        let mut straight = Agent::new(scripted(), 3, 10_000, 1, CancellationToken::new());
+        straight.register_tool("echo", EchoProvider).unwrap();
+        let expected = straight.run(ask.clone()).await;
+
+        let provider = scripted();
+        let mut agent = Agent::new(provider.clone(), 3, 10_000, 1, CancellationToken::new());
+        agent.register_tool("echo", EchoProvider).unwrap();
+        let state = agent.step_once(agent.start(ask)).await;
+        assert_eq!(state.step(), 1);
+        assert!(!state.is_finished());
+        assert_eq!(state.current().input, json!("ping"));
+
+        let saved = serde_json::to_string(&state).unwrap();
+        let restored: RunState = serde_json::from_str(&saved).unwrap();
+        let reply = agent.run_from(restored).await;
+        assert!(reply.ok);
+        assert_eq!(reply.output, expected.output);
+        assert_eq!(
+            provider.asks()[1].context,
+            json!({
+                "reasoning": "direct",
+                "request_id": "r-1",
+                "tool": "echo",
+            })
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn approval_hook_denies_and_rewrites_tool_calls() {
+        use crate::testing::MockProvider;
+        let provider = Arc::new(MockProvider::new());
+        provider.push_reply(Reply {
+            ok: false,
+            output: json!({"tool_calls": [
+                {"op": "echo", "input": "original"},
+                {"op": "delete", "input": "/"}
+            ]}),
+            latency_ms: 0,
+            cost: json!({}),
+        });
+        let delete = Arc::new(MockProvider::new());
+        let mut agent = Agent::new(provider.clone(), 3, 10_000, 1, CancellationToken::new())
+            .require_approval("delete", |_: &str, _: &Value| {
+                ApprovalDecision::Deny("destructive".into())
+            });
+        agent.register_tool("echo", EchoProvider).unwrap();
+        agent.register_tool("delete", delete.clone()).unwrap();
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("hi"),
+                context: json!({}),
+            })
+            .await;
+        assert_eq!(
+            reply.output,
+            json!({"error": "tool_denied", "tool": "delete", "reason": "destructive"})
+        );
+        assert_eq!(delete.call_count(), 0);
+
+        provider.push_reply(Reply {
+            ok: false,
+            output: json!({"tool_calls": [{"op": "echo", "input": "original"}]}),
+            latency_ms: 0,
+            cost: json!({}),
+        });
+        provider.push_ok(json!("done"));
+        let agent = agent.require_approval("echo", |_: &str, _: &Value| {
+            ApprovalDecision::Modify(json!("rewritten"))
+        });
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("hi"),
+                context: json!({}),
+            })
+            .await;
+        assert!(reply.ok);
+        assert_eq!(provider.asks()[2].input, json!("rewritten"));
+    }
+
+    #[test]
+    fn history_limit_drops_oldest_turns_but_keeps_system() {
+        let agent =
+            Agent::new(EchoProvider, 1, 1_000, 1, CancellationToken::new()).with_history_limit(0.3);
+        let turn = |role: &str, n: usize| json!({"role": role, "content": "x".repeat(60) + &n.to_string()});
+        let history = json!([
+            {"role": "system", "content": "be brief"},
+            turn("user", 1),
+            turn("assistant", 2),
+            turn("user", 3),
+            turn("assistant", 4),
+            turn("user", 5),
+        ]);
+        let plan = agent.plan(&Ask {
+            op: "chat".into(),
+            input: history.clone(),
+            context: json!({}),
+        });
+        let kept = plan.first_ask.input.as_array().unwrap();
+        assert!(plan.truncated_messages > 0);
+        assert!(plan.within_budget);
+        assert_eq!(kept[0], history[0]);
+        assert_eq!(kept.last().unwrap(), &history[5]);
+        assert_eq!(kept.len(), 6 - plan.truncated_messages);
+        assert!(!kept.contains(&history[1]));
+        assert!(estimate_tokens(&plan.first_ask.input) <= 300);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn summarizer_shrinks_carried_context_past_threshold() {
+        use crate::testing::MockProvider;
+        let provider = Arc::new(MockProvider::new());
+        provider.push_reply(Reply {
+            ok: false,
+            output: json!({"tool_calls": [{"op": "echo", "input": "y".repeat(400)}]}),
+            latency_ms: 0,
+            cost: json!({}),
+        });
+        provider.push_ok(json!("done"));
+        let summarizer = Arc::new(MockProvider::new());
+        summarizer.push_reply(Reply {
+            ok: true,
+            output: json!("short summary"),
+            latency_ms: 0,
+            cost: json!({"total_tokens": 7}),
+        });
+        let mut agent = Agent::new(provider.clone(), 3, 10_000, 1, CancellationToken::new())
+            .with_summarizer(100, summarizer.clone());
+        agent.register_tool("echo", EchoProvider).unwrap();
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("hi"),
+                context: json!({}),
+            })
+            .await;
+        assert!(reply.ok);
+
+        let seen = summarizer.asks();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].op, "summarize");
+        assert_eq!(seen[0].context["target_tokens"], 100);
+        let second = &provider.asks()[1];
+        assert_eq!(second.input, json!("short summary"));
+        assert_eq!(second.context["summarized"], true);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn tool_output_violating_schema_is_rejected() {
+        use crate::testing::MockProvider;
+        let provider = Arc::new(MockProvider::new());
+        provider.push_reply(Reply {
+            ok: false,
+            output: json!({"tool_calls": [{"op": "weather", "input": {}}]}),
+            latency_ms: 0,
+            cost: json!({}),
+        });
+        let weather = MockProvider::new();
+        weather.push_ok(json!({"temp": "warm"}));
+        let mut agent = Agent::new(provider.clone(), 3, 10_000, 1, CancellationToken::new())
+            .with_output_schema(
+                "weather",
+                json!({"type": "object", "properties": {"temp": {"type": "number"}}}),
+            );
+        agent.register_tool("weather", weather).unwrap();
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("hi"),
+                context: json!({}),
+            })
+            .await;
+        assert!(!reply.ok);
+        assert_eq!(reply.output["error"], "tool invocation failed");
+        assert_eq!(reply.output["tool"], "weather");
+        assert_eq!(
+            reply.output["detail"],
+            json!({"error": "invalid tool output", "detail": "$.temp: expected number"})
+        );
+        assert_eq!(provider.call_count(), 1);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn tool_retries_reuse_idempotency_key() {
+        use crate::testing::MockProvider;
+
+        let provider = MockProvider::new();
+        provider
+            .push_reply(Reply {
+                ok: false,
+                output: json!({"tool_calls": [
+                    {"op": "pay", "input": {"amount": 5}},
+                    {"op": "pay", "input": {"amount": 5}}
+                ]}),
+                latency_ms: 0,
+                cost: json!({}),
+            })
+            .push_ok(json!("done"));
+        let tool = Arc::new(MockProvider::new());
+        tool.push_failure("gateway timeout")
+            .push_ok(json!("paid"))
+            .push_ok(json!("paid"));
+        let mut agent =
+            Agent::new(provider, 3, 1000, 2, CancellationToken::new()).with_backoff(Backoff {
+                initial: Duration::from_millis(1),
+                ..Backoff::default()
+            });
+        agent.register_tool("pay", tool.clone()).unwrap();
+
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("pay twice"),
+                context: json!({}),
+            })
+            .await;
+
+        assert!(reply.ok, "{:?}", reply.output);
+        let keys: Vec<Value> = tool
+            .asks()
+            .iter()
+            .map(|ask| ask.context["idempotency_key"].clone())
+            .collect();
+        // first call fails, second call succeeds while the first backs off, then the retry
+        assert_eq!(keys.len(), 3);
+        assert!(keys[0].is_string());
+        assert_eq!(keys[0], keys[2]);
+        // identical inputs, but a different call in the step
+        assert_ne!(keys[0], keys[1]);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn malformed_tool_input_yields_bad_input_reply() {
+        use crate::testing::MockProvider;
+
+        #[derive(Deserialize)]
+        struct AddArgs {
+            a: i64,
+            b: i64,
+        }
+
+        struct Adder;
+
+        impl Provider for Adder {
+            fn kind(&self) -> ProviderKind {
+                ProviderKind::Embedded
+            }
+
+            fn ask(&self, ask: Ask) -> Reply {
+                match ask.parse_input::<AddArgs>() {
+                    Ok(args) => Reply {
+                        ok: true,
+                        output: json!(args.a + args.b),
+                        latency_ms: 0,
+                        cost: json!({}),
+                    },
+                    Err(e) => Reply::bad_input(e),
+                }
+            }
+        }
+
+        let good = Adder.ask(Ask {
+            op: "add".into(),
+            input: json!({"a": 2, "b": 3}),
+            context: json!({}),
+        });
+        assert_eq!(good.output, json!(5));
+
+        let provider = MockProvider::new();
+        provider.push_reply(Reply {
+            ok: false,
+            output: json!({"tool_calls": [{"op": "add", "input": {"a": "two"}}]}),
+            latency_ms: 0,
+            cost: json!({}),
+        });
+        let mut agent = Agent::new(provider, 2, 1000, 1, CancellationToken::new());
+        agent.register_tool("add", Adder).unwrap();
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("add"),
+                context: json!({}),
+            })
+            .await;
+
+        assert!(!reply.ok);
+        assert_eq!(reply.output["error"], "tool invocation failed");
+        assert_eq!(reply.output["detail"]["error"], "bad input");
+        assert!(reply.output["detail"]["detail"]
+            .as_str()
+            .unwrap()
+            .contains("expected i64"));
+    }
+
+    /// Holds each call for a while, tracking how many run at the same time.
+    struct SlowCounter {
+        active: AtomicUsize,
+        peak: Arc<AtomicUsize>,
+    }
+
+    impl Provider for SlowCounter {
+        fn kind(&self) -> ProviderKind {
+            ProviderKind::Embedded
+        }
+
+        fn ask(&self, _ask: Ask) -> Reply {
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(active, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(30));
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            Reply {
+                ok: true,
+                output: json!("done"),
+                latency_ms: 0,
+                cost: json!({}),
+            }
+        }
+    }
+
+    async fn peak_parallel_tools(limit: Option<usize>) -> usize {
+        use crate::testing::MockProvider;
+
+        let calls: Vec<Value> = (0..6)
+            .map(|i| json!({"op": "work", "input": {"job": i}}))
+            .collect();
+        let provider = MockProvider::new();
+        provider
+            .push_reply(Reply {
+                ok: false,
+                output: json!({"tool_calls": calls}),
+                latency_ms: 0,
+                cost: json!({}),
+            })
+            .push_ok(json!("finished"));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let mut agent = Agent::new(provider, 3, 10_000, 1, CancellationToken::new());
+        if let Some(limit) = limit {
+            agent = agent.with_max_parallel_tools(limit);
+        }
+        agent
+            .register_tool(
+                "work",
+                SlowCounter {
+                    active: AtomicUsize::new(0),
+                    peak: peak.clone(),
+                },
+            )
+            .unwrap();
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("go"),
+                context: json!({}),
+            })
+            .await;
+        assert!(reply.ok, "{:?}", reply.output);
+        peak.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn max_parallel_tools_bounds_in_flight_calls() {
+        assert!(peak_parallel_tools(None).await > 2);
+        let peak = peak_parallel_tools(Some(2)).await;
+        assert!((1..=2).contains(&peak), "{peak} calls overlapped");
+    }
+
+    struct Down(Arc<Mutex<Vec<Instant>>>);
+
+    impl Provider for Down {
+        fn kind(&self) -> ProviderKind {
+            ProviderKind::Embedded
+        }
+
+        fn ask(&self, _ask: Ask) -> Reply {
+            self.0.lock().unwrap().push(Instant::now());
+            Reply {
+                ok: false,
+                output: json!({"error": "backend down"}),
+                latency_ms: 0,
+                cost: json!({}),
+            }
+        }
+    }
+
+    /// Gaps between consecutive retries of two parallel calls to a failing backend.
+    async fn retry_gaps(grouped: bool) -> Vec<Duration> {
+        use crate::testing::MockProvider;
+
+        let provider = MockProvider::new();
+        provider.push_reply(Reply {
+            ok: false,
+            output: json!({"tool_calls": [
+                {"op": "inventory", "input": {}},
+                {"op": "pricing", "input": {}},
+            ]}),
+            latency_ms: 0,
+            cost: json!({}),
+        });
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let mut agent =
+            Agent::new(provider, 2, 10_000, 3, CancellationToken::new()).with_backoff(Backoff {
+                initial: Duration::from_millis(20),
+                ..Backoff::default()
+            });
+        if grouped {
+            agent = agent.with_retry_group("warehouse", &["inventory", "pricing"]);
+        }
+        agent
+            .register_tool("inventory", Down(attempts.clone()))
+            .unwrap();
+        agent
+            .register_tool("pricing", Down(attempts.clone()))
+            .unwrap();
+        agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("go"),
+                context: json!({}),
+            })
+            .await;
+        let mut retries: Vec<Instant> = attempts.lock().unwrap()[2..].to_vec();
+        assert_eq!(retries.len(), 4);
+        retries.sort();
+        retries.windows(2).map(|w| w[1] - w[0]).collect()
+    }
+
+    // Tools run on the blocking pool, so the recorded times carry some scheduling
+    // noise; grouped retries are at least 40ms apart and independent ones coincide.
+    #[tokio::test(flavor = "current_thread")]
+    async fn retry_group_staggers_parallel_retries() {
+        let independent = retry_gaps(false).await;
+        assert!(
+            independent
+                .iter()
+                .any(|gap| *gap < Duration::from_millis(20)),
+            "{independent:?}"
+        );
+        let grouped = retry_gaps(true).await;
+        assert!(
+            grouped.iter().all(|gap| *gap >= Duration::from_millis(20)),
+            "{grouped:?}"
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn unknown_tool_is_fed_back_to_model() {
+        use crate::testing::MockProvider;
+
+        let tool_call = |op: &str| Reply {
+            ok: false,
+            output: json!({"tool_calls": [{"op": op, "input": {"q": "rust"}}]}),
+            latency_ms: 0,
+            cost: json!({}),
+        };
+        let model = Arc::new(MockProvider::new());
+        model
+            .push_reply(tool_call("serch"))
+            .push_reply(tool_call("search"))
+            .push_ok(json!("answer"));
+        let search = MockProvider::new();
+        search.push_ok(json!(["result"]));
+        let mut agent = Agent::new(model.clone(), 4, 10_000, 1, CancellationToken::new())
+            .with_unknown_tool_policy(UnknownToolPolicy::FeedbackToModel);
+        agent.register_tool("search", search).unwrap();
+
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("find rust"),
+                context: json!({}),
+            })
+            .await;
+
+        assert!(reply.ok, "{:?}", reply.output);
+        assert_eq!(reply.output, json!("answer"));
+        let asks = model.asks();
+        assert_eq!(
+            asks[1].input,
+            json!({"error": "no such tool", "tool": "serch", "available": ["search"]})
+        );
+        assert_eq!(asks[2].input, json!(["result"]));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn unknown_parallel_tool_is_fed_back_alongside_others() {
+        use crate::testing::MockProvider;
+
+        let model = Arc::new(MockProvider::new());
+        model
+            .push_reply(Reply {
+                ok: false,
+                output: json!({"tool_calls": [
+                    {"op": "search", "input": {}},
+                    {"op": "missing", "input": {}}
+                ]}),
+                latency_ms: 0,
+                cost: json!({}),
+            })
+            .push_ok(json!("answer"));
+        let search = MockProvider::new();
+        search.push_ok(json!("hit"));
+        let mut agent = Agent::new(model.clone(), 3, 10_000, 1, CancellationToken::new())
+            .with_unknown_tool_policy(UnknownToolPolicy::FeedbackToModel);
+        agent.register_tool("search", search).unwrap();
+
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("go"),
+                context: json!({}),
+            })
+            .await;
+
+        assert!(reply.ok, "{:?}", reply.output);
+        let fed = &model.asks()[1].input;
+        assert_eq!(fed[0], "hit");
+        assert_eq!(fed[1]["error"], "no such tool");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn oversized_provider_reply_is_rejected() {
+        use crate::testing::MockProvider;
+
+        let model = MockProvider::new();
+        model.push_ok(json!("x".repeat(2_000)));
+        let agent = Agent::new(model, 2, 1_000_000, 1, CancellationToken::new())
+            .with_max_reply_bytes(1_024);
+
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("hi"),
+                context: json!({}),
+            })
+            .await;
+
+        assert!(!reply.ok);
+        assert_eq!(reply.output, json!({"error": "response_too_large"}));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn oversized_tool_reply_fails_the_call() {
+        use crate::testing::MockProvider;
+
+        let model = MockProvider::new();
+        model.push_reply(Reply {
+            ok: false,
+            output: json!({"tool_calls": [{"op": "dump", "input": {}}]}),
+            latency_ms: 0,
+            cost: json!({}),
+        });
+        let dump = MockProvider::new();
+        dump.push_ok(json!(vec![0; 1_000]));
+        let mut agent = Agent::new(model, 3, 1_000_000, 1, CancellationToken::new())
+            .with_max_reply_bytes(1_024);
+        agent.register_tool("dump", dump).unwrap();
+
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("hi"),
+                context: json!({}),
+            })
+            .await;
+
+        assert!(!reply.ok);
+        assert_eq!(reply.output["tool"], "dump");
+        assert_eq!(
+            reply.output["detail"],
+            json!({"error": "response_too_large"})
+        );
+    }
+
+    #[test]
+    fn exceeds_bytes_matches_serialized_length() {
+        let value = json!({"a": [1, 2, 3]});
+        let len = value.to_string().len();
+        assert!(!exceeds_bytes(&value, len));
+        assert!(exceeds_bytes(&value, len - 1));
+    }
+
+    fn candidates_reply() -> Reply {
+        Reply {
+            ok: true,
+            output: json!({
+                "choices": [
+                    {"message": {"content": "short"}},
+                    {"message": {"content": "the longest answer", "reasoning_content": "why"}},
+                    {"message": {"content": "medium one"}},
+                ],
+                "candidates": [
+                    {"content": "short"},
+                    {"content": "the longest answer", "reasoning_content": "why"},
+                    {"content": "medium one"},
+                ],
+                "content": "short",
+            }),
+            latency_ms: 0,
+            cost: json!({}),
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn selector_collapses_candidates_onto_longest() {
+        use crate::select::Longest;
+        use crate::testing::MockProvider;
+
+        let ask = Ask {
+            op: "chat".into(),
+            input: json!("hi"),
+            context: json!({}),
+        };
+        let model = MockProvider::new();
+        model.push_reply(candidates_reply());
+        let agent = Agent::new(model, 1, 10_000, 1, CancellationToken::new())
+            .with_candidate_selector(Longest);
+        let reply = agent.run(ask.clone()).await;
+        assert_eq!(reply.output["content"], "the longest answer");
+        assert_eq!(reply.output["reasoning_content"], "why");
+        assert_eq!(
+            reply.output["choices"][0]["message"]["content"],
+            "the longest answer"
+        );
+
+        let model = MockProvider::new();
+        model.push_reply(candidates_reply());
+        let agent = Agent::new(model, 1, 10_000, 1, CancellationToken::new());
+        let reply = agent.run(ask).await;
+        assert_eq!(reply.output["content"], "short");
+    }
+
+    /// Emits its parts one chunk at a time, stopping early once cancelled.
+    struct Chunked {
+        parts: Vec<&'static str>,
+        emitted: Arc<AtomicUsize>,
+    }
+
+    impl Provider for Chunked {
+        fn kind(&self) -> ProviderKind {
+            ProviderKind::Embedded
+        }
+
+        fn ask(&self, ask: Ask) -> Reply {
+            self.ask_stream(ask, &CancellationToken::new(), &mut |_| {})
+        }
+
+        fn ask_stream(
+            &self,
+            _ask: Ask,
+            token: &CancellationToken,
+            on_chunk: &mut dyn FnMut(Value),
+        ) -> Reply {
+            for part in &self.parts {
+                if token.is_cancelled() {
+                    return Reply {
+                        ok: false,
+                        output: json!({"error": "cancelled"}),
+                        latency_ms: 0,
+                        cost: json!({}),
+                    };
+                }
+                self.emitted.fetch_add(1, Ordering::SeqCst);
+                on_chunk(json!(part));
+            }
+            Reply {
+                ok: true,
+                output: json!(self.parts),
+                latency_ms: 0,
+                cost: json!({}),
+            }
+        }
+    }
+
+    fn chunked_agent(
+        model: Arc<crate::testing::MockProvider>,
+        emitted: Arc<AtomicUsize>,
+    ) -> Agent<Arc<crate::testing::MockProvider>> {
+        let mut agent =
+            Agent::new(model, 3, 10_000, 1, CancellationToken::new()).with_tool_streaming(true);
+        agent
+            .register_tool(
+                "crawl",
+                Chunked {
+                    parts: vec!["page 1", "page 2", "page 3"],
+                    emitted,
+                },
+            )
+            .unwrap();
+        agent
+    }
+
+    fn crawl_call() -> Reply {
+        Reply {
+            ok: false,
+            output: json!({"tool_calls": [{"op": "crawl", "input": {}}]}),
+            latency_ms: 0,
+            cost: json!({}),
+        }
+    }
+
+    #[test]
+    fn streamed_chunks_pass_through_decorators() {
+        use crate::middleware::Layered;
+        use crate::providers::{
+            BalanceStrategy, CachingProvider, ContextDefaults, CostRouter, FallbackProvider,
+            LoadBalancer, RateLimiter, SingleFlightProvider,
+        };
+        use crate::testing::SequenceRecorder;
+
+        let chunked = || Chunked {
+            parts: vec!["a", "b"],
+            emitted: Arc::new(AtomicUsize::new(0)),
+        };
+        let wrapped: Vec<Box<dyn Provider>> = vec![
+            Box::new(RateLimiter::new(chunked(), 1_000.0)),
+            Box::new(CachingProvider::new(chunked(), 4, Duration::from_secs(60))),
+            Box::new(SingleFlightProvider::new(chunked())),
+            Box::new(ContextDefaults::new(
+                chunked(),
+                json!({"dialect": "openai"}),
+            )),
+            Box::new(FallbackProvider::new(vec![Box::new(chunked())])),
+            Box::new(LoadBalancer::new(
+                vec![Box::new(chunked())],
+                BalanceStrategy::RoundRobin,
+            )),
+            Box::new(CostRouter::new(ReasoningPolicy::default()).route(
+                "only",
+                1.0,
+                100,
+                chunked(),
+            )),
+            Box::new(Layered::new(chunked())),
+            Box::new(SequenceRecorder::new().record(chunked())),
+        ];
+        for provider in wrapped {
+            let mut chunks = Vec::new();
+            let reply = provider.ask_stream(
+                Ask {
+                    op: "crawl".into(),
+                    input: json!({}),
+                    context: json!({}),
+                },
+                &CancellationToken::new(),
+                &mut |chunk| chunks.push(chunk),
+            );
+            assert!(reply.ok);
+            assert_eq!(chunks, vec![json!("a"), json!("b")]);
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn streamed_tool_chunks_reach_the_model() {
+        use crate::testing::MockProvider;
+
+        let model = Arc::new(MockProvider::new());
+        model
+            .push_reply(crawl_call())
+            .push_failure("keep going")
+            .push_failure("keep going")
+            .push_failure("keep going")
+            .push_ok(json!("summary"));
+        let emitted = Arc::new(AtomicUsize::new(0));
+        let agent = chunked_agent(model.clone(), emitted.clone());
+
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("crawl"),
+                context: json!({}),
+            })
+            .await;
+
+        assert!(reply.ok, "{:?}", reply.output);
+        assert_eq!(reply.output, json!("summary"));
+        let asks = model.asks();
+        assert_eq!(asks.len(), 5);
+        for (i, part) in ["page 1", "page 2", "page 3"].iter().enumerate() {
+            assert_eq!(asks[i + 1].input, json!(part));
+            assert_eq!(asks[i + 1].context["partial"], true);
+            assert_eq!(asks[i + 1].context["chunk"], i);
+        }
+        assert_eq!(asks[4].input, json!(["page 1", "page 2", "page 3"]));
+        assert_eq!(emitted.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn model_answer_on_partial_output_stops_the_tool() {
+        use crate::testing::MockProvider;
+
+        let model = Arc::new(MockProvider::new());
+        model
+            .push_reply(crawl_call())
+            .push_failure("keep going")
+            .push_ok(json!("seen enough"));
+        let emitted = Arc::new(AtomicUsize::new(0));
+        let agent = chunked_agent(model.clone(), emitted.clone());
+
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("crawl"),
+                context: json!({}),
+            })
+            .await;
+
+        assert!(reply.ok, "{:?}", reply.output);
+        assert_eq!(reply.output, json!("seen enough"));
+        assert_eq!(model.call_count(), 3);
+        assert_eq!(emitted.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn reserve_is_left_for_the_final_answer() {
+        use crate::providers::func::FnProvider;
+        let model = FnProvider::new(|ask: Ask| {
+            if ask.context["final"] == json!(true) {
+                return Reply {
+                    ok: true,
+                    output: json!("z".repeat(200)),
+                    latency_ms: 0,
+                    cost: json!({}),
+                };
+            }
+            Reply {
+                ok: false,
+                output: json!({"tool_calls": [{"op": "echo", "input": "y".repeat(50)}]}),
+                latency_ms: 0,
+                cost: json!({}),
+            }
+        });
+        let reserve = 400;
+        let mut agent =
+            Agent::new(model, 20, 1_500, 1, CancellationToken::new()).with_reserve_tokens(reserve);
+        agent.register_tool("echo", EchoProvider).unwrap();
+        let mut state = agent.start(Ask {
+            op: "chat".into(),
+            input: json!("hi"),
+            context: json!({}),
+        });
+        let mut steps = 0;
+        loop {
+            state = agent.step_once(state).await;
+            if state.is_finished() {
+                break;
+            }
+            steps += 1;
+            if state.current().context.get("final").is_none() {
+                assert!(state.remaining_tokens() >= reserve);
+            }
+        }
+        assert!(steps > 1);
+        // the answer is bigger than what intermediate steps had left to spend
+        let reply = state.outcome.unwrap();
+        assert!(reply.ok, "{:?}", reply.output);
+        assert_eq!(reply.output, json!("z".repeat(200)));
+    }
+
+    #[tokio::test]
+    async fn run_retry_budget_caps_retries_across_steps() {
+        use crate::testing::MockProvider;
+        let provider = Arc::new(MockProvider::new());
+        for _ in 0..20 {
+            provider.push_failure("overloaded");
+        }
+        // 5 steps of up to 3 attempts would make 15 calls
+        let agent = Agent::new(provider.clone(), 5, 100_000, 3, CancellationToken::new())
+            .with_backoff(Backoff {
+                initial: Duration::from_millis(1),
+                ..Backoff::default()
+            })
+            .with_run_retry_budget(3);
+        let mut state = agent.start(Ask {
+            op: "chat".into(),
+            input: json!("hi"),
+            context: json!({}),
+        });
+        let mut calls = Vec::new();
+        while !state.is_finished() {
+            state = agent.step_once(state).await;
+            calls.push(provider.call_count());
+        }
+        assert_eq!(state.retries(), 3);
+        assert_eq!(provider.call_count(), 5 + 3);
+        // the first step spends two retries, the second the last one
+        assert_eq!(&calls[..3], &[3, 5, 6]);
+        assert!(!state.outcome.unwrap().ok);
+    }
+
+    #[tokio::test]
+    async fn stringified_arguments_are_coerced_to_the_schema() {
+        use crate::testing::MockProvider;
+        let provider = Arc::new(MockProvider::new());
+        provider
+            .push_reply(Reply {
+                ok: false,
+                output: json!({"tool_calls": [
+                    {"op": "forecast", "input": {"location": "San Francisco", "days": "2"}}
+                ]}),
+                latency_ms: 0,
+                cost: json!({}),
+            })
+            .push_ok(json!("done"));
+        let days = json!({
+            "type": "object",
+            "properties": {"location": {"type": "string"}, "days": {"type": "integer"}},
+            "required": ["location", "days"]
+        });
+        let mut agent = Agent::new(provider.clone(), 3, 10_000, 1, CancellationToken::new())
+            .with_input_schema("forecast", days.clone())
+            .with_input_coercion(true);
+        agent.register_tool("forecast", EchoProvider).unwrap();
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("forecast?"),
+                context: json!({}),
+            })
+            .await;
+        assert!(reply.ok);
+        let received = &provider.asks()[1].input;
+        assert_eq!(received, &json!({"location": "San Francisco", "days": 2}));
+        assert!(schema::validate(&days, received).is_ok());
+    }
+
+    #[tokio::test]
+    async fn empty_tool_calls_answer_with_content() {
+        use crate::testing::MockProvider;
+        let ask = Ask {
+            op: "chat".into(),
+            input: json!("weather?"),
+            context: json!({}),
+        };
+        let empty = || Reply {
+            ok: false,
+            output: json!({"content": "It is sunny.", "tool_calls": []}),
+            latency_ms: 0,
+            cost: json!({}),
+        };
+
+        let provider = Arc::new(MockProvider::new());
+        provider.push_reply(empty()).push_ok(json!("unused"));
+        let agent = Agent::new(provider.clone(), 3, 10_000, 1, CancellationToken::new());
+        let reply = agent.run(ask.clone()).await;
+        assert!(reply.ok);
+        assert_eq!(reply.output, json!({"content": "It is sunny."}));
+        assert_eq!(provider.call_count(), 1);
+
+        let provider = Arc::new(MockProvider::new());
+        provider.push_reply(empty()).push_ok(json!("retried"));
+        let agent = Agent::new(provider.clone(), 3, 10_000, 1, CancellationToken::new())
+            .with_empty_tool_calls_policy(EmptyToolCallsPolicy::Retry);
+        let reply = agent.run(ask).await;
+        assert_eq!(reply.output, json!("retried"));
+        let retry = &provider.asks()[1];
+        assert_eq!(retry.input, empty().output);
+        assert_eq!(retry.context["retry"], 1);
+    }
+
+    #[tokio::test]
+    async fn reasoning_tokens_are_deducted_from_the_budget() {
+        use crate::testing::MockProvider;
+        let thinking = |tokens: u64| Reply {
+            ok: false,
+            output: json!({"tool_calls": [{"op": "echo", "input": "x"}]}),
+            latency_ms: 0,
+            cost: json!({"completion_tokens_details": {"reasoning_tokens": tokens}}),
+        };
+        let first_step = |tokens| async move {
+            let provider = Arc::new(MockProvider::new());
+            provider.push_reply(thinking(tokens));
+            let mut agent = Agent::new(provider, 3, 10_000, 1, CancellationToken::new());
+            agent.register_tool("echo", EchoProvider).unwrap();
+            let state = agent.start(Ask {
+                op: "chat".into(),
+                input: json!("hi"),
+                context: json!({}),
+            });
+            agent.step_once(state).await
+        };
+        let plain = first_step(0).await;
+        let reasoned = first_step(400).await;
+        assert!(!reasoned.is_finished());
+        assert_eq!(plain.remaining_tokens() - reasoned.remaining_tokens(), 400);
+        assert_eq!(
+            reasoned.totals.cost["completion_tokens_details"]["reasoning_tokens"],
+            400
+        );
+
+        let over = first_step(20_000).await;
+        assert_eq!(
+            over.outcome.unwrap().output,
+            json!({"error": "token budget exceeded"})
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn retry_delays_follow_a_manual_clock() {
+        use crate::testing::{ManualClock, MockProvider};
+        let provider = Arc::new(MockProvider::new());
+        provider
+            .push_failure("busy")
+            .push_failure("busy")
+            .push_failure("busy")
+            .push_ok(json!("done"));
+        let clock = ManualClock::new();
+        let agent = Agent::new(provider.clone(), 1, 1_000, 4, CancellationToken::new())
+            .with_backoff(Backoff {
+                initial: Duration::from_secs(60),
+                ..Backoff::default()
+            })
+            .with_clock(clock.clone());
+        let started = std::time::Instant::now();
+        let run = agent.run(Ask {
+            op: "chat".into(),
+            input: json!("hi"),
+            context: json!({}),
+        });
+        let (reply, ()) = tokio::join!(run, async {
+            for (calls, delay) in [(1, 60), (2, 120), (3, 240)] {
+                // a retry is parked once the failed call is in and its sleep registered
+                while provider.call_count() < calls || clock.sleepers() == 0 {
+                    tokio::task::yield_now().await;
+                }
+                clock.advance(Duration::from_secs(delay - 1));
+                tokio::task::yield_now().await;
+                assert_eq!(provider.call_count(), calls);
+                clock.advance(Duration::from_secs(1));
+            }
+        });
+        assert!(reply.ok, "{:?}", reply.output);
+        assert_eq!(provider.call_count(), 4);
+        assert_eq!(clock.elapsed(), Duration::from_secs(420));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn oversized_tool_output_is_capped() {
+        use crate::testing::MockProvider;
+        let ask = Ask {
+            op: "chat".into(),
+            input: json!("hi"),
+            context: json!({}),
+        };
+        let dump = || Reply {
+            ok: false,
+            output: json!({"tool_calls": [{"op": "echo", "input": "x".repeat(20_000)}]}),
+            latency_ms: 0,
+            cost: json!({}),
+        };
+
+        let provider = Arc::new(MockProvider::new());
+        provider.push_reply(dump()).push_ok(json!("done"));
+        let mut agent = Agent::new(provider.clone(), 3, 100_000, 1, CancellationToken::new())
+            .with_max_output_tokens("echo", 100, OutputCapPolicy::Truncate);
+        agent.register_tool("echo", EchoProvider).unwrap();
+        let reply = agent.run(ask.clone()).await;
+        assert!(reply.ok, "{:?}", reply.output);
+        let fed = &provider.asks()[1].input;
+        assert!(estimate_tokens(fed) <= 100);
+        let text = fed.as_str().unwrap();
+        assert!(text.starts_with("xxxx"));
+        assert!(text.ends_with(TRUNCATION_MARKER));
+
+        let provider = Arc::new(MockProvider::new());
+        provider.push_reply(dump());
+        let mut agent = Agent::new(provider.clone(), 3, 100_000, 1, CancellationToken::new())
+            .with_max_output_tokens("echo", 100, OutputCapPolicy::Fail);
+        agent.register_tool("echo", EchoProvider).unwrap();
+        let reply = agent.run(ask).await;
+        assert!(!reply.ok);
+        assert_eq!(reply.output["error"], "tool invocation failed");
+        assert_eq!(reply.output["detail"]["error"], "tool_output_too_large");
+        assert_eq!(reply.output["detail"]["max_output_tokens"], 100);
+        assert_eq!(provider.call_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn on_reply_reshapes_output_before_tool_detection() {
+        use crate::providers::func::FnProvider;
+        let model = FnProvider::new(|ask: Ask| {
+            if ask.context.get("tool").is_some() {
                 return Reply {
-                    ok: false,
-                    output: json!({"error": "token budget exceeded"}),
+                    ok: true,
+                    output: json!({"text": format!("echoed {}", ask.input)}),
                     latency_ms: 0,
                     cost: json!({}),
                 };
             }
-            remaining -= next_tokens;
-        }
-        Reply {
+            Reply {
+                ok: false,
+                output: json!({
+                    "text": "Calling a tool:\n```json\n{\"tool_calls\": [{\"op\": \"echo\", \"input\": \"x\"}]}\n```"
+                }),
+                latency_ms: 0,
+                cost: json!({}),
+            }
+        });
+        let mut agent = Agent::new(model, 3, 10_000, 1, CancellationToken::new()).on_reply(
+            |mut reply: Reply| {
+                let text = reply.output["text"].as_str().unwrap_or_default();
+                if let Some(block) = text
+                    .split_once("```json")
+                    .and_then(|(_, rest)| rest.split_once("```"))
+                {
+                    if let Ok(parsed) = serde_json::from_str(block.0) {
+                        reply.output = parsed;
+                    }
+                }
+                reply
+            },
+        );
+        agent.register_tool("echo", EchoProvider).unwrap();
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("hi"),
+                context: json!({}),
+            })
+            .await;
+        assert!(reply.ok, "{:?}", reply.output);
+        assert_eq!(reply.output, json!({"text": "echoed \"x\""}));
+    }
+
+    #[tokio::test]
+    async fn tool_results_carry_the_originating_call_id() {
+        use crate::testing::MockProvider;
+        let provider = Arc::new(MockProvider::new());
+        provider.push_reply(Reply {
+            ok: false,
+            output: json!({"tool_calls": [{"id": "call_1", "op": "echo", "input": "a"}]}),
+            latency_ms: 0,
+            cost: json!({}),
+        });
+        provider.push_reply(Reply {
             ok: false,
-            output: json!({"error": "step limit exceeded"}),
+            output: json!({"tool_calls": [
+                {"id": "call_2", "op": "echo", "input": "b"},
+                {"op": "echo", "input": "c"}
+            ]}),
             latency_ms: 0,
             cost: json!({}),
+        });
+        provider.push_ok(json!("done"));
+        let mut agent = Agent::new(provider.clone(), 5, 10_000, 1, CancellationToken::new());
+        agent.register_tool("echo", EchoProvider).unwrap();
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("hi"),
+                context: json!({}),
+            })
+            .await;
+        assert!(reply.ok, "{:?}", reply.output);
+        let asks = provider.asks();
+        assert_eq!(asks[1].input, json!("a"));
+        assert_eq!(asks[1].context["tool_call_id"], "call_1");
+        assert_eq!(asks[2].context["tool_call_ids"], json!(["call_2", null]));
+    }
+
+    #[tokio::test]
+    async fn kind_defaults_do_not_retry_embedded_failures() {
+        use crate::testing::MockProvider;
+        let ask = Ask {
+            op: "chat".into(),
+            input: json!("hi"),
+            context: json!({}),
+        };
+        let provider = Arc::new(MockProvider::new());
+        for _ in 0..3 {
+            provider.push_failure("deterministic");
+        }
+        let agent = Agent::new(provider.clone(), 1, 10_000, 3, CancellationToken::new())
+            .with_kind_defaults();
+        agent.run(ask.clone()).await;
+        assert_eq!(provider.call_count(), 1);
+
+        let remote = Arc::new(MockProvider::new().with_kind(ProviderKind::RemoteGrpc));
+        for _ in 0..3 {
+            remote.push_failure("flaky");
         }
+        let agent = Agent::new(remote.clone(), 1, 10_000, 1, CancellationToken::new())
+            .with_kind_profile(
+                ProviderKind::RemoteGrpc,
+                KindProfile {
+                    max_attempts: 2,
+                    timeout: None,
+                },
+            );
+        agent.run(ask).await;
+        assert_eq!(remote.call_count(), 2);
     }
-}
 
-fn estimate_tokens(value: &Value) -> usize {
-    value.to_string().chars().count()
-}
+    #[tokio::test]
+    async fn reasoning_hook_overrides_decided_mode() {
+        use crate::testing::MockProvider;
+        use std::sync::Mutex;
+        let provider = Arc::new(MockProvider::new());
+        provider.push_ok(json!("done"));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let agent = Agent::new(provider.clone(), 2, 10_000, 1, CancellationToken::new())
+            .on_reasoning_decided({
+                let seen = seen.clone();
+                move |mode| {
+                    seen.lock().unwrap().push(mode);
+                    ReasoningMode::Direct
+                }
+            });
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("a".repeat(300)),
+                context: json!({}),
+            })
+            .await;
+        assert!(reply.ok);
+        assert_eq!(*seen.lock().unwrap(), vec![ReasoningMode::Reasoned]);
+        assert_eq!(provider.asks()[0].context["reasoning"], "direct");
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde_json::json;
-    use std::cell::Cell;
-    use std::rc::Rc;
-    use tokio_util::sync::CancellationToken;
+    #[tokio::test]
+    async fn truncated_answers_are_continued_and_joined() {
+        use crate::testing::MockProvider;
+        let provider = Arc::new(MockProvider::new());
+        provider.push_ok(json!({"content": "Hello, wor", "finish_reason": "length"}));
+        provider.push_ok(json!({"content": "ld!", "finish_reason": "stop"}));
+        let agent = Agent::new(provider.clone(), 2, 10_000, 1, CancellationToken::new())
+            .with_auto_continue(3);
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("greet"),
+                context: json!({}),
+            })
+            .await;
+        assert!(reply.ok, "{:?}", reply.output);
+        assert_eq!(
+            reply.output,
+            json!({"content": "Hello, world!", "finish_reason": "stop"})
+        );
+        let asks = provider.asks();
+        assert_eq!(asks.len(), 2);
+        assert_eq!(asks[1].context["continuation"], 1);
+        assert_eq!(
+            asks[1].input,
+            json!([
+                {"role": "user", "content": "greet"},
+                {"role": "assistant", "content": "Hello, wor"},
+                {"role": "user", "content": CONTINUE_PROMPT}
+            ])
+        );
+    }
 
-    struct EchoProvider;
+    #[tokio::test]
+    async fn factory_tools_are_built_on_first_call() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let builds = Arc::new(AtomicUsize::new(0));
+        let mut agent = Agent::new(EchoProvider, 1, 1_000, 1, CancellationToken::new());
+        agent
+            .register_tool(
+                "echo",
+                ToolSpec::factory({
+                    let builds = builds.clone();
+                    move || {
+                        let builds = builds.clone();
+                        async move {
+                            builds.fetch_add(1, Ordering::SeqCst);
+                            tokio::task::yield_now().await;
+                            Ok(Box::new(EchoProvider) as Box<dyn Provider + Send + Sync>)
+                        }
+                    }
+                }),
+            )
+            .unwrap();
+        agent
+            .register_tool(
+                "broken",
+                ToolSpec::factory(|| async { Err("no binary".into()) }),
+            )
+            .unwrap();
+        assert_eq!(builds.load(Ordering::SeqCst), 0);
 
-    impl Provider for EchoProvider {
-        fn kind(&self) -> ProviderKind {
-            ProviderKind::Embedded
+        let ask = Ask {
+            op: "echo".into(),
+            input: json!("hi"),
+            context: json!({}),
+        };
+        for _ in 0..2 {
+            let reply = agent.call_tool("echo", ask.clone()).unwrap();
+            assert_eq!(reply.output, json!("hi"));
         }
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
 
-        fn ask(&self, ask: Ask) -> Reply {
-            Reply {
-                ok: true,
-                output: ask.input,
-                latency_ms: 0,
-                cost: json!({}),
-            }
-        }
+        let reply = agent.call_tool("broken", ask).unwrap();
+        assert!(!reply.ok);
+        assert_eq!(
+            reply.output,
+            json!({"error": "tool construction failed", "detail": "no binary"})
+        );
     }
 
-    #[test]
-    fn echo_roundtrip() {
+    #[tokio::test(flavor = "current_thread")]
+    async fn drain_waits_for_active_runs() {
+        use crate::testing::MockProvider;
+        let provider = Arc::new(MockProvider::new());
+        provider.push_failure("busy").push_ok(json!("done"));
+        let shutdown = Shutdown::new(CancellationToken::new());
+        let agent = Agent::new(provider.clone(), 1, 1_000, 2, CancellationToken::new())
+            .with_backoff(Backoff {
+                initial: Duration::from_millis(50),
+                ..Backoff::default()
+            })
+            .with_shutdown(shutdown.clone());
         let ask = Ask {
-            op: "echo".into(),
-            input: json!({"msg": "hi"}),
+            op: "chat".into(),
+            input: json!("hi"),
             context: json!({}),
         };
-        let provider = EchoProvider;
-        let reply = provider.ask(ask);
+
+        // the run is parked in its retry delay when the drain starts
+        let (reply, drained) = tokio::join!(agent.run(ask.clone()), async {
+            assert_eq!(shutdown.active(), 1);
+            shutdown.drain(Duration::from_secs(5)).await
+        });
+        assert!(drained);
         assert!(reply.ok);
-        assert_eq!(reply.output, json!({"msg": "hi"}));
+        assert_eq!(reply.output, json!("done"));
+        assert_eq!(shutdown.active(), 0);
+        assert!(shutdown.token().is_cancelled());
+
+        let refused = agent.run(ask).await;
+        assert!(!refused.ok);
+        assert_eq!(refused.output, json!({"error": "shutting_down"}));
+        assert_eq!(provider.call_count(), 2);
     }
 
     #[tokio::test(flavor = "current_thread")]
-    async fn agent_runs_until_ok() {
+    async fn cancelling_one_run_leaves_concurrent_runs_alone() {
+        use crate::testing::MockProvider;
+        let provider = Arc::new(MockProvider::new());
+        provider
+            .push_failure("busy")
+            .push_failure("busy")
+            .push_ok(json!("done"));
+        let shared = CancellationToken::new();
+        let agent =
+            Agent::new(provider.clone(), 1, 1_000, 2, shared.clone()).with_backoff(Backoff {
+                initial: Duration::from_millis(50),
+                ..Backoff::default()
+            });
         let ask = Ask {
-            op: "echo".into(),
-            input: json!({"msg": "hi"}),
+            op: "chat".into(),
+            input: json!("hi"),
             context: json!({}),
         };
-        let agent = Agent::new(EchoProvider, 3, 1000, 3, CancellationToken::new());
-        let reply = agent.run(ask).await;
-        assert!(reply.ok);
-        assert_eq!(reply.output, json!({"msg": "hi"}));
+        let (first, second) = (CancellationToken::new(), CancellationToken::new());
+
+        // both runs are parked in their retry delay when the first is cancelled
+        let (cancelled, completed, _) = tokio::join!(
+            agent.run_cancellable(ask.clone(), &first),
+            agent.run_cancellable(ask, &second),
+            async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                first.cancel();
+            }
+        );
+        assert!(!cancelled.ok);
+        assert_eq!(cancelled.output["error"], "cancelled");
+        assert!(completed.ok, "{:?}", completed.output);
+        assert_eq!(completed.output, json!("done"));
+        assert!(!shared.is_cancelled());
+        assert_eq!(provider.call_count(), 3);
+
+        fn assert_sync<T: Sync>(_: &T) {}
+        assert_sync(&agent);
     }
 
-    struct FailProvider;
+    struct Closable(Arc<AtomicUsize>);
 
-    impl Provider for FailProvider {
+    impl Provider for Closable {
         fn kind(&self) -> ProviderKind {
             ProviderKind::Embedded
         }
 
-        fn ask(&self, _ask: Ask) -> Reply {
-            Reply {
-                ok: false,
-                output: json!({"error": "fail"}),
-                latency_ms: 0,
-                cost: json!({}),
-            }
+        fn ask(&self, ask: Ask) -> Reply {
+            EchoProvider.ask(ask)
+        }
+
+        fn close(&self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
         }
     }
 
-    #[tokio::test(flavor = "current_thread")]
-    async fn agent_respects_step_limit() {
-        let ask = Ask {
-            op: "fail".into(),
-            input: json!({}),
-            context: json!({}),
+    #[test]
+    fn tools_are_closed_once_on_close_or_drop() {
+        use crate::testing::MockProvider;
+        let closed = Arc::new(AtomicUsize::new(0));
+        let new_agent = || {
+            let mut agent = Agent::new(MockProvider::new(), 1, 1_000, 0, CancellationToken::new());
+            agent.register_tool("a", Closable(closed.clone())).unwrap();
+            agent
         };
-        let agent = Agent::new(FailProvider, 2, 1000, 3, CancellationToken::new());
-        let reply = agent.run(ask).await;
-        assert!(!reply.ok);
-        assert_eq!(reply.output, json!({"error": "step limit exceeded"}));
+
+        drop(new_agent());
+        assert_eq!(closed.load(Ordering::SeqCst), 1);
+
+        let agent = new_agent();
+        agent.close();
+        assert_eq!(closed.load(Ordering::SeqCst), 2);
+        agent.close();
+        drop(agent);
+        assert_eq!(closed.load(Ordering::SeqCst), 2);
     }
 
     #[test]
-    fn reasoning_policy_scores_inputs() {
-        let policy = ReasoningPolicy::default();
-        assert_eq!(policy.decide(&json!("short"), 0), ReasoningMode::Direct);
-        let long = "x".repeat(300);
-        assert_eq!(policy.decide(&json!(long), 0), ReasoningMode::Reasoned);
+    fn close_reaches_tools_through_decorators() {
+        use crate::middleware::Layered;
+        use crate::providers::{CachingProvider, FallbackProvider, RateLimiter};
+        use crate::testing::MockProvider;
+        let closed = Arc::new(AtomicUsize::new(0));
+        let mut agent = Agent::new(MockProvider::new(), 1, 1_000, 0, CancellationToken::new());
+        agent
+            .register_tool(
+                "cached",
+                CachingProvider::new(
+                    RateLimiter::new(Closable(closed.clone()), 10.0),
+                    8,
+                    Duration::from_secs(60),
+                ),
+            )
+            .unwrap();
+        agent
+            .register_tool(
+                "fallback",
+                FallbackProvider::new(vec![
+                    Box::new(Closable(closed.clone())),
+                    Box::new(Layered::new(Closable(closed.clone()))),
+                ]),
+            )
+            .unwrap();
+
+        agent.close();
+        assert_eq!(closed.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn plan_reports_mode_without_calling_provider() {
+        use crate::testing::MockProvider;
+        let provider = Arc::new(MockProvider::new());
+        let agent = Agent::new(provider.clone(), 2, 10_000, 0, CancellationToken::new());
+        let short = agent.plan(&Ask {
+            op: "chat".into(),
+            input: json!("hi"),
+            context: json!({"request_id": "r1"}),
+        });
+        assert_eq!(short.mode, ReasoningMode::Direct);
+        assert!(short.within_budget);
+        assert_eq!(short.first_ask.context["reasoning"], "direct");
+        assert_eq!(short.first_ask.context["request_id"], "r1");
+
+        let long = agent.plan(&Ask {
+            op: "chat".into(),
+            input: json!("a".repeat(300)),
+            context: json!({}),
+        });
+        assert_eq!(long.mode, ReasoningMode::Reasoned);
+        assert_eq!(long.remaining_tokens, 10_000 - long.estimated_tokens);
+        assert_eq!(provider.call_count(), 0);
+    }
+
+    #[test]
+    fn op_overrides_take_precedence_over_score() {
+        use crate::testing::MockProvider;
+        let policy = ReasoningPolicy::default()
+            .with_override("prove", ReasoningMode::Reasoned)
+            .with_override("echo", ReasoningMode::Direct);
+        let agent = Agent::with_policy(
+            MockProvider::new(),
+            2,
+            10_000,
+            policy.clone(),
+            1,
+            CancellationToken::new(),
+        );
+        let plan = |op: &str, input: Value| {
+            agent
+                .plan(&Ask {
+                    op: op.into(),
+                    input,
+                    context: json!({}),
+                })
+                .mode
+        };
+        assert_eq!(plan("prove", json!("1+1")), ReasoningMode::Reasoned);
+        assert_eq!(plan("echo", json!("a".repeat(300))), ReasoningMode::Direct);
+        assert_eq!(
+            plan("chat", json!("a".repeat(300))),
+            ReasoningMode::Reasoned
+        );
+
+        let restored: ReasoningPolicy =
+            serde_json::from_value(serde_json::to_value(&policy).unwrap()).unwrap();
+        assert_eq!(restored.overrides, policy.overrides);
+        let legacy: ReasoningPolicy =
+            serde_json::from_value(json!({"threshold": 200, "tool_weight": 50})).unwrap();
+        assert!(legacy.overrides.is_empty());
     }
 
     struct InspectProvider;
@@ -701,6 +4582,7 @@ mod tests {
         let policy = ReasoningPolicy {
             threshold: 10,
             tool_weight: 50,
+            ..ReasoningPolicy::default()
         };
         let agent = Agent::with_policy(ReasoningEcho, 1, 105, policy, 3, CancellationToken::new());
         let reply = agent.run(ask).await;
@@ -777,7 +4659,271 @@ mod tests {
         let reply = agent.run(ask).await;
         handle.await.unwrap();
         assert!(!reply.ok);
-        assert_eq!(reply.output, json!({"error": "cancelled"}));
+        assert_eq!(reply.output["error"], "cancelled");
+        assert_eq!(reply.output["cancelled"], json!(true));
         assert_eq!(attempts.get(), 1);
     }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn cancel_during_parallel_tools_keeps_partial_accounting() {
+        use crate::testing::MockProvider;
+
+        let provider = MockProvider::new();
+        provider.push_reply(Reply {
+            ok: false,
+            output: json!({"tool_calls": [
+                {"op": "fast", "input": {}},
+                {"op": "slow", "input": {}}
+            ]}),
+            latency_ms: 3,
+            cost: json!({"total_tokens": 10}),
+        });
+        let fast = MockProvider::new();
+        fast.push_reply(Reply {
+            ok: true,
+            output: json!("fast done"),
+            latency_ms: 5,
+            cost: json!({"total_tokens": 2}),
+        });
+        let token = CancellationToken::new();
+        let mut agent = Agent::new(provider, 3, 1000, 5, token.clone());
+        agent.register_tool("fast", fast).unwrap();
+        agent.register_tool("slow", FailProvider).unwrap();
+
+        let handle = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            token.cancel();
+        });
+        let reply = agent
+            .run(Ask {
+                op: "plan".into(),
+                input: json!("go"),
+                context: json!({}),
+            })
+            .await;
+        handle.await.unwrap();
+        assert!(!reply.ok);
+        assert_eq!(reply.output["cancelled"], json!(true));
+        assert_eq!(
+            reply.output["partial"],
+            json!([{"tool": "fast", "output": "fast done"}])
+        );
+        assert_eq!(reply.latency_ms, 8);
+        assert_eq!(reply.cost, json!({"total_tokens": 12}));
+    }
+
+    struct InspectTool {
+        seen: Arc<Mutex<Vec<Value>>>,
+    }
+
+    impl Provider for InspectTool {
+        fn kind(&self) -> ProviderKind {
+            ProviderKind::Embedded
+        }
+
+        fn ask(&self, ask: Ask) -> Reply {
+            self.seen.lock().unwrap().push(ask.context);
+            Reply {
+                ok: true,
+                output: json!("inspected"),
+                latency_ms: 0,
+                cost: json!({}),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn tools_receive_reasoning_and_step_context() {
+        use crate::testing::MockProvider;
+
+        let provider = MockProvider::new();
+        provider
+            .push_failure("warming up")
+            .push_reply(Reply {
+                ok: false,
+                output: json!({"tool_calls": [{"op": "inspect", "input": {}}]}),
+                latency_ms: 0,
+                cost: json!({}),
+            })
+            .push_ok(json!("done"));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut agent = Agent::new(provider, 3, 1000, 1, CancellationToken::new());
+        agent
+            .register_tool("inspect", InspectTool { seen: seen.clone() })
+            .unwrap();
+
+        let reply = agent
+            .run(Ask {
+                op: "chat".into(),
+                input: json!("hi"),
+                context: json!({}),
+            })
+            .await;
+
+        assert!(reply.ok);
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0]["reasoning"], "direct");
+        assert_eq!(seen[0]["step"], 1);
+        assert_eq!(seen[0]["tool"], "inspect");
+    }
+
+    struct ToolLoopProvider;
+
+    impl Provider for ToolLoopProvider {
+        fn kind(&self) -> ProviderKind {
+            ProviderKind::Embedded
+        }
+
+        fn ask(&self, _ask: Ask) -> Reply {
+            Reply {
+                ok: false,
+                output: json!({"tool_calls": [{"op": "inspect", "input": {}}]}),
+                latency_ms: 0,
+                cost: json!({}),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn tool_call_limit_triggers_before_step_limit() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut agent = Agent::new(ToolLoopProvider, 10, 10_000, 1, CancellationToken::new())
+            .with_max_tool_calls(3);
+        agent
+            .register_tool("inspect", InspectTool { seen: seen.clone() })
+            .unwrap();
+
+        let reply = agent
+            .run(Ask {
+                op: "loop".into(),
+                input: json!("go"),
+                context: json!({}),
+            })
+            .await;
+
+        assert!(!reply.ok);
+        assert_eq!(reply.output, json!({"error": "tool_call_limit_exceeded"}));
+        assert_eq!(seen.lock().unwrap().len(), 3);
+    }
+
+    struct ToolsThenEcho {
+        called: Cell<bool>,
+    }
+
+    impl Provider for ToolsThenEcho {
+        fn kind(&self) -> ProviderKind {
+            ProviderKind::Embedded
+        }
+
+        fn ask(&self, ask: Ask) -> Reply {
+            if self.called.replace(true) {
+                return Reply {
+                    ok: true,
+                    output: ask.input,
+                    latency_ms: 0,
+                    cost: json!({}),
+                };
+            }
+            Reply {
+                ok: false,
+                output: json!({"tool_calls": [
+                    {"op": "first", "input": {}},
+                    {"op": "broken", "input": {}},
+                    {"op": "last", "input": {}}
+                ]}),
+                latency_ms: 0,
+                cost: json!({}),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn aggregate_errors_reports_every_tool_result() {
+        use crate::testing::MockProvider;
+
+        let first = MockProvider::new();
+        first.push_ok(json!(1));
+        let last = MockProvider::new();
+        last.push_ok(json!(3));
+        let provider = ToolsThenEcho {
+            called: Cell::new(false),
+        };
+        let mut agent = Agent::new(provider, 3, 10_000, 1, CancellationToken::new())
+            .with_tool_error_policy(ToolErrorPolicy::AggregateErrors);
+        agent.register_tool("first", first).unwrap();
+        agent.register_tool("broken", FailProvider).unwrap();
+        agent.register_tool("last", last).unwrap();
+
+        let reply = agent
+            .run(Ask {
+                op: "fan_out".into(),
+                input: json!("go"),
+                context: json!({}),
+            })
+            .await;
+
+        assert!(reply.ok);
+        assert_eq!(
+            reply.output,
+            json!([
+                {"tool": "first", "ok": true, "output": 1},
+                {"tool": "broken", "ok": false, "output": {"error": "fail"}},
+                {"tool": "last", "ok": true, "output": 3}
+            ])
+        );
+    }
+
+    struct PanicTool;
+
+    impl Provider for PanicTool {
+        fn kind(&self) -> ProviderKind {
+            ProviderKind::Embedded
+        }
+
+        fn ask(&self, _ask: Ask) -> Reply {
+            panic!("tool exploded");
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn panicking_parallel_tool_yields_failed_reply() {
+        let provider = ToolsThenEcho {
+            called: Cell::new(false),
+        };
+        let mut agent = Agent::new(provider, 3, 10_000, 1, CancellationToken::new());
+        agent.register_tool("first", EchoProvider).unwrap();
+        agent.register_tool("broken", PanicTool).unwrap();
+        agent.register_tool("last", EchoProvider).unwrap();
+
+        let reply = agent
+            .run(Ask {
+                op: "fan_out".into(),
+                input: json!("go"),
+                context: json!({}),
+            })
+            .await;
+
+        assert!(!reply.ok);
+        assert_eq!(reply.output["error"], "tool invocation failed");
+        assert_eq!(reply.output["tool"], "broken");
+        assert_eq!(
+            reply.output["detail"],
+            json!({"error": "tool panicked", "detail": "tool exploded"})
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn run_generates_request_id_when_missing() {
+        let agent = Agent::new(InspectProvider, 1, 1000, 1, CancellationToken::new());
+        let reply = agent
+            .run(Ask {
+                op: "inspect".into(),
+                input: json!("hello"),
+                context: json!({}),
+            })
+            .await;
+        let id = reply.output["request_id"].as_str().unwrap();
+        assert!(Uuid::parse_str(id).is_ok());
+    }
 }
@@ -1,49 +1,172 @@
+pub mod server;
+
+pub use server::{McpServer, McpServerHandle};
+
 use std::collections::HashMap;
-use std::sync::Mutex;
-use std::time::Instant;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use serde_json::{json, Value};
 
-use crate::{Ask, Provider, ProviderKind, Reply};
-use mcp_client::{Error as McpError, McpClient};
+use crate::{off_runtime, Ask, Health, HealthStatus, Provider, ProviderKind, Reply, USER_AGENT};
+use mcp_client::{Error as McpError, McpClient, RpcConfig};
+
+/// Hit and miss counts for an [`McpProvider`]'s schema cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SchemaCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
 
 pub struct McpProvider {
     client: McpClient,
-    schemas: Mutex<HashMap<String, Value>>,
+    tool: Option<String>,
+    schemas: Mutex<HashMap<String, (Value, Instant)>>,
+    schema_ttl: Option<Duration>,
+    progress: Option<Arc<dyn Fn(Value) + Send + Sync>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
 }
 
 impl McpProvider {
     pub fn new(url: impl Into<String>) -> Result<Self, McpError> {
-        let client = McpClient::new(url.into())?;
+        let config = RpcConfig {
+            user_agent: USER_AGENT.into(),
+            ..RpcConfig::default()
+        };
+        Self::with_config(url, config)
+    }
+
+    /// Like [`McpProvider::new`], but speaking the wire settings in `config`,
+    /// including its `user_agent`.
+    pub fn with_config(url: impl Into<String>, config: RpcConfig) -> Result<Self, McpError> {
+        let url = url.into();
+        let client = off_runtime(move || McpClient::with_config(url, config))?;
         Ok(Self {
             client,
+            tool: None,
             schemas: Mutex::new(HashMap::new()),
+            schema_ttl: None,
+            progress: None,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
         })
     }
-}
 
-impl Provider for McpProvider {
-    fn kind(&self) -> ProviderKind {
-        ProviderKind::RemoteGrpc
+    /// Binds the provider to one remote tool, invoked whatever `ask.op` says.
+    pub fn for_tool(mut self, tool: impl Into<String>) -> Self {
+        self.tool = Some(tool.into());
+        self
     }
 
-    fn ask(&self, ask: Ask) -> Reply {
-        let start = Instant::now();
-        {
-            let mut schemas = self.schemas.lock().unwrap();
-            if !schemas.contains_key(&ask.op) {
-                if let Ok(schema) = self.client.schema(&ask.op) {
-                    schemas.insert(ask.op.clone(), schema);
-                }
+    /// Fails a call whose response body exceeds `max_reply_bytes` with
+    /// `{"error": "response_too_large"}`.
+    pub fn with_max_reply_bytes(mut self, max_reply_bytes: usize) -> Self {
+        self.client = self.client.with_max_response_bytes(max_reply_bytes);
+        self
+    }
+
+    /// Receives the params of every `notifications/progress` the server streams
+    /// while a tool runs.
+    pub fn on_progress(mut self, hook: impl Fn(Value) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(hook));
+        self
+    }
+
+    /// Expires cached schemas `ttl` after they were fetched; without a TTL they are
+    /// kept until [`McpProvider::clear_schema_cache`].
+    pub fn with_schema_ttl(mut self, ttl: Duration) -> Self {
+        self.schema_ttl = Some(ttl);
+        self
+    }
+
+    /// Returns the server's schema for `tool`, fetching and caching it on first use.
+    ///
+    /// Failed fetches are not cached, so the next call tries again.
+    pub fn schema(&self, tool: &str) -> Option<Value> {
+        let mut schemas = self.schemas.lock().unwrap();
+        if let Some((schema, fetched)) = schemas.get(tool) {
+            if self.schema_ttl.is_none_or(|ttl| fetched.elapsed() < ttl) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(schema.clone());
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        self.fetch_schema(&mut schemas, tool)
+    }
+
+    /// Re-fetches the schema for `tool`, replacing any cached copy.
+    pub fn refresh_schema(&self, tool: &str) -> Option<Value> {
+        let mut schemas = self.schemas.lock().unwrap();
+        self.fetch_schema(&mut schemas, tool)
+    }
+
+    /// Drops every cached schema.
+    pub fn clear_schema_cache(&self) {
+        self.schemas.lock().unwrap().clear();
+    }
+
+    pub fn schema_cache_stats(&self) -> SchemaCacheStats {
+        SchemaCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn fetch_schema(
+        &self,
+        schemas: &mut HashMap<String, (Value, Instant)>,
+        tool: &str,
+    ) -> Option<Value> {
+        match off_runtime(|| self.client.schema(tool)) {
+            Ok(schema) => {
+                schemas.insert(tool.to_string(), (schema.clone(), Instant::now()));
+                Some(schema)
+            }
+            Err(_) => {
+                schemas.remove(tool);
+                None
             }
         }
-        match self.client.invoke(&ask.op, ask.input.clone()) {
+    }
+
+    fn call(&self, ask: Ask) -> Reply {
+        let start = Instant::now();
+        let op = self.tool.as_deref().unwrap_or(&ask.op);
+        let _ = self.schema(op);
+        let mut headers = Vec::new();
+        if let Some(id) = ask.context.get("request_id").and_then(|v| v.as_str()) {
+            headers.push(("x-request-id", id));
+        }
+        if let Some(key) = ask.context.get("idempotency_key").and_then(|v| v.as_str()) {
+            headers.push(("idempotency-key", key));
+        }
+        if let Some(parent) = ask.context.get("traceparent").and_then(|v| v.as_str()) {
+            headers.push(("traceparent", parent));
+        }
+        let result = match &self.progress {
+            Some(hook) => {
+                self.client
+                    .invoke_with_progress(op, ask.input.clone(), &headers, &mut |p| hook(p))
+            }
+            None => self
+                .client
+                .invoke_with_headers(op, ask.input.clone(), &headers),
+        };
+        match result {
             Ok(out) => Reply {
                 ok: true,
                 output: out,
                 latency_ms: start.elapsed().as_millis() as u64,
                 cost: json!({}),
             },
+            Err(McpError::TooLarge(_)) => Reply {
+                ok: false,
+                output: json!({"error": "response_too_large"}),
+                latency_ms: start.elapsed().as_millis() as u64,
+                cost: json!({}),
+            },
             Err(e) => Reply {
                 ok: false,
                 output: json!({"error": e.to_string()}),
@@ -53,3 +176,32 @@ impl Provider for McpProvider {
         }
     }
 }
+
+impl Provider for McpProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::RemoteGrpc
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        off_runtime(|| self.call(ask))
+    }
+
+    /// Re-runs the handshake.
+    fn health_check(&self) -> HealthStatus {
+        let start = Instant::now();
+        let result = off_runtime(|| self.client.handshake());
+        let latency_ms = start.elapsed().as_millis() as u64;
+        match result {
+            Ok(_) => HealthStatus {
+                health: Health::Ok,
+                latency_ms,
+                detail: None,
+            },
+            Err(e) => HealthStatus {
+                health: Health::Down,
+                latency_ms,
+                detail: Some(e.to_string()),
+            },
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::{Ask, Provider};
+
+struct Tool {
+    provider: Box<dyn Provider + Send + Sync>,
+    schema: Value,
+}
+
+/// McpServer answers MCP JSON-RPC calls by delegating to registered providers.
+///
+/// It speaks the same methods [`super::McpProvider`] uses, `handshake`, `tools/list`,
+/// `schema` and `invoke`, so any provider or agent here can be registered as a tool
+/// by another agent. Serve it over HTTP with [`McpServer::spawn`] or over line-delimited
+/// stdio with [`McpServer::serve_stdio`].
+pub struct McpServer {
+    tools: HashMap<String, Tool>,
+    max_body_bytes: usize,
+    max_connections: usize,
+}
+
+/// Largest HTTP request body served by default.
+const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+/// Connections served at once by default.
+const DEFAULT_MAX_CONNECTIONS: usize = 64;
+/// How long a connection may stall while its request is read.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl Default for McpServer {
+    fn default() -> Self {
+        Self {
+            tools: HashMap::new(),
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+        }
+    }
+}
+
+impl McpServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers HTTP requests whose `Content-Length` exceeds `max_body_bytes` with
+    /// `413 Payload Too Large` without reading the body. Defaults to 1 MiB.
+    pub fn with_max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    /// Serves at most `max_connections` HTTP connections at once; further ones wait
+    /// to be accepted until one finishes. Defaults to 64.
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections.max(1);
+        self
+    }
+
+    /// Exposes `provider` as `name`; `schema` is what `schema` and `tools/list` report.
+    pub fn with_tool(
+        mut self,
+        name: impl Into<String>,
+        provider: impl Provider + Send + Sync + 'static,
+        schema: Value,
+    ) -> Self {
+        self.tools.insert(
+            name.into(),
+            Tool {
+                provider: Box::new(provider),
+                schema,
+            },
+        );
+        self
+    }
+
+    /// Answers one JSON-RPC request. `request_id` is forwarded to the tool's context.
+    pub fn handle(&self, request: &Value, request_id: Option<&str>) -> Value {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let params = &request["params"];
+        let result = match request["method"].as_str().unwrap_or_default() {
+            "handshake" => Ok(json!({"ok": true})),
+            "tools/list" => {
+                let mut names: Vec<&String> = self.tools.keys().collect();
+                names.sort();
+                let tools: Vec<Value> = names
+                    .into_iter()
+                    .map(|name| json!({"name": name, "inputSchema": self.tools[name].schema}))
+                    .collect();
+                Ok(json!({"tools": tools}))
+            }
+            "schema" => self.tool(params).map(|(_, tool)| tool.schema.clone()),
+            "invoke" => self.tool(params).and_then(|(name, tool)| {
+                let mut context = json!({});
+                if let Some(request_id) = request_id {
+                    context["request_id"] = json!(request_id);
+                }
+                let reply = tool.provider.ask(Ask {
+                    op: name.to_string(),
+                    input: params["input"].clone(),
+                    context,
+                });
+                if reply.ok {
+                    Ok(reply.output)
+                } else {
+                    Err(json!({"code": -32000, "message": "tool failed", "data": reply.output}))
+                }
+            }),
+            other => Err(json!({"code": -32601, "message": format!("unknown method {other}")})),
+        };
+        match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(error) => json!({"jsonrpc": "2.0", "id": id, "error": error}),
+        }
+    }
+
+    fn tool<'a>(&'a self, params: &'a Value) -> Result<(&'a str, &'a Tool), Value> {
+        let name = params["tool"].as_str().unwrap_or_default();
+        self.tools
+            .get(name)
+            .map(|tool| (name, tool))
+            .ok_or_else(|| json!({"code": -32602, "message": format!("unknown tool {name}")}))
+    }
+
+    /// Reads one JSON-RPC request per line and writes one response per line until EOF.
+    pub fn serve_stdio(&self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str::<Value>(&line) {
+                Ok(request) => self.handle(&request, None),
+                Err(e) => parse_error(e),
+            };
+            writeln!(output, "{response}")?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Serves JSON-RPC over HTTP on `addr` from a background thread, one thread per
+    /// connection up to [`McpServer::with_max_connections`]. The server stops when
+    /// the returned handle is dropped.
+    pub fn spawn(self, addr: impl ToSocketAddrs) -> io::Result<McpServerHandle> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = Arc::new(self);
+        let slots = Arc::new((Mutex::new(0usize), Condvar::new()));
+        thread::spawn({
+            let stop = stop.clone();
+            move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(stream) = stream else { continue };
+                    {
+                        let (active, freed) = &*slots;
+                        let mut active = active.lock().unwrap();
+                        while *active >= server.max_connections {
+                            active = freed.wait(active).unwrap();
+                        }
+                        *active += 1;
+                    }
+                    let server = server.clone();
+                    let slots = slots.clone();
+                    thread::spawn(move || {
+                        let _ = server.serve_http(stream);
+                        let (active, freed) = &*slots;
+                        *active.lock().unwrap() -= 1;
+                        freed.notify_one();
+                    });
+                }
+            }
+        });
+        Ok(McpServerHandle { addr, stop })
+    }
+
+    fn serve_http(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let post = line.starts_with("POST ");
+        let mut length = Some(0);
+        let mut request_id = None;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "content-length" => length = value.trim().parse().ok(),
+                    "x-request-id" => request_id = Some(value.trim().to_string()),
+                    _ => {}
+                }
+            }
+        }
+        let (status, response) = match length {
+            None => (
+                "400 Bad Request",
+                invalid_request("malformed Content-Length"),
+            ),
+            Some(length) if length > self.max_body_bytes => (
+                "413 Payload Too Large",
+                invalid_request(&format!(
+                    "body of {length} bytes exceeds the limit of {}",
+                    self.max_body_bytes
+                )),
+            ),
+            Some(length) => self.respond(&mut reader, length, post, request_id.as_deref())?,
+        };
+        let body = response.to_string();
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        stream.flush()
+    }
+
+    /// Reads a body of `length` bytes and answers the request it holds.
+    fn respond(
+        &self,
+        reader: &mut impl Read,
+        length: usize,
+        post: bool,
+        request_id: Option<&str>,
+    ) -> io::Result<(&'static str, Value)> {
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        Ok(if !post {
+            ("405 Method Not Allowed", json!({}))
+        } else {
+            let response = match serde_json::from_slice::<Value>(&body) {
+                Ok(request) => self.handle(&request, request_id),
+                Err(e) => parse_error(e),
+            };
+            ("200 OK", response)
+        })
+    }
+}
+
+fn invalid_request(message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": null,
+        "error": {"code": -32600, "message": message}
+    })
+}
+
+fn parse_error(e: serde_json::Error) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": null,
+        "error": {"code": -32700, "message": e.to_string()}
+    })
+}
+
+/// A running [`McpServer`]; dropping it stops accepting connections.
+pub struct McpServerHandle {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+}
+
+impl McpServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The URL an [`mcp_client::McpClient`] should connect to.
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+}
+
+impl Drop for McpServerHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // wake the accept loop so it sees the flag
+        let _ = TcpStream::connect(self.addr);
+    }
+}
@@ -0,0 +1,39 @@
+pub mod prometheus;
+
+pub use prometheus::{MetricsServerHandle, PrometheusMetricsSink};
+
+use crate::{ProviderKind, Reply};
+
+/// MetricsSink receives an event for every provider attempt, retry and tool call an
+/// [`crate::Agent`] makes. Every method defaults to doing nothing.
+pub trait MetricsSink: Send + Sync {
+    /// One attempt at the agent's provider finished with `reply`.
+    fn ask(&self, _kind: ProviderKind, _op: &str, _reply: &Reply) {}
+    /// A failed provider or tool attempt for `op` is being retried.
+    fn retry(&self, _kind: ProviderKind, _op: &str) {}
+    /// One attempt at the tool `tool` finished with `reply`.
+    fn tool_call(&self, _kind: ProviderKind, _tool: &str, _reply: &Reply) {}
+}
+
+/// Records one attempt and counts it as a retry when it is not the first.
+pub(crate) fn attempt(
+    sink: Option<&dyn MetricsSink>,
+    kind: ProviderKind,
+    op: &str,
+    tool: bool,
+    attempts: &mut usize,
+    reply: &Reply,
+) {
+    let Some(sink) = sink else {
+        return;
+    };
+    if *attempts > 0 {
+        sink.retry(kind, op);
+    }
+    *attempts += 1;
+    if tool {
+        sink.tool_call(kind, op, reply);
+    } else {
+        sink.ask(kind, op, reply);
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::MetricsSink;
+use crate::{ProviderKind, Reply};
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+type Labels = (&'static str, String);
+
+#[derive(Default, Clone)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    asks: BTreeMap<Labels, u64>,
+    retries: BTreeMap<Labels, u64>,
+    tool_calls: BTreeMap<Labels, u64>,
+    tokens: BTreeMap<Labels, u64>,
+    latency: BTreeMap<Labels, Histogram>,
+}
+
+fn kind_label(kind: ProviderKind) -> &'static str {
+    match kind {
+        ProviderKind::Embedded => "embedded",
+        ProviderKind::SidecarUds => "sidecar_uds",
+        ProviderKind::RemoteGrpc => "remote_grpc",
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn counter(out: &mut String, name: &str, help: &str, values: &BTreeMap<Labels, u64>) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    for ((kind, op), value) in values {
+        let _ = writeln!(
+            out,
+            "{name}{{kind=\"{kind}\",op=\"{}\"}} {value}",
+            escape(op)
+        );
+    }
+}
+
+/// PrometheusMetricsSink keeps counters and a latency histogram labelled by provider
+/// kind and op, and renders them in the Prometheus text exposition format.
+///
+/// Series: `soma_ask_total`, `soma_retry_total`, `soma_tool_call_total`,
+/// `soma_tokens_total` (from `cost.total_tokens`) and `soma_latency_seconds`, which
+/// covers provider and tool attempts alike.
+#[derive(Default)]
+pub struct PrometheusMetricsSink {
+    registry: Mutex<Registry>,
+}
+
+impl PrometheusMetricsSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn observe(&self, kind: ProviderKind, op: &str, reply: &Reply) {
+        let labels = (kind_label(kind), op.to_string());
+        let mut registry = self.registry.lock().unwrap();
+        if let Some(tokens) = reply.cost.get("total_tokens").and_then(|v| v.as_u64()) {
+            *registry.tokens.entry(labels.clone()).or_default() += tokens;
+        }
+        let seconds = reply.latency_ms as f64 / 1000.0;
+        let histogram = registry.latency.entry(labels).or_default();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    /// Renders every series in the text exposition format served at `/metrics`.
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "soma_ask_total",
+            "Provider attempts.",
+            &registry.asks,
+        );
+        counter(
+            &mut out,
+            "soma_retry_total",
+            "Retried provider and tool attempts.",
+            &registry.retries,
+        );
+        counter(
+            &mut out,
+            "soma_tool_call_total",
+            "Tool attempts.",
+            &registry.tool_calls,
+        );
+        counter(
+            &mut out,
+            "soma_tokens_total",
+            "Tokens reported in reply costs.",
+            &registry.tokens,
+        );
+        let name = "soma_latency_seconds";
+        let _ = writeln!(out, "# HELP {name} Provider and tool attempt latency.");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        for ((kind, op), histogram) in &registry.latency {
+            let labels = format!("kind=\"{kind}\",op=\"{}\"", escape(op));
+            for (count, bound) in histogram.buckets.iter().zip(BUCKETS) {
+                let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {count}");
+            }
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(out, "{name}_sum{{{labels}}} {}", histogram.sum);
+            let _ = writeln!(out, "{name}_count{{{labels}}} {}", histogram.count);
+        }
+        out
+    }
+
+    /// Serves [`render`](Self::render) over HTTP at `GET /metrics` from a background
+    /// thread until the returned handle is dropped.
+    pub fn serve(self: Arc<Self>, addr: impl ToSocketAddrs) -> io::Result<MetricsServerHandle> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        thread::spawn({
+            let stop = stop.clone();
+            move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let _ = self.scrape(stream);
+                    }
+                }
+            }
+        });
+        Ok(MetricsServerHandle { addr, stop })
+    }
+
+    fn scrape(&self, mut stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 && !line.trim().is_empty() {
+            line.clear();
+        }
+        let (status, body) = if request.starts_with("GET /metrics") {
+            ("200 OK", self.render())
+        } else {
+            ("404 Not Found", String::new())
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\ncontent-type: text/plain; version=0.0.4\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        stream.flush()
+    }
+}
+
+impl MetricsSink for PrometheusMetricsSink {
+    fn ask(&self, kind: ProviderKind, op: &str, reply: &Reply) {
+        let labels = (kind_label(kind), op.to_string());
+        *self
+            .registry
+            .lock()
+            .unwrap()
+            .asks
+            .entry(labels)
+            .or_default() += 1;
+        self.observe(kind, op, reply);
+    }
+
+    fn retry(&self, kind: ProviderKind, op: &str) {
+        let labels = (kind_label(kind), op.to_string());
+        *self
+            .registry
+            .lock()
+            .unwrap()
+            .retries
+            .entry(labels)
+            .or_default() += 1;
+    }
+
+    fn tool_call(&self, kind: ProviderKind, tool: &str, reply: &Reply) {
+        let labels = (kind_label(kind), tool.to_string());
+        *self
+            .registry
+            .lock()
+            .unwrap()
+            .tool_calls
+            .entry(labels)
+            .or_default() += 1;
+        self.observe(kind, tool, reply);
+    }
+}
+
+/// A running `/metrics` endpoint; dropping it stops accepting connections.
+pub struct MetricsServerHandle {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+}
+
+impl MetricsServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for MetricsServerHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // wake the accept loop so it sees the flag
+        let _ = TcpStream::connect(self.addr);
+    }
+}
@@ -0,0 +1,253 @@
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::redact::Redactor;
+use crate::{Ask, HealthStatus, Provider, ProviderKind, Reply};
+
+/// Middleware intercepts an ask on its way to a provider and the reply on its way back.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, ask: Ask, next: Next<'_>) -> Reply;
+}
+
+/// Next forwards an ask to the rest of the middleware chain and finally the provider.
+///
+/// It carries the caller's cancellation token and, for a streamed ask, its chunk
+/// callback, so the provider is reached with `ask_cancellable` or `ask_stream` just
+/// as if it were called directly.
+pub struct Next<'a> {
+    middlewares: &'a [Box<dyn Middleware>],
+    provider: &'a dyn Provider,
+    token: &'a CancellationToken,
+    on_chunk: Option<&'a mut dyn FnMut(Value)>,
+}
+
+impl<'a> Next<'a> {
+    /// The caller's cancellation token, for middleware that waits on its own.
+    pub fn token(&self) -> &'a CancellationToken {
+        self.token
+    }
+
+    pub fn run(self, ask: Ask) -> Reply {
+        match self.middlewares.split_first() {
+            Some((head, rest)) => head.handle(
+                ask,
+                Next {
+                    middlewares: rest,
+                    provider: self.provider,
+                    token: self.token,
+                    on_chunk: self.on_chunk,
+                },
+            ),
+            None => match self.on_chunk {
+                Some(on_chunk) => self.provider.ask_stream(ask, self.token, on_chunk),
+                None => self.provider.ask_cancellable(ask, self.token),
+            },
+        }
+    }
+}
+
+/// Layered wraps a provider with an ordered stack of middleware.
+///
+/// Middleware added first sees the ask first and the reply last.
+pub struct Layered<P: Provider> {
+    provider: P,
+    middlewares: Vec<Box<dyn Middleware>>,
+}
+
+impl<P: Provider> Layered<P> {
+    pub fn new(provider: P) -> Self {
+        Self {
+            provider,
+            middlewares: Vec::new(),
+        }
+    }
+
+    pub fn layer<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+}
+
+impl<P: Provider> Provider for Layered<P> {
+    fn kind(&self) -> ProviderKind {
+        self.provider.kind()
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        Next {
+            middlewares: &self.middlewares,
+            provider: &self.provider,
+            token,
+            on_chunk: None,
+        }
+        .run(ask)
+    }
+
+    fn ask_stream(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: &mut dyn FnMut(Value),
+    ) -> Reply {
+        Next {
+            middlewares: &self.middlewares,
+            provider: &self.provider,
+            token,
+            on_chunk: Some(on_chunk),
+        }
+        .run(ask)
+    }
+
+    fn health_check(&self) -> HealthStatus {
+        self.provider.health_check()
+    }
+
+    fn close(&self) {
+        self.provider.close()
+    }
+}
+
+type EventSink = Box<dyn Fn(&Value) + Send + Sync>;
+
+/// LoggingMiddleware emits an `ask` event before and a `reply` event after each call.
+pub struct LoggingMiddleware {
+    sink: EventSink,
+    redactor: Option<Redactor>,
+}
+
+impl LoggingMiddleware {
+    pub fn new<F>(sink: F) -> Self
+    where
+        F: Fn(&Value) + Send + Sync + 'static,
+    {
+        Self {
+            sink: Box::new(sink),
+            redactor: None,
+        }
+    }
+
+    /// Masks events with `redactor`; the ask forwarded to the provider is unchanged.
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
+
+    /// Writes each event as a JSON line to stderr.
+    pub fn stderr() -> Self {
+        Self::new(|event| eprintln!("{event}"))
+    }
+}
+
+impl Middleware for LoggingMiddleware {
+    fn handle(&self, ask: Ask, next: Next<'_>) -> Reply {
+        let op = ask.op.clone();
+        let logged = match &self.redactor {
+            Some(redactor) => redactor.redact_ask(&ask),
+            None => ask.clone(),
+        };
+        (self.sink)(&json!({
+            "event": "ask",
+            "op": op,
+            "input": logged.input,
+            "context": logged.context,
+        }));
+        let reply = next.run(ask);
+        let (output, cost) = match &self.redactor {
+            Some(redactor) => (redactor.redact(&reply.output), redactor.redact(&reply.cost)),
+            None => (reply.output.clone(), reply.cost.clone()),
+        };
+        (self.sink)(&json!({
+            "event": "reply",
+            "op": op,
+            "ok": reply.ok,
+            "output": output,
+            "latency_ms": reply.latency_ms,
+            "cost": cost,
+        }));
+        reply
+    }
+}
+
+/// ContextMiddleware adds fixed fields to every ask context before forwarding it.
+///
+/// Built with [`ContextMiddleware::new`], its fields overwrite the caller's; built
+/// with [`ContextMiddleware::defaults`], the caller's fields win and only the gaps
+/// are filled.
+pub struct ContextMiddleware {
+    fields: serde_json::Map<String, Value>,
+    overwrite: bool,
+}
+
+impl ContextMiddleware {
+    /// Sets `fields` over whatever the caller put under the same keys. Non-object
+    /// values are ignored.
+    pub fn new(fields: Value) -> Self {
+        Self {
+            fields: object(fields),
+            overwrite: true,
+        }
+    }
+
+    /// Deep-merges `defaults` under the caller's context: keys the caller set are
+    /// kept, and objects present on both sides are merged recursively. Non-object
+    /// values are ignored.
+    pub fn defaults(defaults: Value) -> Self {
+        Self {
+            fields: object(defaults),
+            overwrite: false,
+        }
+    }
+
+    /// `ask` with the fields applied.
+    pub(crate) fn apply(&self, mut ask: Ask) -> Ask {
+        if !ask.context.is_object() {
+            ask.context = json!({});
+        }
+        if let Some(context) = ask.context.as_object_mut() {
+            if self.overwrite {
+                for (key, value) in &self.fields {
+                    context.insert(key.clone(), value.clone());
+                }
+            } else {
+                fill_missing(context, &self.fields);
+            }
+        }
+        ask
+    }
+}
+
+fn object(value: Value) -> serde_json::Map<String, Value> {
+    match value {
+        Value::Object(map) => map,
+        _ => serde_json::Map::new(),
+    }
+}
+
+/// Inserts the keys of `defaults` missing from `context`, recursing into objects
+/// present on both sides.
+fn fill_missing(
+    context: &mut serde_json::Map<String, Value>,
+    defaults: &serde_json::Map<String, Value>,
+) {
+    for (key, default) in defaults {
+        match (context.get_mut(key), default) {
+            (Some(Value::Object(existing)), Value::Object(default)) => {
+                fill_missing(existing, default)
+            }
+            (Some(_), _) => {}
+            (None, _) => {
+                context.insert(key.clone(), default.clone());
+            }
+        }
+    }
+}
+
+impl Middleware for ContextMiddleware {
+    fn handle(&self, ask: Ask, next: Next<'_>) -> Reply {
+        next.run(self.apply(ask))
+    }
+}
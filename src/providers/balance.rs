@@ -0,0 +1,178 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_util::sync::CancellationToken;
+
+use crate::{forward, Ask, Health, HealthStatus, Provider, ProviderKind, Reply};
+
+/// BalanceStrategy picks which pooled provider serves the next ask.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BalanceStrategy {
+    #[default]
+    RoundRobin,
+    /// The provider with the fewest asks currently in flight wins; ties go to the earliest.
+    LeastOutstanding,
+}
+
+struct Backend {
+    provider: Box<dyn Provider + Send + Sync>,
+    in_flight: AtomicUsize,
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl Backend {
+    fn healthy(&self, now: Instant) -> bool {
+        self.unhealthy_until
+            .lock()
+            .unwrap()
+            .is_none_or(|until| until <= now)
+    }
+}
+
+/// Decrements the in-flight count even if the provider panics.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// LoadBalancer spreads asks across a pool of equivalent providers.
+///
+/// A provider that returns a failed reply (other than a `tool_calls` request) is
+/// skipped for the cooldown period; when every provider is cooling down the whole
+/// pool is eligible again.
+pub struct LoadBalancer {
+    backends: Vec<Backend>,
+    strategy: BalanceStrategy,
+    cooldown: Duration,
+    next: AtomicUsize,
+}
+
+impl LoadBalancer {
+    pub fn new(providers: Vec<Box<dyn Provider + Send + Sync>>, strategy: BalanceStrategy) -> Self {
+        Self {
+            backends: providers
+                .into_iter()
+                .map(|provider| Backend {
+                    provider,
+                    in_flight: AtomicUsize::new(0),
+                    unhealthy_until: Mutex::new(None),
+                })
+                .collect(),
+            strategy,
+            cooldown: Duration::from_secs(5),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// How long a failing provider is skipped. Defaults to five seconds.
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Number of asks currently running on each pooled provider, in pool order.
+    pub fn in_flight(&self) -> Vec<usize> {
+        self.backends
+            .iter()
+            .map(|b| b.in_flight.load(Ordering::SeqCst))
+            .collect()
+    }
+
+    fn call(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: Option<&mut (dyn FnMut(Value) + '_)>,
+    ) -> Reply {
+        let Some(backend) = self.pick() else {
+            return Reply {
+                ok: false,
+                output: serde_json::json!({"error": "no providers configured"}),
+                latency_ms: 0,
+                cost: serde_json::json!({}),
+            };
+        };
+        backend.in_flight.fetch_add(1, Ordering::SeqCst);
+        let _guard = InFlight(&backend.in_flight);
+        let reply = forward(backend.provider.as_ref(), ask, token, on_chunk);
+        let mut unhealthy_until = backend.unhealthy_until.lock().unwrap();
+        if reply.ok || reply.is_tool_call() {
+            *unhealthy_until = None;
+        } else {
+            *unhealthy_until = Some(Instant::now() + self.cooldown);
+        }
+        reply
+    }
+
+    fn pick(&self) -> Option<&Backend> {
+        let now = Instant::now();
+        let healthy: Vec<&Backend> = self.backends.iter().filter(|b| b.healthy(now)).collect();
+        let pool: Vec<&Backend> = if healthy.is_empty() {
+            self.backends.iter().collect()
+        } else {
+            healthy
+        };
+        if pool.is_empty() {
+            return None;
+        }
+        match self.strategy {
+            BalanceStrategy::RoundRobin => {
+                let index = self.next.fetch_add(1, Ordering::SeqCst) % pool.len();
+                Some(pool[index])
+            }
+            BalanceStrategy::LeastOutstanding => pool
+                .into_iter()
+                .min_by_key(|b| b.in_flight.load(Ordering::SeqCst)),
+        }
+    }
+}
+
+impl Provider for LoadBalancer {
+    fn kind(&self) -> ProviderKind {
+        self.backends
+            .first()
+            .map(|b| b.provider.kind())
+            .unwrap_or(ProviderKind::Embedded)
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        self.call(ask, token, None)
+    }
+
+    fn ask_stream(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: &mut dyn FnMut(Value),
+    ) -> Reply {
+        self.call(ask, token, Some(on_chunk))
+    }
+
+    /// Probes every backend and reports them aggregated. A backend found down is
+    /// skipped for the cooldown period, as if an ask had failed.
+    fn health_check(&self) -> HealthStatus {
+        HealthStatus::aggregate(self.backends.iter().map(|backend| {
+            let status = backend.provider.health_check();
+            if status.health == Health::Down {
+                *backend.unhealthy_until.lock().unwrap() = Some(Instant::now() + self.cooldown);
+            }
+            status
+        }))
+    }
+
+    fn close(&self) {
+        for backend in &self.backends {
+            backend.provider.close();
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::{Ask, HealthStatus, Provider, ProviderKind, Reply};
+
+const POLL: Duration = Duration::from_millis(5);
+
+struct Item {
+    input: Value,
+    context: Value,
+    token: CancellationToken,
+    reply: Sender<Reply>,
+}
+
+/// Open batches per op, each tagged with a generation so a stale window timer
+/// never flushes the batch that replaced it.
+#[derive(Default)]
+struct Batches {
+    generation: u64,
+    open: HashMap<String, (u64, Vec<Item>)>,
+}
+
+/// BatchingProvider coalesces asks that arrive close together into one upstream ask.
+///
+/// Asks with the same op are buffered for up to `window`, or until `max_batch` have
+/// arrived, and sent as a single ask whose input is the array of their inputs and
+/// whose context is the first caller's with `batch_size` added. The inner provider
+/// must answer with an array of outputs in the same order; each caller gets its own
+/// element. The batch's cost is reported once, on the first caller's reply, so
+/// summing replies does not double count. A cancelled caller returns immediately
+/// and is left out of the batch if it has not been sent yet. Batches are sent with
+/// a plain ask, so streaming callers get no chunks.
+pub struct BatchingProvider<P: Provider + Send + Sync + 'static> {
+    provider: Arc<P>,
+    window: Duration,
+    max_batch: usize,
+    batches: Arc<Mutex<Batches>>,
+}
+
+impl<P: Provider + Send + Sync + 'static> BatchingProvider<P> {
+    pub fn new(provider: P, window: Duration, max_batch: usize) -> Self {
+        Self {
+            provider: Arc::new(provider),
+            window,
+            max_batch: max_batch.max(1),
+            batches: Arc::new(Mutex::new(Batches::default())),
+        }
+    }
+
+    fn enqueue(&self, op: String, item: Item) {
+        let mut batches = self.batches.lock().unwrap();
+        let Batches { generation, open } = &mut *batches;
+        let opened = !open.contains_key(&op);
+        if opened {
+            *generation += 1;
+        }
+        let (batch, items) = open.entry(op.clone()).or_insert((*generation, Vec::new()));
+        let batch = *batch;
+        items.push(item);
+        if items.len() >= self.max_batch {
+            let (_, items) = open.remove(&op).unwrap();
+            let provider = self.provider.clone();
+            thread::spawn(move || dispatch(provider.as_ref(), op, items));
+        } else if opened {
+            let provider = self.provider.clone();
+            let batches = self.batches.clone();
+            let window = self.window;
+            thread::spawn(move || {
+                thread::sleep(window);
+                let items = {
+                    let open = &mut batches.lock().unwrap().open;
+                    match open.get(&op) {
+                        Some((current, _)) if *current == batch => open.remove(&op),
+                        _ => None,
+                    }
+                };
+                if let Some((_, items)) = items {
+                    dispatch(provider.as_ref(), op, items);
+                }
+            });
+        }
+    }
+}
+
+fn dispatch<P: Provider>(provider: &P, op: String, items: Vec<Item>) {
+    let items: Vec<Item> = items
+        .into_iter()
+        .filter(|item| !item.token.is_cancelled())
+        .collect();
+    let Some(first) = items.first() else {
+        return;
+    };
+    let mut context = first.context.clone();
+    if let Some(map) = context.as_object_mut() {
+        map.insert("batch_size".into(), json!(items.len()));
+    }
+    let reply = provider.ask(Ask {
+        op,
+        input: Value::Array(items.iter().map(|item| item.input.clone()).collect()),
+        context,
+    });
+    let outputs = match reply.output.as_array() {
+        Some(outputs) if reply.ok && outputs.len() == items.len() => Some(outputs.clone()),
+        _ => None,
+    };
+    for (i, item) in items.into_iter().enumerate() {
+        let cost = if i == 0 {
+            reply.cost.clone()
+        } else {
+            json!({})
+        };
+        let split = match &outputs {
+            Some(outputs) => Reply {
+                ok: true,
+                output: outputs[i].clone(),
+                latency_ms: reply.latency_ms,
+                cost,
+            },
+            None if reply.ok => Reply {
+                ok: false,
+                output: json!({"error": "batch reply does not match batch size"}),
+                latency_ms: reply.latency_ms,
+                cost,
+            },
+            None => Reply {
+                ok: false,
+                output: reply.output.clone(),
+                latency_ms: reply.latency_ms,
+                cost,
+            },
+        };
+        let _ = item.reply.send(split);
+    }
+}
+
+impl<P: Provider + Send + Sync + 'static> Provider for BatchingProvider<P> {
+    fn kind(&self) -> ProviderKind {
+        self.provider.kind()
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        let (tx, rx) = mpsc::channel();
+        self.enqueue(
+            ask.op,
+            Item {
+                input: ask.input,
+                context: ask.context,
+                token: token.clone(),
+                reply: tx,
+            },
+        );
+        loop {
+            match rx.recv_timeout(POLL) {
+                Ok(reply) => return reply,
+                Err(RecvTimeoutError::Timeout) if !token.is_cancelled() => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    return Reply {
+                        ok: false,
+                        output: json!({"error": "cancelled"}),
+                        latency_ms: 0,
+                        cost: json!({}),
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Reply {
+                        ok: false,
+                        output: json!({"error": "batch dropped"}),
+                        latency_ms: 0,
+                        cost: json!({}),
+                    }
+                }
+            }
+        }
+    }
+
+    fn health_check(&self) -> HealthStatus {
+        self.provider.health_check()
+    }
+
+    fn close(&self) {
+        self.provider.close()
+    }
+}
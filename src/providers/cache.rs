@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::{forward, Ask, HealthStatus, Provider, ProviderKind, Reply};
+
+/// Context keys that differ on every call without changing the answer.
+const VOLATILE: [&str; 5] = [
+    "request_id",
+    "idempotency_key",
+    "traceparent",
+    "remaining_tokens",
+    "no_cache",
+];
+
+/// Storage behind a [`CachingProvider`], keyed by [`cache_key`].
+pub trait ReplyCache: Send + Sync {
+    /// Returns the stored reply for `key` unless it is missing or expired.
+    fn get(&self, key: &str) -> Option<Reply>;
+    fn put(&self, key: &str, reply: &Reply);
+}
+
+/// The cache key for `ask` against a provider of `kind`: the op, the input and the
+/// context minus per-call bookkeeping such as `request_id`.
+pub fn cache_key(kind: ProviderKind, ask: &Ask) -> String {
+    let mut context = ask.context.clone();
+    if let Some(map) = context.as_object_mut() {
+        for key in VOLATILE {
+            map.remove(key);
+        }
+    }
+    json!([format!("{kind:?}"), ask.op, ask.input, context]).to_string()
+}
+
+struct Entry {
+    reply: Reply,
+    stored: Instant,
+    used: u64,
+}
+
+/// An in-memory least-recently-used cache whose entries expire after a TTL.
+pub struct MemoryCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<(HashMap<String, Entry>, u64)>,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            ttl,
+            entries: Mutex::new((HashMap::new(), 0)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ReplyCache for MemoryCache {
+    fn get(&self, key: &str) -> Option<Reply> {
+        let mut guard = self.entries.lock().unwrap();
+        let (entries, clock) = &mut *guard;
+        *clock += 1;
+        let entry = entries.get_mut(key)?;
+        if entry.stored.elapsed() >= self.ttl {
+            entries.remove(key);
+            return None;
+        }
+        entry.used = *clock;
+        Some(entry.reply.clone())
+    }
+
+    fn put(&self, key: &str, reply: &Reply) {
+        let mut guard = self.entries.lock().unwrap();
+        let (entries, clock) = &mut *guard;
+        *clock += 1;
+        if !entries.contains_key(key) && entries.len() >= self.capacity {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key.to_string(),
+            Entry {
+                reply: reply.clone(),
+                stored: Instant::now(),
+                used: *clock,
+            },
+        );
+    }
+}
+
+/// CachingProvider answers repeated asks from a cache instead of the inner provider.
+///
+/// Only successful replies are stored. A hit comes back with zero latency and
+/// `cost: {"cache_hit": true}`, since nothing was spent producing it. Set
+/// `"no_cache": true` in the ask's context to bypass the cache for that ask.
+/// Meant for deterministic, read-only calls; anything with side effects should not
+/// be wrapped.
+pub struct CachingProvider<P: Provider, C: ReplyCache = MemoryCache> {
+    provider: P,
+    cache: C,
+}
+
+impl<P: Provider> CachingProvider<P> {
+    /// Caches up to `capacity` replies in memory for `ttl` each.
+    pub fn new(provider: P, capacity: usize, ttl: Duration) -> Self {
+        Self::with_cache(provider, MemoryCache::new(capacity, ttl))
+    }
+}
+
+impl<P: Provider, C: ReplyCache> CachingProvider<P, C> {
+    pub fn with_cache(provider: P, cache: C) -> Self {
+        Self { provider, cache }
+    }
+
+    pub fn cache(&self) -> &C {
+        &self.cache
+    }
+
+    fn call(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: Option<&mut (dyn FnMut(Value) + '_)>,
+    ) -> Reply {
+        let bypass = ask.context.get("no_cache").and_then(Value::as_bool) == Some(true);
+        if bypass {
+            return forward(&self.provider, ask, token, on_chunk);
+        }
+        let key = cache_key(self.provider.kind(), &ask);
+        if let Some(reply) = self.cache.get(&key) {
+            return Reply {
+                latency_ms: 0,
+                cost: json!({"cache_hit": true}),
+                ..reply
+            };
+        }
+        let reply = forward(&self.provider, ask, token, on_chunk);
+        if reply.ok {
+            self.cache.put(&key, &reply);
+        }
+        reply
+    }
+}
+
+impl<P: Provider, C: ReplyCache> Provider for CachingProvider<P, C> {
+    fn kind(&self) -> ProviderKind {
+        self.provider.kind()
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        self.call(ask, token, None)
+    }
+
+    /// A miss streams from the inner provider; a hit produces no chunks.
+    fn ask_stream(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: &mut dyn FnMut(Value),
+    ) -> Reply {
+        self.call(ask, token, Some(on_chunk))
+    }
+
+    fn health_check(&self) -> HealthStatus {
+        self.provider.health_check()
+    }
+
+    fn close(&self) {
+        self.provider.close()
+    }
+}
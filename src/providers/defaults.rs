@@ -0,0 +1,59 @@
+use serde_json::Value;
+use tokio_util::sync::CancellationToken;
+
+use crate::middleware::ContextMiddleware;
+use crate::{Ask, HealthStatus, Provider, ProviderKind, Reply};
+
+/// ContextDefaults deep-merges a base context into every ask before forwarding it,
+/// so settings like `dialect`, `tools` and `tool_choice` live in one place.
+///
+/// Fields the caller sets win over the defaults; this is the provider form of
+/// [`ContextMiddleware::defaults`], for stacks without a [`Layered`](crate::middleware::Layered).
+pub struct ContextDefaults<P: Provider> {
+    provider: P,
+    defaults: ContextMiddleware,
+}
+
+impl<P: Provider> ContextDefaults<P> {
+    pub fn new(provider: P, defaults: Value) -> Self {
+        Self {
+            provider,
+            defaults: ContextMiddleware::defaults(defaults),
+        }
+    }
+
+    fn apply(&self, ask: Ask) -> Ask {
+        self.defaults.apply(ask)
+    }
+}
+
+impl<P: Provider> Provider for ContextDefaults<P> {
+    fn kind(&self) -> ProviderKind {
+        self.provider.kind()
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.provider.ask(self.apply(ask))
+    }
+
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        self.provider.ask_cancellable(self.apply(ask), token)
+    }
+
+    fn ask_stream(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: &mut dyn FnMut(Value),
+    ) -> Reply {
+        self.provider.ask_stream(self.apply(ask), token, on_chunk)
+    }
+
+    fn health_check(&self) -> HealthStatus {
+        self.provider.health_check()
+    }
+
+    fn close(&self) {
+        self.provider.close()
+    }
+}
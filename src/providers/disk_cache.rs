@@ -0,0 +1,121 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::cache::ReplyCache;
+use crate::{fnv1a, Reply};
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    key: String,
+    stored_ms: u64,
+    reply: Reply,
+}
+
+/// Numbers temp files so concurrent writers in this process never share one.
+static TMP_SEQ: AtomicU64 = AtomicU64::new(0);
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// DiskCache keeps replies as JSON files in a directory so they survive restarts.
+///
+/// Each entry is written to a temporary file and renamed into place, so concurrent
+/// processes sharing the directory only ever read whole entries. When the directory
+/// holds more than `max_entries`, the oldest files are removed.
+pub struct DiskCache {
+    dir: PathBuf,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+impl DiskCache {
+    /// Opens (creating if needed) a cache in `dir` whose entries expire after `ttl`.
+    pub fn open(dir: impl AsRef<Path>, ttl: Duration) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            ttl,
+            max_entries: 1_000,
+        })
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.json", fnv1a(key.as_bytes())))
+    }
+
+    fn entries(&self) -> Vec<(PathBuf, SystemTime)> {
+        let Ok(dir) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        dir.flatten()
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|e| Some((e.path(), e.metadata().ok()?.modified().ok()?)))
+            .collect()
+    }
+
+    fn evict(&self) {
+        let mut entries = self.entries();
+        if entries.len() <= self.max_entries {
+            return;
+        }
+        entries.sort_by_key(|(_, modified)| *modified);
+        let excess = entries.len() - self.max_entries;
+        for (path, _) in entries.into_iter().take(excess) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    fn write(&self, key: &str, reply: &Reply) -> io::Result<()> {
+        let record = Record {
+            key: key.to_string(),
+            stored_ms: now_ms(),
+            reply: reply.clone(),
+        };
+        let path = self.path(key);
+        let seq = TMP_SEQ.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_extension(format!("{}.{seq}.tmp", std::process::id()));
+        fs::write(&tmp, serde_json::to_vec(&record)?)?;
+        fs::rename(&tmp, &path).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+    }
+}
+
+impl ReplyCache for DiskCache {
+    fn get(&self, key: &str) -> Option<Reply> {
+        let path = self.path(key);
+        let record: Record = serde_json::from_slice(&fs::read(&path).ok()?).ok()?;
+        // a hash collision reads another key's entry
+        if record.key != key {
+            return None;
+        }
+        if now_ms().saturating_sub(record.stored_ms) >= self.ttl.as_millis() as u64 {
+            let _ = fs::remove_file(path);
+            return None;
+        }
+        Some(record.reply)
+    }
+
+    /// Write failures are ignored; the reply simply is not cached.
+    fn put(&self, key: &str, reply: &Reply) {
+        if self.write(key, reply).is_ok() {
+            self.evict();
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::{forward, merge_cost, Ask, HealthStatus, Provider, ProviderKind, Reply};
+
+type Retriable = dyn Fn(&Reply) -> bool + Send + Sync;
+
+/// FallbackProvider tries an ordered list of providers until one succeeds.
+///
+/// A failed reply moves on to the next provider unless it is non-retriable; by
+/// default replies requesting `tool_calls` are returned as-is. The successful
+/// reply's cost gains `{"fallback": {"index", "attempts"}}` and includes the
+/// cost of every failed attempt before it.
+pub struct FallbackProvider {
+    providers: Vec<Box<dyn Provider + Send + Sync>>,
+    retriable: Box<Retriable>,
+}
+
+impl FallbackProvider {
+    pub fn new(providers: Vec<Box<dyn Provider + Send + Sync>>) -> Self {
+        Self {
+            providers,
+            retriable: Box::new(|reply: &Reply| !reply.is_tool_call()),
+        }
+    }
+
+    /// Overrides which failed replies fall through to the next provider.
+    pub fn with_retriable(
+        mut self,
+        retriable: impl Fn(&Reply) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.retriable = Box::new(retriable);
+        self
+    }
+
+    fn call(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        mut on_chunk: Option<&mut (dyn FnMut(Value) + '_)>,
+    ) -> Reply {
+        let mut cost = json!({});
+        let mut latency_ms = 0;
+        let mut last = None;
+        for (index, provider) in self.providers.iter().enumerate() {
+            if token.is_cancelled() {
+                return Reply {
+                    ok: false,
+                    output: json!({"error": "cancelled"}),
+                    latency_ms,
+                    cost,
+                };
+            }
+            let mut reply = forward(
+                provider.as_ref(),
+                ask.clone(),
+                token,
+                on_chunk.as_deref_mut(),
+            );
+            latency_ms += reply.latency_ms;
+            merge_cost(&mut cost, &reply.cost);
+            if reply.ok || !(self.retriable)(&reply) {
+                if let Value::Object(map) = &mut cost {
+                    map.insert(
+                        "fallback".into(),
+                        json!({"index": index, "attempts": index + 1}),
+                    );
+                }
+                reply.latency_ms = latency_ms;
+                reply.cost = cost;
+                return reply;
+            }
+            last = Some(reply);
+        }
+        match last {
+            Some(mut reply) => {
+                reply.latency_ms = latency_ms;
+                reply.cost = cost;
+                reply
+            }
+            None => Reply {
+                ok: false,
+                output: json!({"error": "no providers configured"}),
+                latency_ms: 0,
+                cost: json!({}),
+            },
+        }
+    }
+}
+
+impl Provider for FallbackProvider {
+    fn kind(&self) -> ProviderKind {
+        self.providers
+            .first()
+            .map(|p| p.kind())
+            .unwrap_or(ProviderKind::Embedded)
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        self.call(ask, token, None)
+    }
+
+    /// Chunks of an attempt that later fails are passed on as they come, before the
+    /// next provider's.
+    fn ask_stream(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: &mut dyn FnMut(Value),
+    ) -> Reply {
+        self.call(ask, token, Some(on_chunk))
+    }
+
+    /// Reports every provider in the chain, aggregated.
+    fn health_check(&self) -> HealthStatus {
+        HealthStatus::aggregate(self.providers.iter().map(|p| p.health_check()))
+    }
+
+    fn close(&self) {
+        for provider in &self.providers {
+            provider.close();
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+
+use crate::{Ask, Provider, ProviderKind, Reply};
+
+type Matcher = Box<dyn Fn(&Ask, &str, &Value) -> bool + Send + Sync>;
+
+/// FixtureProvider answers asks from JSON fixtures, for running agent flows offline.
+///
+/// Each `*.json` file in the directory is a fixture named after its file stem. An ask
+/// matches a fixture when its op is the fixture's name or its last user message equals
+/// the fixture's (see [`FixtureProvider::with_matcher`] to choose differently); the
+/// first match in name order answers:
+///
+/// - `expected_tool_call` / `expected_tool_calls` (`{name, arguments}`) become a
+///   `tool_calls` request,
+/// - otherwise `response` is the `ok` output.
+///
+/// Follow-up asks carrying tool results (`tool` or `tools` in the context) are
+/// answered by the fixture matched earlier for the same `request_id`: its `response`
+/// if it has one, else the tool output itself. Unmatched asks fail with
+/// `{"error": "no matching fixture", "available"}`.
+pub struct FixtureProvider {
+    fixtures: Vec<(String, Value)>,
+    matcher: Matcher,
+    matched: Mutex<HashMap<String, usize>>,
+}
+
+impl FixtureProvider {
+    /// Loads every `*.json` file in `dir`; a file that is not JSON is an
+    /// `InvalidData` error.
+    pub fn from_dir(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let mut fixtures = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let fixture = serde_json::from_slice(&fs::read(&path)?).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {e}", path.display()),
+                )
+            })?;
+            fixtures.push((name.to_string(), fixture));
+        }
+        Ok(Self::new(fixtures))
+    }
+
+    /// Serves `fixtures` given as `(name, fixture)` pairs.
+    pub fn new(mut fixtures: Vec<(String, Value)>) -> Self {
+        fixtures.sort_by(|a, b| a.0.cmp(&b.0));
+        Self {
+            fixtures,
+            matcher: Box::new(default_match),
+            matched: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Replaces the default matching with `matcher(ask, name, fixture)`.
+    pub fn with_matcher<F>(mut self, matcher: F) -> Self
+    where
+        F: Fn(&Ask, &str, &Value) -> bool + Send + Sync + 'static,
+    {
+        self.matcher = Box::new(matcher);
+        self
+    }
+
+    /// The fixture names, sorted.
+    pub fn names(&self) -> Vec<String> {
+        self.fixtures.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    fn reply(ok: bool, output: Value) -> Reply {
+        Reply {
+            ok,
+            output,
+            latency_ms: 0,
+            cost: json!({}),
+        }
+    }
+}
+
+impl Provider for FixtureProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Embedded
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        let request_id = ask.context["request_id"].as_str().unwrap_or_default();
+        let follow_up = ask.context.get("tool").is_some() || ask.context.get("tools").is_some();
+        if follow_up {
+            let earlier = self.matched.lock().unwrap().get(request_id).copied();
+            if let Some(index) = earlier {
+                return match self.fixtures[index].1.get("response") {
+                    Some(response) => Self::reply(true, response.clone()),
+                    None => Self::reply(true, ask.input),
+                };
+            }
+        }
+        let Some(index) = self
+            .fixtures
+            .iter()
+            .position(|(name, fixture)| (self.matcher)(&ask, name, fixture))
+        else {
+            return Self::reply(
+                false,
+                json!({"error": "no matching fixture", "available": self.names()}),
+            );
+        };
+        self.matched
+            .lock()
+            .unwrap()
+            .insert(request_id.to_string(), index);
+        let fixture = &self.fixtures[index].1;
+        let calls = match (
+            fixture.get("expected_tool_call"),
+            fixture["expected_tool_calls"].as_array(),
+        ) {
+            (Some(call), _) => vec![call],
+            (None, Some(calls)) => calls.iter().collect(),
+            (None, None) => Vec::new(),
+        };
+        if calls.is_empty() {
+            let response = fixture.get("response").cloned().unwrap_or(Value::Null);
+            return Self::reply(true, response);
+        }
+        let tool_calls: Vec<Value> = calls
+            .into_iter()
+            .map(|call| json!({"op": call["name"], "input": call["arguments"]}))
+            .collect();
+        Self::reply(false, json!({"tool_calls": tool_calls}))
+    }
+}
+
+/// Matches on the op naming the fixture or on the last user message.
+fn default_match(ask: &Ask, name: &str, fixture: &Value) -> bool {
+    if ask.op == name {
+        return true;
+    }
+    let asked = match &ask.input {
+        Value::String(text) => Some(text.as_str()),
+        Value::Object(fields) => fields.get("messages").and_then(last_user_text),
+        messages => last_user_text(messages),
+    };
+    asked.is_some() && asked == fixture.get("messages").and_then(last_user_text)
+}
+
+fn last_user_text(messages: &Value) -> Option<&str> {
+    messages
+        .as_array()?
+        .iter()
+        .rev()
+        .find(|m| m["role"] == "user")?
+        .get("content")?
+        .as_str()
+}
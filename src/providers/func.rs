@@ -0,0 +1,31 @@
+use crate::{Ask, Provider, ProviderKind, Reply};
+
+/// FnProvider turns a closure into a provider for tests and quick glue code.
+pub struct FnProvider<F> {
+    kind: ProviderKind,
+    f: F,
+}
+
+impl<F: Fn(Ask) -> Reply> FnProvider<F> {
+    pub fn new(f: F) -> Self {
+        Self {
+            kind: ProviderKind::Embedded,
+            f,
+        }
+    }
+
+    pub fn with_kind(mut self, kind: ProviderKind) -> Self {
+        self.kind = kind;
+        self
+    }
+}
+
+impl<F: Fn(Ask) -> Reply> Provider for FnProvider<F> {
+    fn kind(&self) -> ProviderKind {
+        self.kind
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        (self.f)(ask)
+    }
+}
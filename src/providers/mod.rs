@@ -0,0 +1,25 @@
+pub mod balance;
+pub mod batch;
+pub mod cache;
+pub mod defaults;
+pub mod disk_cache;
+pub mod fallback;
+pub mod fixture;
+pub mod func;
+pub mod rate_limit;
+pub mod registry;
+pub mod router;
+pub mod single_flight;
+
+pub use balance::{BalanceStrategy, LoadBalancer};
+pub use batch::BatchingProvider;
+pub use cache::{CachingProvider, MemoryCache, ReplyCache};
+pub use defaults::ContextDefaults;
+pub use disk_cache::DiskCache;
+pub use fallback::FallbackProvider;
+pub use fixture::FixtureProvider;
+pub use func::FnProvider;
+pub use rate_limit::RateLimiter;
+pub use registry::EmbeddedRegistry;
+pub use router::CostRouter;
+pub use single_flight::SingleFlightProvider;
@@ -0,0 +1,190 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::{forward, Ask, HealthStatus, Provider, ProviderKind, Reply};
+
+const POLL: Duration = Duration::from_millis(5);
+
+struct Buckets {
+    requests: f64,
+    request_capacity: f64,
+    /// `None` when requests are unlimited.
+    requests_per_second: Option<f64>,
+    tokens: Option<f64>,
+    tokens_per_minute: f64,
+    refilled: Instant,
+}
+
+impl Buckets {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.refilled = now;
+        if let Some(rate) = self.requests_per_second {
+            self.requests = (self.requests + elapsed * rate).min(self.request_capacity);
+        }
+        if let Some(tokens) = self.tokens.as_mut() {
+            *tokens =
+                (*tokens + elapsed * self.tokens_per_minute / 60.0).min(self.tokens_per_minute);
+        }
+    }
+
+    /// Takes a request slot, or returns how long until one is available.
+    fn try_acquire(&mut self) -> Result<(), Duration> {
+        self.refill();
+        let mut wait: f64 = 0.0;
+        if let Some(rate) = self.requests_per_second {
+            if self.requests < 1.0 {
+                wait = wait.max((1.0 - self.requests) / rate);
+            }
+        }
+        if let Some(tokens) = self.tokens {
+            if tokens < 1.0 {
+                wait = wait.max((1.0 - tokens) * 60.0 / self.tokens_per_minute);
+            }
+        }
+        if wait > 0.0 {
+            return Err(Duration::from_secs_f64(wait));
+        }
+        if self.requests_per_second.is_some() {
+            self.requests -= 1.0;
+        }
+        Ok(())
+    }
+}
+
+/// RateLimiter gates a provider with a requests-per-second token bucket and an
+/// optional tokens-per-minute budget charged from `cost.total_tokens`.
+///
+/// Clones share the same buckets, so one limit can cover several call sites.
+pub struct RateLimiter<P: Provider> {
+    provider: Arc<P>,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl<P: Provider> Clone for RateLimiter<P> {
+    fn clone(&self) -> Self {
+        Self {
+            provider: self.provider.clone(),
+            buckets: self.buckets.clone(),
+        }
+    }
+}
+
+impl<P: Provider> RateLimiter<P> {
+    /// Allows one request every `1 / requests_per_second` seconds with no burst.
+    /// A rate of zero (or any non-positive or non-finite rate) leaves requests unlimited.
+    pub fn new(provider: P, requests_per_second: f64) -> Self {
+        Self {
+            provider: Arc::new(provider),
+            buckets: Arc::new(Mutex::new(Buckets {
+                requests: 1.0,
+                request_capacity: 1.0,
+                requests_per_second: (requests_per_second.is_finite() && requests_per_second > 0.0)
+                    .then_some(requests_per_second),
+                tokens: None,
+                tokens_per_minute: 0.0,
+                refilled: Instant::now(),
+            })),
+        }
+    }
+
+    /// Lets up to `burst` requests through back to back before spacing kicks in.
+    pub fn with_burst(self, burst: u32) -> Self {
+        {
+            let mut buckets = self.buckets.lock().unwrap();
+            buckets.request_capacity = f64::from(burst.max(1));
+            buckets.requests = buckets.request_capacity;
+        }
+        self
+    }
+
+    /// Caps the tokens charged per minute; zero leaves tokens unlimited.
+    pub fn with_tokens_per_minute(self, tokens_per_minute: u64) -> Self {
+        {
+            let mut buckets = self.buckets.lock().unwrap();
+            buckets.tokens_per_minute = tokens_per_minute as f64;
+            buckets.tokens = (tokens_per_minute > 0).then_some(tokens_per_minute as f64);
+        }
+        self
+    }
+
+    fn acquire(&self, token: &CancellationToken) -> bool {
+        loop {
+            if token.is_cancelled() {
+                return false;
+            }
+            let wait = match self.buckets.lock().unwrap().try_acquire() {
+                Ok(()) => return true,
+                Err(wait) => wait,
+            };
+            thread::sleep(wait.min(POLL));
+        }
+    }
+
+    fn call(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: Option<&mut (dyn FnMut(Value) + '_)>,
+    ) -> Reply {
+        if !self.acquire(token) {
+            return Reply {
+                ok: false,
+                output: json!({"error": "cancelled"}),
+                latency_ms: 0,
+                cost: json!({}),
+            };
+        }
+        let reply = forward(&*self.provider, ask, token, on_chunk);
+        self.charge(&reply);
+        reply
+    }
+
+    fn charge(&self, reply: &Reply) {
+        let used = reply
+            .cost
+            .get("total_tokens")
+            .and_then(|v| v.as_f64())
+            .unwrap_or(0.0);
+        let mut buckets = self.buckets.lock().unwrap();
+        if let Some(tokens) = buckets.tokens.as_mut() {
+            *tokens -= used;
+        }
+    }
+}
+
+impl<P: Provider> Provider for RateLimiter<P> {
+    fn kind(&self) -> ProviderKind {
+        self.provider.kind()
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        self.call(ask, token, None)
+    }
+
+    fn ask_stream(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: &mut dyn FnMut(Value),
+    ) -> Reply {
+        self.call(ask, token, Some(on_chunk))
+    }
+
+    fn health_check(&self) -> HealthStatus {
+        self.provider.health_check()
+    }
+
+    fn close(&self) {
+        self.provider.close()
+    }
+}
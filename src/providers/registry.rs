@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+
+use crate::{Ask, Provider, ProviderKind, Reply};
+
+type LocalOp = Arc<dyn Fn(Value) -> Reply + Send + Sync>;
+
+/// EmbeddedRegistry serves many named Rust functions as one in-process provider.
+///
+/// `ask` calls the function registered under `ask.op` with the input. An op nobody
+/// registered fails with `{"error": "unknown op", "op", "available"}`.
+#[derive(Clone, Default)]
+pub struct EmbeddedRegistry {
+    ops: HashMap<String, LocalOp>,
+}
+
+impl EmbeddedRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_op<F>(mut self, op: impl Into<String>, f: F) -> Self
+    where
+        F: Fn(Value) -> Reply + Send + Sync + 'static,
+    {
+        self.register(op, f);
+        self
+    }
+
+    /// Registers `f` under `op`, replacing any earlier function of that name.
+    pub fn register<F>(&mut self, op: impl Into<String>, f: F)
+    where
+        F: Fn(Value) -> Reply + Send + Sync + 'static,
+    {
+        self.ops.insert(op.into(), Arc::new(f));
+    }
+
+    /// The registered op names, sorted.
+    pub fn ops(&self) -> Vec<String> {
+        let mut ops: Vec<String> = self.ops.keys().cloned().collect();
+        ops.sort();
+        ops
+    }
+}
+
+impl Provider for EmbeddedRegistry {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Embedded
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        match self.ops.get(&ask.op) {
+            Some(f) => f(ask.input),
+            None => Reply {
+                ok: false,
+                output: json!({"error": "unknown op", "op": ask.op, "available": self.ops()}),
+                latency_ms: 0,
+                cost: json!({}),
+            },
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::{forward, Ask, HealthStatus, Provider, ProviderKind, ReasoningPolicy, Reply};
+
+struct Route {
+    label: String,
+    price: f64,
+    max_score: usize,
+    provider: Box<dyn Provider + Send + Sync>,
+}
+
+/// CostRouter sends each ask to the cheapest provider able to handle it.
+///
+/// Asks are scored with [`ReasoningPolicy::score`], counting `context.tools`. A route
+/// accepts asks scoring up to its `max_score`; when none does, the route with the
+/// highest `max_score` takes the ask. The chosen label is added to the forwarded
+/// ask's context and to the reply cost as `"route"`.
+pub struct CostRouter {
+    policy: ReasoningPolicy,
+    routes: Vec<Route>,
+}
+
+impl CostRouter {
+    pub fn new(policy: ReasoningPolicy) -> Self {
+        Self {
+            policy,
+            routes: Vec::new(),
+        }
+    }
+
+    /// Adds a provider costing `price` (any consistent unit) for asks scoring up to `max_score`.
+    pub fn route(
+        mut self,
+        label: impl Into<String>,
+        price: f64,
+        max_score: usize,
+        provider: impl Provider + Send + Sync + 'static,
+    ) -> Self {
+        self.routes.push(Route {
+            label: label.into(),
+            price,
+            max_score,
+            provider: Box::new(provider),
+        });
+        self
+    }
+
+    /// The label of the route an ask would take, without calling it.
+    pub fn select(&self, ask: &Ask) -> Option<&str> {
+        self.pick(ask).map(|r| r.label.as_str())
+    }
+
+    fn call(
+        &self,
+        mut ask: Ask,
+        token: &CancellationToken,
+        on_chunk: Option<&mut (dyn FnMut(Value) + '_)>,
+    ) -> Reply {
+        let Some(route) = self.pick(&ask) else {
+            return Reply {
+                ok: false,
+                output: json!({"error": "no providers configured"}),
+                latency_ms: 0,
+                cost: json!({}),
+            };
+        };
+        if let Value::Object(context) = &mut ask.context {
+            context.insert("route".into(), json!(route.label));
+        }
+        let mut reply = forward(route.provider.as_ref(), ask, token, on_chunk);
+        if !reply.cost.is_object() {
+            reply.cost = json!({});
+        }
+        reply.cost["route"] = json!(route.label);
+        reply
+    }
+
+    fn pick(&self, ask: &Ask) -> Option<&Route> {
+        let tool_count = ask
+            .context
+            .get("tools")
+            .and_then(|v| v.as_array())
+            .map_or(0, |t| t.len());
+        let score = self.policy.score(&ask.input, tool_count);
+        self.routes
+            .iter()
+            .filter(|r| r.max_score >= score)
+            .min_by(|a, b| a.price.total_cmp(&b.price))
+            .or_else(|| self.routes.iter().max_by_key(|r| r.max_score))
+    }
+}
+
+impl Provider for CostRouter {
+    fn kind(&self) -> ProviderKind {
+        self.routes
+            .first()
+            .map(|r| r.provider.kind())
+            .unwrap_or(ProviderKind::Embedded)
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        self.call(ask, token, None)
+    }
+
+    fn ask_stream(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: &mut dyn FnMut(Value),
+    ) -> Reply {
+        self.call(ask, token, Some(on_chunk))
+    }
+
+    /// Reports every route, aggregated.
+    fn health_check(&self) -> HealthStatus {
+        HealthStatus::aggregate(self.routes.iter().map(|r| r.provider.health_check()))
+    }
+
+    fn close(&self) {
+        for route in &self.routes {
+            route.provider.close();
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use super::cache::cache_key;
+use crate::{forward, Ask, HealthStatus, Provider, ProviderKind, Reply};
+
+const POLL: Duration = Duration::from_millis(5);
+
+/// One in-flight ask; `reply` is filled once the leader's call returns.
+#[derive(Default)]
+struct Flight {
+    reply: Mutex<Option<Reply>>,
+    done: Condvar,
+}
+
+/// Publishes the leader's result and retires the flight, even if the leader panics.
+struct Landing<'a> {
+    flights: &'a Mutex<HashMap<String, Arc<Flight>>>,
+    key: &'a str,
+    flight: &'a Flight,
+    reply: Option<Reply>,
+}
+
+impl Drop for Landing<'_> {
+    fn drop(&mut self) {
+        self.flights.lock().unwrap().remove(self.key);
+        let reply = self.reply.take().unwrap_or_else(|| Reply {
+            ok: false,
+            output: json!({"error": "shared call failed"}),
+            latency_ms: 0,
+            cost: json!({}),
+        });
+        *self.flight.reply.lock().unwrap() = Some(reply);
+        self.flight.done.notify_all();
+    }
+}
+
+/// SingleFlightProvider collapses concurrent identical asks into one upstream call.
+///
+/// Asks are identical when their [`cache_key`] matches. The first caller runs the
+/// ask; callers arriving while it is in flight wait for the same reply instead of
+/// sending their own. Nothing is kept once the call returns, so unlike
+/// [`super::CachingProvider`] a later ask always reaches the inner provider. The cost
+/// is reported on the first caller's reply only; the others get
+/// `cost: {"coalesced": true}`. The first caller's token governs the shared call; a
+/// cancelled waiter returns immediately without affecting it.
+pub struct SingleFlightProvider<P: Provider> {
+    provider: P,
+    flights: Mutex<HashMap<String, Arc<Flight>>>,
+}
+
+impl<P: Provider> SingleFlightProvider<P> {
+    pub fn new(provider: P) -> Self {
+        Self {
+            provider,
+            flights: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Number of distinct asks currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.flights.lock().unwrap().len()
+    }
+
+    fn call(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: Option<&mut (dyn FnMut(Value) + '_)>,
+    ) -> Reply {
+        let key = cache_key(self.provider.kind(), &ask);
+        let (flight, leader) = {
+            let mut flights = self.flights.lock().unwrap();
+            match flights.get(&key) {
+                Some(flight) => (flight.clone(), false),
+                None => {
+                    let flight = Arc::new(Flight::default());
+                    flights.insert(key.clone(), flight.clone());
+                    (flight, true)
+                }
+            }
+        };
+        if !leader {
+            return wait(&flight, token);
+        }
+        let mut landing = Landing {
+            flights: &self.flights,
+            key: &key,
+            flight: &flight,
+            reply: None,
+        };
+        let reply = forward(&self.provider, ask, token, on_chunk);
+        landing.reply = Some(reply.clone());
+        reply
+    }
+}
+
+fn wait(flight: &Flight, token: &CancellationToken) -> Reply {
+    let mut reply = flight.reply.lock().unwrap();
+    loop {
+        if let Some(reply) = reply.as_ref() {
+            return Reply {
+                cost: json!({"coalesced": true}),
+                ..reply.clone()
+            };
+        }
+        if token.is_cancelled() {
+            return Reply {
+                ok: false,
+                output: json!({"error": "cancelled"}),
+                latency_ms: 0,
+                cost: json!({}),
+            };
+        }
+        reply = flight.done.wait_timeout(reply, POLL).unwrap().0;
+    }
+}
+
+impl<P: Provider> Provider for SingleFlightProvider<P> {
+    fn kind(&self) -> ProviderKind {
+        self.provider.kind()
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        self.call(ask, token, None)
+    }
+
+    /// Only the caller whose ask goes upstream receives chunks; coalesced callers get
+    /// the final reply alone.
+    fn ask_stream(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: &mut dyn FnMut(Value),
+    ) -> Reply {
+        self.call(ask, token, Some(on_chunk))
+    }
+
+    fn health_check(&self) -> HealthStatus {
+        self.provider.health_check()
+    }
+
+    fn close(&self) {
+        self.provider.close()
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+
+use serde_json::{json, Value};
+
+use crate::{Ask, Reply};
+
+/// Redactor masks sensitive values before an ask or reply is logged or exported.
+///
+/// Keys match object fields at any depth (case-insensitive). Pointers are JSON
+/// pointers resolved against `input`/`context` of an ask and `output`/`cost` of a reply.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    keys: HashSet<String>,
+    pointers: Vec<String>,
+    mask: Value,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self {
+            keys: HashSet::new(),
+            pointers: Vec::new(),
+            mask: json!("[REDACTED]"),
+        }
+    }
+}
+
+impl Redactor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn key(mut self, key: impl Into<String>) -> Self {
+        self.keys.insert(key.into().to_lowercase());
+        self
+    }
+
+    pub fn pointer(mut self, pointer: impl Into<String>) -> Self {
+        self.pointers.push(pointer.into());
+        self
+    }
+
+    pub fn mask(mut self, mask: Value) -> Self {
+        self.mask = mask;
+        self
+    }
+
+    /// Returns a masked copy of `value`; the original is left untouched.
+    pub fn redact(&self, value: &Value) -> Value {
+        let mut out = value.clone();
+        self.mask_keys(&mut out);
+        for pointer in &self.pointers {
+            if let Some(target) = out.pointer_mut(pointer) {
+                *target = self.mask.clone();
+            }
+        }
+        out
+    }
+
+    pub fn redact_ask(&self, ask: &Ask) -> Ask {
+        Ask {
+            op: ask.op.clone(),
+            input: self.redact(&ask.input),
+            context: self.redact(&ask.context),
+        }
+    }
+
+    pub fn redact_reply(&self, reply: &Reply) -> Reply {
+        Reply {
+            ok: reply.ok,
+            output: self.redact(&reply.output),
+            latency_ms: reply.latency_ms,
+            cost: self.redact(&reply.cost),
+        }
+    }
+
+    fn mask_keys(&self, value: &mut Value) {
+        if self.keys.is_empty() {
+            return;
+        }
+        match value {
+            Value::Object(map) => {
+                for (key, field) in map.iter_mut() {
+                    if self.keys.contains(&key.to_lowercase()) {
+                        *field = self.mask.clone();
+                    } else {
+                        self.mask_keys(field);
+                    }
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    self.mask_keys(item);
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::{Ask, HealthStatus, Provider, ProviderKind, Reply};
+
+/// TranscriptEntry is one recorded provider call.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptEntry {
+    pub step: usize,
+    pub ask: Ask,
+    pub reply: Reply,
+}
+
+/// Recorder wraps a provider and appends every ask and reply to a JSON-lines transcript.
+pub struct Recorder<P: Provider> {
+    provider: P,
+    file: Mutex<File>,
+    step: AtomicUsize,
+}
+
+impl<P: Provider> Recorder<P> {
+    /// Creates the transcript at `path`, truncating any previous recording.
+    pub fn new(provider: P, path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self {
+            provider,
+            file: Mutex::new(file),
+            step: AtomicUsize::new(0),
+        })
+    }
+
+    /// Appends one call to the transcript and hands its reply back.
+    fn record(&self, ask: Ask, reply: Reply) -> Reply {
+        let entry = TranscriptEntry {
+            step: self.step.fetch_add(1, Ordering::SeqCst),
+            ask,
+            reply,
+        };
+        if let Ok(line) = serde_json::to_string(&entry) {
+            let mut file = self.file.lock().unwrap();
+            let _ = writeln!(file, "{line}").and_then(|_| file.flush());
+        }
+        entry.reply
+    }
+}
+
+impl<P: Provider> Provider for Recorder<P> {
+    fn kind(&self) -> ProviderKind {
+        self.provider.kind()
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        let reply = self.provider.ask_cancellable(ask.clone(), token);
+        self.record(ask, reply)
+    }
+
+    /// Chunks pass through unrecorded; the transcript holds the final reply.
+    fn ask_stream(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: &mut dyn FnMut(Value),
+    ) -> Reply {
+        let reply = self.provider.ask_stream(ask.clone(), token, on_chunk);
+        self.record(ask, reply)
+    }
+
+    fn health_check(&self) -> HealthStatus {
+        self.provider.health_check()
+    }
+
+    fn close(&self) {
+        self.provider.close()
+    }
+}
+
+/// ReplayProvider returns recorded replies by step index without calling a real backend.
+pub struct ReplayProvider {
+    entries: Vec<TranscriptEntry>,
+    step: AtomicUsize,
+}
+
+impl ReplayProvider {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path)?;
+        let mut entries = Vec::new();
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            entries.push(serde_json::from_str::<TranscriptEntry>(line)?);
+        }
+        entries.sort_by_key(|e| e.step);
+        Ok(Self::new(entries))
+    }
+
+    pub fn new(entries: Vec<TranscriptEntry>) -> Self {
+        Self {
+            entries,
+            step: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn entries(&self) -> &[TranscriptEntry] {
+        &self.entries
+    }
+}
+
+impl Provider for ReplayProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Embedded
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        let step = self.step.fetch_add(1, Ordering::SeqCst);
+        match self.entries.get(step) {
+            Some(entry) if entry.ask.op == ask.op => entry.reply.clone(),
+            Some(entry) => Reply {
+                ok: false,
+                output: json!({
+                    "error": "transcript mismatch",
+                    "step": step,
+                    "expected": entry.ask.op,
+                    "actual": ask.op,
+                }),
+                latency_ms: 0,
+                cost: json!({}),
+            },
+            None => Reply {
+                ok: false,
+                output: json!({"error": "transcript exhausted", "step": step}),
+                latency_ms: 0,
+                cost: json!({}),
+            },
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use serde_json::Value;
+
+/// Builds a function declaration, `{"name", "description", "parameters"}`, whose
+/// parameters are the JSON Schema of `T`, ready for an `HttpProvider` `tools` context.
+///
+/// Subschemas are inlined rather than referenced, since function-calling APIs handle
+/// `$ref` poorly.
+#[cfg(feature = "typed_schema")]
+pub fn function_schema<T: schemars::JsonSchema>(name: &str, description: &str) -> Value {
+    let generator = schemars::gen::SchemaSettings::draft07()
+        .with(|s| s.inline_subschemas = true)
+        .into_generator();
+    let mut parameters = serde_json::to_value(generator.into_root_schema_for::<T>())
+        .unwrap_or_else(|_| serde_json::json!({}));
+    if let Some(map) = parameters.as_object_mut() {
+        map.remove("$schema");
+        map.remove("title");
+        map.remove("definitions");
+    }
+    serde_json::json!({
+        "name": name,
+        "description": description,
+        "parameters": parameters,
+    })
+}
+
+/// Checks `value` against a JSON Schema subset: `type` (a name or list of names),
+/// `enum`, `const`, `properties`, `required`, `additionalProperties: false`, `items`,
+/// `minimum` and `maximum`. Unknown keywords are ignored.
+///
+/// The error names the first offending location, e.g. `$.items[2]: expected string`.
+pub fn validate(schema: &Value, value: &Value) -> Result<(), String> {
+    check(schema, value, "$")
+}
+
+/// Converts strings in `value` to the numbers or booleans `schema` asks for, e.g. a
+/// stringified `"2"` for an `integer` property. Only plain `"true"`/`"false"` and
+/// numerals are converted, only where the value matches none of the allowed types and
+/// the allowed types agree on a single result. Descends through `properties` and
+/// `items`. Returns whether anything changed.
+pub fn coerce(schema: &Value, value: &mut Value) -> bool {
+    let Some(schema) = schema.as_object() else {
+        return false;
+    };
+    let mut changed = false;
+    let names: Vec<&str> = match schema.get("type") {
+        Some(Value::String(name)) => vec![name.as_str()],
+        Some(Value::Array(names)) => names.iter().filter_map(|n| n.as_str()).collect(),
+        _ => Vec::new(),
+    };
+    if let Value::String(text) = &*value {
+        if !names.is_empty() && !names.iter().any(|n| type_matches(n, value)) {
+            let mut results: Vec<Value> = names.iter().filter_map(|n| parse_as(n, text)).collect();
+            results.dedup();
+            if let [result] = results.as_slice() {
+                *value = result.clone();
+                changed = true;
+            }
+        }
+    }
+    if let (Some(properties), Some(object)) = (
+        schema.get("properties").and_then(|p| p.as_object()),
+        value.as_object_mut(),
+    ) {
+        for (key, item) in object.iter_mut() {
+            if let Some(sub) = properties.get(key) {
+                changed |= coerce(sub, item);
+            }
+        }
+    }
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array_mut()) {
+        for item in array {
+            changed |= coerce(items, item);
+        }
+    }
+    changed
+}
+
+/// `text` read as a value of the JSON Schema type `name`, if it is one unambiguously.
+fn parse_as(name: &str, text: &str) -> Option<Value> {
+    match name {
+        "boolean" => match text {
+            "true" => Some(Value::Bool(true)),
+            "false" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        "integer" | "number" => {
+            if let Ok(n) = text.parse::<i64>() {
+                return Some(n.into());
+            }
+            if let Ok(n) = text.parse::<u64>() {
+                return Some(n.into());
+            }
+            if name == "integer" {
+                return None;
+            }
+            // rejects "inf", "NaN" and the like, which JSON cannot carry
+            text.parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .and_then(serde_json::Number::from_f64)
+                .map(Value::Number)
+        }
+        _ => None,
+    }
+}
+
+fn type_matches(name: &str, value: &Value) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0)
+        }
+        _ => true,
+    }
+}
+
+fn check(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    let Some(schema) = schema.as_object() else {
+        return Ok(());
+    };
+    if let Some(kind) = schema.get("type") {
+        let names: Vec<&str> = match kind {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(|n| n.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        if !names.is_empty() && !names.iter().any(|n| type_matches(n, value)) {
+            return Err(format!("{path}: expected {}", names.join(" or ")));
+        }
+    }
+    if let Some(options) = schema.get("enum").and_then(|e| e.as_array()) {
+        if !options.contains(value) {
+            return Err(format!(
+                "{path}: {value} is not one of {}",
+                Value::from(options.clone())
+            ));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            return Err(format!("{path}: expected {expected}"));
+        }
+    }
+    if let Some(n) = value.as_f64() {
+        if let Some(min) = schema.get("minimum").and_then(|m| m.as_f64()) {
+            if n < min {
+                return Err(format!("{path}: {n} is below minimum {min}"));
+            }
+        }
+        if let Some(max) = schema.get("maximum").and_then(|m| m.as_f64()) {
+            if n > max {
+                return Err(format!("{path}: {n} is above maximum {max}"));
+            }
+        }
+    }
+    if let Some(object) = value.as_object() {
+        if let Some(required) = schema.get("required").and_then(|r| r.as_array()) {
+            for key in required.iter().filter_map(|k| k.as_str()) {
+                if !object.contains_key(key) {
+                    return Err(format!("{path}: missing required property {key}"));
+                }
+            }
+        }
+        let properties = schema.get("properties").and_then(|p| p.as_object());
+        for (key, item) in object {
+            match properties.and_then(|p| p.get(key)) {
+                Some(sub) => check(sub, item, &format!("{path}.{key}"))?,
+                None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                    return Err(format!("{path}: unexpected property {key}"));
+                }
+                None => {}
+            }
+        }
+    }
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+        for (i, item) in array.iter().enumerate() {
+            check(items, item, &format!("{path}[{i}]"))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reports_first_violation_with_path() {
+        let schema = json!({
+            "type": "object",
+            "required": ["temp"],
+            "properties": {
+                "temp": {"type": "number", "minimum": -100},
+                "tags": {"type": "array", "items": {"type": "string"}},
+                "unit": {"enum": ["c", "f"]}
+            },
+            "additionalProperties": false
+        });
+        assert!(validate(&schema, &json!({"temp": 3.5, "tags": ["a"], "unit": "c"})).is_ok());
+        assert_eq!(
+            validate(&schema, &json!({})),
+            Err("$: missing required property temp".into())
+        );
+        assert_eq!(
+            validate(&schema, &json!({"temp": 1, "tags": ["a", 2]})),
+            Err("$.tags[1]: expected string".into())
+        );
+        assert_eq!(
+            validate(&schema, &json!({"temp": 1, "wind": 2})),
+            Err("$: unexpected property wind".into())
+        );
+        assert!(validate(&schema, &json!({"temp": -200})).is_err());
+        assert!(validate(&schema, &json!({"temp": 1, "unit": "k"})).is_err());
+    }
+
+    #[test]
+    fn coerces_only_unambiguous_strings() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "days": {"type": "integer"},
+                "ratio": {"type": "number"},
+                "hot": {"type": "boolean"},
+                "id": {"type": ["integer", "string"]},
+                "ids": {"type": "array", "items": {"type": "integer"}},
+            }
+        });
+        let mut args = json!({
+            "days": "2",
+            "ratio": "0.5",
+            "hot": "true",
+            "id": "7",
+            "ids": ["1", 2, "x"],
+            "extra": "3",
+        });
+        assert!(coerce(&schema, &mut args));
+        assert_eq!(
+            args,
+            json!({
+                "days": 2,
+                "ratio": 0.5,
+                "hot": true,
+                "id": "7",
+                "ids": [1, 2, "x"],
+                "extra": "3",
+            })
+        );
+        assert!(validate(&schema, &json!({"days": 2})).is_ok());
+
+        for (kind, text) in [
+            ("integer", "2.5"),
+            ("integer", " 2"),
+            ("boolean", "yes"),
+            ("number", "NaN"),
+        ] {
+            let mut value = json!(text);
+            assert!(!coerce(&json!({"type": kind}), &mut value));
+            assert_eq!(value, json!(text));
+        }
+    }
+
+    #[test]
+    fn integer_and_union_types() {
+        assert!(validate(&json!({"type": "integer"}), &json!(2.0)).is_ok());
+        assert!(validate(&json!({"type": "integer"}), &json!(2.5)).is_err());
+        assert!(validate(&json!({"type": ["string", "null"]}), &json!(null)).is_ok());
+    }
+}
@@ -0,0 +1,85 @@
+use serde_json::Value;
+
+use crate::backends::stream::reasoning_text;
+
+/// CandidateSelector picks which of a reply's `candidates` the agent continues with.
+///
+/// It is consulted only when a reply carries more than one candidate, e.g. after
+/// asking for `n` completions. Closures `Fn(&[Value]) -> usize` implement it
+/// directly; an index past the end falls back to the first candidate.
+pub trait CandidateSelector: Send + Sync {
+    fn select(&self, candidates: &[Value]) -> usize;
+}
+
+impl<F> CandidateSelector for F
+where
+    F: Fn(&[Value]) -> usize + Send + Sync,
+{
+    fn select(&self, candidates: &[Value]) -> usize {
+        self(candidates)
+    }
+}
+
+/// Keeps the first candidate, as if only one had been returned.
+pub struct First;
+
+impl CandidateSelector for First {
+    fn select(&self, _candidates: &[Value]) -> usize {
+        0
+    }
+}
+
+/// Picks the candidate with the longest content, the earliest on ties.
+pub struct Longest;
+
+impl CandidateSelector for Longest {
+    fn select(&self, candidates: &[Value]) -> usize {
+        ScoreBy(|c: &Value| content(c).chars().count() as f64).select(candidates)
+    }
+}
+
+/// Picks the candidate `score` rates highest, the earliest on ties.
+pub struct ScoreBy<F>(pub F);
+
+impl<F> CandidateSelector for ScoreBy<F>
+where
+    F: Fn(&Value) -> f64 + Send + Sync,
+{
+    fn select(&self, candidates: &[Value]) -> usize {
+        let mut best = (0, f64::NEG_INFINITY);
+        for (i, candidate) in candidates.iter().enumerate() {
+            let score = (self.0)(candidate);
+            if score > best.1 {
+                best = (i, score);
+            }
+        }
+        best.0
+    }
+}
+
+/// A candidate's text: its `content` string, or the candidate itself serialized.
+pub fn content(candidate: &Value) -> String {
+    match candidate["content"].as_str() {
+        Some(text) => text.to_string(),
+        None => candidate.to_string(),
+    }
+}
+
+/// Makes candidate `index` the reply's answer: its choice moves to the front of
+/// `choices` and its content and reasoning become the top-level fields.
+pub(crate) fn promote(output: &mut Value, index: usize) {
+    let candidate = output["candidates"][index].clone();
+    if let Some(choices) = output["choices"].as_array_mut() {
+        if index < choices.len() {
+            choices.swap(0, index);
+        }
+    }
+    let Some(map) = output.as_object_mut() else {
+        return;
+    };
+    map.insert("content".into(), candidate["content"].clone());
+    match reasoning_text(&candidate) {
+        Some(reasoning) => map.insert("reasoning_content".into(), reasoning.into()),
+        None => map.remove("reasoning_content"),
+    };
+}
@@ -0,0 +1,101 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+/// Shutdown coordinates stopping an agent without cutting off the runs it is serving.
+///
+/// Clones share state. Attach one with `Agent::with_shutdown`; every run then counts
+/// as active until it returns, and [`drain`](Self::drain) waits for them before
+/// cancelling the token.
+#[derive(Clone)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    token: CancellationToken,
+    closing: AtomicBool,
+    active: AtomicUsize,
+    idle: Notify,
+}
+
+/// ActiveRun marks one run as in flight until it is dropped.
+pub struct ActiveRun {
+    inner: Arc<Inner>,
+}
+
+impl Shutdown {
+    pub fn new(token: CancellationToken) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                token,
+                closing: AtomicBool::new(false),
+                active: AtomicUsize::new(0),
+                idle: Notify::new(),
+            }),
+        }
+    }
+
+    /// The token cancelled when a drain ends.
+    pub fn token(&self) -> CancellationToken {
+        self.inner.token.clone()
+    }
+
+    /// Number of runs currently in flight.
+    pub fn active(&self) -> usize {
+        self.inner.active.load(Ordering::SeqCst)
+    }
+
+    /// Whether a drain has started; new runs are refused from then on.
+    pub fn is_closing(&self) -> bool {
+        self.inner.closing.load(Ordering::SeqCst)
+    }
+
+    /// Registers a run, or returns `None` once a drain has started.
+    pub fn enter(&self) -> Option<ActiveRun> {
+        self.inner.active.fetch_add(1, Ordering::SeqCst);
+        let run = ActiveRun {
+            inner: self.inner.clone(),
+        };
+        // checked after counting so a concurrent drain either sees this run or
+        // the run sees the drain
+        if self.is_closing() {
+            return None;
+        }
+        Some(run)
+    }
+
+    /// Refuses new runs and waits up to `timeout` for the active ones to finish,
+    /// then cancels the token either way.
+    ///
+    /// Returns `true` when every run finished before the timeout.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.inner.closing.store(true, Ordering::SeqCst);
+        let drained = tokio::time::timeout(timeout, self.idle()).await.is_ok();
+        self.inner.token.cancel();
+        drained
+    }
+
+    async fn idle(&self) {
+        loop {
+            let notified = self.inner.idle.notified();
+            tokio::pin!(notified);
+            // registered before the check so a run ending in between still wakes us
+            notified.as_mut().enable();
+            if self.active() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl Drop for ActiveRun {
+    fn drop(&mut self) {
+        if self.inner.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.idle.notify_waiters();
+        }
+    }
+}
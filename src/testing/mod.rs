@@ -0,0 +1,396 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::future::{self, BoxFuture};
+use serde_json::{json, Value};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::clock::Clock;
+use crate::{Ask, HealthStatus, Provider, ProviderKind, Reply};
+
+/// MockProvider returns scripted replies in order and records every ask it receives.
+///
+/// Once the script is exhausted it replies with `{"error": "mock exhausted"}`.
+pub struct MockProvider {
+    kind: ProviderKind,
+    latency: Duration,
+    replies: Mutex<VecDeque<Reply>>,
+    asks: Mutex<Vec<Ask>>,
+}
+
+impl Default for MockProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        Self {
+            kind: ProviderKind::Embedded,
+            latency: Duration::ZERO,
+            replies: Mutex::new(VecDeque::new()),
+            asks: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn with_kind(mut self, kind: ProviderKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Blocks each call for `latency` and reports it in `latency_ms`.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn push_reply(&self, reply: Reply) -> &Self {
+        self.replies.lock().unwrap().push_back(reply);
+        self
+    }
+
+    pub fn push_ok(&self, output: Value) -> &Self {
+        self.push_reply(Reply {
+            ok: true,
+            output,
+            latency_ms: 0,
+            cost: json!({}),
+        })
+    }
+
+    pub fn push_failure(&self, error: impl Into<String>) -> &Self {
+        self.push_reply(Reply {
+            ok: false,
+            output: json!({"error": error.into()}),
+            latency_ms: 0,
+            cost: json!({}),
+        })
+    }
+
+    /// Returns the asks received so far, oldest first.
+    pub fn asks(&self) -> Vec<Ask> {
+        self.asks.lock().unwrap().clone()
+    }
+
+    pub fn call_count(&self) -> usize {
+        self.asks.lock().unwrap().len()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.replies.lock().unwrap().len()
+    }
+}
+
+impl Provider for MockProvider {
+    fn kind(&self) -> ProviderKind {
+        self.kind
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.asks.lock().unwrap().push(ask);
+        if !self.latency.is_zero() {
+            thread::sleep(self.latency);
+        }
+        let mut reply = self
+            .replies
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| Reply {
+                ok: false,
+                output: json!({"error": "mock exhausted"}),
+                latency_ms: 0,
+                cost: json!({}),
+            });
+        reply.latency_ms = reply.latency_ms.max(self.latency.as_millis() as u64);
+        reply
+    }
+}
+
+/// One ask seen by a [`SequenceRecorder`].
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    pub ask: Ask,
+    pub at: Instant,
+    /// The agent step from the ask's context; only tool asks carry one.
+    pub step: Option<usize>,
+}
+
+/// SequenceRecorder logs the asks received by every provider it wraps, in the
+/// order they arrived, so a test can check a whole multi-step flow at once.
+///
+/// Clones share one log: wrap the model and each tool with [`SequenceRecorder::record`]
+/// and assert on the recorder afterwards.
+#[derive(Clone, Default)]
+pub struct SequenceRecorder {
+    log: Arc<Mutex<Vec<Recorded>>>,
+}
+
+impl SequenceRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps `provider` so each ask it receives is logged before it is answered.
+    pub fn record<P: Provider>(&self, provider: P) -> Recording<P> {
+        Recording {
+            provider,
+            log: self.log.clone(),
+        }
+    }
+
+    /// Returns everything recorded so far, oldest first.
+    pub fn entries(&self) -> Vec<Recorded> {
+        self.log.lock().unwrap().clone()
+    }
+
+    pub fn ops(&self) -> Vec<String> {
+        self.log
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| r.ask.op.clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.log.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Panics unless the recorded ops are exactly `expected`, in order.
+    #[track_caller]
+    pub fn assert_ops(&self, expected: &[&str]) {
+        let ops = self.ops();
+        assert_eq!(
+            ops, expected,
+            "recorded ops differ from the expected sequence"
+        );
+    }
+
+    /// Panics unless the recorded steps are exactly `expected`, in order.
+    #[track_caller]
+    pub fn assert_steps(&self, expected: &[Option<usize>]) {
+        let steps: Vec<Option<usize>> = self.log.lock().unwrap().iter().map(|r| r.step).collect();
+        assert_eq!(
+            steps, expected,
+            "recorded steps differ from the expected sequence"
+        );
+    }
+}
+
+/// A provider wrapped by [`SequenceRecorder::record`].
+pub struct Recording<P> {
+    provider: P,
+    log: Arc<Mutex<Vec<Recorded>>>,
+}
+
+impl<P> Recording<P> {
+    fn log(&self, ask: &Ask) {
+        let step = ask
+            .context
+            .get("step")
+            .and_then(Value::as_u64)
+            .map(|s| s as usize);
+        self.log.lock().unwrap().push(Recorded {
+            ask: ask.clone(),
+            at: Instant::now(),
+            step,
+        });
+    }
+}
+
+impl<P: Provider> Provider for Recording<P> {
+    fn kind(&self) -> ProviderKind {
+        self.provider.kind()
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        self.log(&ask);
+        self.provider.ask_cancellable(ask, token)
+    }
+
+    fn ask_stream(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: &mut dyn FnMut(Value),
+    ) -> Reply {
+        self.log(&ask);
+        self.provider.ask_stream(ask, token, on_chunk)
+    }
+
+    fn health_check(&self) -> HealthStatus {
+        self.provider.health_check()
+    }
+
+    fn close(&self) {
+        self.provider.close()
+    }
+}
+
+/// ManualClock is a [`Clock`] that only moves when [`advance`](Self::advance) is
+/// called, so retry delays and deadlines pass instantly in tests.
+///
+/// Clones share one time line. Starts at the tokio instant it was created.
+#[derive(Clone)]
+pub struct ManualClock {
+    origin: tokio::time::Instant,
+    elapsed: Arc<watch::Sender<Duration>>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            origin: tokio::time::Instant::now(),
+            elapsed: Arc::new(watch::Sender::new(Duration::ZERO)),
+        }
+    }
+
+    /// Moves the clock forward, waking every sleep whose deadline is reached.
+    pub fn advance(&self, by: Duration) {
+        self.elapsed.send_modify(|elapsed| *elapsed += by);
+    }
+
+    /// Time advanced since the clock was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.borrow()
+    }
+
+    /// Number of sleeps currently waiting on the clock.
+    pub fn sleepers(&self) -> usize {
+        self.elapsed.receiver_count()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> tokio::time::Instant {
+        self.origin + self.elapsed()
+    }
+
+    fn sleep_until(&self, deadline: tokio::time::Instant) -> BoxFuture<'static, ()> {
+        let origin = self.origin;
+        let mut elapsed = self.elapsed.subscribe();
+        Box::pin(async move {
+            // the sender lives as long as any clone of the clock; without it time stops
+            if elapsed.wait_for(|e| origin + *e >= deadline).await.is_err() {
+                future::pending::<()>().await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Agent;
+
+    fn ask(op: &str) -> Ask {
+        Ask {
+            op: op.into(),
+            input: json!({"op": op}),
+            context: json!({}),
+        }
+    }
+
+    #[test]
+    fn scripted_replies_returned_in_order() {
+        let mock = MockProvider::new();
+        mock.push_ok(json!(1))
+            .push_failure("boom")
+            .push_ok(json!(3));
+
+        assert_eq!(mock.ask(ask("a")).output, json!(1));
+        let failed = mock.ask(ask("b"));
+        assert!(!failed.ok);
+        assert_eq!(failed.output, json!({"error": "boom"}));
+        assert_eq!(mock.ask(ask("c")).output, json!(3));
+        assert_eq!(
+            mock.ask(ask("d")).output,
+            json!({"error": "mock exhausted"})
+        );
+    }
+
+    #[test]
+    fn records_asks_and_simulates_latency() {
+        let mock = Arc::new(MockProvider::new().with_latency(Duration::from_millis(5)));
+        mock.push_ok(json!("done"));
+
+        let reply = mock.ask(ask("first"));
+        assert!(reply.latency_ms >= 5);
+        let ops: Vec<String> = mock.asks().into_iter().map(|a| a.op).collect();
+        assert_eq!(ops, vec!["first"]);
+        assert_eq!(mock.call_count(), 1);
+        assert_eq!(mock.remaining(), 0);
+    }
+
+    #[test]
+    fn is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<MockProvider>();
+        assert_send_sync::<SequenceRecorder>();
+        assert_send_sync::<Recording<MockProvider>>();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn recorder_captures_order_of_tool_run() {
+        let recorder = SequenceRecorder::new();
+        let model = MockProvider::new();
+        model
+            .push_reply(Reply {
+                ok: false,
+                output: json!({"tool_calls": [{"op": "search", "input": "rust"}]}),
+                latency_ms: 0,
+                cost: json!({}),
+            })
+            .push_ok(json!("answer"));
+        let search = MockProvider::new();
+        search.push_ok(json!(["hit"]));
+        let mut agent = Agent::new(
+            recorder.record(model),
+            4,
+            10_000,
+            1,
+            CancellationToken::new(),
+        );
+        agent
+            .register_tool("search", recorder.record(search))
+            .unwrap();
+
+        let reply = agent.run(ask("chat")).await;
+
+        assert!(reply.ok, "{:?}", reply.output);
+        recorder.assert_ops(&["chat", "search", "chat"]);
+        recorder.assert_steps(&[None, Some(0), None]);
+        let entries = recorder.entries();
+        assert!(entries.windows(2).all(|w| w[0].at <= w[1].at));
+        assert_eq!(entries[1].ask.input, json!("rust"));
+        assert_eq!(entries[2].ask.input, json!(["hit"]));
+    }
+
+    #[test]
+    #[should_panic(expected = "recorded ops differ")]
+    fn assert_ops_reports_mismatch() {
+        let recorder = SequenceRecorder::new();
+        let mock = recorder.record(MockProvider::new());
+        mock.ask(ask("a"));
+        recorder.assert_ops(&["b"]);
+    }
+}
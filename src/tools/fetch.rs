@@ -0,0 +1,252 @@
+use crate::{off_runtime, Ask, Provider, ProviderKind, Reply};
+use base64::Engine as _;
+use reqwest::blocking::Client;
+use reqwest::dns::{Name, Resolve, Resolving};
+use reqwest::{redirect, Method, Url};
+use serde_json::{json, Map, Value};
+use std::error::Error;
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// FetchTool performs an HTTP request described by `ask.input`:
+/// `{"url", "method"?, "headers"?, "body"?}`.
+///
+/// Hosts resolving to loopback, private, link-local or unspecified addresses are
+/// refused unless explicitly allowed, and redirects are not followed so a public
+/// URL cannot bounce the request inward. Names are vetted by the client's own
+/// resolver, so the addresses checked are the ones connected to.
+pub struct FetchTool {
+    client: Client,
+    allow_hosts: Arc<RwLock<Vec<String>>>,
+    deny_hosts: Vec<String>,
+    max_bytes: usize,
+}
+
+impl Default for FetchTool {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(10))
+    }
+}
+
+impl FetchTool {
+    pub fn new(timeout: Duration) -> Self {
+        let allow_hosts = Arc::new(RwLock::new(Vec::new()));
+        let resolver = Arc::new(PublicResolver {
+            allow_hosts: allow_hosts.clone(),
+        });
+        let client = off_runtime(move || {
+            Client::builder()
+                .timeout(timeout)
+                .redirect(redirect::Policy::none())
+                .dns_resolver(resolver)
+                .build()
+        })
+        .expect("http client");
+        Self {
+            client,
+            allow_hosts,
+            deny_hosts: Vec::new(),
+            max_bytes: 1024 * 1024,
+        }
+    }
+
+    /// Restricts fetches to the listed hosts. Allowed hosts skip the private-address check.
+    pub fn allow_host(self, host: impl Into<String>) -> Self {
+        self.allow_hosts
+            .write()
+            .unwrap()
+            .push(host.into().to_lowercase());
+        self
+    }
+
+    pub fn deny_host(mut self, host: impl Into<String>) -> Self {
+        self.deny_hosts.push(host.into().to_lowercase());
+        self
+    }
+
+    /// Caps the response body; larger bodies fail the call.
+    pub fn with_max_bytes(mut self, bytes: usize) -> Self {
+        self.max_bytes = bytes;
+        self
+    }
+
+    fn check_url(&self, url: &Url) -> Result<(), String> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("scheme {} is not allowed", url.scheme()));
+        }
+        let host = url.host_str().ok_or("url has no host")?.to_lowercase();
+        if self.deny_hosts.contains(&host) {
+            return Err(format!("host {host} is denied"));
+        }
+        let allow_hosts = self.allow_hosts.read().unwrap();
+        if !allow_hosts.is_empty() {
+            return if allow_hosts.contains(&host) {
+                Ok(())
+            } else {
+                Err(format!("host {host} is not allowed"))
+            };
+        }
+        // Literal addresses never reach the resolver, so they are checked here.
+        let literal = host.trim_start_matches('[').trim_end_matches(']');
+        match literal.parse::<IpAddr>() {
+            Ok(ip) if is_private(ip) => Err(format!("address {ip} is private")),
+            _ => Ok(()),
+        }
+    }
+
+    fn fetch(&self, input: &Value) -> Result<Value, String> {
+        let url = input
+            .get("url")
+            .and_then(|v| v.as_str())
+            .ok_or("input.url is required")?;
+        let url = Url::parse(url).map_err(|e| e.to_string())?;
+        self.check_url(&url)?;
+        let method = input
+            .get("method")
+            .and_then(|v| v.as_str())
+            .unwrap_or("GET");
+        let method =
+            Method::from_bytes(method.to_uppercase().as_bytes()).map_err(|e| e.to_string())?;
+        let mut request = self.client.request(method, url);
+        if let Some(headers) = input.get("headers").and_then(|v| v.as_object()) {
+            for (name, value) in headers {
+                if let Some(value) = value.as_str() {
+                    request = request.header(name.as_str(), value);
+                }
+            }
+        }
+        match input.get("body") {
+            Some(Value::String(body)) => request = request.body(body.clone()),
+            Some(Value::Null) | None => {}
+            Some(body) => request = request.json(body),
+        }
+        let max_bytes = self.max_bytes;
+        off_runtime(move || {
+            let resp = request.send().map_err(|e| error_chain(&e))?;
+            let status = resp.status().as_u16();
+            let headers: Map<String, Value> = resp
+                .headers()
+                .iter()
+                .filter_map(|(k, v)| Some((k.to_string(), json!(v.to_str().ok()?))))
+                .collect();
+            let mut bytes = Vec::new();
+            resp.take(max_bytes as u64 + 1)
+                .read_to_end(&mut bytes)
+                .map_err(|e| e.to_string())?;
+            if bytes.len() > max_bytes {
+                return Err(format!("response exceeds {max_bytes} bytes"));
+            }
+            let body = match String::from_utf8(bytes) {
+                Ok(text) => json!({ "text": text }),
+                Err(e) => json!({
+                    "base64": base64::engine::general_purpose::STANDARD.encode(e.into_bytes())
+                }),
+            };
+            Ok(json!({ "status": status, "headers": headers, "body": body }))
+        })
+    }
+}
+
+/// Resolves names for the fetch client and refuses any that reach a private
+/// address, unless the host was explicitly allowed.
+struct PublicResolver {
+    allow_hosts: Arc<RwLock<Vec<String>>>,
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_lowercase();
+        let allowed = self.allow_hosts.read().unwrap().contains(&host);
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::task::spawn_blocking(move || {
+                (host.as_str(), 0).to_socket_addrs().map(Iterator::collect)
+            })
+            .await??;
+            if !allowed {
+                if let Some(addr) = addrs.iter().find(|addr| is_private(addr.ip())) {
+                    return Err(format!("address {} is private", addr.ip()).into());
+                }
+            }
+            Ok(Box::new(addrs.into_iter()) as Box<dyn Iterator<Item = SocketAddr> + Send>)
+        })
+    }
+}
+
+/// Joins an error with its sources, so resolver refusals survive reqwest's wrapping.
+fn error_chain(e: &dyn Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        message.push_str(": ");
+        message.push_str(&e.to_string());
+        source = e.source();
+    }
+    message
+}
+
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_multicast()
+                // "this network", 0.0.0.0/8
+                || a == 0
+                // reserved and broadcast, 240.0.0.0/4
+                || a >= 240
+                // carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (b & 0xc0) == 64)
+        }
+        IpAddr::V6(ip) => {
+            // IPv4-mapped ::ffff:a.b.c.d and IPv4-compatible ::a.b.c.d
+            if let Some(v4) = ip.to_ipv4() {
+                return is_private(IpAddr::V4(v4));
+            }
+            let segments = ip.segments();
+            // 6to4, 2002:aabb:ccdd::/48 carries a.b.c.d
+            if segments[0] == 0x2002 {
+                let [a, b] = segments[1].to_be_bytes();
+                let [c, d] = segments[2].to_be_bytes();
+                return is_private(IpAddr::V4([a, b, c, d].into()));
+            }
+            let first = segments[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+impl Provider for FetchTool {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::RemoteGrpc
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        let start = Instant::now();
+        let result = self.fetch(&ask.input);
+        let latency_ms = start.elapsed().as_millis() as u64;
+        match result {
+            Ok(output) => Reply {
+                ok: output["status"]
+                    .as_u64()
+                    .is_some_and(|s| (200..300).contains(&s)),
+                output,
+                latency_ms,
+                cost: json!({}),
+            },
+            Err(e) => Reply {
+                ok: false,
+                output: json!({ "error": e }),
+                latency_ms,
+                cost: json!({}),
+            },
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::OnceLock;
+
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+
+use crate::{Ask, Provider, ProviderKind, Reply};
+
+/// What a tool factory resolves to: the built tool or why it could not be built.
+pub type FactoryResult =
+    Result<Box<dyn Provider + Send + Sync>, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Builds a tool asynchronously; see [`crate::ToolSpec::Factory`].
+pub type ToolFactory =
+    Box<dyn Fn() -> Pin<Box<dyn Future<Output = FactoryResult> + Send>> + Send + Sync>;
+
+/// LazyTool defers building its tool until the first ask and keeps it afterwards.
+///
+/// The factory future runs to completion on a private current-thread runtime, off
+/// the caller's runtime when there is one, so a tool needing a live reactor after
+/// construction must bring its own, as [`HttpProvider`](crate::backends::http::HttpProvider)
+/// does. A failed build answers that ask with `{"error": "tool construction failed",
+/// "detail"}` and is not cached, so the next ask tries again.
+pub struct LazyTool {
+    factory: ToolFactory,
+    tool: OnceLock<Box<dyn Provider + Send + Sync>>,
+}
+
+impl LazyTool {
+    pub fn new(factory: ToolFactory) -> Self {
+        Self {
+            factory,
+            tool: OnceLock::new(),
+        }
+    }
+
+    /// Whether the factory has produced the tool yet.
+    pub fn is_built(&self) -> bool {
+        self.tool.get().is_some()
+    }
+
+    fn build(&self) -> Result<&dyn Provider, String> {
+        if let Some(tool) = self.tool.get() {
+            return Ok(tool.as_ref());
+        }
+        let factory = &self.factory;
+        let built = crate::off_runtime(|| {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| e.to_string())?
+                .block_on(factory())
+                .map_err(|e| e.to_string())
+        })?;
+        Ok(self.tool.get_or_init(|| built).as_ref())
+    }
+}
+
+impl Provider for LazyTool {
+    /// The built tool's kind; `Embedded` until the first ask builds it.
+    fn kind(&self) -> ProviderKind {
+        self.tool
+            .get()
+            .map_or(ProviderKind::Embedded, |tool| tool.kind())
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        match self.build() {
+            Ok(tool) => tool.ask_cancellable(ask, token),
+            Err(detail) => Reply {
+                ok: false,
+                output: json!({ "error": "tool construction failed", "detail": detail }),
+                latency_ms: 0,
+                cost: json!({}),
+            },
+        }
+    }
+
+    /// Closes the built tool; a tool never built has nothing to release.
+    fn close(&self) {
+        if let Some(tool) = self.tool.get() {
+            tool.close();
+        }
+    }
+}
@@ -1,3 +1,13 @@
+pub mod fetch;
+pub mod lazy;
+#[cfg(feature = "sandboxed_exec")]
+pub mod process;
+#[cfg(feature = "sandboxed_exec")]
 pub mod wasm;
 
-pub use wasm::WasmTool;
+pub use fetch::FetchTool;
+pub use lazy::{LazyTool, ToolFactory};
+#[cfg(feature = "sandboxed_exec")]
+pub use process::ProcessTool;
+#[cfg(feature = "sandboxed_exec")]
+pub use wasm::{WasmStats, WasmTool};
@@ -0,0 +1,166 @@
+use crate::{Ask, Provider, ProviderKind, Reply};
+use serde_json::{json, Value};
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// ProcessTool runs one pre-approved command with an argv template.
+///
+/// Template entries of the form `{name}` are replaced by the `name` field of an
+/// object `ask.input`; every other entry is passed verbatim. No shell is involved,
+/// so input values can never add arguments or chain commands.
+///
+/// [`Provider::close`] kills a command still running and fails later asks with
+/// `{"error": "closed"}`.
+pub struct ProcessTool {
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+    max_output: usize,
+    closed: AtomicBool,
+}
+
+impl ProcessTool {
+    pub fn new(program: impl Into<String>, args: Vec<String>) -> Self {
+        Self {
+            program: program.into(),
+            args,
+            timeout: Duration::from_secs(10),
+            max_output: 64 * 1024,
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Caps stdout and stderr at `bytes` each; extra output is discarded.
+    pub fn with_max_output(mut self, bytes: usize) -> Self {
+        self.max_output = bytes;
+        self
+    }
+
+    fn argv(&self, input: &Value) -> Result<Vec<String>, String> {
+        self.args
+            .iter()
+            .map(|arg| {
+                let Some(name) = arg.strip_prefix('{').and_then(|a| a.strip_suffix('}')) else {
+                    return Ok(arg.clone());
+                };
+                match input.get(name) {
+                    Some(Value::String(s)) => Ok(s.clone()),
+                    Some(v @ (Value::Number(_) | Value::Bool(_))) => Ok(v.to_string()),
+                    Some(_) => Err(format!("argument {name} must be a string, number or bool")),
+                    None => Err(format!("missing argument {name}")),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Reads up to `cap` bytes, draining the rest so the child never blocks on a full pipe.
+fn capture(mut pipe: impl Read, cap: usize) -> (String, bool) {
+    let mut kept = Vec::new();
+    let mut truncated = false;
+    let mut buf = [0u8; 8192];
+    while let Ok(n) = pipe.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        let room = cap.saturating_sub(kept.len());
+        if n > room {
+            truncated = true;
+        }
+        kept.extend_from_slice(&buf[..n.min(room)]);
+    }
+    (String::from_utf8_lossy(&kept).into_owned(), truncated)
+}
+
+impl Provider for ProcessTool {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Embedded
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        let start = Instant::now();
+        let failed = |error: String| Reply {
+            ok: false,
+            output: json!({ "error": error }),
+            latency_ms: start.elapsed().as_millis() as u64,
+            cost: json!({}),
+        };
+        if self.closed.load(Ordering::SeqCst) {
+            return failed("closed".into());
+        }
+        let argv = match self.argv(&ask.input) {
+            Ok(argv) => argv,
+            Err(e) => return failed(e),
+        };
+        let mut child = match Command::new(&self.program)
+            .args(&argv)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) => return failed(e.to_string()),
+        };
+        let cap = self.max_output;
+        let stdout = child
+            .stdout
+            .take()
+            .map(|p| thread::spawn(move || capture(p, cap)));
+        let stderr = child
+            .stderr
+            .take()
+            .map(|p| thread::spawn(move || capture(p, cap)));
+
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) if start.elapsed() >= self.timeout => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Reply {
+                        ok: false,
+                        output: json!({ "error": "timeout" }),
+                        latency_ms: self.timeout.as_millis() as u64,
+                        cost: json!({}),
+                    };
+                }
+                Ok(None) if self.closed.load(Ordering::SeqCst) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return failed("closed".into());
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(5)),
+                Err(e) => return failed(e.to_string()),
+            }
+        };
+        let join = |h: Option<thread::JoinHandle<(String, bool)>>| {
+            h.and_then(|h| h.join().ok()).unwrap_or_default()
+        };
+        let (stdout, stdout_truncated) = join(stdout);
+        let (stderr, stderr_truncated) = join(stderr);
+        Reply {
+            ok: status.success(),
+            output: json!({
+                "stdout": stdout,
+                "stderr": stderr,
+                "exit_code": status.code(),
+                "truncated": stdout_truncated || stderr_truncated,
+            }),
+            latency_ms: start.elapsed().as_millis() as u64,
+            cost: json!({}),
+        }
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+}
@@ -1,18 +1,468 @@
 use crate::{Ask, Provider, ProviderKind, Reply};
-use serde_json::json;
-use std::sync::mpsc;
-use std::thread;
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::time::{Duration, Instant};
-use wasmtime::{Config, Engine, Linker, Module, Store, StoreLimitsBuilder};
-use wasmtime_wasi::{preview1::add_to_linker_sync, preview1::WasiP1Ctx, WasiCtxBuilder};
+use tokio::runtime::Runtime;
+use tokio_util::sync::CancellationToken;
+use wasmtime::component::{self, Component, ResourceTable};
+use wasmtime::{
+    Caller, Config, Engine, Extern, InstancePre, Linker, Module, Store, StoreLimits,
+    StoreLimitsBuilder, UpdateDeadline, Val, ValType,
+};
+use wasmtime_wasi::{
+    preview1::add_to_linker_async, preview1::WasiP1Ctx, Deterministic, HostMonotonicClock,
+    HostWallClock, WasiCtx, WasiCtxBuilder, WasiView,
+};
+
+/// Converts `input` into call arguments: an array supplies one value per parameter,
+/// `null` means no arguments and any other value is the single argument.
+fn to_vals(
+    input: &Value,
+    params: impl ExactSizeIterator<Item = ValType>,
+) -> Result<Vec<Val>, String> {
+    let args: Vec<&Value> = match input {
+        Value::Array(items) => items.iter().collect(),
+        Value::Null => Vec::new(),
+        other => vec![other],
+    };
+    if args.len() != params.len() {
+        return Err(format!(
+            "expected {} arguments, got {}",
+            params.len(),
+            args.len()
+        ));
+    }
+    args.into_iter()
+        .zip(params)
+        .map(|(arg, ty)| {
+            let val = match ty {
+                ValType::I32 => arg
+                    .as_i64()
+                    .and_then(|v| i32::try_from(v).ok())
+                    .map(Val::I32),
+                ValType::I64 => arg.as_i64().map(Val::I64),
+                ValType::F32 => arg.as_f64().map(|v| Val::F32((v as f32).to_bits())),
+                ValType::F64 => arg.as_f64().map(|v| Val::F64(v.to_bits())),
+                _ => return Err(format!("unsupported parameter type {ty}")),
+            };
+            val.ok_or_else(|| format!("argument {arg} does not fit {ty}"))
+        })
+        .collect()
+}
+
+/// A single result becomes a scalar, several become an array and none becomes `null`.
+fn from_vals(results: &[Val]) -> Value {
+    let mut values: Vec<Value> = results
+        .iter()
+        .map(|v| match v {
+            Val::I32(v) => json!(v),
+            Val::I64(v) => json!(v),
+            Val::F32(bits) => json!(f32::from_bits(*bits)),
+            Val::F64(bits) => json!(f64::from_bits(*bits)),
+            _ => Value::Null,
+        })
+        .collect();
+    match values.len() {
+        0 => Value::Null,
+        1 => values.remove(0),
+        _ => Value::Array(values),
+    }
+}
+
+/// Converts `input` into component-model arguments, like [`to_vals`].
+fn to_component_vals(
+    input: &Value,
+    params: &[component::Type],
+) -> Result<Vec<component::Val>, String> {
+    use component::{Type, Val as CVal};
+    let args: Vec<&Value> = match input {
+        Value::Array(items) => items.iter().collect(),
+        Value::Null => Vec::new(),
+        other => vec![other],
+    };
+    if args.len() != params.len() {
+        return Err(format!(
+            "expected {} arguments, got {}",
+            params.len(),
+            args.len()
+        ));
+    }
+    args.into_iter()
+        .zip(params)
+        .map(|(arg, ty)| {
+            let val = match ty {
+                Type::Bool => arg.as_bool().map(CVal::Bool),
+                Type::S32 => arg
+                    .as_i64()
+                    .and_then(|v| i32::try_from(v).ok())
+                    .map(CVal::S32),
+                Type::U32 => arg
+                    .as_u64()
+                    .and_then(|v| u32::try_from(v).ok())
+                    .map(CVal::U32),
+                Type::S64 => arg.as_i64().map(CVal::S64),
+                Type::U64 => arg.as_u64().map(CVal::U64),
+                Type::Float32 => arg.as_f64().map(|v| CVal::Float32(v as f32)),
+                Type::Float64 => arg.as_f64().map(CVal::Float64),
+                Type::String => arg.as_str().map(|v| CVal::String(v.into())),
+                _ => return Err(format!("unsupported parameter type {ty:?}")),
+            };
+            val.ok_or_else(|| format!("argument {arg} does not fit {ty:?}"))
+        })
+        .collect()
+}
+
+/// Converts component-model results, like [`from_vals`].
+fn from_component_vals(results: &[component::Val]) -> Value {
+    use component::Val as CVal;
+    let mut values: Vec<Value> = results
+        .iter()
+        .map(|v| match v {
+            CVal::Bool(v) => json!(v),
+            CVal::S8(v) => json!(v),
+            CVal::U8(v) => json!(v),
+            CVal::S16(v) => json!(v),
+            CVal::U16(v) => json!(v),
+            CVal::S32(v) => json!(v),
+            CVal::U32(v) => json!(v),
+            CVal::S64(v) => json!(v),
+            CVal::U64(v) => json!(v),
+            CVal::Float32(v) => json!(v),
+            CVal::Float64(v) => json!(v),
+            CVal::Char(v) => json!(v),
+            CVal::String(v) => json!(v),
+            _ => Value::Null,
+        })
+        .collect();
+    match values.len() {
+        0 => Value::Null,
+        1 => values.remove(0),
+        _ => Value::Array(values),
+    }
+}
+
+type HostTools = HashMap<String, Arc<dyn Provider + Send + Sync>>;
+
+/// Store state for WASI preview 1 core modules.
+struct CoreCtx {
+    wasi: WasiP1Ctx,
+    limits: StoreLimits,
+    host: Arc<HostTools>,
+    /// Cancelled with the call, or when it is dropped, e.g. on timeout, to stop a
+    /// running host tool.
+    token: CancellationToken,
+}
+
+/// Import module under which host functions are offered to core modules.
+const HOST_MODULE: &str = "soma";
+
+/// Fuel charged for every host call on top of one unit per byte passed either way.
+const HOST_CALL_FUEL: u64 = 1_000;
+
+/// Takes `cost` units from the guest's remaining fuel, trapping when it runs out.
+fn charge(caller: &mut Caller<'_, CoreCtx>, cost: u64) -> wasmtime::Result<()> {
+    let fuel = caller.get_fuel()?;
+    let left = fuel
+        .checked_sub(cost)
+        .ok_or_else(|| wasmtime::Error::msg("all fuel consumed by host call"))?;
+    caller.set_fuel(left)
+}
+
+/// Copies `len` bytes at `ptr` out of the guest's exported `memory`.
+fn read_guest(caller: &mut Caller<'_, CoreCtx>, ptr: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+    let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+        return Err(wasmtime::Error::msg("host call needs an exported memory"));
+    };
+    let start = ptr as u32 as usize;
+    memory
+        .data(&caller)
+        .get(start..start + len as u32 as usize)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| wasmtime::Error::msg("host call argument out of bounds"))
+}
+
+/// Copies `bytes` into a buffer from the guest's exported `alloc(len) -> ptr`.
+async fn write_guest(caller: &mut Caller<'_, CoreCtx>, bytes: &[u8]) -> wasmtime::Result<i32> {
+    let Some(Extern::Func(alloc)) = caller.get_export("alloc") else {
+        return Err(wasmtime::Error::msg("host call needs an exported alloc"));
+    };
+    let ptr = alloc
+        .typed::<i32, i32>(&caller)?
+        .call_async(&mut *caller, bytes.len() as i32)
+        .await?;
+    let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+        return Err(wasmtime::Error::msg("host call needs an exported memory"));
+    };
+    memory.write(&mut *caller, ptr as u32 as usize, bytes)?;
+    Ok(ptr)
+}
+
+/// Runs the host tool named by the guest and returns its reply as
+/// `{"ok", "output"}` JSON bytes.
+async fn host_invoke(
+    caller: &mut Caller<'_, CoreCtx>,
+    (op_ptr, op_len, in_ptr, in_len): (i32, i32, i32, i32),
+) -> wasmtime::Result<i64> {
+    let op = String::from_utf8_lossy(&read_guest(caller, op_ptr, op_len)?).into_owned();
+    let input = read_guest(caller, in_ptr, in_len)?;
+    charge(caller, HOST_CALL_FUEL + input.len() as u64)?;
+    let reply = match (
+        caller.data().host.get(&op).cloned(),
+        serde_json::from_slice::<Value>(&input),
+    ) {
+        (None, _) => json!({"ok": false, "output": {"error": "unknown host tool", "op": op}}),
+        (_, Err(e)) => json!({"ok": false, "output": {"error": e.to_string()}}),
+        (Some(tool), Ok(input)) => {
+            let token = caller.data().token.clone();
+            let ask = Ask {
+                op,
+                input,
+                context: json!({}),
+            };
+            let reply =
+                tokio::task::spawn_blocking(move || tool.ask_cancellable(ask, &token)).await?;
+            json!({"ok": reply.ok, "output": reply.output})
+        }
+    };
+    let bytes = serde_json::to_vec(&reply)?;
+    charge(caller, bytes.len() as u64)?;
+    let ptr = write_guest(caller, &bytes).await?;
+    Ok(((ptr as u32 as i64) << 32) | bytes.len() as i64)
+}
+
+/// Store state for WASI preview 2 components.
+struct ComponentCtx {
+    wasi: WasiCtx,
+    table: ResourceTable,
+    limits: StoreLimits,
+}
+
+impl WasiView for ComponentCtx {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+
+    fn ctx(&mut self) -> &mut WasiCtx {
+        &mut self.wasi
+    }
+}
+
+/// A program linked against WASI once, ready for cheap per-call instantiation.
+#[derive(Clone)]
+enum Program {
+    Core(InstancePre<CoreCtx>),
+    Component(component::InstancePre<ComponentCtx>),
+}
+
+/// Components carry layer 1 in the binary header; core modules carry layer 0.
+fn is_component(wasm: &[u8]) -> bool {
+    wasm.len() >= 8 && wasm.starts_with(b"\0asm") && wasm[6..8] == [1, 0]
+}
+
+fn limits(memory_limit: Option<usize>) -> StoreLimits {
+    match memory_limit {
+        Some(limit) => StoreLimitsBuilder::new()
+            .memory_size(limit)
+            .instances(1)
+            .trap_on_grow_failure(true)
+            .build(),
+        None => StoreLimitsBuilder::new().build(),
+    }
+}
+
+/// Fuel a guest may burn between cooperative yields back to the async runtime.
+const YIELD_INTERVAL: u64 = 10_000;
+
+/// A clock stuck at zero, so deterministic guests read the same time every run.
+struct FrozenClock;
+
+impl HostWallClock for FrozenClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+impl HostMonotonicClock for FrozenClock {
+    fn resolution(&self) -> u64 {
+        1
+    }
+
+    fn now(&self) -> u64 {
+        0
+    }
+}
+
+/// WASI context builder; deterministic runs get frozen clocks and a fixed
+/// random byte cycle in place of the host's.
+fn wasi(deterministic: bool) -> WasiCtxBuilder {
+    let mut builder = WasiCtxBuilder::new();
+    if deterministic {
+        builder
+            .wall_clock(FrozenClock)
+            .monotonic_clock(FrozenClock)
+            .secure_random(Deterministic::new(vec![0x5e, 0xed]))
+            .insecure_random(Deterministic::new(vec![0x5e, 0xed]))
+            .insecure_random_seed(0);
+    }
+    builder
+}
+
+/// A store with `fuel` that traps at the next epoch bump once `token` is cancelled.
+///
+/// The epoch is engine-wide, so every running store sees the bump; the others check
+/// their own token and carry on.
+fn store<T: Send>(
+    engine: &Engine,
+    ctx: T,
+    fuel: u64,
+    token: &CancellationToken,
+) -> Result<Store<T>, String> {
+    let mut store = Store::new(engine, ctx);
+    store.set_fuel(fuel).ok();
+    store
+        .fuel_async_yield_interval(Some(YIELD_INTERVAL))
+        .map_err(|e| e.to_string())?;
+    let token = token.clone();
+    store.epoch_deadline_callback(move |_| {
+        if token.is_cancelled() {
+            Err(wasmtime::Error::msg("cancelled"))
+        } else {
+            Ok(UpdateDeadline::Continue(1))
+        }
+    });
+    store.set_epoch_deadline(1);
+    Ok(store)
+}
+
+/// Runs `func` and returns its results with the fuel it burned.
+async fn run_core(
+    tool: &WasmTool,
+    pre: &InstancePre<CoreCtx>,
+    func: &str,
+    input: &Value,
+    token: &CancellationToken,
+) -> Result<(Value, u64), String> {
+    let host_token = token.child_token();
+    let _cancel_on_drop = host_token.clone().drop_guard();
+    let ctx = CoreCtx {
+        wasi: wasi(tool.deterministic).build_p1(),
+        limits: limits(tool.memory_limit),
+        host: Arc::clone(&tool.host),
+        token: host_token,
+    };
+    let mut store = store(&tool.engine, ctx, tool.fuel, token)?;
+    store.limiter(|cx| &mut cx.limits);
+    let instance = pre
+        .instantiate_async(&mut store)
+        .await
+        .map_err(|e| e.to_string())?;
+    let func = instance
+        .get_func(&mut store, func)
+        .ok_or_else(|| format!("function {func} not exported"))?;
+    let ty = func.ty(&store);
+    let params = to_vals(input, ty.params())?;
+    let mut results: Vec<Val> = ty.results().map(|_| Val::I32(0)).collect();
+    // the root cause names the trap, e.g. a host call running out of fuel,
+    // rather than the wasm backtrace wrapped around it
+    func.call_async(&mut store, &params, &mut results)
+        .await
+        .map_err(|e| e.root_cause().to_string())?;
+    Ok((from_vals(&results), fuel_used(&store, tool.fuel)))
+}
+
+/// Runs `func` like [`run_core`].
+async fn run_component(
+    tool: &WasmTool,
+    pre: &component::InstancePre<ComponentCtx>,
+    func: &str,
+    input: &Value,
+    token: &CancellationToken,
+) -> Result<(Value, u64), String> {
+    let ctx = ComponentCtx {
+        wasi: wasi(tool.deterministic).build(),
+        table: ResourceTable::new(),
+        limits: limits(tool.memory_limit),
+    };
+    let mut store = store(&tool.engine, ctx, tool.fuel, token)?;
+    store.limiter(|cx| &mut cx.limits);
+    let instance = pre
+        .instantiate_async(&mut store)
+        .await
+        .map_err(|e| e.to_string())?;
+    let func = instance
+        .get_func(&mut store, func)
+        .ok_or_else(|| format!("function {func} not exported"))?;
+    let params = to_component_vals(input, &func.params(&store))?;
+    let mut results = vec![component::Val::Bool(false); func.results(&store).len()];
+    func.call_async(&mut store, &params, &mut results)
+        .await
+        .map_err(|e| e.to_string())?;
+    func.post_return_async(&mut store)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok((from_component_vals(&results), fuel_used(&store, tool.fuel)))
+}
+
+fn fuel_used<T>(store: &Store<T>, fuel: u64) -> u64 {
+    fuel - store.get_fuel().unwrap_or(0)
+}
+
+/// The runtime blocking callers drive guests on, built on first use. Its worker runs
+/// timers and the cancellation watcher while the caller's thread runs the guest.
+fn runtime() -> Result<&'static Runtime, &'static std::io::Error> {
+    static RUNTIME: OnceLock<std::io::Result<Runtime>> = OnceLock::new();
+    RUNTIME
+        .get_or_init(|| {
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .thread_name("soma-wasm")
+                .enable_time()
+                .build()
+        })
+        .as_ref()
+}
+
+/// WasmStats counts how often a [`WasmTool`] linked and instantiated its program.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WasmStats {
+    /// Import resolution runs; once per tool, at construction.
+    pub linked: usize,
+    /// Fresh instances created from the pre-linked program; one per call.
+    pub instantiated: usize,
+}
 
 /// WasmTool executes WebAssembly modules inside a sandbox using wasmtime.
+///
+/// Both core modules (WASI preview 1) and components (WASI preview 2) are accepted;
+/// the binary header decides which path runs. Imports are resolved once up front;
+/// every call still gets a fresh instance and store, so no guest state leaks between
+/// calls and fuel and memory limits apply per call.
+///
+/// Guests run on wasmtime's async support and yield to the runtime every few
+/// thousand units of fuel, so [`WasmTool::ask_async`] never monopolizes an executor
+/// thread and the timeout drops a runaway guest instead of abandoning a thread. An
+/// [`Agent`](crate::Agent) awaits it through [`Provider::ask_future`]; blocking
+/// callers share one runtime rather than building their own.
+///
+/// Core modules may import `soma.host_invoke` to call tools registered with
+/// [`WasmTool::with_host_tool`]; see there for the calling convention.
 pub struct WasmTool {
     engine: Engine,
-    module: Module,
+    program: Program,
+    host: Arc<HostTools>,
+    linked: AtomicUsize,
+    instantiated: AtomicUsize,
     fuel: u64,
     memory_limit: Option<usize>,
     timeout: Duration,
+    deterministic: bool,
 }
 
 impl WasmTool {
@@ -25,80 +475,134 @@ impl WasmTool {
     ) -> Result<Self, wasmtime::Error> {
         let mut config = Config::new();
         config.consume_fuel(true);
+        config.wasm_component_model(true);
+        config.async_support(true);
+        config.epoch_interruption(true);
         let engine = Engine::new(&config)?;
-        let module = Module::from_binary(&engine, wasm)?;
+        let program = if is_component(wasm) {
+            let component = Component::from_binary(&engine, wasm)?;
+            let mut linker: component::Linker<ComponentCtx> = component::Linker::new(&engine);
+            wasmtime_wasi::add_to_linker_async(&mut linker)?;
+            Program::Component(linker.instantiate_pre(&component)?)
+        } else {
+            let module = Module::from_binary(&engine, wasm)?;
+            let mut linker: Linker<CoreCtx> = Linker::new(&engine);
+            add_to_linker_async(&mut linker, |cx| &mut cx.wasi)?;
+            linker.func_wrap_async(HOST_MODULE, "host_invoke", |mut caller, args| {
+                Box::new(async move { host_invoke(&mut caller, args).await })
+            })?;
+            Program::Core(linker.instantiate_pre(&module)?)
+        };
         Ok(Self {
             engine,
-            module,
+            program,
+            host: Arc::default(),
+            linked: AtomicUsize::new(1),
+            instantiated: AtomicUsize::new(0),
             fuel,
             memory_limit,
             timeout,
+            deterministic: false,
         })
     }
-}
 
-impl Provider for WasmTool {
-    fn kind(&self) -> ProviderKind {
-        ProviderKind::Embedded
+    /// Lets core-module guests call `tool` as `name` through the imported
+    /// `soma.host_invoke(op_ptr, op_len, in_ptr, in_len) -> i64`.
+    ///
+    /// The guest passes the tool name and JSON input as UTF-8 in its exported
+    /// `memory`; the host stores `{"ok", "output"}` JSON in a buffer from the
+    /// guest's exported `alloc(len) -> ptr` and returns `ptr << 32 | len`. Each
+    /// call costs 1000 fuel plus one per byte passed either way, and the tool runs
+    /// within the same timeout as the guest, cancelled if that expires.
+    pub fn with_host_tool(
+        mut self,
+        name: impl Into<String>,
+        tool: impl Provider + Send + Sync + 'static,
+    ) -> Self {
+        Arc::make_mut(&mut self.host).insert(name.into(), Arc::new(tool));
+        self
     }
 
-    fn ask(&self, ask: Ask) -> Reply {
+    /// With `deterministic` set, guests see WASI clocks frozen at zero and a fixed
+    /// random byte stream, so the same input always yields the same output and
+    /// burns the same fuel. Host tools are outside the sandbox and not covered.
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Link and instantiation counts since construction.
+    pub fn stats(&self) -> WasmStats {
+        WasmStats {
+            linked: self.linked.load(Ordering::Relaxed),
+            instantiated: self.instantiated.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Whether the tool wraps a component rather than a core module.
+    pub fn is_component(&self) -> bool {
+        matches!(self.program, Program::Component(_))
+    }
+
+    /// Runs the export named by `ask.op` without blocking the calling task.
+    ///
+    /// A successful reply reports the fuel the guest burned as `cost.fuel`.
+    pub async fn ask_async(&self, ask: Ask) -> Reply {
+        self.ask_async_cancellable(ask, &CancellationToken::new())
+            .await
+    }
+
+    /// Like [`WasmTool::ask_async`], but cancelling `token` stops the guest and
+    /// replies `{"error": "cancelled"}`.
+    ///
+    /// A watcher task bumps the engine's epoch on cancellation, which traps the guest
+    /// even mid-computation. On a current-thread runtime that task cannot run while
+    /// the guest does, so there the guest stops at its next fuel yield instead.
+    pub async fn ask_async_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
         let start = Instant::now();
-        let func = ask.op.clone();
-        let arg = ask.input.as_i64().unwrap_or(0) as i32;
-        let engine = self.engine.clone();
-        let module = self.module.clone();
-        let fuel = self.fuel;
-        let mem = self.memory_limit;
-        let timeout = self.timeout;
-
-        let (tx, rx) = mpsc::channel();
-        thread::spawn(move || {
-            let wasi = WasiCtxBuilder::new().build_p1();
-            let limits_builder = if let Some(limit) = mem {
-                StoreLimitsBuilder::new().memory_size(limit).instances(1)
-            } else {
-                StoreLimitsBuilder::new()
-            };
-            struct Ctx {
-                wasi: WasiP1Ctx,
-                limits: wasmtime::StoreLimits,
-            }
-            let ctx = Ctx {
-                wasi,
-                limits: limits_builder.build(),
-            };
-            let mut store: Store<Ctx> = Store::new(&engine, ctx);
-            store.limiter(|cx| &mut cx.limits);
-            store.set_fuel(fuel).ok();
-            let mut linker: Linker<Ctx> = Linker::new(&engine);
-            add_to_linker_sync(&mut linker, |cx| &mut cx.wasi).unwrap();
-            let instance = match linker.instantiate(&mut store, &module) {
-                Ok(i) => i,
-                Err(e) => {
-                    let _ = tx.send(Err(e.to_string()));
-                    return;
+        self.instantiated.fetch_add(1, Ordering::Relaxed);
+        let run = async {
+            match &self.program {
+                Program::Core(pre) => run_core(self, pre, &ask.op, &ask.input, token).await,
+                Program::Component(pre) => {
+                    run_component(self, pre, &ask.op, &ask.input, token).await
                 }
-            };
-            let func = match instance.get_typed_func::<i32, i32>(&mut store, &func) {
-                Ok(f) => f,
-                Err(e) => {
-                    let _ = tx.send(Err(e.to_string()));
-                    return;
+            }
+        };
+        // the guest only notices the token itself between fuel yields; bumping the
+        // epoch from another task interrupts it mid-computation
+        let done = CancellationToken::new();
+        let _stop_watching = done.clone().drop_guard();
+        tokio::spawn({
+            let (engine, token) = (self.engine.clone(), token.clone());
+            async move {
+                tokio::select! {
+                    _ = token.cancelled() => engine.increment_epoch(),
+                    _ = done.cancelled() => {}
                 }
-            };
-            let result = func.call(&mut store, arg).map_err(|e| e.to_string());
-            let _ = tx.send(result);
+            }
         });
-
-        match rx.recv_timeout(timeout) {
-            Ok(Ok(val)) => Reply {
-                ok: true,
-                output: json!(val),
+        let run = async {
+            tokio::select! {
+                biased;
+                result = run => Some(result),
+                _ = token.cancelled() => None,
+            }
+        };
+        match tokio::time::timeout(self.timeout, run).await {
+            Ok(None) => Reply {
+                ok: false,
+                output: json!({ "error": "cancelled" }),
                 latency_ms: start.elapsed().as_millis() as u64,
                 cost: json!({}),
             },
-            Ok(Err(err)) => Reply {
+            Ok(Some(Ok((val, fuel)))) => Reply {
+                ok: true,
+                output: val,
+                latency_ms: start.elapsed().as_millis() as u64,
+                cost: json!({ "fuel": fuel }),
+            },
+            Ok(Some(Err(err))) => Reply {
                 ok: false,
                 output: json!({ "error": err }),
                 latency_ms: start.elapsed().as_millis() as u64,
@@ -107,9 +611,42 @@ impl Provider for WasmTool {
             Err(_) => Reply {
                 ok: false,
                 output: json!({ "error": "timeout" }),
-                latency_ms: timeout.as_millis() as u64,
+                latency_ms: self.timeout.as_millis() as u64,
                 cost: json!({}),
             },
         }
     }
 }
+
+impl Provider for WasmTool {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Embedded
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    /// Drives [`WasmTool::ask_async_cancellable`] on the runtime shared by all wasm
+    /// tools, off the caller's runtime when there is one.
+    fn ask_cancellable(&self, ask: Ask, token: &CancellationToken) -> Reply {
+        crate::off_runtime(|| match runtime() {
+            Ok(runtime) => runtime.block_on(self.ask_async_cancellable(ask, token)),
+            Err(err) => Reply {
+                ok: false,
+                output: json!({ "error": err.to_string() }),
+                latency_ms: 0,
+                cost: json!({}),
+            },
+        })
+    }
+
+    /// The agent awaits [`WasmTool::ask_async_cancellable`] directly.
+    fn ask_future<'a>(
+        &'a self,
+        ask: &Ask,
+        token: &'a CancellationToken,
+    ) -> Option<BoxFuture<'a, Reply>> {
+        Some(Box::pin(self.ask_async_cancellable(ask.clone(), token)))
+    }
+}
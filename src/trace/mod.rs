@@ -0,0 +1,107 @@
+//! Run, step and tool spans for an OpenTelemetry tracer, behind the `otel` feature.
+//!
+//! Without the feature, or when no tracer is set, every span is a no-op.
+
+#[cfg(feature = "otel")]
+use std::sync::Arc;
+
+#[cfg(feature = "otel")]
+use opentelemetry::global::BoxedTracer;
+#[cfg(feature = "otel")]
+use opentelemetry::trace::{TraceContextExt, Tracer as _};
+#[cfg(feature = "otel")]
+use opentelemetry::{Context, KeyValue, StringValue};
+use serde_json::Value;
+
+#[derive(Clone, Default)]
+pub(crate) struct Tracer(#[cfg(feature = "otel")] Option<Arc<BoxedTracer>>);
+
+impl Tracer {
+    #[cfg(feature = "otel")]
+    pub(crate) fn new(tracer: BoxedTracer) -> Self {
+        Self(Some(Arc::new(tracer)))
+    }
+
+    /// Starts `name` under `parent`, or under the caller's current context without one.
+    #[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+    pub(crate) fn start(&self, name: &'static str, parent: Option<&Span>) -> Span {
+        #[cfg(feature = "otel")]
+        if let Some(tracer) = &self.0 {
+            let parent = parent
+                .and_then(|p| p.cx.clone())
+                .unwrap_or_else(Context::current);
+            let span = tracer.start_with_context(name, &parent);
+            return Span {
+                cx: Some(parent.with_span(span)),
+            };
+        }
+        Span {
+            #[cfg(feature = "otel")]
+            cx: None,
+        }
+    }
+}
+
+/// An open span; it ends when dropped.
+pub(crate) struct Span {
+    #[cfg(feature = "otel")]
+    cx: Option<Context>,
+}
+
+#[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+impl Span {
+    pub(crate) fn set_str(&self, key: &'static str, value: &str) {
+        #[cfg(feature = "otel")]
+        if let Some(cx) = &self.cx {
+            cx.span()
+                .set_attribute(KeyValue::new(key, value.to_string()));
+        }
+    }
+
+    pub(crate) fn set_int(&self, key: &'static str, value: usize) {
+        #[cfg(feature = "otel")]
+        if let Some(cx) = &self.cx {
+            cx.span().set_attribute(KeyValue::new(key, value as i64));
+        }
+    }
+
+    pub(crate) fn set_bool(&self, key: &'static str, value: bool) {
+        #[cfg(feature = "otel")]
+        if let Some(cx) = &self.cx {
+            cx.span().set_attribute(KeyValue::new(key, value));
+        }
+    }
+
+    pub(crate) fn set_strs(&self, key: &'static str, values: &[String]) {
+        #[cfg(feature = "otel")]
+        if let Some(cx) = &self.cx {
+            let values: Vec<StringValue> = values.iter().map(|v| v.clone().into()).collect();
+            cx.span().set_attribute(KeyValue::new(
+                key,
+                opentelemetry::Value::Array(values.into()),
+            ));
+        }
+    }
+
+    /// Ends the span before it goes out of scope.
+    pub(crate) fn end(self) {}
+
+    /// Adds the span's W3C `traceparent` to an ask context, which HTTP and MCP
+    /// providers forward as a header.
+    pub(crate) fn inject(&self, context: &mut Value) {
+        #[cfg(feature = "otel")]
+        if let (Some(cx), Some(map)) = (&self.cx, context.as_object_mut()) {
+            let span = cx.span();
+            let sc = span.span_context();
+            if sc.is_valid() {
+                let traceparent = format!(
+                    "00-{}-{}-{:02x}",
+                    sc.trace_id(),
+                    sc.span_id(),
+                    sc.trace_flags().to_u8()
+                );
+                map.insert("traceparent".into(), Value::String(traceparent));
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use serde_json::{Map, Value};
+
+/// Token counts that add up when two usage objects are merged.
+const TOKEN_FIELDS: [&str; 5] = [
+    "prompt_tokens",
+    "completion_tokens",
+    "total_tokens",
+    "input_tokens",
+    "output_tokens",
+];
+
+/// Thinking tokens billed by reasoning models. Summed on merge, but not part of the
+/// fallback total: providers already count them in the completion.
+const REASONING_TOKENS: &str = "reasoning_tokens";
+
+/// Where providers report thinking tokens besides a top-level `reasoning_tokens`.
+const REASONING_DETAILS: [&str; 2] = ["completion_tokens_details", "output_tokens_details"];
+
+/// Merges two provider `usage` objects, e.g. from consecutive calls of one run.
+///
+/// Token counts are summed, with a missing count treated as zero. When only one side
+/// reports `total_tokens`, the other side's total is taken from its prompt and
+/// completion (or input and output) counts. Other fields are kept: nested objects are
+/// merged the same way and for anything else `b`'s value wins. A side that is not an
+/// object is ignored.
+pub fn merge_usage(a: &Value, b: &Value) -> Value {
+    let (a, b) = match (a.as_object(), b.as_object()) {
+        (Some(a), Some(b)) => (a, b),
+        (Some(_), None) => return a.clone(),
+        _ => return b.clone(),
+    };
+    let mut merged = a.clone();
+    for (key, value) in b {
+        let sum = match (merged.get(key.as_str()), value) {
+            (Some(Value::Number(x)), Value::Number(y))
+                if TOKEN_FIELDS.contains(&key.as_str()) || key == REASONING_TOKENS =>
+            {
+                match (x.as_u64(), y.as_u64()) {
+                    (Some(x), Some(y)) => Value::from(x + y),
+                    _ => value.clone(),
+                }
+            }
+            (Some(existing @ Value::Object(_)), Value::Object(_)) => merge_usage(existing, value),
+            _ => value.clone(),
+        };
+        merged.insert(key.clone(), sum);
+    }
+    if a.contains_key("total_tokens") != b.contains_key("total_tokens") {
+        merged.insert("total_tokens".into(), Value::from(total(a) + total(b)));
+    }
+    Value::Object(merged)
+}
+
+/// `total_tokens`, or the sum of the other token counts when it is missing.
+fn total(usage: &Map<String, Value>) -> u64 {
+    if let Some(total) = usage.get("total_tokens") {
+        return total.as_u64().unwrap_or(0);
+    }
+    TOKEN_FIELDS
+        .iter()
+        .filter_map(|field| usage.get(*field).and_then(Value::as_u64))
+        .sum()
+}
+
+/// Thinking tokens reported in `usage`: a top-level `reasoning_tokens`, else the one
+/// under `completion_tokens_details` or `output_tokens_details`; zero when absent.
+pub fn reasoning_tokens(usage: &Value) -> u64 {
+    usage
+        .get(REASONING_TOKENS)
+        .or_else(|| {
+            REASONING_DETAILS
+                .iter()
+                .find_map(|details| usage.get(*details)?.get(REASONING_TOKENS))
+        })
+        .and_then(Value::as_u64)
+        .unwrap_or(0)
+}
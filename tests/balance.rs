@@ -0,0 +1,68 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::json;
+
+use soma_agent::{
+    providers::{BalanceStrategy, LoadBalancer},
+    testing::MockProvider,
+    Ask, Provider,
+};
+
+fn ask() -> Ask {
+    Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({}),
+    }
+}
+
+fn pool(n: usize) -> Vec<Arc<MockProvider>> {
+    (0..n)
+        .map(|_| {
+            let mock = MockProvider::new();
+            for _ in 0..10 {
+                mock.push_ok(json!("ok"));
+            }
+            Arc::new(mock)
+        })
+        .collect()
+}
+
+fn boxed(mocks: &[Arc<MockProvider>]) -> Vec<Box<dyn Provider + Send + Sync>> {
+    mocks
+        .iter()
+        .map(|m| Box::new(m.clone()) as Box<dyn Provider + Send + Sync>)
+        .collect()
+}
+
+#[test]
+fn round_robin_distributes_evenly() {
+    let mocks = pool(3);
+    let balancer = LoadBalancer::new(boxed(&mocks), BalanceStrategy::RoundRobin);
+    for _ in 0..9 {
+        assert!(balancer.ask(ask()).ok);
+    }
+    for mock in &mocks {
+        assert_eq!(mock.call_count(), 3);
+    }
+    assert_eq!(balancer.in_flight(), vec![0, 0, 0]);
+}
+
+#[test]
+fn failing_provider_is_skipped_during_cooldown() {
+    let mocks = pool(2);
+    let failing = Arc::new(MockProvider::new());
+    failing.push_failure("down");
+    let mut providers = boxed(&mocks);
+    providers.insert(0, Box::new(failing.clone()));
+    let balancer = LoadBalancer::new(providers, BalanceStrategy::LeastOutstanding)
+        .with_cooldown(Duration::from_secs(60));
+
+    assert!(!balancer.ask(ask()).ok);
+    for _ in 0..4 {
+        assert!(balancer.ask(ask()).ok);
+    }
+    assert_eq!(failing.call_count(), 1);
+    assert_eq!(mocks[0].call_count(), 4);
+}
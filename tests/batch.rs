@@ -0,0 +1,107 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use soma_agent::{
+    providers::{BatchingProvider, FnProvider},
+    Ask, Provider, Reply,
+};
+
+fn doubler(calls: Arc<AtomicUsize>) -> FnProvider<impl Fn(Ask) -> Reply> {
+    FnProvider::new(move |ask: Ask| {
+        calls.fetch_add(1, Ordering::SeqCst);
+        let outputs: Vec<Value> = ask
+            .input
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| json!(v.as_i64().unwrap() * 2))
+            .collect();
+        Reply {
+            ok: true,
+            output: json!(outputs),
+            latency_ms: 1,
+            cost: json!({"total_tokens": outputs.len(), "batch": ask.context["batch_size"]}),
+        }
+    })
+}
+
+fn ask(n: i64) -> Ask {
+    Ask {
+        op: "double".into(),
+        input: json!(n),
+        context: json!({}),
+    }
+}
+
+#[test]
+fn concurrent_asks_within_window_share_one_call() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let provider = Arc::new(BatchingProvider::new(
+        doubler(calls.clone()),
+        Duration::from_millis(100),
+        10,
+    ));
+
+    let handles: Vec<_> = (1..=3)
+        .map(|n| {
+            let provider = provider.clone();
+            thread::spawn(move || (n, provider.ask(ask(n))))
+        })
+        .collect();
+    let replies: Vec<(i64, Reply)> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    for (n, reply) in &replies {
+        assert!(reply.ok);
+        assert_eq!(reply.output, json!(n * 2));
+    }
+    let charged: Vec<&Value> = replies
+        .iter()
+        .map(|(_, r)| &r.cost)
+        .filter(|c| c.get("total_tokens").is_some())
+        .collect();
+    assert_eq!(charged, vec![&json!({"total_tokens": 3, "batch": 3})]);
+}
+
+#[test]
+fn full_batch_dispatches_before_window() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let provider = Arc::new(BatchingProvider::new(
+        doubler(calls.clone()),
+        Duration::from_secs(10),
+        2,
+    ));
+
+    let handles: Vec<_> = (1..=2)
+        .map(|n| {
+            let provider = provider.clone();
+            thread::spawn(move || provider.ask(ask(n)))
+        })
+        .collect();
+    for handle in handles {
+        assert!(handle.join().unwrap().ok);
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn cancelled_caller_returns_and_is_left_out() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let provider = Arc::new(BatchingProvider::new(
+        doubler(calls.clone()),
+        Duration::from_millis(100),
+        10,
+    ));
+    let token = CancellationToken::new();
+    token.cancel();
+
+    let reply = provider.ask_cancellable(ask(1), &token);
+    assert_eq!(reply.output, json!({"error": "cancelled"}));
+    assert_eq!(provider.ask(ask(4)).output, json!(8));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,174 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::json;
+
+use soma_agent::{
+    providers::{CachingProvider, ReplyCache},
+    testing::MockProvider,
+    Ask, Provider,
+};
+
+fn ask(input: &str) -> Ask {
+    Ask {
+        op: "lookup".into(),
+        input: json!(input),
+        context: json!({"request_id": input.len()}),
+    }
+}
+
+#[test]
+fn second_identical_ask_is_served_from_cache() {
+    let inner = Arc::new(MockProvider::new());
+    inner.push_ok(json!("paris"));
+    let provider = CachingProvider::new(inner.clone(), 8, Duration::from_secs(60));
+
+    let first = provider.ask(ask("capital of france"));
+    let second = provider.ask(Ask {
+        context: json!({"request_id": "another-run"}),
+        ..ask("capital of france")
+    });
+
+    assert_eq!(inner.call_count(), 1);
+    assert_eq!(second.output, first.output);
+    assert_eq!(second.cost, json!({"cache_hit": true}));
+    assert_eq!(second.latency_ms, 0);
+}
+
+#[test]
+fn failures_and_opt_outs_are_not_cached() {
+    let inner = Arc::new(MockProvider::new());
+    inner
+        .push_failure("busy")
+        .push_ok(json!(1))
+        .push_ok(json!(2));
+    let provider = CachingProvider::new(inner.clone(), 8, Duration::from_secs(60));
+
+    assert!(!provider.ask(ask("q")).ok);
+    assert_eq!(provider.ask(ask("q")).output, json!(1));
+    let fresh = provider.ask(Ask {
+        context: json!({"no_cache": true}),
+        ..ask("q")
+    });
+    assert_eq!(fresh.output, json!(2));
+    assert_eq!(inner.call_count(), 3);
+}
+
+#[test]
+fn entries_expire_and_least_recently_used_is_evicted() {
+    let inner = Arc::new(MockProvider::new());
+    for i in 0..5 {
+        inner.push_ok(json!(i));
+    }
+    let provider = CachingProvider::new(inner.clone(), 2, Duration::from_millis(50));
+
+    provider.ask(ask("a"));
+    provider.ask(ask("b"));
+    provider.ask(ask("a"));
+    provider.ask(ask("c")); // evicts "b", the least recently used
+    assert_eq!(provider.cache().len(), 2);
+    assert_eq!(inner.call_count(), 3);
+    assert_eq!(provider.ask(ask("b")).output, json!(3));
+
+    thread::sleep(Duration::from_millis(80));
+    assert_eq!(provider.ask(ask("b")).output, json!(4));
+    assert!(provider.cache().get("missing").is_none());
+}
+
+#[test]
+fn disk_cache_survives_reopen() {
+    use soma_agent::providers::DiskCache;
+
+    let dir = std::env::temp_dir().join(format!("soma_disk_cache_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let inner = Arc::new(MockProvider::new());
+    inner.push_ok(json!("paris"));
+    let cache = DiskCache::open(&dir, Duration::from_secs(60)).unwrap();
+    let provider = CachingProvider::with_cache(inner.clone(), cache);
+    provider.ask(ask("capital of france"));
+    drop(provider);
+
+    let reopened = DiskCache::open(&dir, Duration::from_secs(60)).unwrap();
+    let provider = CachingProvider::with_cache(inner.clone(), reopened);
+    let reply = provider.ask(ask("capital of france"));
+    assert_eq!(reply.output, json!("paris"));
+    assert_eq!(reply.cost, json!({"cache_hit": true}));
+    assert_eq!(inner.call_count(), 1);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn disk_cache_expires_and_evicts() {
+    use soma_agent::providers::DiskCache;
+    use soma_agent::Reply;
+
+    let dir = std::env::temp_dir().join(format!("soma_disk_evict_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let reply = |n: i32| Reply {
+        ok: true,
+        output: json!(n),
+        latency_ms: 0,
+        cost: json!({}),
+    };
+
+    let cache = DiskCache::open(&dir, Duration::from_millis(100))
+        .unwrap()
+        .with_max_entries(2);
+    cache.put("a", &reply(1));
+    thread::sleep(Duration::from_millis(20));
+    cache.put("b", &reply(2));
+    thread::sleep(Duration::from_millis(20));
+    cache.put("c", &reply(3));
+    assert!(cache.get("a").is_none());
+    assert_eq!(cache.get("c").unwrap().output, json!(3));
+
+    thread::sleep(Duration::from_millis(120));
+    assert!(cache.get("c").is_none());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn disk_cache_concurrent_writes_of_one_key_do_not_collide() {
+    use soma_agent::providers::DiskCache;
+    use soma_agent::Reply;
+
+    let dir = std::env::temp_dir().join(format!("soma_disk_race_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let cache = Arc::new(DiskCache::open(&dir, Duration::from_secs(60)).unwrap());
+
+    let handles: Vec<_> = (0..8)
+        .map(|n| {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                for _ in 0..50 {
+                    cache.put(
+                        "shared",
+                        &Reply {
+                            ok: true,
+                            output: json!(n),
+                            latency_ms: 0,
+                            cost: json!({}),
+                        },
+                    );
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert!(cache.get("shared").is_some());
+    let leftovers = std::fs::read_dir(&dir)
+        .unwrap()
+        .flatten()
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "tmp"))
+        .count();
+    assert_eq!(leftovers, 0);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
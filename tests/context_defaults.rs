@@ -0,0 +1,48 @@
+use std::sync::Arc;
+
+use serde_json::json;
+
+use soma_agent::{providers::ContextDefaults, testing::MockProvider, Ask, Provider};
+
+#[test]
+fn defaults_fill_gaps_but_caller_fields_win() {
+    let inner = Arc::new(MockProvider::new());
+    inner.push_ok(json!(1)).push_ok(json!(2));
+    let provider = ContextDefaults::new(
+        inner.clone(),
+        json!({
+            "dialect": "dashscope",
+            "tool_choice": "auto",
+            "tools": [{"name": "search", "description": "", "parameters": {}}],
+            "limits": {"max_tokens": 256, "temperature": 0.2}
+        }),
+    );
+
+    provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({
+            "dialect": "openai",
+            "limits": {"temperature": 0.9},
+            "request_id": "r-1"
+        }),
+    });
+    provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: serde_json::Value::Null,
+    });
+
+    let asks = inner.asks();
+    assert_eq!(
+        asks[0].context,
+        json!({
+            "dialect": "openai",
+            "tool_choice": "auto",
+            "tools": [{"name": "search", "description": "", "parameters": {}}],
+            "limits": {"max_tokens": 256, "temperature": 0.9},
+            "request_id": "r-1"
+        })
+    );
+    assert_eq!(asks[1].context["dialect"], "dashscope");
+}
@@ -0,0 +1,90 @@
+//! Every shipped provider decorator must stay registrable as a tool, which requires
+//! `Send + Sync`; this file fails to compile when one loses that.
+
+use std::time::Duration;
+
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+
+use soma_agent::middleware::Layered;
+use soma_agent::providers::{
+    BalanceStrategy, BatchingProvider, CachingProvider, ContextDefaults, CostRouter, DiskCache,
+    EmbeddedRegistry, FallbackProvider, FnProvider, LoadBalancer, RateLimiter,
+    SingleFlightProvider,
+};
+use soma_agent::replay::Recorder;
+use soma_agent::testing::{MockProvider, SequenceRecorder};
+use soma_agent::{Agent, Ask, Provider, ReasoningPolicy};
+
+#[test]
+fn every_decorator_registers_as_a_tool() {
+    let dir = std::env::temp_dir().join(format!("soma-decorators-{}", std::process::id()));
+    let mut agent = Agent::new(MockProvider::new(), 1, 1_000, 0, CancellationToken::new());
+    let mut register = |name: &str, tool: soma_agent::ToolSpec| {
+        agent.register_tool(name, tool).unwrap();
+    };
+
+    register(
+        "fallback",
+        FallbackProvider::new(vec![Box::new(MockProvider::new())]).into(),
+    );
+    register(
+        "router",
+        CostRouter::new(ReasoningPolicy::default())
+            .route("cheap", 1.0, 10, MockProvider::new())
+            .into(),
+    );
+    register(
+        "balance",
+        LoadBalancer::new(
+            vec![Box::new(MockProvider::new())],
+            BalanceStrategy::RoundRobin,
+        )
+        .into(),
+    );
+    register(
+        "batch",
+        BatchingProvider::new(MockProvider::new(), Duration::from_millis(1), 4).into(),
+    );
+    register(
+        "cache",
+        CachingProvider::new(MockProvider::new(), 8, Duration::from_secs(1)).into(),
+    );
+    register(
+        "disk_cache",
+        CachingProvider::with_cache(
+            MockProvider::new(),
+            DiskCache::open(dir.join("cache"), Duration::from_secs(1)).unwrap(),
+        )
+        .into(),
+    );
+    register(
+        "defaults",
+        ContextDefaults::new(MockProvider::new(), json!({})).into(),
+    );
+    register(
+        "rate_limit",
+        RateLimiter::new(MockProvider::new(), 10.0).into(),
+    );
+    register(
+        "single_flight",
+        SingleFlightProvider::new(MockProvider::new()).into(),
+    );
+    register("layered", Layered::new(MockProvider::new()).into());
+    register(
+        "recorder",
+        Recorder::new(MockProvider::new(), dir.join("transcript.jsonl"))
+            .unwrap()
+            .into(),
+    );
+    register(
+        "recording",
+        SequenceRecorder::new().record(MockProvider::new()).into(),
+    );
+    register("registry", EmbeddedRegistry::new().into());
+    register(
+        "fn",
+        FnProvider::new(|ask: Ask| MockProvider::new().ask(ask)).into(),
+    );
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -0,0 +1,134 @@
+use serde_json::json;
+
+use soma_agent::backends::dialect::{convert_messages, convert_tools, DialectAdapter};
+use soma_agent::Ask;
+
+#[test]
+fn tool_schemas_round_trip_between_openai_and_anthropic() {
+    let openai = json!([{
+        "type": "function",
+        "function": {
+            "name": "get_current_weather",
+            "description": "Get the current weather in a given location",
+            "parameters": {
+                "type": "object",
+                "properties": {"location": {"type": "string"}},
+                "required": ["location"]
+            },
+            "strict": true
+        }
+    }]);
+
+    let anthropic = convert_tools("openai", "anthropic", &openai);
+    assert_eq!(
+        anthropic,
+        json!([{
+            "name": "get_current_weather",
+            "description": "Get the current weather in a given location",
+            "input_schema": {
+                "type": "object",
+                "properties": {"location": {"type": "string"}},
+                "required": ["location"]
+            },
+            "strict": true
+        }])
+    );
+    assert_eq!(convert_tools("anthropic", "openai", &anthropic), openai);
+    assert_eq!(
+        convert_tools("openai", "dashscope", &openai),
+        json!([openai[0]["function"]])
+    );
+}
+
+#[test]
+fn tool_calls_and_results_convert_between_dialects() {
+    let openai = json!([
+        {"role": "system", "content": "be brief"},
+        {"role": "user", "content": [
+            {"type": "text", "text": "weather here?"},
+            {"type": "image_url", "image_url": {"url": "data:image/png;base64,aGk="}}
+        ]},
+        {"role": "assistant", "content": "", "tool_calls": [
+            {"id": "call_1", "type": "function",
+             "function": {"name": "weather", "arguments": "{\"city\":\"Paris\"}"}},
+            {"id": "call_2", "type": "function",
+             "function": {"name": "time", "arguments": "{}"}}
+        ]},
+        {"role": "tool", "tool_call_id": "call_1", "content": "sunny"},
+        {"role": "tool", "tool_call_id": "call_2", "content": "noon"}
+    ]);
+
+    let anthropic = convert_messages("openai", "anthropic", &openai);
+    assert_eq!(
+        anthropic,
+        json!([
+            {"role": "system", "content": "be brief"},
+            {"role": "user", "content": [
+                {"type": "text", "text": "weather here?"},
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "aGk="}}
+            ]},
+            {"role": "assistant", "content": [
+                {"type": "tool_use", "id": "call_1", "name": "weather", "input": {"city": "Paris"}},
+                {"type": "tool_use", "id": "call_2", "name": "time", "input": {}}
+            ]},
+            {"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": "call_1", "content": [{"type": "text", "text": "sunny"}]},
+                {"type": "tool_result", "tool_use_id": "call_2", "content": [{"type": "text", "text": "noon"}]}
+            ]}
+        ])
+    );
+    assert_eq!(convert_messages("anthropic", "openai", &anthropic), openai);
+
+    let dashscope = convert_messages("openai", "dashscope", &openai);
+    assert_eq!(
+        dashscope[2],
+        json!({"role": "assistant", "content": "",
+               "function_call": {"name": "weather", "arguments": "{\"city\":\"Paris\"}"}})
+    );
+    assert_eq!(
+        dashscope[4],
+        json!({"role": "function", "name": "weather", "content": "sunny"})
+    );
+    assert_eq!(
+        dashscope[1]["content"][1],
+        json!({"image": "data:image/png;base64,aGk="})
+    );
+
+    // dashscope calls carry no id, so they get positional ones
+    let back = convert_messages("dashscope", "openai", &dashscope);
+    assert_eq!(back[2]["tool_calls"][0]["id"], "call_2");
+    assert_eq!(back[4]["tool_call_id"], "call_2");
+    assert_eq!(back[5]["tool_call_id"], "call_3");
+}
+
+#[test]
+fn adapter_retargets_an_ask() {
+    let adapter = DialectAdapter::new("openai", "dashscope");
+    let ask = adapter.ask(Ask {
+        op: "chat".into(),
+        input: json!([{"role": "user", "content": [{"type": "text", "text": "hi"}]}]),
+        context: json!({"tools": [{"name": "ping", "parameters": {}}]}),
+    });
+    assert_eq!(
+        ask.input,
+        json!([{"role": "user", "content": [{"text": "hi"}]}])
+    );
+    assert_eq!(ask.context["dialect"], "dashscope");
+    assert_eq!(
+        ask.context["tools"],
+        json!([{"name": "ping", "parameters": {}}])
+    );
+}
+
+#[test]
+fn adapter_replaces_a_non_object_context() {
+    let adapter = DialectAdapter::new("openai", "anthropic");
+    for context in [json!(null), json!("ctx"), json!(7), json!([1, 2])] {
+        let ask = adapter.ask(Ask {
+            op: "chat".into(),
+            input: json!("hi"),
+            context,
+        });
+        assert_eq!(ask.context, json!({"dialect": "anthropic"}));
+    }
+}
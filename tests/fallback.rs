@@ -0,0 +1,43 @@
+use serde_json::json;
+
+use soma_agent::{providers::FallbackProvider, testing::MockProvider, Ask, Provider, Reply};
+
+fn ask() -> Ask {
+    Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({}),
+    }
+}
+
+#[test]
+fn falls_back_to_second_provider() {
+    let primary = MockProvider::new();
+    primary.push_failure("rate limited");
+    let secondary = MockProvider::new();
+    secondary.push_ok(json!("from secondary"));
+
+    let provider = FallbackProvider::new(vec![Box::new(primary), Box::new(secondary)]);
+    let reply = provider.ask(ask());
+    assert!(reply.ok);
+    assert_eq!(reply.output, json!("from secondary"));
+    assert_eq!(reply.cost["fallback"], json!({"index": 1, "attempts": 2}));
+}
+
+#[test]
+fn tool_call_replies_do_not_fall_through() {
+    let primary = MockProvider::new();
+    primary.push_reply(Reply {
+        ok: false,
+        output: json!({"tool_calls": [{"op": "ping", "input": {}}]}),
+        latency_ms: 0,
+        cost: json!({}),
+    });
+    let secondary = MockProvider::new();
+    secondary.push_ok(json!("unused"));
+
+    let provider = FallbackProvider::new(vec![Box::new(primary), Box::new(secondary)]);
+    let reply = provider.ask(ask());
+    assert!(reply.output.get("tool_calls").is_some());
+    assert_eq!(reply.cost["fallback"]["index"], 0);
+}
@@ -0,0 +1,121 @@
+use httpmock::prelude::*;
+use serde_json::json;
+
+use soma_agent::{tools::FetchTool, Ask, Provider};
+
+fn ask(input: serde_json::Value) -> Ask {
+    Ask {
+        op: "fetch".into(),
+        input,
+        context: json!({}),
+    }
+}
+
+#[test]
+fn fetches_allowed_host() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path("/page")
+            .header("accept", "text/plain");
+        then.status(200)
+            .header("content-type", "text/plain")
+            .body("hello");
+    });
+
+    let tool = FetchTool::default().allow_host("127.0.0.1");
+    let reply = tool.ask(ask(json!({
+        "url": server.url("/page"),
+        "headers": {"accept": "text/plain"}
+    })));
+    mock.assert();
+    assert!(reply.ok);
+    assert_eq!(reply.output["status"], 200);
+    assert_eq!(reply.output["headers"]["content-type"], "text/plain");
+    assert_eq!(reply.output["body"]["text"], "hello");
+}
+
+#[test]
+fn blocks_private_addresses_by_default() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(GET);
+        then.status(200);
+    });
+
+    let reply = FetchTool::default().ask(ask(json!({"url": server.url("/")})));
+    assert!(!reply.ok);
+    assert!(reply.output["error"]
+        .as_str()
+        .unwrap()
+        .contains("is private"));
+    mock.assert_hits(0);
+
+    let reply = FetchTool::default().ask(ask(json!({"url": "http://10.0.0.1/admin"})));
+    assert!(!reply.ok);
+}
+
+#[test]
+fn blocks_names_resolving_to_private_addresses_at_connect_time() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(GET);
+        then.status(200);
+    });
+
+    let url = format!("http://localhost:{}/", server.port());
+    let reply = FetchTool::default().ask(ask(json!({ "url": url })));
+    assert!(!reply.ok);
+    assert!(reply.output["error"]
+        .as_str()
+        .unwrap()
+        .contains("is private"));
+    mock.assert_hits(0);
+
+    let reply = FetchTool::default()
+        .allow_host("localhost")
+        .ask(ask(json!({ "url": url })));
+    assert!(reply.ok);
+    mock.assert_hits(1);
+}
+
+#[test]
+fn blocks_reserved_multicast_and_embedded_private_ranges() {
+    for url in [
+        "http://0.1.2.3/",
+        "http://224.0.0.1/",
+        "http://240.0.0.1/",
+        "http://255.255.255.255/",
+        "http://[::10.0.0.1]/",
+        "http://[::ffff:127.0.0.1]/",
+        "http://[2002:c0a8:0101::1]/",
+        "http://[ff02::1]/",
+    ] {
+        let reply = FetchTool::default().ask(ask(json!({ "url": url })));
+        assert!(!reply.ok, "{url}");
+        assert!(
+            reply.output["error"]
+                .as_str()
+                .unwrap()
+                .contains("is private"),
+            "{url}: {}",
+            reply.output["error"]
+        );
+    }
+}
+
+#[test]
+fn rejects_oversized_bodies() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(GET);
+        then.status(200).body("0123456789");
+    });
+
+    let tool = FetchTool::default()
+        .allow_host("127.0.0.1")
+        .with_max_bytes(4);
+    let reply = tool.ask(ask(json!({"url": server.url("/")})));
+    assert!(!reply.ok);
+    assert_eq!(reply.output["error"], "response exceeds 4 bytes");
+}
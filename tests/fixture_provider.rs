@@ -0,0 +1,68 @@
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use soma_agent::providers::{FixtureProvider, FnProvider};
+use soma_agent::{Agent, Ask, Provider, Reply};
+
+fn fixtures() -> FixtureProvider {
+    FixtureProvider::from_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures")).unwrap()
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn weather_fixture_drives_the_expected_tool_call() {
+    let seen: Arc<Mutex<Vec<Value>>> = Arc::default();
+    let weather = FnProvider::new({
+        let seen = seen.clone();
+        move |ask: Ask| {
+            seen.lock().unwrap().push(ask.input);
+            Reply {
+                ok: true,
+                output: json!({"temperature": 72}),
+                latency_ms: 0,
+                cost: json!({}),
+            }
+        }
+    });
+    let mut agent = Agent::new(fixtures(), 3, 10_000, 1, CancellationToken::new());
+    agent.register_tool("get_current_weather", weather).unwrap();
+
+    let reply = agent
+        .run(Ask {
+            op: "chat".into(),
+            input: json!([{"role": "user", "content": "What's the weather like in San Francisco?"}]),
+            context: json!({"request_id": "r-1"}),
+        })
+        .await;
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![json!({"location": "San Francisco", "unit": "fahrenheit"})]
+    );
+    // without a `response` the fixture answers the follow-up with the tool output
+    assert!(reply.ok, "{:?}", reply.output);
+    assert_eq!(reply.output, json!({"temperature": 72}));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn fixtures_match_by_op_and_report_misses() {
+    let agent = Agent::new(fixtures(), 1, 10_000, 1, CancellationToken::new());
+    let reply = agent
+        .run(Ask {
+            op: "reasoning_trace".into(),
+            input: json!("anything"),
+            context: json!({}),
+        })
+        .await;
+    assert!(reply.ok);
+    assert_eq!(reply.output["content"], "4");
+
+    let reply = fixtures().ask(Ask {
+        op: "chat".into(),
+        input: json!("unknown question"),
+        context: json!({}),
+    });
+    assert!(!reply.ok);
+    assert_eq!(reply.output["error"], "no matching fixture");
+    assert_eq!(reply.output["available"].as_array().unwrap().len(), 5);
+}
@@ -0,0 +1,67 @@
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+
+use soma_agent::{providers::FnProvider, Agent, Ask, Provider, ProviderKind, Reply};
+
+#[tokio::test(flavor = "current_thread")]
+async fn agent_runs_closure_provider() {
+    let provider = FnProvider::new(|ask: Ask| Reply {
+        ok: true,
+        output: json!({"echo": ask.input}),
+        latency_ms: 0,
+        cost: json!({}),
+    })
+    .with_kind(ProviderKind::SidecarUds);
+    assert_eq!(provider.kind(), ProviderKind::SidecarUds);
+
+    let agent = Agent::new(provider, 1, 1000, 1, CancellationToken::new());
+    let reply = agent
+        .run(Ask {
+            op: "chat".into(),
+            input: json!("hi"),
+            context: json!({}),
+        })
+        .await;
+    assert!(reply.ok);
+    assert_eq!(reply.output, json!({"echo": "hi"}));
+}
+
+#[test]
+fn registry_dispatches_on_op() {
+    use soma_agent::providers::EmbeddedRegistry;
+
+    let ok = |output| Reply {
+        ok: true,
+        output,
+        latency_ms: 0,
+        cost: json!({}),
+    };
+    let registry = EmbeddedRegistry::new()
+        .with_op("add", move |input| {
+            ok(json!(
+                input["a"].as_i64().unwrap() + input["b"].as_i64().unwrap()
+            ))
+        })
+        .with_op("upper", move |input| {
+            ok(json!(input.as_str().unwrap().to_uppercase()))
+        });
+    assert_eq!(registry.kind(), ProviderKind::Embedded);
+    assert_eq!(registry.ops(), ["add", "upper"]);
+
+    let ask = |op: &str, input| Ask {
+        op: op.into(),
+        input,
+        context: json!({}),
+    };
+    assert_eq!(
+        registry.ask(ask("add", json!({"a": 2, "b": 3}))).output,
+        json!(5)
+    );
+    assert_eq!(registry.ask(ask("upper", json!("hi"))).output, json!("HI"));
+    let missing = registry.ask(ask("mul", json!({})));
+    assert!(!missing.ok);
+    assert_eq!(
+        missing.output,
+        json!({"error": "unknown op", "op": "mul", "available": ["add", "upper"]})
+    );
+}
@@ -98,3 +98,1167 @@ fn dashscope_dialect_maps_fields() {
     assert!(reply.ok);
     assert_eq!(reply.output["id"], "2");
 }
+
+#[tokio::test(flavor = "current_thread")]
+async fn request_id_header_stable_across_steps() {
+    use soma_agent::Agent;
+    use tokio_util::sync::CancellationToken;
+
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .header("x-request-id", "req-42");
+        then.status(500).json_body(json!({"error": "busy"}));
+    });
+
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "gpt-test".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    });
+    let agent = Agent::new(provider, 2, 10_000, 1, CancellationToken::new());
+    let reply = agent
+        .run(Ask {
+            op: "chat".into(),
+            input: json!([{ "role": "user", "content": "hi" }]),
+            context: json!({"request_id": "req-42"}),
+        })
+        .await;
+
+    assert!(!reply.ok);
+    mock.assert_hits(2);
+}
+
+#[test]
+fn string_input_is_wrapped_as_user_message() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .json_body(json!({
+                "model": "gpt-test",
+                "messages": [{"role": "user", "content": "hello"}]
+            }));
+        then.status(200).json_body(json!({"id": "1"}));
+    });
+
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "gpt-test".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    });
+    for input in [json!("hello"), json!({"prompt": "hello"})] {
+        let reply = provider.ask(Ask {
+            op: "chat".into(),
+            input,
+            context: json!({}),
+        });
+        assert!(reply.ok);
+    }
+    mock.assert_hits(2);
+}
+
+#[test]
+fn image_message_serializes_per_dialect() {
+    use soma_agent::backends::content::{image_message, ImageSource};
+
+    let url = ImageSource::Url("https://example.com/dog.png".into());
+    assert_eq!(
+        image_message("openai", "what is this?", &url),
+        json!({"role": "user", "content": [
+            {"type": "text", "text": "what is this?"},
+            {"type": "image_url", "image_url": {"url": "https://example.com/dog.png"}}
+        ]})
+    );
+    assert_eq!(
+        image_message("dashscope", "what is this?", &url),
+        json!({"role": "user", "content": [
+            {"image": "https://example.com/dog.png"},
+            {"text": "what is this?"}
+        ]})
+    );
+    let inline = ImageSource::Base64 {
+        media_type: "image/png".into(),
+        data: "aGk=".into(),
+    };
+    assert_eq!(
+        image_message("anthropic", "what is this?", &inline),
+        json!({"role": "user", "content": [
+            {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "aGk="}},
+            {"type": "text", "text": "what is this?"}
+        ]})
+    );
+    assert_eq!(
+        image_message("openai", "x", &inline)["content"][1]["image_url"]["url"],
+        "data:image/png;base64,aGk="
+    );
+}
+
+#[test]
+fn image_parts_are_sent_and_mismatched_dialects_rejected() {
+    use soma_agent::backends::content::{image_message, ImageSource};
+
+    let server = MockServer::start();
+    let image = ImageSource::Url("https://example.com/dog.png".into());
+    let message = image_message("openai", "describe", &image);
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .json_body(json!({"model": "gpt-test", "messages": [message.clone()]}));
+        then.status(200).json_body(json!({"id": "1"}));
+    });
+
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "gpt-test".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    });
+    let reply = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!([message.clone()]),
+        context: json!({}),
+    });
+    assert!(reply.ok);
+
+    let reply = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!([message]),
+        context: json!({"dialect": "dashscope"}),
+    });
+    assert!(!reply.ok);
+    assert!(reply.output["error"]
+        .as_str()
+        .unwrap()
+        .contains("not supported by dialect dashscope"));
+    mock.assert_hits(1);
+}
+
+#[test]
+fn health_check_reports_ok_degraded_and_down() {
+    use soma_agent::Health;
+
+    let server = MockServer::start();
+    let mut models = server.mock(|when, then| {
+        when.method(GET)
+            .path("/v1/models")
+            .header("authorization", "Bearer k");
+        then.status(200).json_body(json!({"data": []}));
+    });
+    let config = HttpConfig {
+        base_url: server.base_url(),
+        model: "gpt-test".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    };
+    let provider = HttpProvider::new(config.clone());
+    assert_eq!(provider.health_check().health, Health::Ok);
+    models.assert();
+
+    models.delete();
+    server.mock(|when, then| {
+        when.method(GET).path("/v1/models");
+        then.status(503);
+    });
+    let status = provider.health_check();
+    assert_eq!(status.health, Health::Degraded);
+    assert_eq!(
+        status.detail.as_deref(),
+        Some("status 503 Service Unavailable")
+    );
+
+    let unreachable = HttpProvider::new(HttpConfig {
+        base_url: "http://127.0.0.1:1".into(),
+        ..config
+    });
+    let status = unreachable.health_check();
+    assert_eq!(status.health, Health::Down);
+    assert!(status.detail.is_some());
+}
+
+#[test]
+fn health_checks_probe_through_wrappers() {
+    use soma_agent::providers::{
+        BalanceStrategy, CachingProvider, FallbackProvider, LoadBalancer, RateLimiter,
+    };
+    use soma_agent::Health;
+
+    let server = MockServer::start();
+    let models = server.mock(|when, then| {
+        when.method(GET).path("/v1/models");
+        then.status(200).json_body(json!({"data": []}));
+    });
+    let up = HttpConfig {
+        base_url: server.base_url(),
+        model: "gpt-test".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    };
+    let down = HttpConfig {
+        base_url: "http://127.0.0.1:1".into(),
+        ..up.clone()
+    };
+
+    let limited = RateLimiter::new(HttpProvider::new(up.clone()), 1.0);
+    assert_eq!(limited.health_check().health, Health::Ok);
+    models.assert_hits(1);
+    let cached = CachingProvider::new(HttpProvider::new(down.clone()), 4, Duration::from_secs(60));
+    assert_eq!(cached.health_check().health, Health::Down);
+
+    let pool = LoadBalancer::new(
+        vec![
+            Box::new(HttpProvider::new(up.clone())),
+            Box::new(HttpProvider::new(down.clone())),
+        ],
+        BalanceStrategy::RoundRobin,
+    );
+    let status = pool.health_check();
+    assert_eq!(status.health, Health::Degraded);
+    assert!(status.detail.unwrap().starts_with("1: Down"));
+
+    let chain = FallbackProvider::new(vec![
+        Box::new(HttpProvider::new(down.clone())),
+        Box::new(HttpProvider::new(down)),
+    ]);
+    assert_eq!(chain.health_check().health, Health::Down);
+    let chain = FallbackProvider::new(vec![Box::new(HttpProvider::new(up))]);
+    assert_eq!(chain.health_check().health, Health::Ok);
+    models.assert_hits(3);
+}
+
+#[test]
+fn debug_hook_reports_body_with_masked_key() {
+    use std::sync::{Arc, Mutex};
+
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST).path("/v1/chat/completions");
+        then.status(200).json_body(json!({"id": "1"}));
+    });
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let sink = seen.clone();
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "gpt-test".into(),
+        api_key: "sk-secret".into(),
+        timeout: Duration::from_secs(1),
+    })
+    .with_debug_hook(move |event| sink.lock().unwrap().push(event.clone()));
+
+    let reply = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({
+            "request_id": "r-1",
+            "headers": {
+                "X-Api-Key": "ctx-api-key",
+                "Cookie": "session=ctx-cookie",
+                "X-Session-Token": "ctx-token",
+                "X-Trace": "visible"
+            }
+        }),
+    });
+    assert!(reply.ok);
+
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 1);
+    let event = &seen[0];
+    assert_eq!(event["url"], server.url("/v1/chat/completions"));
+    assert_eq!(event["body"]["model"], "gpt-test");
+    assert_eq!(
+        event["body"]["messages"],
+        json!([{"role": "user", "content": "hi"}])
+    );
+    assert_eq!(event["headers"]["x-request-id"], "r-1");
+    assert_eq!(event["headers"]["X-Trace"], "visible");
+    assert_eq!(event["headers"]["X-Api-Key"], "[REDACTED]");
+    let shown = event.to_string();
+    for secret in ["sk-secret", "ctx-api-key", "ctx-cookie", "ctx-token"] {
+        assert!(!shown.contains(secret), "{secret} leaked");
+    }
+}
+
+#[test]
+fn forced_tool_choice_is_encoded_per_dialect() {
+    let forced = json!({"type": "function", "function": {"name": "ping"}});
+    let server = MockServer::start();
+    let openai = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .json_body_partial(json!({"tool_choice": forced}).to_string());
+        then.status(200).json_body(json!({"id": "1"}));
+    });
+    let dashscope = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .json_body_partial(json!({"function_call": forced}).to_string());
+        then.status(200).json_body(json!({"id": "2"}));
+    });
+
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "m".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    });
+    let ask = |context| Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context,
+    };
+    let tools = json!([{ "name": "ping", "description": "", "parameters": {} }]);
+
+    let reply = provider.ask(ask(
+        json!({"tools": tools, "tool_choice": {"force": "ping"}}),
+    ));
+    assert_eq!(reply.output["id"], "1");
+    let reply = provider.ask(ask(json!({
+        "dialect": "dashscope",
+        "tools": tools,
+        "tool_choice": {"force": "ping"}
+    })));
+    assert_eq!(reply.output["id"], "2");
+    openai.assert();
+    dashscope.assert();
+
+    let reply = provider.ask(ask(json!({
+        "dialect": "dashscope",
+        "tools": tools,
+        "tool_choice": "required"
+    })));
+    assert!(!reply.ok);
+    assert!(reply.output["error"]
+        .as_str()
+        .unwrap()
+        .contains("not supported by dialect dashscope"));
+}
+
+#[test]
+fn required_tool_choice_passes_through_for_openai() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .json_body_partial(json!({"tool_choice": "required"}).to_string());
+        then.status(200).json_body(json!({"id": "1"}));
+    });
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "m".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    });
+    let reply = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({"tool_choice": "required"}),
+    });
+    assert!(reply.ok);
+    mock.assert();
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn idempotency_key_header_stable_across_tool_retries() {
+    use soma_agent::{testing::MockProvider, Agent, Backoff, Reply};
+    use std::sync::Mutex;
+    use tokio_util::sync::CancellationToken;
+
+    static KEYS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    fn record(req: &HttpMockRequest) -> bool {
+        let key = req.headers.iter().flatten().find_map(|(name, value)| {
+            name.eq_ignore_ascii_case("idempotency-key")
+                .then(|| value.clone())
+        });
+        KEYS.lock().unwrap().extend(key);
+        true
+    }
+
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .matches(record);
+        then.status(500).json_body(json!({"error": "busy"}));
+    });
+    let model = MockProvider::new();
+    model.push_reply(Reply {
+        ok: false,
+        output: json!({"tool_calls": [{"op": "charge", "input": "card-1"}]}),
+        latency_ms: 0,
+        cost: json!({}),
+    });
+    let mut agent =
+        Agent::new(model, 2, 10_000, 3, CancellationToken::new()).with_backoff(Backoff {
+            initial: Duration::from_millis(1),
+            ..Backoff::default()
+        });
+    agent
+        .register_tool(
+            "charge",
+            HttpProvider::new(HttpConfig {
+                base_url: server.base_url(),
+                model: "gpt-test".into(),
+                api_key: "k".into(),
+                timeout: Duration::from_secs(1),
+            }),
+        )
+        .unwrap();
+
+    let reply = agent
+        .run(Ask {
+            op: "chat".into(),
+            input: json!("charge it"),
+            context: json!({"request_id": "req-7"}),
+        })
+        .await;
+
+    assert!(!reply.ok);
+    mock.assert_hits(3);
+    let keys = KEYS.lock().unwrap();
+    assert_eq!(keys.len(), 3);
+    assert!(keys[0].starts_with("req-7:"), "{}", keys[0]);
+    assert!(keys.iter().all(|k| *k == keys[0]));
+}
+
+#[test]
+fn reasoning_content_is_split_from_answer() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST).path("/v1/chat/completions");
+        then.status(200).json_body(json!({
+            "id": "1",
+            "choices": [{"index": 0, "message": {
+                "role": "assistant",
+                "reasoning_content": "To compute 2 + 2, add the numbers to get 4.",
+                "content": "4"
+            }}]
+        }));
+    });
+
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "qwen-test".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    });
+    let reply = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("Think step by step: what is 2 + 2?"),
+        context: json!({}),
+    });
+
+    assert!(reply.ok);
+    assert_eq!(
+        reply.output["reasoning_content"],
+        "To compute 2 + 2, add the numbers to get 4."
+    );
+    assert_eq!(reply.output["content"], "4");
+    // the raw response is still there
+    assert_eq!(reply.output["choices"][0]["message"]["content"], "4");
+}
+
+#[test]
+fn cancel_aborts_slow_request() {
+    use std::time::Instant;
+    use tokio_util::sync::CancellationToken;
+
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/v1/chat/completions");
+        then.status(200)
+            .delay(Duration::from_secs(5))
+            .json_body(json!({"id": "1"}));
+    });
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "m".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(30),
+    });
+    let token = CancellationToken::new();
+    let canceller = token.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        canceller.cancel();
+    });
+
+    let start = Instant::now();
+    let reply = provider.ask_cancellable(
+        Ask {
+            op: "chat".into(),
+            input: json!("hi"),
+            context: json!({}),
+        },
+        &token,
+    );
+
+    assert!(
+        start.elapsed() < Duration::from_secs(2),
+        "{:?}",
+        start.elapsed()
+    );
+    assert!(!reply.ok);
+    assert_eq!(reply.output, json!({"error": "cancelled"}));
+    mock.assert();
+}
+
+#[test]
+fn oversized_response_is_rejected() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST).path("/v1/chat/completions");
+        then.status(200)
+            .json_body(json!({"id": "1", "content": "x".repeat(4_096)}));
+    });
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "m".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    })
+    .with_max_reply_bytes(1_024);
+
+    let reply = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({}),
+    });
+
+    assert!(!reply.ok);
+    assert_eq!(reply.output, json!({"error": "response_too_large"}));
+}
+
+#[test]
+fn requests_carry_user_agent() {
+    let server = MockServer::start();
+    let default_ua = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .header("user-agent", soma_agent::USER_AGENT);
+        then.status(200).json_body(json!({"id": "1"}));
+    });
+    let custom_ua = server.mock(|when, then| {
+        when.method(GET)
+            .path("/v1/models")
+            .header("user-agent", "my-app/2.0");
+        then.status(200).json_body(json!({"data": []}));
+    });
+    let config = HttpConfig {
+        base_url: server.base_url(),
+        model: "m".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    };
+
+    let reply = HttpProvider::new(config.clone()).ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({}),
+    });
+    let health = HttpProvider::new(config)
+        .with_user_agent("my-app/2.0")
+        .health_check();
+
+    assert!(reply.ok);
+    assert_eq!(health.health, soma_agent::Health::Ok);
+    default_ua.assert();
+    custom_ua.assert();
+}
+
+#[test]
+fn non_json_success_is_not_ok() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST).path("/v1/chat/completions");
+        then.status(200)
+            .header("content-type", "text/html")
+            .body("<html>gateway error</html>");
+    });
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "m".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    });
+
+    let reply = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({}),
+    });
+
+    assert!(!reply.ok);
+    assert_eq!(reply.output["error"], json!("invalid_json"));
+    assert_eq!(reply.output["status"], json!(200));
+    assert_eq!(reply.output["content_type"], json!("text/html"));
+    assert_eq!(reply.output["body"], json!("<html>gateway error</html>"));
+}
+
+#[test]
+fn n_completions_surface_every_choice() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .json_body_partial(json!({"n": 3}).to_string());
+        then.status(200).json_body(json!({
+            "id": "1",
+            "choices": [
+                {"index": 0, "message": {"role": "assistant", "content": "a"}},
+                {"index": 1, "message": {"role": "assistant", "content": "b"}},
+                {"index": 2, "message": {"role": "assistant", "content": "c"}},
+            ]
+        }));
+    });
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "m".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    });
+
+    let reply = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({"n": 3}),
+    });
+
+    mock.assert();
+    assert!(reply.ok);
+    let contents: Vec<&str> = reply.output["candidates"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["content"].as_str().unwrap())
+        .collect();
+    assert_eq!(contents, ["a", "b", "c"]);
+}
+
+#[test]
+fn n_over_dashscope_limit_is_rejected() {
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: "http://127.0.0.1:9".into(),
+        model: "m".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    });
+    let reply = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({"dialect": "dashscope", "n": 5}),
+    });
+    assert!(!reply.ok);
+    assert_eq!(
+        reply.output["error"],
+        "n 5 exceeds dialect dashscope's limit of 4"
+    );
+}
+
+#[test]
+fn context_headers_are_sent_without_overriding_auth() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .header("X-Title", "soma")
+            .header("HTTP-Referer", "https://example.com")
+            .header("Authorization", "Bearer k")
+            .matches(|req| {
+                let auth = req
+                    .headers
+                    .iter()
+                    .flatten()
+                    .filter(|(name, _)| name.eq_ignore_ascii_case("authorization"));
+                auth.count() == 1
+            });
+        then.status(200).json_body(json!({"id": "1"}));
+    });
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "m".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    });
+
+    let reply = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({"headers": {
+            "X-Title": "soma",
+            "HTTP-Referer": "https://example.com",
+            "authorization": "Bearer stolen",
+        }}),
+    });
+
+    assert!(reply.ok, "{:?}", reply.output);
+    mock.assert();
+}
+
+#[test]
+fn openrouter_dialect_sets_app_headers_and_routing() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .header("HTTP-Referer", "https://soma.example")
+            .header("X-Title", "soma")
+            .json_body_partial(
+                json!({
+                    "models": ["anthropic/claude-3.5-sonnet", "openai/gpt-4o"],
+                    "provider": {"order": ["Anthropic"], "allow_fallbacks": false},
+                    "usage": {"include": true},
+                })
+                .to_string(),
+            );
+        then.status(200).json_body(json!({
+            "id": "1",
+            "choices": [{"message": {"role": "assistant", "content": "hi"}}],
+            "usage": {"prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4, "cost": 0.0012},
+        }));
+    });
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "anthropic/claude-3.5-sonnet".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    })
+    .with_openrouter_app("https://soma.example", "soma");
+
+    let reply = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({
+            "dialect": "openrouter",
+            "models": ["anthropic/claude-3.5-sonnet", "openai/gpt-4o"],
+            "provider": {"order": ["Anthropic"], "allow_fallbacks": false},
+        }),
+    });
+
+    mock.assert();
+    assert!(reply.ok, "{:?}", reply.output);
+    assert_eq!(reply.cost["cost"], json!(0.0012));
+    assert_eq!(reply.cost["total_tokens"], json!(4));
+}
+
+#[test]
+fn validate_rejects_bad_config() {
+    use soma_agent::backends::http::ConfigError;
+
+    let config = HttpConfig {
+        base_url: "https://api.example.com".into(),
+        model: "m".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    };
+    assert_eq!(config.validate(), Ok(()));
+
+    let empty_url = HttpConfig {
+        base_url: String::new(),
+        ..config.clone()
+    };
+    assert_eq!(
+        empty_url.validate(),
+        Err(ConfigError::InvalidUrl(String::new()))
+    );
+
+    let not_a_url = HttpConfig {
+        base_url: "api.example.com".into(),
+        ..config.clone()
+    };
+    assert!(matches!(
+        not_a_url.validate(),
+        Err(ConfigError::InvalidUrl(_))
+    ));
+    assert!(HttpProvider::try_new(not_a_url).is_err());
+
+    let ftp = HttpConfig {
+        base_url: "ftp://api.example.com".into(),
+        ..config.clone()
+    };
+    assert_eq!(
+        ftp.validate(),
+        Err(ConfigError::UnsupportedScheme("ftp".into()))
+    );
+
+    let no_key = HttpConfig {
+        api_key: "  ".into(),
+        ..config
+    };
+    assert_eq!(no_key.validate(), Err(ConfigError::MissingApiKey));
+    assert!(HttpProvider::try_new(no_key.clone()).is_err());
+    assert!(HttpProvider::without_auth(no_key.clone()).is_ok());
+    // `new` keeps accepting what it always did, e.g. a keyless local server.
+    let _ = HttpProvider::new(no_key);
+}
+
+#[test]
+fn without_auth_sends_no_authorization() {
+    let server = MockServer::start();
+    let unauthorized = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .header_exists("authorization");
+        then.status(401);
+    });
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/v1/chat/completions");
+        then.status(200).json_body(json!({"id": "1"}));
+    });
+    let provider = HttpProvider::without_auth(HttpConfig {
+        base_url: server.base_url(),
+        model: "m".into(),
+        api_key: String::new(),
+        timeout: Duration::from_secs(1),
+    })
+    .unwrap();
+
+    let reply = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({}),
+    });
+
+    assert!(reply.ok);
+    unauthorized.assert_hits(0);
+    mock.assert();
+}
+
+#[test]
+fn seed_and_logprobs_round_trip() {
+    use soma_agent::backends::http::{token_logprobs, TokenLogprob, TopLogprob};
+
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .json_body_partial(json!({"seed": 7, "logprobs": true, "top_logprobs": 1}).to_string());
+        then.status(200).json_body(json!({
+            "id": "1",
+            "choices": [{
+                "message": {"role": "assistant", "content": "Hi"},
+                "logprobs": {"content": [{
+                    "token": "Hi",
+                    "logprob": -0.25,
+                    "bytes": [72, 105],
+                    "top_logprobs": [{"token": "Hi", "logprob": -0.25, "bytes": [72, 105]}]
+                }]}
+            }]
+        }));
+    });
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "m".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    });
+
+    let reply = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({"seed": 7, "top_logprobs": 1}),
+    });
+
+    assert!(reply.ok);
+    mock.assert();
+    assert_eq!(
+        token_logprobs(&reply.output),
+        Some(vec![TokenLogprob {
+            token: "Hi".into(),
+            logprob: -0.25,
+            top_logprobs: vec![TopLogprob {
+                token: "Hi".into(),
+                logprob: -0.25,
+            }],
+        }])
+    );
+
+    let rejected = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({"dialect": "dashscope", "top_logprobs": 6}),
+    });
+    assert!(!rejected.ok);
+    assert_eq!(
+        rejected.output,
+        json!({"error": "top_logprobs 6 exceeds dialect dashscope's limit of 5"})
+    );
+}
+
+#[test]
+fn role_validation_rejects_orphan_tool_messages() {
+    use soma_agent::backends::content::validate_roles;
+
+    let valid = json!([
+        {"role": "system", "content": "be brief"},
+        {"role": "developer", "content": "answer in English"},
+        {"role": "user", "content": "weather?"},
+        {"role": "assistant", "content": null, "tool_calls": [
+            {"id": "call_1", "type": "function", "function": {"name": "weather", "arguments": "{}"}}
+        ]},
+        {"role": "tool", "tool_call_id": "call_1", "content": "sunny"},
+        {"role": "assistant", "content": "Sunny."}
+    ]);
+    assert_eq!(validate_roles("openai", &valid), Ok(()));
+    assert!(validate_roles("dashscope", &valid).is_err());
+
+    let orphan = json!([
+        {"role": "user", "content": "hi"},
+        {"role": "tool", "tool_call_id": "call_1", "content": "sunny"}
+    ]);
+    assert_eq!(
+        validate_roles("openai", &orphan),
+        Err("message 1: tool message without a preceding tool call".into())
+    );
+
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/v1/chat/completions");
+        then.status(200).json_body(json!({"id": "1"}));
+    });
+    let config = HttpConfig {
+        base_url: server.base_url(),
+        model: "m".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    };
+    let ask = Ask {
+        op: "chat".into(),
+        input: orphan,
+        context: json!({}),
+    };
+
+    let checked = HttpProvider::new(config.clone()).with_role_validation(true);
+    let reply = checked.ask(ask.clone());
+    assert!(!reply.ok);
+    assert_eq!(
+        reply.output,
+        json!({"error": "message 1: tool message without a preceding tool call"})
+    );
+    assert!(
+        checked
+            .ask(Ask {
+                input: valid,
+                ..ask.clone()
+            })
+            .ok
+    );
+
+    // off by default: the server gets to judge
+    assert!(HttpProvider::new(config).ask(ask).ok);
+    mock.assert_hits(2);
+}
+
+#[test]
+fn large_bodies_are_gzipped() {
+    fn gunzipped_chat(req: &HttpMockRequest) -> bool {
+        use std::io::Read;
+        let mut text = String::new();
+        let body = req.body.as_deref().unwrap_or_default();
+        if flate2::read::GzDecoder::new(body)
+            .read_to_string(&mut text)
+            .is_err()
+        {
+            return false;
+        }
+        let json: serde_json::Value = serde_json::from_str(&text).unwrap_or_default();
+        json["model"] == "m" && json["messages"][0]["content"] == "x".repeat(2_000)
+    }
+
+    let server = MockServer::start();
+    let compressed = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .header("content-encoding", "gzip")
+            .header("content-type", "application/json")
+            .matches(gunzipped_chat);
+        then.status(200).json_body(json!({"id": "big"}));
+    });
+    let plain = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .json_body(json!({"model": "m", "messages": [{"role": "user", "content": "hi"}]}));
+        then.status(200).json_body(json!({"id": "small"}));
+    });
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "m".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    })
+    .with_gzip_requests(1_024);
+
+    let big = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("x".repeat(2_000)),
+        context: json!({}),
+    });
+    let small = provider.ask(Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({}),
+    });
+
+    assert_eq!(big.output["id"], "big");
+    assert_eq!(small.output["id"], "small");
+    compressed.assert();
+    plain.assert();
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn image_tool_results_are_threaded_back_as_content_parts() {
+    use soma_agent::providers::{FixtureProvider, FnProvider};
+    use soma_agent::{Agent, Reply};
+    use tokio_util::sync::CancellationToken;
+
+    let server = MockServer::start();
+    let image = "https://example.com/dachshund.png";
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .json_body(json!({
+                "model": "gpt-test",
+                "messages": [{"role": "user", "content": [
+                    {"type": "text", "text": "my_image_gen returned an image"},
+                    {"type": "image_url", "image_url": {"url": image}}
+                ]}]
+            }));
+        then.status(200)
+            .json_body(json!({"choices": [{"message": {"content": "A dachshund."}}]}));
+    });
+    let http = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "gpt-test".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    });
+    let fixtures =
+        FixtureProvider::from_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures")).unwrap();
+    // the fixture picks the tool; the model's follow-up goes over HTTP
+    let model = FnProvider::new(move |ask: Ask| {
+        if ask.context.get("tool").is_some() {
+            http.ask(ask)
+        } else {
+            fixtures.ask(ask)
+        }
+    });
+    let image_gen = FnProvider::new(move |_: Ask| Reply {
+        ok: true,
+        output: json!({"image_url": image}),
+        latency_ms: 0,
+        cost: json!({}),
+    });
+    let mut agent = Agent::new(model, 3, 10_000, 1, CancellationToken::new());
+    agent.register_tool("my_image_gen", image_gen).unwrap();
+
+    let reply = agent
+        .run(Ask {
+            op: "chat".into(),
+            input: json!([{"role": "user", "content": "画一只可爱的小腊肠狗"}]),
+            context: json!({"request_id": "r-1"}),
+        })
+        .await;
+    mock.assert();
+    assert!(reply.ok, "{:?}", reply.output);
+}
+
+#[test]
+fn tool_result_messages_follow_each_dialect() {
+    use soma_agent::backends::content::{tool_result_messages, validate_messages, ToolResult};
+
+    let image = json!({"image_url": "data:image/png;base64,aGk="});
+    let text = json!("sunny");
+    let results = [
+        ToolResult {
+            name: "draw",
+            call_id: Some("call_1"),
+            output: &image,
+        },
+        ToolResult {
+            name: "weather",
+            call_id: Some("call_2"),
+            output: &text,
+        },
+    ];
+
+    let openai = tool_result_messages("openai", &results);
+    assert_eq!(
+        openai,
+        vec![
+            json!({"role": "tool", "tool_call_id": "call_1", "content": "draw returned an image"}),
+            json!({"role": "tool", "tool_call_id": "call_2", "content": "sunny"}),
+            json!({"role": "user", "content": [
+                {"type": "text", "text": "draw returned an image"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,aGk="}}
+            ]}),
+        ]
+    );
+
+    let anthropic = tool_result_messages("anthropic", &results);
+    assert_eq!(
+        anthropic,
+        vec![json!({"role": "user", "content": [
+            {"type": "tool_result", "tool_use_id": "call_1", "content": [
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "aGk="}}
+            ]},
+            {"type": "tool_result", "tool_use_id": "call_2", "content": [
+                {"type": "text", "text": "sunny"}
+            ]}
+        ]})]
+    );
+
+    let dashscope = tool_result_messages("dashscope", &results);
+    assert_eq!(
+        dashscope,
+        vec![
+            json!({"role": "function", "name": "draw", "content": [
+                {"image": "data:image/png;base64,aGk="}
+            ]}),
+            json!({"role": "function", "name": "weather", "content": "sunny"}),
+        ]
+    );
+
+    for (dialect, messages) in [
+        ("openai", openai),
+        ("anthropic", anthropic),
+        ("dashscope", dashscope),
+    ] {
+        assert_eq!(validate_messages(dialect, &json!(messages)), Ok(()));
+    }
+}
+
+#[test]
+fn over_limit_message_arrays_are_rejected_locally() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/v1/chat/completions");
+        then.status(200).json_body(json!({"id": "1"}));
+    });
+    let provider = HttpProvider::new(HttpConfig {
+        base_url: server.base_url(),
+        model: "m".into(),
+        api_key: "k".into(),
+        timeout: Duration::from_secs(1),
+    })
+    .with_max_input_messages(2);
+    let message = json!({"role": "user", "content": "hi"});
+    let ask = |count: usize| Ask {
+        op: "chat".into(),
+        input: json!(vec![message.clone(); count]),
+        context: json!({}),
+    };
+
+    let reply = provider.ask(ask(3));
+    assert!(!reply.ok);
+    assert_eq!(
+        reply.output,
+        json!({"error": "3 messages exceed max_input_messages of 2"})
+    );
+    mock.assert_hits(0);
+
+    assert!(provider.ask(ask(2)).ok);
+    mock.assert_hits(1);
+}
@@ -38,7 +38,7 @@ fn mcp_tool_from_config_invokes() {
     });
     let _invoke = server.mock(|when, then| {
         when.method(POST)
-            .json_body_partial(json!({"method": "invoke"}).to_string());
+            .json_body_partial(json!({"method": "invoke", "params": {"tool": "ping"}}).to_string());
         then.status(200)
             .json_body(json!({"jsonrpc":"2.0","id":3,"result":"pong"}));
     });
@@ -47,16 +47,18 @@ fn mcp_tool_from_config_invokes() {
     std::fs::write(&cfg_path, format!("{{\"ping\": \"{}\"}}", server.url("/"))).unwrap();
 
     let mut agent = Agent::new(Dummy, 1, 1000, 1, CancellationToken::new());
-    agent
+    let registered = agent
         .register_tool("cfg", ToolSpec::McpConfigFile(cfg_path))
         .unwrap();
 
-    assert!(agent.has_tool("ping"));
+    assert_eq!(registered, vec!["cfg.ping"]);
+    assert!(agent.has_tool("cfg.ping"));
+    assert!(!agent.has_tool("ping"));
     let reply = agent
         .call_tool(
-            "ping",
+            "cfg.ping",
             Ask {
-                op: "ping".into(),
+                op: "cfg.ping".into(),
                 input: json!({}),
                 context: json!({}),
             },
@@ -68,3 +70,197 @@ fn mcp_tool_from_config_invokes() {
     _schema.assert();
     _invoke.assert();
 }
+
+#[test]
+fn mcp_endpoint_schema_is_discoverable() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST)
+            .json_body_partial(json!({"method": "handshake"}).to_string());
+        then.status(200)
+            .json_body(json!({"jsonrpc":"2.0","id":1,"result":{"ok":true}}));
+    });
+    let schema = json!({"type": "object", "properties": {"host": {"type": "string"}}});
+    server.mock(|when, then| {
+        when.method(POST)
+            .json_body_partial(json!({"method": "schema"}).to_string());
+        then.status(200)
+            .json_body(json!({"jsonrpc":"2.0","id":2,"result":schema}));
+    });
+
+    let mut agent = Agent::new(Dummy, 1, 1000, 1, CancellationToken::new());
+    let registered = agent
+        .register_tool("ping", ToolSpec::McpEndpoint(server.url("/")))
+        .unwrap();
+
+    assert_eq!(registered, vec!["ping"]);
+    assert_eq!(agent.tool_names(), vec!["ping"]);
+    assert_eq!(agent.tool_schema("ping"), Some(&schema));
+    assert!(agent.remove_tool("ping"));
+    assert!(agent.tool_schema("ping").is_none());
+}
+
+#[test]
+fn mcp_health_check_reruns_handshake() {
+    use soma_agent::{mcp::McpProvider, Health};
+
+    let server = MockServer::start();
+    let handshake = server.mock(|when, then| {
+        when.method(POST)
+            .json_body_partial(json!({"method": "handshake"}).to_string());
+        then.status(200)
+            .json_body(json!({"jsonrpc":"2.0","id":1,"result":{"ok":true}}));
+    });
+    let provider = McpProvider::new(server.url("/")).unwrap();
+    assert_eq!(provider.health_check().health, Health::Ok);
+    handshake.assert_hits(2);
+}
+
+#[test]
+fn mcp_schema_past_ttl_is_refetched() {
+    use soma_agent::mcp::{McpProvider, SchemaCacheStats};
+    use std::time::Duration;
+
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST)
+            .json_body_partial(json!({"method": "handshake"}).to_string());
+        then.status(200)
+            .json_body(json!({"jsonrpc":"2.0","id":1,"result":{"ok":true}}));
+    });
+    let schema_mock = server.mock(|when, then| {
+        when.method(POST)
+            .json_body_partial(json!({"method": "schema"}).to_string());
+        then.status(200)
+            .json_body(json!({"jsonrpc":"2.0","id":2,"result":{"type":"object"}}));
+    });
+    let provider = McpProvider::new(server.url("/"))
+        .unwrap()
+        .with_schema_ttl(Duration::from_millis(50));
+
+    assert_eq!(provider.schema("ping"), Some(json!({"type": "object"})));
+    assert!(provider.schema("ping").is_some());
+    schema_mock.assert_hits(1);
+
+    std::thread::sleep(Duration::from_millis(80));
+    assert!(provider.schema("ping").is_some());
+    schema_mock.assert_hits(2);
+    assert_eq!(
+        provider.schema_cache_stats(),
+        SchemaCacheStats { hits: 1, misses: 2 }
+    );
+
+    assert!(provider.refresh_schema("ping").is_some());
+    schema_mock.assert_hits(3);
+    provider.clear_schema_cache();
+    assert!(provider.schema("ping").is_some());
+    schema_mock.assert_hits(4);
+}
+
+#[test]
+fn mcp_provider_reports_progress() {
+    use soma_agent::mcp::McpProvider;
+    use std::sync::{Arc, Mutex};
+
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST)
+            .json_body_partial(json!({"method": "handshake"}).to_string());
+        then.status(200)
+            .json_body(json!({"jsonrpc":"2.0","id":1,"result":{"ok":true}}));
+    });
+    server.mock(|when, then| {
+        when.method(POST)
+            .json_body_partial(json!({"method": "schema"}).to_string());
+        then.status(200)
+            .json_body(json!({"jsonrpc":"2.0","id":2,"result":{}}));
+    });
+    let progress = json!({"jsonrpc":"2.0","method":"notifications/progress",
+                          "params":{"progressToken":3,"progress":1}});
+    let result = json!({"jsonrpc":"2.0","id":3,"result":{"pong":true}});
+    server.mock(|when, then| {
+        when.method(POST)
+            .json_body_partial(json!({"method": "invoke"}).to_string());
+        then.status(200)
+            .header("content-type", "text/event-stream")
+            .body(format!("data: {progress}\n\ndata: {result}\n\n"));
+    });
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let provider = McpProvider::new(server.url("/")).unwrap().on_progress({
+        let seen = seen.clone();
+        move |p| seen.lock().unwrap().push(p)
+    });
+    let reply = provider.ask(Ask {
+        op: "ping".into(),
+        input: json!({}),
+        context: json!({}),
+    });
+    assert!(reply.ok, "{:?}", reply.output);
+    assert_eq!(reply.output, json!({"pong": true}));
+    assert_eq!(seen.lock().unwrap()[0]["progress"], 1);
+}
+
+#[test]
+fn mcp_oversized_response_is_rejected() {
+    use soma_agent::mcp::McpProvider;
+
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST)
+            .json_body_partial(json!({"method": "handshake"}).to_string());
+        then.status(200)
+            .json_body(json!({"jsonrpc":"2.0","id":1,"result":{"ok":true}}));
+    });
+    server.mock(|when, then| {
+        when.method(POST)
+            .json_body_partial(json!({"method": "schema"}).to_string());
+        then.status(200)
+            .json_body(json!({"jsonrpc":"2.0","id":2,"result":{}}));
+    });
+    server.mock(|when, then| {
+        when.method(POST)
+            .json_body_partial(json!({"method": "invoke"}).to_string());
+        then.status(200)
+            .json_body(json!({"jsonrpc":"2.0","id":3,"result":"x".repeat(4_096)}));
+    });
+
+    let provider = McpProvider::new(server.url("/"))
+        .unwrap()
+        .with_max_reply_bytes(1_024);
+    let reply = provider.ask(Ask {
+        op: "dump".into(),
+        input: json!({}),
+        context: json!({}),
+    });
+    assert!(!reply.ok);
+    assert_eq!(reply.output, json!({"error": "response_too_large"}));
+}
+
+#[test]
+fn mcp_requests_carry_user_agent() {
+    use mcp_client::RpcConfig;
+    use soma_agent::{mcp::McpProvider, USER_AGENT};
+
+    let server = MockServer::start();
+    let default_ua = server.mock(|when, then| {
+        when.method(POST).header("user-agent", USER_AGENT);
+        then.status(200)
+            .json_body(json!({"jsonrpc":"2.0","id":1,"result":{"ok":true}}));
+    });
+    let custom_ua = server.mock(|when, then| {
+        when.method(POST).header("user-agent", "my-app/2.0");
+        then.status(200)
+            .json_body(json!({"jsonrpc":"2.0","id":1,"result":{"ok":true}}));
+    });
+
+    McpProvider::new(server.url("/")).unwrap();
+    let config = RpcConfig {
+        user_agent: "my-app/2.0".into(),
+        ..RpcConfig::default()
+    };
+    McpProvider::with_config(server.url("/"), config).unwrap();
+
+    default_ua.assert();
+    custom_ua.assert();
+}
@@ -0,0 +1,154 @@
+use mcp_client::{Error, McpClient};
+use serde_json::json;
+use soma_agent::mcp::{McpProvider, McpServer};
+use soma_agent::providers::FnProvider;
+use soma_agent::{Ask, Provider, Reply};
+
+fn upper() -> FnProvider<impl Fn(Ask) -> Reply> {
+    FnProvider::new(|ask: Ask| Reply {
+        ok: ask.input.is_string(),
+        output: match ask.input.as_str() {
+            Some(text) => json!({"text": text.to_uppercase(), "context": ask.context}),
+            None => json!({"error": "expected a string"}),
+        },
+        latency_ms: 0,
+        cost: json!({}),
+    })
+}
+
+#[test]
+fn client_invokes_served_provider() {
+    let schema = json!({"type": "string"});
+    let handle = McpServer::new()
+        .with_tool("upper", upper(), schema.clone())
+        .spawn("127.0.0.1:0")
+        .unwrap();
+
+    let client = McpClient::new(handle.url()).unwrap();
+    assert_eq!(client.schema("upper").unwrap(), schema);
+    let out = client
+        .invoke_with_headers("upper", json!("hi"), &[("x-request-id", "req-9")])
+        .unwrap();
+    assert_eq!(out["text"], "HI");
+    assert_eq!(out["context"]["request_id"], "req-9");
+
+    match client.invoke("upper", json!(1)) {
+        Err(Error::Rpc(err)) => assert_eq!(err["data"]["error"], "expected a string"),
+        other => panic!("expected rpc error, got {other:?}"),
+    }
+    match client.invoke("missing", json!({})) {
+        Err(Error::Rpc(err)) => assert_eq!(err["code"], -32602),
+        other => panic!("expected rpc error, got {other:?}"),
+    }
+}
+
+#[test]
+fn served_tool_is_usable_as_mcp_provider() {
+    let handle = McpServer::new()
+        .with_tool("upper", upper(), json!({"type": "string"}))
+        .spawn("127.0.0.1:0")
+        .unwrap();
+
+    let provider = McpProvider::new(handle.url()).unwrap().for_tool("upper");
+    let reply = provider.ask(Ask {
+        op: "anything".into(),
+        input: json!("bridge"),
+        context: json!({}),
+    });
+    assert!(reply.ok, "{:?}", reply.output);
+    assert_eq!(reply.output["text"], "BRIDGE");
+}
+
+#[test]
+fn lists_tools_over_stdio() {
+    let server = McpServer::new().with_tool("upper", upper(), json!({"type": "string"}));
+    let input = concat!(
+        r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#,
+        "\n",
+        r#"{"jsonrpc":"2.0","id":2,"method":"invoke","params":{"tool":"upper","input":"x"}}"#,
+        "\n"
+    );
+    let mut output = Vec::new();
+    server.serve_stdio(input.as_bytes(), &mut output).unwrap();
+    let lines: Vec<serde_json::Value> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines[0]["result"]["tools"][0]["name"], "upper");
+    assert_eq!(lines[1]["result"]["text"], "X");
+}
+
+fn raw_request(stream: &mut std::net::TcpStream, head: &str) -> String {
+    use std::io::{Read, Write};
+    stream.write_all(head.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn oversized_and_malformed_bodies_are_refused() {
+    use std::net::TcpStream;
+    let handle = McpServer::new()
+        .with_tool("upper", upper(), json!({"type": "string"}))
+        .with_max_body_bytes(256)
+        .spawn("127.0.0.1:0")
+        .unwrap();
+
+    let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+    let response = raw_request(
+        &mut stream,
+        "POST / HTTP/1.1\r\ncontent-length: 99999999999\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 413"), "{response}");
+
+    let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+    let response = raw_request(
+        &mut stream,
+        "POST / HTTP/1.1\r\ncontent-length: lots\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+
+    // within the cap the request is still served
+    let client = McpClient::new(handle.url()).unwrap();
+    assert_eq!(client.invoke("upper", json!("hi")).unwrap()["text"], "HI");
+}
+
+#[test]
+fn connections_beyond_the_limit_wait_for_a_free_slot() {
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+    let handle = McpServer::new()
+        .with_max_connections(1)
+        .spawn("127.0.0.1:0")
+        .unwrap();
+
+    let idle = TcpStream::connect(handle.local_addr()).unwrap();
+    let mut waiting = TcpStream::connect(handle.local_addr()).unwrap();
+    let body = r#"{"jsonrpc":"2.0","id":1,"method":"handshake"}"#;
+    write!(
+        waiting,
+        "POST / HTTP/1.1\r\ncontent-length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    waiting
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let mut buf = [0; 1];
+    let err = waiting.read(&mut buf).unwrap_err();
+    assert!(matches!(
+        err.kind(),
+        ErrorKind::WouldBlock | ErrorKind::TimedOut
+    ));
+
+    drop(idle);
+    waiting
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut response = String::new();
+    waiting.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+}
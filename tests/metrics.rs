@@ -0,0 +1,82 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+
+use soma_agent::{
+    metrics::PrometheusMetricsSink, testing::MockProvider, Agent, Ask, Backoff, ProviderKind, Reply,
+};
+
+async fn run_with_metrics(sink: Arc<PrometheusMetricsSink>) {
+    let model = MockProvider::new().with_kind(ProviderKind::RemoteGrpc);
+    model
+        .push_failure("busy")
+        .push_reply(Reply {
+            ok: false,
+            output: json!({"tool_calls": [{"op": "search", "input": {"q": "rust"}}]}),
+            latency_ms: 120,
+            cost: json!({"total_tokens": 40}),
+        })
+        .push_reply(Reply {
+            ok: true,
+            output: json!("answer"),
+            latency_ms: 30,
+            cost: json!({"total_tokens": 12}),
+        });
+    let search = MockProvider::new();
+    search.push_ok(json!(["hit"]));
+    let mut agent = Agent::new(model, 4, 10_000, 2, CancellationToken::new())
+        .with_backoff(Backoff {
+            initial: Duration::from_millis(1),
+            ..Backoff::default()
+        })
+        .with_metrics(sink);
+    agent.register_tool("search", search).unwrap();
+    let reply = agent
+        .run(Ask {
+            op: "chat".into(),
+            input: json!("find rust"),
+            context: json!({}),
+        })
+        .await;
+    assert!(reply.ok, "{:?}", reply.output);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn run_populates_prometheus_counters() {
+    let sink = Arc::new(PrometheusMetricsSink::new());
+    run_with_metrics(sink.clone()).await;
+
+    let text = sink.render();
+    for line in [
+        "# TYPE soma_ask_total counter",
+        r#"soma_ask_total{kind="remote_grpc",op="chat"} 3"#,
+        r#"soma_retry_total{kind="remote_grpc",op="chat"} 1"#,
+        r#"soma_tool_call_total{kind="embedded",op="search"} 1"#,
+        r#"soma_tokens_total{kind="remote_grpc",op="chat"} 52"#,
+        "# TYPE soma_latency_seconds histogram",
+        r#"soma_latency_seconds_bucket{kind="remote_grpc",op="chat",le="0.1"} 2"#,
+        r#"soma_latency_seconds_bucket{kind="remote_grpc",op="chat",le="+Inf"} 3"#,
+        r#"soma_latency_seconds_count{kind="remote_grpc",op="chat"} 3"#,
+    ] {
+        assert!(
+            text.lines().any(|l| l == line),
+            "missing {line:?} in\n{text}"
+        );
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn metrics_endpoint_serves_exposition() {
+    let sink = Arc::new(PrometheusMetricsSink::new());
+    run_with_metrics(sink.clone()).await;
+    let handle = sink.clone().serve("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/metrics", handle.local_addr());
+
+    let body =
+        tokio::task::spawn_blocking(move || reqwest::blocking::get(url).unwrap().text().unwrap())
+            .await
+            .unwrap();
+    assert_eq!(body, sink.render());
+}
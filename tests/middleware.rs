@@ -0,0 +1,190 @@
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use soma_agent::{
+    middleware::{ContextMiddleware, Layered, LoggingMiddleware, Middleware, Next},
+    Ask, Provider, ProviderKind, Reply,
+};
+
+struct ContextEcho;
+
+impl Provider for ContextEcho {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Embedded
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        Reply {
+            ok: true,
+            output: ask.context,
+            latency_ms: 0,
+            cost: json!({}),
+        }
+    }
+}
+
+struct Tag {
+    name: &'static str,
+    trail: Arc<Mutex<Vec<String>>>,
+}
+
+impl Middleware for Tag {
+    fn handle(&self, mut ask: Ask, next: Next<'_>) -> Reply {
+        self.trail
+            .lock()
+            .unwrap()
+            .push(format!("{}:ask", self.name));
+        ask.context[self.name] = json!(true);
+        let mut reply = next.run(ask);
+        self.trail
+            .lock()
+            .unwrap()
+            .push(format!("{}:reply", self.name));
+        reply.output["seen_by"]
+            .as_array_mut()
+            .map(|seen| seen.push(json!(self.name)))
+            .unwrap_or_else(|| reply.output["seen_by"] = json!([self.name]));
+        reply
+    }
+}
+
+#[test]
+fn middlewares_run_in_order_and_modify_ask_and_reply() {
+    let trail = Arc::new(Mutex::new(Vec::new()));
+    let provider = Layered::new(ContextEcho)
+        .layer(Tag {
+            name: "outer",
+            trail: trail.clone(),
+        })
+        .layer(Tag {
+            name: "inner",
+            trail: trail.clone(),
+        });
+
+    let reply = provider.ask(Ask {
+        op: "echo".into(),
+        input: json!("hi"),
+        context: json!({}),
+    });
+
+    assert_eq!(
+        *trail.lock().unwrap(),
+        vec!["outer:ask", "inner:ask", "inner:reply", "outer:reply"]
+    );
+    assert_eq!(reply.output["outer"], json!(true));
+    assert_eq!(reply.output["inner"], json!(true));
+    assert_eq!(reply.output["seen_by"], json!(["inner", "outer"]));
+}
+
+#[test]
+fn builtin_logging_and_context_middlewares() {
+    let events: Arc<Mutex<Vec<Value>>> = Arc::new(Mutex::new(Vec::new()));
+    let sink = events.clone();
+    let provider = Layered::new(ContextEcho)
+        .layer(LoggingMiddleware::new(move |event| {
+            sink.lock().unwrap().push(event.clone())
+        }))
+        .layer(ContextMiddleware::new(json!({"dialect": "dashscope"})));
+
+    let reply = provider.ask(Ask {
+        op: "echo".into(),
+        input: json!("hi"),
+        context: json!({"reasoning": "direct"}),
+    });
+
+    assert_eq!(
+        reply.output,
+        json!({"reasoning": "direct", "dialect": "dashscope"})
+    );
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["event"], "ask");
+    assert_eq!(events[0]["context"], json!({"reasoning": "direct"}));
+    assert_eq!(events[1]["event"], "reply");
+    assert_eq!(events[1]["output"]["dialect"], "dashscope");
+}
+
+#[test]
+fn context_fields_overwrite_and_defaults_yield() {
+    let ask = Ask {
+        op: "echo".into(),
+        input: json!("hi"),
+        context: json!({"dialect": "openai", "limits": {"temperature": 0.9}}),
+    };
+    let fields = json!({
+        "dialect": "dashscope",
+        "limits": {"max_tokens": 256, "temperature": 0.2}
+    });
+
+    let forced = Layered::new(ContextEcho).layer(ContextMiddleware::new(fields.clone()));
+    assert_eq!(forced.ask(ask.clone()).output, fields);
+
+    let defaulted = Layered::new(ContextEcho).layer(ContextMiddleware::defaults(fields));
+    assert_eq!(
+        defaulted.ask(ask).output,
+        json!({
+            "dialect": "openai",
+            "limits": {"max_tokens": 256, "temperature": 0.9}
+        })
+    );
+}
+
+/// Streams its input back one chunk per character and reports whether it was cancelled.
+struct Streamer;
+
+impl Provider for Streamer {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Embedded
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    fn ask_cancellable(&self, _ask: Ask, token: &CancellationToken) -> Reply {
+        Reply {
+            ok: !token.is_cancelled(),
+            output: json!({"cancelled": token.is_cancelled()}),
+            latency_ms: 0,
+            cost: json!({}),
+        }
+    }
+
+    fn ask_stream(
+        &self,
+        ask: Ask,
+        token: &CancellationToken,
+        on_chunk: &mut dyn FnMut(Value),
+    ) -> Reply {
+        for c in ask.input.as_str().unwrap_or_default().chars() {
+            on_chunk(json!(c.to_string()));
+        }
+        self.ask_cancellable(ask, token)
+    }
+}
+
+#[test]
+fn layered_keeps_cancellation_and_streaming() {
+    let provider = Layered::new(Streamer)
+        .layer(LoggingMiddleware::new(|_| {}))
+        .layer(ContextMiddleware::new(json!({"tenant": "t"})));
+    let ask = Ask {
+        op: "chat".into(),
+        input: json!("abc"),
+        context: json!({}),
+    };
+
+    let token = CancellationToken::new();
+    token.cancel();
+    let reply = provider.ask_cancellable(ask.clone(), &token);
+    assert_eq!(reply.output["cancelled"], true);
+
+    let mut chunks = Vec::new();
+    let reply = provider.ask_stream(ask, &CancellationToken::new(), &mut |chunk| {
+        chunks.push(chunk)
+    });
+    assert!(reply.ok);
+    assert_eq!(chunks, vec![json!("a"), json!("b"), json!("c")]);
+}
@@ -0,0 +1,175 @@
+#![cfg(feature = "otel")]
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use httpmock::prelude::*;
+use opentelemetry::global::BoxedTracer;
+use opentelemetry::trace::{SpanId, TracerProvider};
+use opentelemetry::Value as OtelValue;
+use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+
+use soma_agent::backends::http::{HttpConfig, HttpProvider};
+use soma_agent::{testing::MockProvider, Agent, Ask, Reply};
+
+static HTTP_PARENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static MCP_PARENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn traceparent(req: &HttpMockRequest) -> Option<String> {
+    req.headers.iter().flatten().find_map(|(name, value)| {
+        name.eq_ignore_ascii_case("traceparent")
+            .then(|| value.clone())
+    })
+}
+
+fn record_http(req: &HttpMockRequest) -> bool {
+    HTTP_PARENTS.lock().unwrap().extend(traceparent(req));
+    true
+}
+
+fn record_mcp(req: &HttpMockRequest) -> bool {
+    MCP_PARENTS.lock().unwrap().extend(traceparent(req));
+    true
+}
+
+fn attr<'a>(span: &'a SpanData, key: &str) -> Option<&'a OtelValue> {
+    span.attributes
+        .iter()
+        .find(|kv| kv.key.as_str() == key)
+        .map(|kv| &kv.value)
+}
+
+fn named<'a>(spans: &'a [SpanData], name: &str) -> Vec<&'a SpanData> {
+    spans.iter().filter(|s| s.name == name).collect()
+}
+
+fn expected_parent(span: &SpanData) -> String {
+    format!(
+        "00-{}-{}-01",
+        span.span_context.trace_id(),
+        span.span_context.span_id()
+    )
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn run_exports_span_tree_and_propagates_traceparent() {
+    let http = MockServer::start();
+    http.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .matches(record_http);
+        then.status(200).json_body(json!({"id": "1"}));
+    });
+    let mcp = MockServer::start();
+    mcp.mock(|when, then| {
+        when.method(POST)
+            .json_body_partial(json!({"method": "handshake"}).to_string());
+        then.status(200)
+            .json_body(json!({"jsonrpc": "2.0", "id": 1, "result": {"ok": true}}));
+    });
+    mcp.mock(|when, then| {
+        when.method(POST)
+            .json_body_partial(json!({"method": "schema"}).to_string());
+        then.status(200)
+            .json_body(json!({"jsonrpc": "2.0", "id": 2, "result": {}}));
+    });
+    mcp.mock(|when, then| {
+        when.method(POST)
+            .json_body_partial(json!({"method": "invoke"}).to_string())
+            .matches(record_mcp);
+        then.status(200)
+            .json_body(json!({"jsonrpc": "2.0", "id": 3, "result": "pong"}));
+    });
+
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let model = Arc::new(MockProvider::new());
+    model
+        .push_reply(Reply {
+            ok: false,
+            output: json!({"tool_calls": [
+                {"op": "fetch", "input": "page"},
+                {"op": "ping", "input": {}},
+            ]}),
+            latency_ms: 0,
+            cost: json!({}),
+        })
+        .push_ok(json!("done"));
+    let mut agent = Agent::new(model.clone(), 4, 10_000, 1, CancellationToken::new())
+        .with_tracer(BoxedTracer::new(Box::new(provider.tracer("test"))));
+    agent
+        .register_tool(
+            "fetch",
+            HttpProvider::new(HttpConfig {
+                base_url: http.base_url(),
+                model: "m".into(),
+                api_key: "k".into(),
+                timeout: Duration::from_secs(1),
+            }),
+        )
+        .unwrap();
+    agent.register_tool("ping", mcp.url("/")).unwrap();
+
+    let reply = agent
+        .run(Ask {
+            op: "chat".into(),
+            input: json!("go"),
+            context: json!({}),
+        })
+        .await;
+    assert!(reply.ok, "{:?}", reply.output);
+
+    let spans = exporter.get_finished_spans().unwrap();
+    let runs = named(&spans, "agent.run");
+    assert_eq!(runs.len(), 1);
+    let run = runs[0];
+    assert_eq!(run.parent_span_id, SpanId::INVALID);
+    assert_eq!(attr(run, "soma.op"), Some(&"chat".into()));
+    assert_eq!(attr(run, "soma.reasoning"), Some(&"direct".into()));
+    assert!(matches!(attr(run, "soma.tokens"), Some(OtelValue::I64(n)) if *n > 0));
+
+    let steps = named(&spans, "agent.step");
+    assert_eq!(steps.len(), 2);
+    for step in &steps {
+        assert_eq!(step.parent_span_id, run.span_context.span_id());
+        assert_eq!(step.span_context.trace_id(), run.span_context.trace_id());
+    }
+    let first = steps
+        .iter()
+        .find(|s| attr(s, "soma.step") == Some(&OtelValue::I64(0)))
+        .unwrap();
+    assert_eq!(
+        attr(first, "soma.tools").map(|v| v.to_string()),
+        Some(r#"["fetch","ping"]"#.to_string())
+    );
+
+    let tools = named(&spans, "agent.tool");
+    assert_eq!(tools.len(), 2);
+    assert!(tools
+        .iter()
+        .all(|t| t.parent_span_id == first.span_context.span_id()));
+    let tool = |name: &str| {
+        *tools
+            .iter()
+            .find(|t| attr(t, "soma.tool") == Some(&name.to_string().into()))
+            .unwrap()
+    };
+
+    assert_eq!(
+        *HTTP_PARENTS.lock().unwrap(),
+        vec![expected_parent(tool("fetch"))]
+    );
+    assert_eq!(
+        *MCP_PARENTS.lock().unwrap(),
+        vec![expected_parent(tool("ping"))]
+    );
+    // the model's asks carry their step's trace context
+    assert_eq!(
+        model.asks()[0].context["traceparent"],
+        json!(expected_parent(first))
+    );
+}
@@ -0,0 +1,63 @@
+#![cfg(feature = "sandboxed_exec")]
+
+use serde_json::json;
+use soma_agent::{tools::ProcessTool, Ask, Provider};
+use std::time::Duration;
+
+fn ask(input: serde_json::Value) -> Ask {
+    Ask {
+        op: "run".into(),
+        input,
+        context: json!({}),
+    }
+}
+
+#[test]
+fn runs_echo_with_templated_args() {
+    let tool = ProcessTool::new("echo", vec!["-n".into(), "{text}".into()]);
+    let reply = tool.ask(ask(json!({"text": "hello; rm -rf /"})));
+    assert!(reply.ok);
+    assert_eq!(reply.output["stdout"], "hello; rm -rf /");
+    assert_eq!(reply.output["exit_code"], 0);
+    assert_eq!(reply.output["truncated"], false);
+
+    let reply = tool.ask(ask(json!({})));
+    assert!(!reply.ok);
+    assert_eq!(reply.output["error"], "missing argument text");
+}
+
+#[test]
+fn caps_output_size() {
+    let tool = ProcessTool::new("echo", vec!["-n".into(), "{text}".into()]).with_max_output(4);
+    let reply = tool.ask(ask(json!({"text": "abcdefgh"})));
+    assert_eq!(reply.output["stdout"], "abcd");
+    assert_eq!(reply.output["truncated"], true);
+}
+
+#[test]
+fn kills_command_on_timeout() {
+    let tool =
+        ProcessTool::new("sleep", vec!["{secs}".into()]).with_timeout(Duration::from_millis(100));
+    let reply = tool.ask(ask(json!({"secs": 5})));
+    assert!(!reply.ok);
+    assert_eq!(reply.output["error"], "timeout");
+    assert!(reply.latency_ms < 1000);
+}
+
+#[test]
+fn close_kills_a_running_command() {
+    let tool = std::sync::Arc::new(ProcessTool::new("sleep", vec!["{secs}".into()]));
+    let closer = {
+        let tool = tool.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            tool.close();
+        })
+    };
+    let reply = tool.ask(ask(json!({"secs": 5})));
+    closer.join().unwrap();
+    assert!(!reply.ok);
+    assert_eq!(reply.output["error"], "closed");
+    assert!(reply.latency_ms < 1000);
+    assert_eq!(tool.ask(ask(json!({"secs": 0}))).output["error"], "closed");
+}
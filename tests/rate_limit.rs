@@ -0,0 +1,74 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+
+use soma_agent::{providers::RateLimiter, Ask, Provider, ProviderKind, Reply};
+
+struct Costly;
+
+impl Provider for Costly {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::RemoteGrpc
+    }
+
+    fn ask(&self, _ask: Ask) -> Reply {
+        Reply {
+            ok: true,
+            output: json!("ok"),
+            latency_ms: 0,
+            cost: json!({"total_tokens": 30}),
+        }
+    }
+}
+
+fn ask() -> Ask {
+    Ask {
+        op: "chat".into(),
+        input: json!("hi"),
+        context: json!({}),
+    }
+}
+
+#[test]
+fn rapid_calls_are_spread_to_respect_rps() {
+    let limiter = RateLimiter::new(Costly, 20.0);
+    let start = Instant::now();
+    let handles: Vec<_> = (0..5)
+        .map(|_| {
+            let limiter = limiter.clone();
+            thread::spawn(move || limiter.ask(ask()))
+        })
+        .collect();
+    for handle in handles {
+        assert!(handle.join().unwrap().ok);
+    }
+    // the first call is free, the other four wait 50ms each
+    assert!(start.elapsed() >= Duration::from_millis(190));
+}
+
+#[test]
+fn zero_rates_mean_unlimited() {
+    let limiter = RateLimiter::new(Costly, 0.0).with_tokens_per_minute(0);
+    let start = Instant::now();
+    for _ in 0..10 {
+        assert!(limiter.ask(ask()).ok);
+    }
+    assert!(start.elapsed() < Duration::from_millis(100));
+}
+
+#[test]
+fn token_budget_blocks_until_cancelled() {
+    let limiter = RateLimiter::new(Costly, 1000.0).with_tokens_per_minute(30);
+    assert!(limiter.ask(ask()).ok);
+
+    let token = CancellationToken::new();
+    let cancel = token.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        cancel.cancel();
+    });
+    let reply = limiter.ask_cancellable(ask(), &token);
+    assert_eq!(reply.output, json!({"error": "cancelled"}));
+}
@@ -0,0 +1,64 @@
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value};
+
+use soma_agent::{
+    middleware::{Layered, LoggingMiddleware},
+    redact::Redactor,
+    Ask, Provider, ProviderKind, Reply,
+};
+
+struct Capture {
+    asks: Arc<Mutex<Vec<Ask>>>,
+}
+
+impl Provider for Capture {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Embedded
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.asks.lock().unwrap().push(ask.clone());
+        Reply {
+            ok: true,
+            output: json!({"echo": ask.input}),
+            latency_ms: 0,
+            cost: json!({}),
+        }
+    }
+}
+
+#[test]
+fn api_key_masked_in_events_but_sent_to_provider() {
+    let asks = Arc::new(Mutex::new(Vec::new()));
+    let events: Arc<Mutex<Vec<Value>>> = Arc::new(Mutex::new(Vec::new()));
+    let sink = events.clone();
+    let provider = Layered::new(Capture { asks: asks.clone() }).layer(
+        LoggingMiddleware::new(move |event| sink.lock().unwrap().push(event.clone()))
+            .with_redactor(Redactor::new().key("api_key")),
+    );
+
+    let reply = provider.ask(Ask {
+        op: "call".into(),
+        input: json!({"query": "weather", "api_key": "sk-secret"}),
+        context: json!({}),
+    });
+
+    assert_eq!(asks.lock().unwrap()[0].input["api_key"], "sk-secret");
+    assert_eq!(reply.output["echo"]["api_key"], "sk-secret");
+
+    let events = events.lock().unwrap();
+    assert_eq!(events[0]["input"]["api_key"], "[REDACTED]");
+    assert_eq!(events[0]["input"]["query"], "weather");
+    assert_eq!(events[1]["output"]["echo"]["api_key"], "[REDACTED]");
+}
+
+#[test]
+fn pointers_mask_nested_values() {
+    let redactor = Redactor::new().pointer("/user/email");
+    let value = json!({"user": {"email": "a@b.c", "name": "Ann"}});
+    let masked = redactor.redact(&value);
+    assert_eq!(masked["user"]["email"], "[REDACTED]");
+    assert_eq!(masked["user"]["name"], "Ann");
+    assert_eq!(value["user"]["email"], "a@b.c");
+}
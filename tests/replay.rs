@@ -0,0 +1,99 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+
+use soma_agent::{
+    replay::{Recorder, ReplayProvider},
+    Agent, Ask, Provider, ProviderKind, Reply,
+};
+
+struct SecondTry {
+    calls: AtomicUsize,
+}
+
+impl Provider for SecondTry {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::RemoteGrpc
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        let call = self.calls.fetch_add(1, Ordering::SeqCst);
+        Reply {
+            ok: call > 0,
+            output: json!({"call": call, "saw": ask.input}),
+            latency_ms: 7,
+            cost: json!({"total_tokens": 3}),
+        }
+    }
+}
+
+fn ask() -> Ask {
+    Ask {
+        op: "chat".into(),
+        input: json!("hello"),
+        context: json!({}),
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn recorded_run_replays_identically() {
+    let path = std::env::temp_dir().join(format!("soma_replay_{}.jsonl", uuid::Uuid::new_v4()));
+    let recorder = Recorder::new(
+        SecondTry {
+            calls: AtomicUsize::new(0),
+        },
+        &path,
+    )
+    .unwrap();
+    let agent = Agent::new(recorder, 2, 1000, 1, CancellationToken::new());
+    let recorded = agent.run(ask()).await;
+    assert!(recorded.ok);
+
+    let replay = ReplayProvider::from_file(&path).unwrap();
+    assert_eq!(replay.entries().len(), 2);
+    let agent = Agent::new(replay, 2, 1000, 1, CancellationToken::new());
+    let replayed = agent.run(ask()).await;
+
+    assert_eq!(replayed.ok, recorded.ok);
+    assert_eq!(replayed.output, recorded.output);
+    assert_eq!(replayed.latency_ms, recorded.latency_ms);
+    assert_eq!(replayed.cost, recorded.cost);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Fails once its token is cancelled, like a provider aborting a request.
+struct Abortable;
+
+impl Provider for Abortable {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::RemoteGrpc
+    }
+
+    fn ask(&self, ask: Ask) -> Reply {
+        self.ask_cancellable(ask, &CancellationToken::new())
+    }
+
+    fn ask_cancellable(&self, _ask: Ask, token: &CancellationToken) -> Reply {
+        Reply {
+            ok: !token.is_cancelled(),
+            output: json!({"cancelled": token.is_cancelled()}),
+            latency_ms: 0,
+            cost: json!({}),
+        }
+    }
+}
+
+#[test]
+fn recorder_passes_cancellation_through_and_records_it() {
+    let path = std::env::temp_dir().join(format!("soma_replay_{}.jsonl", uuid::Uuid::new_v4()));
+    let recorder = Recorder::new(Abortable, &path).unwrap();
+    let token = CancellationToken::new();
+    token.cancel();
+    let reply = recorder.ask_cancellable(ask(), &token);
+    assert_eq!(reply.output["cancelled"], true);
+
+    let replay = ReplayProvider::from_file(&path).unwrap();
+    assert_eq!(replay.entries()[0].reply.output["cancelled"], true);
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use serde_json::json;
+
+use soma_agent::{providers::CostRouter, testing::MockProvider, Ask, Provider, ReasoningPolicy};
+
+fn ask(input: &str) -> Ask {
+    Ask {
+        op: "chat".into(),
+        input: json!(input),
+        context: json!({}),
+    }
+}
+
+#[test]
+fn routes_by_complexity_and_price() {
+    let cheap = Arc::new(MockProvider::new());
+    cheap.push_ok(json!("small"));
+    let expensive = Arc::new(MockProvider::new());
+    expensive.push_ok(json!("large"));
+
+    let router = CostRouter::new(ReasoningPolicy::default())
+        .route("large", 10.0, usize::MAX, expensive.clone())
+        .route("small", 0.5, 200, cheap.clone());
+
+    let reply = router.ask(ask("short question"));
+    assert_eq!(reply.output, json!("small"));
+    assert_eq!(reply.cost["route"], "small");
+    assert_eq!(cheap.asks()[0].context["route"], "small");
+
+    let long = "x".repeat(500);
+    assert_eq!(router.select(&ask(&long)), Some("large"));
+    let reply = router.ask(ask(&long));
+    assert_eq!(reply.output, json!("large"));
+    assert_eq!(reply.cost["route"], "large");
+    assert_eq!(cheap.call_count(), 1);
+    assert_eq!(expensive.call_count(), 1);
+}
@@ -0,0 +1,59 @@
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::json;
+
+use soma_agent::{providers::SingleFlightProvider, testing::MockProvider, Ask, Provider};
+
+fn ask(request_id: usize) -> Ask {
+    Ask {
+        op: "lookup".into(),
+        input: json!({"sku": 42}),
+        context: json!({"request_id": format!("req-{request_id}")}),
+    }
+}
+
+#[test]
+fn concurrent_identical_asks_share_one_call() {
+    let inner = Arc::new(MockProvider::new().with_latency(Duration::from_millis(100)));
+    inner.push_ok(json!({"stock": 7}));
+    let provider = Arc::new(SingleFlightProvider::new(inner.clone()));
+    let start = Arc::new(Barrier::new(5));
+
+    let replies: Vec<_> = (0..5)
+        .map(|i| {
+            let provider = provider.clone();
+            let start = start.clone();
+            thread::spawn(move || {
+                start.wait();
+                provider.ask(ask(i))
+            })
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|h| h.join().unwrap())
+        .collect();
+
+    assert_eq!(inner.call_count(), 1);
+    assert!(replies
+        .iter()
+        .all(|r| r.ok && r.output == json!({"stock": 7})));
+    let coalesced = replies
+        .iter()
+        .filter(|r| r.cost == json!({"coalesced": true}))
+        .count();
+    assert_eq!(coalesced, 4);
+    assert_eq!(provider.in_flight(), 0);
+}
+
+#[test]
+fn later_asks_are_not_served_from_memory() {
+    let inner = Arc::new(MockProvider::new());
+    inner.push_ok(json!(1)).push_ok(json!(2));
+    let provider = SingleFlightProvider::new(inner.clone());
+
+    assert_eq!(provider.ask(ask(0)).output, json!(1));
+    assert_eq!(provider.ask(ask(1)).output, json!(2));
+    assert_eq!(inner.call_count(), 2);
+}
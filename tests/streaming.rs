@@ -0,0 +1,75 @@
+use serde_json::json;
+
+use soma_agent::backends::stream::{parse_sse, DeltaRouter, ToolCallAccumulator};
+
+const FRAGMENTED: &str = r#"data: {"choices":[{"index":0,"delta":{"role":"assistant","tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"get_current_weather","arguments":""}}]}}]}
+
+data: {"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"loca"}}]}}]}
+
+data: {"choices":[{"index":0,"delta":{"tool_calls":[{"index":1,"id":"call_2","function":{"name":"get_weather_forecast","arguments":"{\"days\":"}}]}}]}
+
+data: {"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"tion\": \"San Francisco\"}"}}]}}]}
+
+data: {"choices":[{"index":0,"delta":{"tool_calls":[{"index":1,"function":{"arguments":"2}"}}]}}]}
+
+data: {"choices":[{"index":0,"delta":{},"finish_reason":"tool_calls"}]}
+
+data: [DONE]
+"#;
+
+#[test]
+fn fragmented_tool_call_arguments_are_reassembled() {
+    let mut acc = ToolCallAccumulator::new();
+    for chunk in parse_sse(FRAGMENTED) {
+        acc.push_chunk(&chunk);
+    }
+    assert_eq!(
+        acc.finish(),
+        vec![
+            json!({
+                "op": "get_current_weather",
+                "input": {"location": "San Francisco"},
+                "id": "call_1"
+            }),
+            json!({
+                "op": "get_weather_forecast",
+                "input": {"days": 2},
+                "id": "call_2"
+            }),
+        ]
+    );
+}
+
+#[test]
+fn incomplete_arguments_are_not_surfaced() {
+    let mut acc = ToolCallAccumulator::new();
+    let chunks = parse_sse(FRAGMENTED);
+    for chunk in &chunks[..3] {
+        acc.push_chunk(chunk);
+    }
+    assert!(acc.finish().is_empty());
+}
+
+#[test]
+fn reasoning_and_content_deltas_use_separate_channels() {
+    let body = r#"data: {"choices":[{"index":0,"delta":{"role":"assistant","reasoning_content":"Add "}}]}
+
+data: {"choices":[{"index":0,"delta":{"reasoning_content":"the numbers."}}]}
+
+data: {"choices":[{"index":0,"delta":{"content":"4"}}]}
+
+data: {"choices":[{"index":0,"delta":{"reasoning":"Checked.","content":""}}]}
+
+data: [DONE]
+"#;
+    let (router, reasoning, content) = DeltaRouter::new();
+    for chunk in parse_sse(body) {
+        router.push_chunk(&chunk);
+    }
+    drop(router);
+    assert_eq!(
+        reasoning.iter().collect::<Vec<_>>(),
+        vec!["Add ", "the numbers.", "Checked."]
+    );
+    assert_eq!(content.iter().collect::<Vec<_>>(), vec!["4"]);
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "typed_schema")]
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use soma_agent::schema::{function_schema, validate};
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum Unit {
+    Celsius,
+    Fahrenheit,
+}
+
+/// Current weather for a location.
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct WeatherArgs {
+    /// City and country, e.g. "Paris, FR".
+    location: String,
+    unit: Unit,
+    days: Option<u8>,
+}
+
+#[test]
+fn derives_function_schema_from_struct() {
+    let schema = function_schema::<WeatherArgs>("get_weather", "Look up the weather");
+
+    assert_eq!(schema["name"], "get_weather");
+    assert_eq!(schema["description"], "Look up the weather");
+    let params = &schema["parameters"];
+    assert_eq!(params["type"], "object");
+    assert_eq!(params["required"], json!(["location", "unit"]));
+    assert_eq!(params["properties"]["location"]["type"], "string");
+    assert_eq!(
+        params["properties"]["unit"]["enum"],
+        json!(["celsius", "fahrenheit"])
+    );
+    assert!(params.get("$schema").is_none());
+    assert!(params.get("definitions").is_none());
+
+    let args = WeatherArgs {
+        location: "Paris, FR".into(),
+        unit: Unit::Celsius,
+        days: Some(2),
+    };
+    validate(params, &serde_json::to_value(&args).unwrap()).unwrap();
+    assert!(validate(params, &json!({"location": "Paris"})).is_err());
+}
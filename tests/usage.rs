@@ -0,0 +1,95 @@
+use serde_json::json;
+
+use soma_agent::usage::{merge_usage, reasoning_tokens};
+
+#[test]
+fn sums_token_counts_and_keeps_missing_ones() {
+    let merged = merge_usage(
+        &json!({"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15}),
+        &json!({"prompt_tokens": 3, "total_tokens": 3}),
+    );
+    assert_eq!(
+        merged,
+        json!({"prompt_tokens": 13, "completion_tokens": 5, "total_tokens": 18})
+    );
+}
+
+#[test]
+fn merges_openai_and_anthropic_shapes() {
+    let merged = merge_usage(
+        &json!({"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15}),
+        &json!({"input_tokens": 7, "output_tokens": 2}),
+    );
+    assert_eq!(
+        merged,
+        json!({
+            "prompt_tokens": 10,
+            "completion_tokens": 5,
+            "input_tokens": 7,
+            "output_tokens": 2,
+            "total_tokens": 24,
+        })
+    );
+}
+
+#[test]
+fn merges_nested_details_and_keeps_unknown_fields() {
+    let merged = merge_usage(
+        &json!({
+            "total_tokens": 4,
+            "prompt_tokens_details": {"cached_tokens": 1},
+            "model": "a",
+            "tier": "free",
+        }),
+        &json!({
+            "total_tokens": 6,
+            "prompt_tokens_details": {"cached_tokens": 2, "audio_tokens": 0},
+            "model": "b",
+        }),
+    );
+    assert_eq!(merged["total_tokens"], json!(10));
+    // counts under unknown keys are not token fields, so the latest wins
+    assert_eq!(
+        merged["prompt_tokens_details"],
+        json!({"cached_tokens": 2, "audio_tokens": 0})
+    );
+    assert_eq!(merged["model"], json!("b"));
+    assert_eq!(merged["tier"], json!("free"));
+}
+
+#[test]
+fn missing_or_non_object_usage_is_ignored() {
+    let usage = json!({"total_tokens": 9});
+    assert_eq!(merge_usage(&usage, &json!(null)), usage);
+    assert_eq!(merge_usage(&json!(null), &usage), usage);
+    assert_eq!(merge_usage(&json!({}), &usage), usage);
+}
+
+#[test]
+fn reads_and_sums_reasoning_tokens() {
+    assert_eq!(reasoning_tokens(&json!({"reasoning_tokens": 7})), 7);
+    assert_eq!(
+        reasoning_tokens(&json!({"completion_tokens_details": {"reasoning_tokens": 5}})),
+        5
+    );
+    assert_eq!(
+        reasoning_tokens(&json!({"output_tokens_details": {"reasoning_tokens": 3}})),
+        3
+    );
+    assert_eq!(reasoning_tokens(&json!({"completion_tokens": 9})), 0);
+
+    let merged = merge_usage(
+        &json!({"completion_tokens": 10, "reasoning_tokens": 6, "completion_tokens_details": {"reasoning_tokens": 6}}),
+        &json!({"completion_tokens": 4, "reasoning_tokens": 2, "completion_tokens_details": {"reasoning_tokens": 2}}),
+    );
+    assert_eq!(reasoning_tokens(&merged), 8);
+    assert_eq!(merged["completion_tokens_details"]["reasoning_tokens"], 8);
+    // already part of the completion, so not added to the derived total
+    assert_eq!(
+        merge_usage(
+            &json!({"total_tokens": 1}),
+            &json!({"completion_tokens": 4, "reasoning_tokens": 2})
+        )["total_tokens"],
+        5
+    );
+}
@@ -2,6 +2,8 @@
 
 use serde_json::json;
 use soma_agent::{tools::WasmTool, Ask, Provider};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
 #[test]
@@ -67,3 +69,318 @@ fn enforces_memory_limit() {
     let reply = tool.ask(ask);
     assert!(!reply.ok);
 }
+
+#[test]
+fn squares_f64() {
+    let wat = r#"(module (func (export "square") (param f64) (result f64)
+                        local.get 0 local.get 0 f64.mul))"#;
+    let wasm = wat::parse_str(wat).unwrap();
+    let tool = WasmTool::from_bytes(&wasm, 10_000, None, Duration::from_secs(1)).unwrap();
+    let reply = tool.ask(Ask {
+        op: "square".into(),
+        input: json!(1.5),
+        context: json!({}),
+    });
+    assert!(reply.ok);
+    assert_eq!(reply.output, json!(2.25));
+}
+
+#[test]
+fn adds_i64_and_returns_multiple_values() {
+    let wat = r#"(module
+        (func (export "add") (param i64 i64) (result i64)
+            local.get 0 local.get 1 i64.add)
+        (func (export "pair") (param i32) (result i32 f32)
+            local.get 0 f32.const 0.5))"#;
+    let wasm = wat::parse_str(wat).unwrap();
+    let tool = WasmTool::from_bytes(&wasm, 10_000, None, Duration::from_secs(1)).unwrap();
+    let reply = tool.ask(Ask {
+        op: "add".into(),
+        input: json!([5_000_000_000i64, 7]),
+        context: json!({}),
+    });
+    assert!(reply.ok);
+    assert_eq!(reply.output, json!(5_000_000_007i64));
+
+    let reply = tool.ask(Ask {
+        op: "pair".into(),
+        input: json!(3),
+        context: json!({}),
+    });
+    assert_eq!(reply.output, json!([3, 0.5]));
+
+    let reply = tool.ask(Ask {
+        op: "add".into(),
+        input: json!(1),
+        context: json!({}),
+    });
+    assert!(!reply.ok);
+    assert_eq!(reply.output["error"], "expected 2 arguments, got 1");
+}
+
+#[test]
+fn executes_component_export() {
+    let wat = r#"(component
+        (core module $m
+            (func (export "double") (param i32) (result i32)
+                local.get 0 i32.const 2 i32.mul))
+        (core instance $i (instantiate $m))
+        (func (export "double") (param "x" s32) (result s32)
+            (canon lift (core func $i "double"))))"#;
+    let wasm = wat::parse_str(wat).unwrap();
+    let tool = WasmTool::from_bytes(&wasm, 10_000, None, Duration::from_secs(1)).unwrap();
+    assert!(tool.is_component());
+    let reply = tool.ask(Ask {
+        op: "double".into(),
+        input: json!(21),
+        context: json!({}),
+    });
+    assert!(reply.ok, "{:?}", reply.output);
+    // same result as the core-module `executes_simple_wasm`
+    assert_eq!(reply.output, json!(42));
+}
+
+#[test]
+fn reuses_linked_module_across_calls() {
+    let wat = r#"(module
+        (global $calls (mut i32) (i32.const 0))
+        (func (export "bump") (param i32) (result i32)
+            global.get $calls i32.const 1 i32.add global.set $calls
+            global.get $calls local.get 0 i32.add))"#;
+    let wasm = wat::parse_str(wat).unwrap();
+    let tool = WasmTool::from_bytes(&wasm, 10_000, Some(65_536), Duration::from_secs(1)).unwrap();
+    for i in 0..100 {
+        let reply = tool.ask(Ask {
+            op: "bump".into(),
+            input: json!(i),
+            context: json!({}),
+        });
+        // a fresh instance every call: the global never carries over
+        assert_eq!(reply.output, json!(i + 1));
+    }
+    let stats = tool.stats();
+    assert_eq!(stats.linked, 1);
+    assert_eq!(stats.instantiated, 100);
+}
+
+#[tokio::test]
+async fn long_guest_yields_to_sibling_tasks() {
+    let wat = r#"(module (func (export "spin") (param i32) (result i32)
+        (local $n i32)
+        (loop $l
+            local.get $n i32.const 1 i32.add local.tee $n
+            local.get 0 i32.lt_u br_if $l)
+        local.get $n))"#;
+    let wasm = wat::parse_str(wat).unwrap();
+    let tool = WasmTool::from_bytes(&wasm, u64::MAX, None, Duration::from_secs(5)).unwrap();
+    let done = Arc::new(AtomicBool::new(false));
+    let sibling = tokio::spawn({
+        let done = done.clone();
+        async move { done.store(true, Ordering::SeqCst) }
+    });
+    // on a current-thread runtime the sibling only runs if the guest yields
+    let reply = tool
+        .ask_async(Ask {
+            op: "spin".into(),
+            input: json!(50_000_000),
+            context: json!({}),
+        })
+        .await;
+    assert!(done.load(Ordering::SeqCst));
+    assert_eq!(reply.output, json!(50_000_000));
+    sibling.await.unwrap();
+}
+
+#[tokio::test]
+async fn async_timeout_drops_runaway_guest() {
+    let wat = r#"(module (func (export "burn") (param i32) (result i32) (loop br 0) i32.const 0))"#;
+    let wasm = wat::parse_str(wat).unwrap();
+    let tool = WasmTool::from_bytes(&wasm, u64::MAX, None, Duration::from_millis(50)).unwrap();
+    let reply = tool
+        .ask_async(Ask {
+            op: "burn".into(),
+            input: json!(0),
+            context: json!({}),
+        })
+        .await;
+    assert_eq!(reply.output, json!({"error": "timeout"}));
+}
+
+/// Asks host tool `upper` with `"hi"` and returns the digit before the closing
+/// brace of the `{"ok", "output"}` reply, i.e. a one-digit `output`.
+const HOST_CALLER: &str = r#"(module
+    (import "soma" "host_invoke" (func $invoke (param i32 i32 i32 i32) (result i64)))
+    (memory (export "memory") 1)
+    (data (i32.const 0) "upper")
+    (data (i32.const 16) "\"hi\"")
+    (global $next (mut i32) (i32.const 1024))
+    (func (export "alloc") (param i32) (result i32)
+        global.get $next
+        global.get $next local.get 0 i32.add global.set $next)
+    (func (export "run") (result i32)
+        (local $reply i64)
+        i32.const 0 i32.const 5 i32.const 16 i32.const 4
+        call $invoke
+        local.set $reply
+        local.get $reply i64.const 32 i64.shr_u i32.wrap_i64
+        local.get $reply i32.wrap_i64
+        i32.add i32.const 2 i32.sub
+        i32.load8_u
+        i32.const 48 i32.sub))"#;
+
+#[test]
+fn guest_calls_host_tool() {
+    use soma_agent::{providers::FnProvider, Reply};
+
+    let wasm = wat::parse_str(HOST_CALLER).unwrap();
+    let tool = WasmTool::from_bytes(&wasm, 100_000, None, Duration::from_secs(1))
+        .unwrap()
+        .with_host_tool(
+            "upper",
+            FnProvider::new(|ask: Ask| Reply {
+                ok: ask.input == json!("hi"),
+                output: json!(7),
+                latency_ms: 0,
+                cost: json!({}),
+            }),
+        );
+    let reply = tool.ask(Ask {
+        op: "run".into(),
+        input: json!(null),
+        context: json!({}),
+    });
+    assert!(reply.ok, "{:?}", reply.output);
+    assert_eq!(reply.output, json!(7));
+}
+
+#[test]
+fn host_calls_are_charged_fuel() {
+    use soma_agent::{providers::FnProvider, Reply};
+
+    let wasm = wat::parse_str(HOST_CALLER).unwrap();
+    let tool = WasmTool::from_bytes(&wasm, 500, None, Duration::from_secs(1))
+        .unwrap()
+        .with_host_tool(
+            "upper",
+            FnProvider::new(|_| Reply {
+                ok: true,
+                output: json!(7),
+                latency_ms: 0,
+                cost: json!({}),
+            }),
+        );
+    let reply = tool.ask(Ask {
+        op: "run".into(),
+        input: json!(null),
+        context: json!({}),
+    });
+    assert!(!reply.ok);
+    assert!(reply.output["error"]
+        .as_str()
+        .unwrap()
+        .contains("all fuel consumed by host call"));
+}
+
+#[test]
+fn deterministic_runs_repeat_output_and_fuel() {
+    // random bytes xor the realtime clock: differs every run unless both are pinned
+    let wat = r#"(module
+        (import "wasi_snapshot_preview1" "random_get"
+            (func $random (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "clock_time_get"
+            (func $clock (param i32 i64 i32) (result i32)))
+        (memory (export "memory") 1)
+        (func (export "noise") (result i64)
+            i32.const 0 i32.const 8 call $random drop
+            i32.const 0 i64.const 1 i32.const 8 call $clock drop
+            i32.const 0 i64.load
+            i32.const 8 i64.load
+            i64.xor))"#;
+    let wasm = wat::parse_str(wat).unwrap();
+    let tool = WasmTool::from_bytes(&wasm, 100_000, None, Duration::from_secs(1))
+        .unwrap()
+        .with_deterministic(true);
+    let ask = Ask {
+        op: "noise".into(),
+        input: json!(null),
+        context: json!({}),
+    };
+    let first = tool.ask(ask.clone());
+    let second = tool.ask(ask);
+    assert!(first.ok, "{:?}", first.output);
+    assert_eq!(first.output, second.output);
+    assert_eq!(first.cost, second.cost);
+    assert!(first.cost["fuel"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn cancelling_stops_busy_guest() {
+    use tokio_util::sync::CancellationToken;
+
+    let wat = r#"(module (func (export "burn") (param i32) (result i32) (loop br 0) i32.const 0))"#;
+    let wasm = wat::parse_str(wat).unwrap();
+    let tool = WasmTool::from_bytes(&wasm, u64::MAX, None, Duration::from_secs(30)).unwrap();
+    let token = CancellationToken::new();
+    let canceller = std::thread::spawn({
+        let token = token.clone();
+        move || {
+            std::thread::sleep(Duration::from_millis(50));
+            token.cancel();
+        }
+    });
+    let start = std::time::Instant::now();
+    let reply = tool.ask_cancellable(
+        Ask {
+            op: "burn".into(),
+            input: json!(0),
+            context: json!({}),
+        },
+        &token,
+    );
+    assert_eq!(reply.output, json!({"error": "cancelled"}));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    canceller.join().unwrap();
+}
+
+#[tokio::test]
+async fn agent_awaits_guest_without_blocking_its_runtime() {
+    use soma_agent::testing::MockProvider;
+    use soma_agent::{Agent, Reply};
+    use tokio_util::sync::CancellationToken;
+
+    let wat = r#"(module (func (export "spin") (param i32) (result i32)
+        (local $n i32)
+        (loop $l
+            local.get $n i32.const 1 i32.add local.tee $n
+            local.get 0 i32.lt_u br_if $l)
+        local.get $n))"#;
+    let wasm = wat::parse_str(wat).unwrap();
+    let tool = WasmTool::from_bytes(&wasm, u64::MAX, None, Duration::from_secs(5)).unwrap();
+    let model = MockProvider::new();
+    model.push_reply(Reply {
+        ok: false,
+        output: json!({"tool_calls": [{"op": "spin", "input": 50_000_000}]}),
+        latency_ms: 0,
+        cost: json!({}),
+    });
+    model.push_ok(json!("done"));
+    let mut agent = Agent::new(model, 3, 10_000, 1, CancellationToken::new());
+    agent.register_tool("spin", tool).unwrap();
+    let done = Arc::new(AtomicBool::new(false));
+    let sibling = tokio::spawn({
+        let done = done.clone();
+        async move { done.store(true, Ordering::SeqCst) }
+    });
+    // the sibling only runs mid-call if the agent awaits the guest rather than
+    // blocking this current-thread runtime on it
+    let reply = agent
+        .run(Ask {
+            op: "chat".into(),
+            input: json!("spin"),
+            context: json!({}),
+        })
+        .await;
+    assert!(done.load(Ordering::SeqCst));
+    assert!(reply.ok, "{:?}", reply.output);
+    sibling.await.unwrap();
+}